@@ -0,0 +1,146 @@
+//! Throughput of the compiled evaluation plan against the rule interpreter.
+//!
+//! Run with `cargo bench --bench rule_plan`
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Instant;
+
+use regex::Regex;
+use usiem::prelude::holder::DatasetHolder;
+use usiem::prelude::rule::{
+    AlertGenerator, MitreInfo, RuleCondition, RuleOperator, SiemRule, SiemSubRule,
+};
+use usiem::prelude::{AlertSeverity, LogString, SiemField, SiemLog};
+
+const ITERATIONS: usize = 200;
+
+fn condition(field: &'static str, operator: RuleOperator) -> RuleCondition {
+    RuleCondition {
+        field: LogString::Borrowed(field),
+        operator,
+    }
+}
+
+fn suspicious_process_rule() -> SiemRule {
+    let mut subrules = BTreeMap::new();
+    subrules.insert(
+        LogString::Borrowed("encoded_powershell"),
+        SiemSubRule {
+            conditions: vec![
+                condition(
+                    "process.command_line",
+                    RuleOperator::Matches(
+                        Regex::from_str(r"(?i)\s-e(nc|ncodedcommand)?\s").unwrap(),
+                    ),
+                ),
+                condition(
+                    "process.command_line",
+                    RuleOperator::Not(Box::new(RuleOperator::Contains("-NoProfile".into()))),
+                ),
+                condition(
+                    "process.name",
+                    RuleOperator::Equals("powershell.exe".into()),
+                ),
+            ],
+            rule_state: None,
+        },
+    );
+    subrules.insert(
+        LogString::Borrowed("internal_network"),
+        SiemSubRule {
+            conditions: vec![
+                condition(
+                    "source.ip",
+                    RuleOperator::SameNet(([10, 0, 0, 0].into(), 8)),
+                ),
+                condition(
+                    "destination.port",
+                    RuleOperator::Any(vec![
+                        Box::new(RuleOperator::GTE(SiemField::I64(1024))),
+                        Box::new(RuleOperator::Equals(SiemField::U64(445))),
+                    ]),
+                ),
+            ],
+            rule_state: None,
+        },
+    );
+    SiemRule {
+        id: LogString::Borrowed("bench001"),
+        name: LogString::Borrowed("Encoded powershell"),
+        description: LogString::Borrowed(""),
+        mitre: Cow::Owned(MitreInfo {
+            tactics: vec![],
+            techniques: vec![],
+        }),
+        needed_datasets: vec![],
+        subrules: Cow::Owned(subrules),
+        conditions: Cow::Owned(vec![]),
+        alert: Cow::Owned(AlertGenerator {
+            content: vec![],
+            severity: AlertSeverity::HIGH,
+            tags: vec![],
+            aggregation: None,
+        }),
+        aggregation: None,
+        sequence: None,
+        expression: None,
+    }
+}
+
+fn process_log(i: usize) -> SiemLog {
+    let name = if i.is_multiple_of(10) {
+        "powershell.exe"
+    } else {
+        "svchost.exe"
+    };
+    let mut log = SiemLog::new("", 0, "localhost");
+    log.add_field("process.name", SiemField::from(name.to_string()));
+    log.add_field(
+        "process.command_line",
+        "C:\\Windows\\system32\\svchost.exe -k netsvcs -p -s Schedule -enc AAAA".into(),
+    );
+    log.add_field(
+        "source.ip",
+        SiemField::IP([10, 0, (i % 255) as u32, 1].into()),
+    );
+    log.add_field("destination.port", SiemField::U64(i as u64));
+    log
+}
+
+fn main() {
+    let rule = suspicious_process_rule();
+    let plan = rule.compile().unwrap();
+    let datasets = DatasetHolder::new();
+    let mut logs: Vec<SiemLog> = (0..1_000).map(process_log).collect();
+    let evaluations = (ITERATIONS * logs.len()) as f64;
+
+    let start = Instant::now();
+    let mut interpreter_matches = 0;
+    for _ in 0..ITERATIONS {
+        for log in logs.iter_mut() {
+            if rule.matches(log, &datasets).is_some() {
+                interpreter_matches += 1;
+            }
+        }
+    }
+    let interpreter = start.elapsed();
+
+    let start = Instant::now();
+    let mut compiled_matches = 0;
+    for _ in 0..ITERATIONS {
+        for log in logs.iter_mut() {
+            if plan.matches(log, &datasets).is_some() {
+                compiled_matches += 1;
+            }
+        }
+    }
+    let compiled = start.elapsed();
+
+    assert_eq!(interpreter_matches, compiled_matches);
+    println!(
+        "Interpreter: {:.0} logs/s, Compiled: {:.0} logs/s",
+        evaluations / interpreter.as_secs_f64(),
+        evaluations / compiled.as_secs_f64()
+    );
+}
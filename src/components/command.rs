@@ -4,6 +4,7 @@ use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 
+use super::query::saved::SavedQuery;
 use super::task::{SiemTask, SiemTaskResult};
 use super::{
     command_types::{
@@ -23,6 +24,8 @@ pub enum SiemFunctionType {
     STOP_COMPONENT,
     START_COMPONENT,
     LOG_QUERY,
+    LIST_SAVED_QUERIES,
+    RUN_SAVED_QUERY,
     ISOLATE_IP,
     ISOLATE_ENDPOINT,
     FILTER_IP,
@@ -121,6 +124,10 @@ pub enum SiemCommandCall {
     STOP_COMPONENT(String),
     /// Query in database format. Ex SQL,  Elastic
     LOG_QUERY(QueryInfo),
+    /// List the saved queries the user can run: offset, limit
+    LIST_SAVED_QUERIES(Pagination),
+    /// Runs the saved query referenced by `saved_query` with the `parameters`
+    RUN_SAVED_QUERY(QueryInfo),
     /// IP of the device to isolate
     ISOLATE_IP(IsolateIp),
     /// IP of the device to isolate
@@ -157,6 +164,8 @@ impl SiemCommandCall {
             SiemCommandCall::START_COMPONENT(_) => SiemFunctionType::START_COMPONENT,
             SiemCommandCall::STOP_COMPONENT(_) => SiemFunctionType::STOP_COMPONENT,
             SiemCommandCall::LOG_QUERY(_) => SiemFunctionType::LOG_QUERY,
+            SiemCommandCall::LIST_SAVED_QUERIES(_) => SiemFunctionType::LIST_SAVED_QUERIES,
+            SiemCommandCall::RUN_SAVED_QUERY(_) => SiemFunctionType::RUN_SAVED_QUERY,
             SiemCommandCall::ISOLATE_IP(_) => SiemFunctionType::ISOLATE_IP,
             SiemCommandCall::ISOLATE_ENDPOINT(_) => SiemFunctionType::ISOLATE_ENDPOINT,
             SiemCommandCall::FILTER_IP(_) => SiemFunctionType::FILTER_IP,
@@ -201,6 +210,9 @@ pub enum SiemCommandResponse {
     STOP_COMPONENT(CommandResult<String>),
     /// Query created with an ID
     LOG_QUERY(QueryInfo, CommandResult<Vec<BTreeMap<String, SiemField>>>),
+    LIST_SAVED_QUERIES(CommandResult<Vec<SavedQuery>>),
+    /// Query with the parameters already bound
+    RUN_SAVED_QUERY(QueryInfo, CommandResult<Vec<BTreeMap<String, SiemField>>>),
     ISOLATE_IP(CommandResult<String>),
     ISOLATE_ENDPOINT(CommandResult<String>),
     /// (IP, Comment)
@@ -316,6 +328,12 @@ pub struct QueryInfo {
     pub query: String,
     /// List of fields to be returned, empty for all
     pub fields: Vec<String>,
+    /// ID of the saved query to run instead of `query`
+    #[serde(default)]
+    pub saved_query: Option<String>,
+    /// Values of the parameters of the saved query: name without `$` => value
+    #[serde(default)]
+    pub parameters: BTreeMap<String, String>,
 }
 
 impl QueryInfo {
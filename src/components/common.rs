@@ -103,8 +103,8 @@ impl SiemComponentCapabilities {
     }
 }
 
-/// An easy to use role based system
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+/// An easy to use role based system. Each role has more permissions than the previous one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum UserRole {
     /// Review the system (Read-Only configuration: rules, use-cases, Sources with parsers)
     Compliance,
@@ -1,4 +1,4 @@
-use super::{SiemDataset, SiemDatasetType, text_map::TextMapSynDataset, geo_ip::GeoIpSynDataset, i18n::I18nSynDataset, ip_map::IpMapSynDataset, ip_map_list::IpMapListSynDataset, ip_set::IpSetSynDataset, text_set::TextSetSynDataset, text_map_list::TextMapListSynDataset, ip_net::IpNetSynDataset};
+use super::{SiemDataset, SiemDatasetType, text_map::TextMapSynDataset, geo_ip::GeoIpSynDataset, i18n::I18nSynDataset, rule_exceptions::RuleExceptionsSynDataset, ip_map::IpMapSynDataset, ip_map_list::IpMapListSynDataset, ip_set::IpSetSynDataset, text_set::TextSetSynDataset, text_map_list::TextMapListSynDataset, ip_net::IpNetSynDataset};
 use std::collections::BTreeMap;
 
 /// The dataset holder allows access to the latest version of a dataset almost instantly without the need to check if there is an update of a dataset using a channel as was done previously.
@@ -46,6 +46,9 @@ impl DatasetHolder {
     pub fn i18n(&self) -> Option<&I18nSynDataset> {
         self.datasets.get(&SiemDatasetType::I18n)?.try_into().ok()
     }
+    pub fn rule_exceptions(&self) -> Option<&RuleExceptionsSynDataset> {
+        self.datasets.get(&SiemDatasetType::RuleExceptions)?.try_into().ok()
+    }
     pub fn ip_mac(&self) -> Option<&IpMapSynDataset> {
         self.datasets.get(&SiemDatasetType::IpMac)?.try_into().ok()
     }
@@ -6,6 +6,7 @@ pub mod ip_map;
 pub mod ip_map_list;
 pub mod ip_net;
 pub mod ip_set;
+pub mod rule_exceptions;
 pub mod rules;
 pub mod text_map;
 pub mod text_map_list;
@@ -28,6 +29,7 @@ use text_map::{TextMapSynDataset, UpdateTextMap};
 use text_map_list::{TextMapListSynDataset, UpdateTextMapList};
 use text_set::{TextSetSynDataset, UpdateTextSet};
 
+use self::rule_exceptions::{RuleExceptionsSynDataset, UpdateRuleExceptions};
 use self::rules::{CorrelationRulesDataset, UpdateRules};
 
 /// Commonly used datasets. They are filled with the information extracted form logs, from the CMDB, from user commands or from repetitive Task like GeoIP.
@@ -38,6 +40,8 @@ use self::rules::{CorrelationRulesDataset, UpdateRules};
 pub enum SiemDataset {
     /// Correlation Rules that can be updated in real-time
     CorrelationRules(CorrelationRulesDataset),
+    /// Exceptions of the correlation rules with their expiration
+    RuleExceptions(RuleExceptionsSynDataset),
     /// Map IP to country, city, latitude and longitude
     GeoIp(GeoIpSynDataset),
     /// IP associated with a MAC address
@@ -445,6 +449,29 @@ impl<'a> TryFrom<&'a SiemDataset> for &'a GeoIpSynDataset {
     }
 }
 
+impl TryFrom<SiemDataset> for RuleExceptionsSynDataset {
+    type Error = &'static str;
+
+    fn try_from(value: SiemDataset) -> Result<Self, Self::Error> {
+        if let SiemDataset::RuleExceptions(v) = value {
+            Ok(v)
+        } else {
+            Err("RuleExceptionsSynDataset is only valid for RuleExceptions dataset!")
+        }
+    }
+}
+impl<'a> TryFrom<&'a SiemDataset> for &'a RuleExceptionsSynDataset {
+    type Error = &'static str;
+
+    fn try_from(value: &'a SiemDataset) -> Result<Self, Self::Error> {
+        if let SiemDataset::RuleExceptions(v) = value {
+            Ok(v)
+        } else {
+            Err("RuleExceptionsSynDataset is only valid for RuleExceptions dataset!")
+        }
+    }
+}
+
 impl TryFrom<SiemDataset> for I18nSynDataset {
     type Error = &'static str;
 
@@ -783,6 +810,8 @@ pub enum SiemDatasetType {
     /// Vulnerabilities on a computer  
     HostVulnerable,
     CorrelationRules,
+    /// Exceptions of the correlation rules
+    RuleExceptions,
     /// User custom dataset IP_NET => Text
     CustomMapIpNet(LogString),
     /// User custom dataset Text => Text
@@ -806,6 +835,7 @@ impl SiemDataset {
     pub fn dataset_type(&self) -> SiemDatasetType {
         match self {
             SiemDataset::CorrelationRules(_) => SiemDatasetType::CorrelationRules,
+            SiemDataset::RuleExceptions(_) => SiemDatasetType::RuleExceptions,
             SiemDataset::GeoIp(_) => SiemDatasetType::GeoIp,
             SiemDataset::IpMac(_) => SiemDatasetType::IpMac,
             SiemDataset::IpDNS(_) => SiemDatasetType::IpDNS,
@@ -871,6 +901,7 @@ impl Serialize for SiemDataset {
         let mut state = serializer.serialize_struct("SiemDataset", 2)?;
         let typ = match self {
             SiemDataset::CorrelationRules(_) => "CorrelationRules",
+            SiemDataset::RuleExceptions(_) => "RuleExceptions",
             SiemDataset::GeoIp(_) => "GeoIp",
             SiemDataset::IpMac(_) => "IpMac",
             SiemDataset::IpDNS(_) => "IpDNS",
@@ -953,5 +984,6 @@ pub enum UpdateDataset {
     Secrets(UpdateTextMap),
     HostVulnerable(UpdateTextMapList),
     CorrelationRules(UpdateRules),
+    RuleExceptions(UpdateRuleExceptions),
     I18n(UpdateI18n),
 }
\ No newline at end of file
@@ -0,0 +1,177 @@
+use crate::prelude::holder::DatasetHolder;
+use crate::prelude::rule::exception::RuleException;
+use crate::prelude::types::LogString;
+use crate::prelude::SiemLog;
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Serialize, Debug)]
+pub enum UpdateRuleExceptions {
+    Add(RuleException),
+    /// Removes an exception: (Rule ID, Exception ID)
+    Remove((LogString, LogString)),
+    /// Removes the exceptions that expired before the timestamp
+    RemoveExpired(i64),
+    Replace(RuleExceptionsDataset),
+}
+
+/// Exceptions of the correlation rules that can be updated in real-time
+#[derive(Debug, Clone)]
+pub struct RuleExceptionsSynDataset {
+    dataset: Arc<RuleExceptionsDataset>,
+    comm: Sender<UpdateRuleExceptions>,
+}
+impl RuleExceptionsSynDataset {
+    pub fn new(dataset: Arc<RuleExceptionsDataset>, comm: Sender<UpdateRuleExceptions>) -> Self {
+        Self { dataset, comm }
+    }
+    pub fn empty() -> Self {
+        let (sender, _) = crossbeam_channel::bounded(1);
+        Self {
+            dataset: Arc::new(RuleExceptionsDataset::new()),
+            comm: sender,
+        }
+    }
+    pub fn insert(&self, exception: RuleException) {
+        let _ = self.comm.send(UpdateRuleExceptions::Add(exception));
+    }
+    pub fn remove(&self, rule: LogString, id: LogString) {
+        let _ = self.comm.send(UpdateRuleExceptions::Remove((rule, id)));
+    }
+    pub fn get(&self, rule: &str) -> Option<&BTreeMap<LogString, RuleException>> {
+        self.dataset.get(rule)
+    }
+    /// First active exception of the rule that matches the log
+    pub fn find(
+        &self,
+        rule: &str,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        now: i64,
+    ) -> Option<&RuleException> {
+        self.dataset.find(rule, log, datasets, now)
+    }
+    pub fn apply_updates(&self, updates: Vec<UpdateRuleExceptions>) -> Self {
+        let mut new = self.dataset.as_ref().clone();
+        for update in updates {
+            match update {
+                UpdateRuleExceptions::Add(v) => new.insert(v),
+                UpdateRuleExceptions::Remove((rule, id)) => new.remove(&rule, &id),
+                UpdateRuleExceptions::RemoveExpired(now) => new.remove_expired(now),
+                UpdateRuleExceptions::Replace(v) => new = v,
+            };
+        }
+        Self::new(Arc::new(new), self.comm.clone())
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RuleExceptionsDataset {
+    /// Rule ID => Exception ID => Exception
+    exceptions: BTreeMap<LogString, BTreeMap<LogString, RuleException>>,
+}
+
+impl RuleExceptionsDataset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds or replaces an exception of a rule
+    pub fn insert(&mut self, exception: RuleException) {
+        self.exceptions
+            .entry(exception.rule.clone())
+            .or_default()
+            .insert(exception.id.clone(), exception);
+    }
+    pub fn remove(&mut self, rule: &str, id: &str) {
+        if let Some(exceptions) = self.exceptions.get_mut(rule) {
+            exceptions.remove(id);
+            if exceptions.is_empty() {
+                self.exceptions.remove(rule);
+            }
+        }
+    }
+    pub fn remove_expired(&mut self, now: i64) {
+        for exceptions in self.exceptions.values_mut() {
+            exceptions.retain(|_, exception| !exception.is_expired(now));
+        }
+        self.exceptions
+            .retain(|_, exceptions| !exceptions.is_empty());
+    }
+    /// Exceptions of a rule
+    pub fn get(&self, rule: &str) -> Option<&BTreeMap<LogString, RuleException>> {
+        self.exceptions.get(rule)
+    }
+    pub fn find(
+        &self,
+        rule: &str,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        now: i64,
+    ) -> Option<&RuleException> {
+        self.exceptions
+            .get(rule)?
+            .values()
+            .find(|exception| exception.matches(log, datasets, now))
+    }
+    /// Number of exceptions of all the rules
+    pub fn len(&self) -> usize {
+        self.exceptions.values().map(|v| v.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.exceptions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::rule::{RuleCondition, RuleOperator};
+
+    fn exception(id: &'static str, user: &'static str, expires: Option<i64>) -> RuleException {
+        RuleException {
+            id: LogString::Borrowed(id),
+            rule: LogString::Borrowed("rule009"),
+            conditions: vec![RuleCondition {
+                field: LogString::Borrowed("user.name"),
+                operator: RuleOperator::Equals(user.into()),
+            }],
+            author: LogString::Borrowed("secops"),
+            reason: LogString::Borrowed("Vulnerability scanner"),
+            expires,
+        }
+    }
+
+    #[test]
+    fn should_apply_active_exceptions() {
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let dataset = RuleExceptionsSynDataset::new(Arc::new(RuleExceptionsDataset::new()), sender);
+        let dataset = dataset.apply_updates(vec![
+            UpdateRuleExceptions::Add(exception("exc001", "scanner", None)),
+            UpdateRuleExceptions::Add(exception("exc002", "backup", Some(1_000))),
+        ]);
+        let datasets = DatasetHolder::new();
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.add_field("user.name", "scanner".into());
+        let found = dataset.find("rule009", &mut log, &datasets, 500).unwrap();
+        assert_eq!("exc001", found.id);
+        assert!(dataset.find("rule010", &mut log, &datasets, 500).is_none());
+
+        log.add_field("user.name", "backup".into());
+        assert!(dataset.find("rule009", &mut log, &datasets, 500).is_some());
+        // Expired
+        assert!(dataset
+            .find("rule009", &mut log, &datasets, 2_000)
+            .is_none());
+
+        let dataset = dataset.apply_updates(vec![
+            UpdateRuleExceptions::RemoveExpired(2_000),
+            UpdateRuleExceptions::Remove((
+                LogString::Borrowed("rule009"),
+                LogString::Borrowed("exc001"),
+            )),
+        ]);
+        assert!(dataset.get("rule009").is_none());
+    }
+}
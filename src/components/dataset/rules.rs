@@ -1,5 +1,7 @@
-use crate::prelude::rule::SiemRule;
+use crate::prelude::rule::index::RuleIndex;
+use crate::prelude::rule::{RuleError, SiemRule};
 use crate::prelude::types::LogString;
+use crate::prelude::SiemLog;
 use crossbeam_channel::Sender;
 use serde::Serialize;
 use std::collections::BTreeMap;
@@ -22,8 +24,11 @@ impl CorrelationRulesDataset {
     pub fn new(dataset: Arc<RulesDataset>, comm: Sender<UpdateRules>) -> Self {
         Self { dataset, comm }
     }
-    pub fn insert(&self, rule: SiemRule) {
+    /// Sends the rule to be added to the dataset. Rules with invalid conditions are rejected.
+    pub fn insert(&self, rule: SiemRule) -> Result<(), RuleError> {
+        rule.validate()?;
         let _ = self.comm.send(UpdateRules::Add(rule));
+        Ok(())
     }
     pub fn insert_timeout(&self, rule: SiemRule, timeout: Duration) -> Result<(), SiemRule> {
         let init = std::time::Instant::now();
@@ -53,17 +58,25 @@ impl CorrelationRulesDataset {
             },
         }
     }
+    /// Sends a new set of rules that replaces all the current ones
+    pub fn replace(&self, dataset: RulesDataset) {
+        let _ = self.comm.send(UpdateRules::Replace(dataset));
+    }
     pub fn get(&self, id: &LogString) -> Option<&SiemRule> {
         // Todo improve with cached added IPs
         self.dataset.get(id)
     }
+    /// Rules that may be triggered by the log. The rest of the rules cannot match it.
+    pub fn candidates(&self, log: &SiemLog) -> Vec<&SiemRule> {
+        self.dataset.candidates(log)
+    }
     pub fn apply_updates(&self, updates : Vec<UpdateRules>) -> Self {
         let mut iter = updates.into_iter();
         let first = iter.next().unwrap();
         let mut new = match first {
             UpdateRules::Add(a) => {
                 let mut dataset = self.dataset.as_ref().clone();
-                dataset.insert(a);
+                insert_or_warn(&mut dataset, a);
                 dataset
             },
             UpdateRules::Remove(v) => {
@@ -76,7 +89,7 @@ impl CorrelationRulesDataset {
         for update in iter {
             match update {
                 UpdateRules::Add(a) => {
-                    new.insert(a);
+                    insert_or_warn(&mut new, a);
                 },
                 UpdateRules::Remove(v) => {
                     new.remove(&v);
@@ -92,21 +105,50 @@ impl CorrelationRulesDataset {
 #[derive(Serialize, Debug, Default, Clone)]
 pub struct RulesDataset {
     rules: BTreeMap<LogString, SiemRule>,
+    /// Updated with each insertion or removal
+    #[serde(skip)]
+    index: RuleIndex,
 }
 
 impl RulesDataset {
     pub fn new() -> Self {
         Self::default()
     }
-    pub fn insert(&mut self, rule: SiemRule) {
-        self.rules.insert(rule.name.clone(), rule);
+    /// Adds a rule to the dataset. Rules with invalid conditions are rejected.
+    pub fn insert(&mut self, rule: SiemRule) -> Result<(), RuleError> {
+        rule.validate()?;
+        self.index.insert(&rule);
+        self.rules.insert(rule.id.clone(), rule);
+        Ok(())
     }
     pub fn get(&self, id: &LogString) -> Option<&SiemRule> {
         self.rules.get(id)
     }
     pub fn remove(&mut self, id: &LogString) {
+        self.index.remove(id);
         self.rules.remove(id);
     }
+    /// Rules that may be triggered by the log
+    pub fn candidates(&self, log: &SiemLog) -> Vec<&SiemRule> {
+        self.index
+            .candidates(log)
+            .into_iter()
+            .filter_map(|id| self.rules.get(id))
+            .collect()
+    }
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+fn insert_or_warn(dataset: &mut RulesDataset, rule: SiemRule) {
+    let id = rule.id.clone();
+    if let Err(err) = dataset.insert(rule) {
+        crate::warn!("Rule {} rejected: {:?}", id, err);
+    }
 }
 
 fn extract_rule_from_update(update: UpdateRules) -> SiemRule {
@@ -116,3 +158,67 @@ fn extract_rule_from_update(update: UpdateRules) -> SiemRule {
         UpdateRules::Replace(_) => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::rule::{
+        AlertGenerator, MitreInfo, RuleCondition, RuleOperator, SiemSubRule,
+    };
+    use crate::prelude::AlertSeverity;
+    use std::borrow::Cow;
+
+    fn product_rule(id: &'static str, product: &'static str) -> SiemRule {
+        let mut subrules = BTreeMap::new();
+        subrules.insert(
+            LogString::Borrowed("selection"),
+            SiemSubRule {
+                conditions: vec![RuleCondition {
+                    field: LogString::Borrowed("product"),
+                    operator: RuleOperator::Equals(product.into()),
+                }],
+                rule_state: None,
+            },
+        );
+        SiemRule {
+            id: LogString::Borrowed(id),
+            name: LogString::Borrowed("Product rule"),
+            description: LogString::Borrowed(""),
+            mitre: Cow::Owned(MitreInfo {
+                tactics: vec![],
+                techniques: vec![],
+            }),
+            needed_datasets: vec![],
+            subrules: Cow::Owned(subrules),
+            conditions: Cow::Owned(vec![]),
+            alert: Cow::Owned(AlertGenerator {
+                content: vec![],
+                severity: AlertSeverity::LOW,
+                tags: vec![],
+                aggregation: None,
+            }),
+            aggregation: None,
+            sequence: None,
+            expression: None,
+        }
+    }
+
+    #[test]
+    fn should_update_the_index_with_the_rules() {
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let dataset = CorrelationRulesDataset::new(Arc::new(RulesDataset::new()), sender);
+        let dataset = dataset.apply_updates(vec![
+            UpdateRules::Add(product_rule("rule001", "windows")),
+            UpdateRules::Add(product_rule("rule002", "linux")),
+        ]);
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.set_product("windows");
+        let candidates: Vec<&LogString> = dataset.candidates(&log).iter().map(|r| &r.id).collect();
+        assert_eq!(vec!["rule001"], candidates);
+
+        let dataset =
+            dataset.apply_updates(vec![UpdateRules::Remove(LogString::Borrowed("rule001"))]);
+        assert!(dataset.candidates(&log).is_empty());
+        assert!(dataset.get(&LogString::Borrowed("rule002")).is_some());
+    }
+}
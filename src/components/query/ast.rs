@@ -0,0 +1,332 @@
+use serde::{Deserialize, Serialize};
+
+use super::time::{QueryTime, RelativeTime};
+
+/// Query made of stages separated by pipes. Each stage works with the rows returned by the previous one.
+///
+/// ```text
+/// filter event.code = 4625 AND user.name != "admin*" | fields user.name as user, lowercase(host.hostname) as host
+/// ```
+///
+/// The query can start with the time range of the logs: `earliest=-24h@h latest=now | stats count() by host.hostname`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Query {
+    pub stages: Vec<QueryStage>,
+    /// `earliest=<time>`: only the logs created at or after this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub earliest: Option<QueryTime>,
+    /// `latest=<time>`: only the logs created at or before this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest: Option<QueryTime>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QueryStage {
+    /// `filter <expression>`: keeps the rows where the expression is true
+    Filter(QueryExpression),
+    /// `fields <expression> [as <alias>], ...`: keeps only the listed fields
+    Fields(Vec<FieldProjection>),
+    /// `stats <aggregation> [as <alias>], ... [by <field>, ...]`: one row for each group of values
+    Stats {
+        aggregations: Vec<Aggregation>,
+        by: Vec<String>,
+    },
+    /// `top [<limit>] <field>, ...`: most common values with the number of rows (`count`), in descending order.
+    /// The rows without the fields are ignored.
+    Top { limit: usize, fields: Vec<String> },
+    /// `dedup <field>, ...`: keeps only the first row of each combination of values
+    Dedup(Vec<String>),
+    /// `sort [-]<field>, ...`: orders the rows. `-` for descending order.
+    Sort(Vec<SortField>),
+    /// `head [<limit>]`: keeps only the first rows
+    Head(usize),
+}
+
+/// Number of rows returned by `top` and `head` when no limit is set
+pub const DEFAULT_STAGE_LIMIT: usize = 10;
+
+/// Aggregation calculated by a `stats` stage for each group of rows
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Aggregation {
+    pub function: AggregationFunction,
+    /// Field used by the function. Only `count()` can be used without field.
+    pub field: Option<String>,
+    /// Name of the column. If None the function call is used: `count(source.ip)`
+    pub alias: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AggregationFunction {
+    /// `count()` number of rows, `count(field)` number of rows with the field
+    Count,
+    /// `distinct_count(field)`
+    DistinctCount,
+    /// `sum(field)`
+    Sum,
+    /// `avg(field)`
+    Avg,
+    /// `min(field)`
+    Min,
+    /// `max(field)`
+    Max,
+    /// `values(field)`: list of the distinct values
+    Values,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortField {
+    pub field: String,
+    pub descending: bool,
+}
+
+/// Field or expression returned by a `fields` stage
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldProjection {
+    pub expression: QueryExpression,
+    /// Name of the column. If None the name of the field is used.
+    pub alias: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QueryExpression {
+    /// Name of a field of the log or an alias of a previous stage
+    Field(String),
+    Literal(QueryLiteral),
+    Function(QueryFunction, Vec<QueryExpression>),
+    Comparison(
+        Box<QueryExpression>,
+        ComparisonOperator,
+        Box<QueryExpression>,
+    ),
+    And(Vec<QueryExpression>),
+    Or(Vec<QueryExpression>),
+    Not(Box<QueryExpression>),
+    /// `now()`, `now()-1d@d`, `-24h`: milliseconds resolved when the query is executed
+    Time(RelativeTime),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum QueryLiteral {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    /// `"text*"`
+    StartsWith(String),
+    /// `"*text"`
+    EndsWith(String),
+    /// `"*text*"`
+    Contains(String),
+    /// `"te*x*t"`: wildcards in any position. Escaped asterisks are kept with the backslash.
+    Like(String),
+    /// Right side of `~=`
+    Regex(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ComparisonOperator {
+    /// `=`. Also matches wildcards and regex literals.
+    Equals,
+    /// `!=`
+    NotEquals,
+    /// `<`
+    LessThan,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    GreaterThan,
+    /// `>=`
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryFunction {
+    /// `to_number(value)`
+    ToNumber,
+    /// `to_string(value)`
+    ToString,
+    /// `lowercase(text)`
+    Lowercase,
+    /// `uppercase(text)`
+    Uppercase,
+    /// `replace(text, pattern, replacement)`
+    Replace,
+    /// `len(text)`
+    Len,
+    /// `floor(number)`
+    Floor,
+    /// `trim(text)`
+    Trim,
+    /// `to_integer(value)`
+    ToInteger,
+    /// `to_float(value)`
+    ToFloat,
+    /// `bin(time, 5m)`: rounds down the number to a multiple of the span
+    Bin,
+    /// `strftime(time, "%Y-%m-%d")`: formats the time in UTC
+    Strftime,
+}
+
+impl ComparisonOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Equals => "=",
+            Self::NotEquals => "!=",
+            Self::LessThan => "<",
+            Self::LessOrEqual => "<=",
+            Self::GreaterThan => ">",
+            Self::GreaterOrEqual => ">=",
+        }
+    }
+}
+
+impl QueryFunction {
+    /// All the functions that can be used in the queries
+    pub const ALL: [QueryFunction; 12] = [
+        Self::ToNumber,
+        Self::ToString,
+        Self::Lowercase,
+        Self::Uppercase,
+        Self::Replace,
+        Self::Len,
+        Self::Floor,
+        Self::Trim,
+        Self::ToInteger,
+        Self::ToFloat,
+        Self::Bin,
+        Self::Strftime,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "to_number" => Self::ToNumber,
+            "to_string" => Self::ToString,
+            "lowercase" => Self::Lowercase,
+            "uppercase" => Self::Uppercase,
+            "replace" => Self::Replace,
+            "len" => Self::Len,
+            "floor" => Self::Floor,
+            "trim" => Self::Trim,
+            "to_integer" => Self::ToInteger,
+            "to_float" => Self::ToFloat,
+            "bin" => Self::Bin,
+            "strftime" => Self::Strftime,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ToNumber => "to_number",
+            Self::ToString => "to_string",
+            Self::Lowercase => "lowercase",
+            Self::Uppercase => "uppercase",
+            Self::Replace => "replace",
+            Self::Len => "len",
+            Self::Floor => "floor",
+            Self::Trim => "trim",
+            Self::ToInteger => "to_integer",
+            Self::ToFloat => "to_float",
+            Self::Bin => "bin",
+            Self::Strftime => "strftime",
+        }
+    }
+
+    /// Number of arguments: (Minimum, Maximum)
+    pub fn arity(&self) -> (usize, usize) {
+        match self {
+            Self::Replace => (3, 3),
+            Self::Bin | Self::Strftime => (2, 2),
+            // to_string(field, default)
+            Self::ToString => (1, 2),
+            _ => (1, 1),
+        }
+    }
+}
+
+impl AggregationFunction {
+    /// All the aggregations that can be used in `stats`
+    pub const ALL: [AggregationFunction; 7] = [
+        Self::Count,
+        Self::DistinctCount,
+        Self::Sum,
+        Self::Avg,
+        Self::Min,
+        Self::Max,
+        Self::Values,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "count" => Self::Count,
+            "distinct_count" => Self::DistinctCount,
+            "sum" => Self::Sum,
+            "avg" => Self::Avg,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "values" => Self::Values,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Count => "count",
+            Self::DistinctCount => "distinct_count",
+            Self::Sum => "sum",
+            Self::Avg => "avg",
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Values => "values",
+        }
+    }
+}
+
+impl Aggregation {
+    /// Name of the column in the results
+    pub fn name(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.clone(),
+            None => format!(
+                "{}({})",
+                self.function.name(),
+                self.field.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl QueryExpression {
+    /// Name of the column when used in a `fields` stage without alias
+    pub fn column_name(&self) -> String {
+        match self {
+            QueryExpression::Field(name) => name.clone(),
+            QueryExpression::Function(function, args) => {
+                let args: Vec<String> = args.iter().map(|v| v.column_name()).collect();
+                format!("{}({})", function.name(), args.join(","))
+            }
+            QueryExpression::Literal(literal) => match literal {
+                QueryLiteral::Text(v)
+                | QueryLiteral::StartsWith(v)
+                | QueryLiteral::EndsWith(v)
+                | QueryLiteral::Contains(v)
+                | QueryLiteral::Like(v)
+                | QueryLiteral::Regex(v) => v.clone(),
+                QueryLiteral::Integer(v) => v.to_string(),
+                QueryLiteral::Float(v) => v.to_string(),
+                QueryLiteral::Boolean(v) => v.to_string(),
+            },
+            _ => String::from("expression"),
+        }
+    }
+}
+
+impl FieldProjection {
+    /// Name of the column in the results
+    pub fn name(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.clone(),
+            None => self.expression.column_name(),
+        }
+    }
+}
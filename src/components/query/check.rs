@@ -0,0 +1,811 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::prelude::rule::lint::{type_name, LintLevel};
+use crate::prelude::{FieldSchema, FieldType, SiemIp};
+
+use super::ast::{
+    Aggregation, AggregationFunction, ComparisonOperator, Query, QueryExpression, QueryFunction,
+    QueryLiteral, QueryStage,
+};
+use super::parser::tokenize;
+use super::Token;
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryCheckKind {
+    /// The field does not exist in the schema or in the results of the previous stages
+    UnknownField,
+    /// The comparison cannot be used with the type of the field: (Operator, Field type)
+    IncompatibleComparison(&'static str, &'static str),
+    /// The aggregation cannot be used with the type of the field: (Aggregation, Field type)
+    IncompatibleAggregation(&'static str, &'static str),
+    /// The value is not one of the options of the field
+    UnknownOption(String),
+}
+
+/// Problem found in a query
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct QueryCheckMessage {
+    pub level: LintLevel,
+    /// Position of the stage in the query, starting at 0
+    pub stage: usize,
+    pub field: String,
+    pub kind: QueryCheckKind,
+}
+
+/// Text that can be written at the position of the cursor
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct QueryCompletion {
+    /// Position of the character where the text to be replaced by the candidate starts
+    pub start: usize,
+    pub candidates: Vec<QueryCandidate>,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct QueryCandidate {
+    pub text: String,
+    pub kind: QueryCandidateKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryCandidateKind {
+    Field,
+    Function,
+    Keyword,
+    /// One of the options of a `TextOptions` field
+    Value,
+}
+
+/// Checks the queries against the fields generated by the parsers and suggests the next word of a query
+#[derive(Clone, Debug, Default)]
+pub struct QueryChecker {
+    schema: FieldSchema,
+}
+
+/// Fields that can be used by a stage
+enum CheckScope {
+    /// Fields of the logs
+    Schema,
+    /// Columns returned by a previous stage. None if the type is not known.
+    Columns(BTreeMap<String, Option<FieldType>>),
+}
+
+struct CheckContext<'a> {
+    messages: &'a mut Vec<QueryCheckMessage>,
+    stage: usize,
+}
+
+impl<'a> CheckContext<'a> {
+    fn push(&mut self, level: LintLevel, field: &str, kind: QueryCheckKind) {
+        self.messages.push(QueryCheckMessage {
+            level,
+            stage: self.stage,
+            field: field.to_string(),
+            kind,
+        });
+    }
+}
+
+const STAGE_KEYWORDS: [&str; 7] = ["filter", "fields", "stats", "top", "dedup", "sort", "head"];
+
+impl QueryChecker {
+    pub fn new(schema: FieldSchema) -> Self {
+        Self { schema }
+    }
+
+    /// Merges the schemas of all the parsers
+    pub fn from_schemas(schemas: &[&FieldSchema]) -> Self {
+        let mut schema = FieldSchema::new();
+        for other in schemas {
+            schema.add_schema(other);
+            schema.allow_unknown_fields |= other.allow_unknown_fields;
+        }
+        Self { schema }
+    }
+
+    pub fn schema(&self) -> &FieldSchema {
+        &self.schema
+    }
+
+    /// Finds the problems of the query, sorted by level with errors first
+    pub fn check(&self, query: &Query) -> Vec<QueryCheckMessage> {
+        let mut messages = Vec::new();
+        self.check_stages(&query.stages, &mut messages);
+        messages.sort_by_key(|message| std::cmp::Reverse(message.level));
+        messages
+    }
+
+    /// Candidates for the word at the position of the cursor (in characters).
+    /// Only the text before the cursor is used, so the query can be incomplete.
+    pub fn complete(&self, query: &str, cursor: usize) -> QueryCompletion {
+        let chars: Vec<char> = query.chars().take(cursor).collect();
+        if let Some(quote) = open_quote(&chars) {
+            let prefix: String = chars[quote + 1..].iter().collect();
+            let (scope, tokens) = self.stage_context(&chars[..quote]);
+            let candidates = match option_field(&tokens) {
+                Some(field) => self
+                    .options(&scope, field)
+                    .into_iter()
+                    .filter(|v| v.text.starts_with(&prefix))
+                    .collect(),
+                None => Vec::new(),
+            };
+            return QueryCompletion {
+                start: quote + 1,
+                candidates,
+            };
+        }
+        let mut start = chars.len();
+        while start > 0 && is_word_char(chars[start - 1]) {
+            start -= 1;
+        }
+        let prefix: String = chars[start..].iter().collect::<String>().to_lowercase();
+        let (scope, tokens) = self.stage_context(&chars[..start]);
+        let candidates = self
+            .candidates(&scope, &tokens)
+            .into_iter()
+            .filter(|v| v.text.to_lowercase().starts_with(&prefix))
+            .collect();
+        QueryCompletion { start, candidates }
+    }
+
+    /// Fields available and tokens of the last stage of the text
+    fn stage_context(&self, chars: &[char]) -> (CheckScope, Vec<Token>) {
+        let tokens = tokenize(chars).unwrap_or_default();
+        let last_pipe = tokens.iter().rposition(|(_, token)| *token == Token::PIPE);
+        let scope = match last_pipe {
+            Some(pipe) => {
+                let previous: String = chars[..tokens[pipe].0].iter().collect();
+                match Query::parse(&previous) {
+                    Ok(query) => self.check_stages(&query.stages, &mut Vec::new()),
+                    Err(_) => CheckScope::Schema,
+                }
+            }
+            None => CheckScope::Schema,
+        };
+        let tokens = tokens
+            .into_iter()
+            .skip(last_pipe.map_or(0, |v| v + 1))
+            .map(|(_, token)| token)
+            .collect();
+        (scope, tokens)
+    }
+
+    fn candidates(&self, scope: &CheckScope, tokens: &[Token]) -> Vec<QueryCandidate> {
+        let keywords = |list: &[&str]| -> Vec<QueryCandidate> {
+            list.iter()
+                .map(|v| QueryCandidate {
+                    text: v.to_string(),
+                    kind: QueryCandidateKind::Keyword,
+                    description: None,
+                })
+                .collect()
+        };
+        let values = |with_not: bool| {
+            let mut candidates = self.fields(scope);
+            candidates.extend(functions());
+            if with_not {
+                candidates.extend(keywords(&["NOT"]));
+            }
+            candidates
+        };
+        let last = match tokens.last() {
+            Some(v) => v,
+            None => return keywords(&STAGE_KEYWORDS),
+        };
+        match &tokens[0] {
+            Token::FILTER => match last {
+                Token::FILTER | Token::AND | Token::OR | Token::NOT | Token::LPAREN(_) => {
+                    values(true)
+                }
+                Token::COMMA(_) => values(false),
+                Token::ASSIGN | Token::LT(_) | Token::GT(_) | Token::BANG(_) => {
+                    let mut candidates = match option_field(tokens) {
+                        Some(field) => self
+                            .options(scope, field)
+                            .into_iter()
+                            .map(|mut v| {
+                                v.text = format!("\"{}\"", v.text);
+                                v
+                            })
+                            .collect(),
+                        None => Vec::new(),
+                    };
+                    candidates.extend(values(false));
+                    candidates
+                }
+                _ => keywords(&["AND", "OR"]),
+            },
+            Token::FIELDS => match last {
+                Token::FIELDS | Token::COMMA(_) | Token::LPAREN(_) => values(false),
+                Token::AS => Vec::new(),
+                _ => keywords(&["as"]),
+            },
+            Token::STATS => {
+                if tokens.contains(&Token::BY) {
+                    match last {
+                        Token::BY | Token::COMMA(_) => self.fields(scope),
+                        _ => Vec::new(),
+                    }
+                } else {
+                    match last {
+                        Token::STATS | Token::COMMA(_) => aggregations(),
+                        Token::LPAREN(_) => self.fields(scope),
+                        Token::RPAREN(_) => keywords(&["as", "by"]),
+                        Token::FIELD(_) | Token::FUNCTION(_) => keywords(&["by"]),
+                        _ => Vec::new(),
+                    }
+                }
+            }
+            Token::TOP | Token::DEDUP | Token::SORT => match last {
+                Token::TOP
+                | Token::DEDUP
+                | Token::SORT
+                | Token::COMMA(_)
+                | Token::INT(_)
+                | Token::MINUS(_)
+                | Token::PLUS(_) => self.fields(scope),
+                _ => Vec::new(),
+            },
+            // Time range before the stages: `earliest=-24h latest=now`
+            Token::EARLIEST | Token::LATEST => match last {
+                Token::ASSIGN => vec![QueryCandidate {
+                    text: String::from("now"),
+                    kind: QueryCandidateKind::Function,
+                    description: None,
+                }],
+                Token::EARLIEST
+                | Token::LATEST
+                | Token::AT(_)
+                | Token::MINUS(_)
+                | Token::PLUS(_) => Vec::new(),
+                _ => {
+                    let mut candidates = keywords(&["earliest", "latest"]);
+                    candidates.extend(keywords(&STAGE_KEYWORDS));
+                    candidates
+                }
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    fn fields(&self, scope: &CheckScope) -> Vec<QueryCandidate> {
+        let field = |name: &str, typ: Option<&FieldType>| QueryCandidate {
+            text: name.to_string(),
+            kind: QueryCandidateKind::Field,
+            description: typ
+                .map(field_description)
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_string()),
+        };
+        match scope {
+            CheckScope::Schema => self
+                .schema
+                .fields
+                .iter()
+                .map(|(name, typ)| field(name, Some(typ)))
+                .collect(),
+            CheckScope::Columns(columns) => columns
+                .iter()
+                .map(|(name, typ)| field(name, typ.as_ref()))
+                .collect(),
+        }
+    }
+
+    fn options(&self, scope: &CheckScope, field: &str) -> Vec<QueryCandidate> {
+        match self.field_type(scope, field) {
+            Some(Some(FieldType::TextOptions(options, _))) => options
+                .iter()
+                .map(|(value, description)| QueryCandidate {
+                    text: value.to_string(),
+                    kind: QueryCandidateKind::Value,
+                    description: Some(description.to_string()),
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Checks the stages and returns the fields available after them
+    fn check_stages(
+        &self,
+        stages: &[QueryStage],
+        messages: &mut Vec<QueryCheckMessage>,
+    ) -> CheckScope {
+        let mut scope = CheckScope::Schema;
+        for (position, stage) in stages.iter().enumerate() {
+            let mut context = CheckContext {
+                messages,
+                stage: position,
+            };
+            match stage {
+                QueryStage::Filter(expression) => {
+                    self.check_expression(&scope, expression, &mut context)
+                }
+                QueryStage::Fields(projections) => {
+                    let mut columns = BTreeMap::new();
+                    for projection in projections {
+                        self.check_expression(&scope, &projection.expression, &mut context);
+                        columns.insert(
+                            projection.name(),
+                            self.expression_type(&scope, &projection.expression),
+                        );
+                    }
+                    scope = CheckScope::Columns(columns);
+                }
+                QueryStage::Stats { aggregations, by } => {
+                    let mut columns = BTreeMap::new();
+                    for field in by {
+                        columns.insert(field.clone(), self.lookup(&scope, field, &mut context));
+                    }
+                    for aggregation in aggregations {
+                        let typ = self.check_aggregation(&scope, aggregation, &mut context);
+                        columns.insert(aggregation.name(), typ);
+                    }
+                    scope = CheckScope::Columns(columns);
+                }
+                QueryStage::Top { fields, .. } => {
+                    let mut columns = BTreeMap::new();
+                    for field in fields {
+                        columns.insert(field.clone(), self.lookup(&scope, field, &mut context));
+                    }
+                    columns.insert(String::from("count"), Some(FieldType::Numeric("")));
+                    scope = CheckScope::Columns(columns);
+                }
+                QueryStage::Dedup(fields) => {
+                    for field in fields {
+                        self.lookup(&scope, field, &mut context);
+                    }
+                }
+                QueryStage::Sort(fields) => {
+                    for sort in fields {
+                        self.lookup(&scope, &sort.field, &mut context);
+                    }
+                }
+                QueryStage::Head(_) => {}
+            }
+        }
+        scope
+    }
+
+    /// Type of the field. The first None if the field is unknown.
+    fn field_type(&self, scope: &CheckScope, field: &str) -> Option<Option<FieldType>> {
+        match scope {
+            CheckScope::Schema => self.schema.get_field(field).map(|v| Some(v.clone())),
+            CheckScope::Columns(columns) => columns.get(field).cloned(),
+        }
+    }
+
+    /// Type of the field, reporting it if unknown
+    fn lookup(
+        &self,
+        scope: &CheckScope,
+        field: &str,
+        context: &mut CheckContext,
+    ) -> Option<FieldType> {
+        match self.field_type(scope, field) {
+            Some(typ) => typ,
+            None => {
+                // The columns of the previous stages are always known
+                let level = match scope {
+                    CheckScope::Schema if self.schema.allow_unknown_fields => LintLevel::Warning,
+                    _ => LintLevel::Error,
+                };
+                context.push(level, field, QueryCheckKind::UnknownField);
+                None
+            }
+        }
+    }
+
+    fn check_expression(
+        &self,
+        scope: &CheckScope,
+        expression: &QueryExpression,
+        context: &mut CheckContext,
+    ) {
+        match expression {
+            QueryExpression::Field(field) => {
+                self.lookup(scope, field, context);
+            }
+            QueryExpression::Literal(_) | QueryExpression::Time(_) => {}
+            QueryExpression::Function(_, list)
+            | QueryExpression::And(list)
+            | QueryExpression::Or(list) => {
+                for expression in list {
+                    self.check_expression(scope, expression, context);
+                }
+            }
+            QueryExpression::Not(expression) => self.check_expression(scope, expression, context),
+            QueryExpression::Comparison(left, operator, right) => {
+                self.check_comparison(scope, left, *operator, right, context)
+            }
+        }
+    }
+
+    fn check_comparison(
+        &self,
+        scope: &CheckScope,
+        left: &QueryExpression,
+        operator: ComparisonOperator,
+        right: &QueryExpression,
+        context: &mut CheckContext,
+    ) {
+        self.check_expression(scope, right, context);
+        let (field, typ) = match left {
+            QueryExpression::Field(field) => match self.lookup(scope, field, context) {
+                Some(typ) => (field, typ),
+                None => return,
+            },
+            left => return self.check_expression(scope, left, context),
+        };
+        let literal = match right {
+            QueryExpression::Literal(literal) => literal,
+            _ => return,
+        };
+        let is_text = matches!(typ, FieldType::Text(_) | FieldType::TextOptions(_, _));
+        let is_number = matches!(
+            typ,
+            FieldType::Numeric(_) | FieldType::Decimal(_) | FieldType::Date(_)
+        );
+        let is_ip = matches!(typ, FieldType::Ip(_));
+        let is_ordering = !matches!(
+            operator,
+            ComparisonOperator::Equals | ComparisonOperator::NotEquals
+        );
+        let level = match literal {
+            // The text representation of the value is used
+            QueryLiteral::StartsWith(_)
+            | QueryLiteral::EndsWith(_)
+            | QueryLiteral::Contains(_)
+            | QueryLiteral::Like(_)
+            | QueryLiteral::Regex(_) => (is_ip || is_number).then_some(LintLevel::Warning),
+            QueryLiteral::Text(value) => {
+                if let (FieldType::TextOptions(options, _), false) = (&typ, is_ordering) {
+                    if !options.contains_key(&value[..]) {
+                        context.push(
+                            LintLevel::Warning,
+                            field,
+                            QueryCheckKind::UnknownOption(value.clone()),
+                        );
+                    }
+                }
+                if is_ip {
+                    (!is_ip_or_network(value)).then_some(LintLevel::Error)
+                } else if is_number {
+                    value
+                        .trim()
+                        .parse::<f64>()
+                        .is_err()
+                        .then_some(LintLevel::Error)
+                } else {
+                    None
+                }
+            }
+            QueryLiteral::Integer(_) | QueryLiteral::Float(_) => {
+                if is_ip {
+                    Some(LintLevel::Error)
+                } else {
+                    // Numbers stored as text are parsed before comparing them
+                    (is_text && is_ordering).then_some(LintLevel::Warning)
+                }
+            }
+            QueryLiteral::Boolean(_) => (is_ip || is_number).then_some(LintLevel::Error),
+        };
+        if let Some(level) = level {
+            let symbol = match literal {
+                QueryLiteral::Regex(_) => "~=",
+                _ => operator.symbol(),
+            };
+            context.push(
+                level,
+                field,
+                QueryCheckKind::IncompatibleComparison(symbol, type_name(&typ)),
+            );
+        }
+    }
+
+    /// Type of the result of the aggregation
+    fn check_aggregation(
+        &self,
+        scope: &CheckScope,
+        aggregation: &Aggregation,
+        context: &mut CheckContext,
+    ) -> Option<FieldType> {
+        let field = match &aggregation.field {
+            Some(field) => field,
+            None => return Some(FieldType::Numeric("")),
+        };
+        let typ = self.lookup(scope, field, context);
+        match aggregation.function {
+            AggregationFunction::Count | AggregationFunction::DistinctCount => {
+                Some(FieldType::Numeric(""))
+            }
+            AggregationFunction::Min | AggregationFunction::Max => typ,
+            AggregationFunction::Values => Some(FieldType::Array("")),
+            AggregationFunction::Sum | AggregationFunction::Avg => {
+                let level = match &typ {
+                    Some(FieldType::Ip(_)) => Some(LintLevel::Error),
+                    // Numbers stored as text are parsed
+                    Some(FieldType::Text(_))
+                    | Some(FieldType::TextOptions(_, _))
+                    | Some(FieldType::Array(_)) => Some(LintLevel::Warning),
+                    _ => None,
+                };
+                if let (Some(level), Some(typ)) = (level, &typ) {
+                    context.push(
+                        level,
+                        field,
+                        QueryCheckKind::IncompatibleAggregation(
+                            aggregation.function.name(),
+                            type_name(typ),
+                        ),
+                    );
+                }
+                Some(FieldType::Decimal(""))
+            }
+        }
+    }
+
+    fn expression_type(
+        &self,
+        scope: &CheckScope,
+        expression: &QueryExpression,
+    ) -> Option<FieldType> {
+        match expression {
+            QueryExpression::Field(field) => self.field_type(scope, field).flatten(),
+            QueryExpression::Literal(QueryLiteral::Integer(_)) => Some(FieldType::Numeric("")),
+            QueryExpression::Literal(QueryLiteral::Float(_)) => Some(FieldType::Decimal("")),
+            QueryExpression::Literal(_) => Some(FieldType::Text("")),
+            QueryExpression::Time(_) => Some(FieldType::Date("")),
+            // The bins of a date are dates
+            QueryExpression::Function(QueryFunction::Bin, args) => args
+                .first()
+                .and_then(|v| self.expression_type(scope, v))
+                .or(Some(FieldType::Numeric(""))),
+            QueryExpression::Function(function, _) => Some(match function {
+                QueryFunction::Len | QueryFunction::Floor | QueryFunction::ToInteger => {
+                    FieldType::Numeric("")
+                }
+                QueryFunction::ToNumber | QueryFunction::ToFloat => FieldType::Decimal(""),
+                QueryFunction::ToString
+                | QueryFunction::Lowercase
+                | QueryFunction::Uppercase
+                | QueryFunction::Replace
+                | QueryFunction::Trim
+                | QueryFunction::Strftime => FieldType::Text(""),
+                QueryFunction::Bin => FieldType::Numeric(""),
+            }),
+            // Conditions are "true" or "false"
+            _ => Some(FieldType::Text("")),
+        }
+    }
+}
+
+fn functions() -> Vec<QueryCandidate> {
+    QueryFunction::ALL
+        .iter()
+        .map(|v| v.name())
+        .chain(std::iter::once("now"))
+        .map(|v| QueryCandidate {
+            text: v.to_string(),
+            kind: QueryCandidateKind::Function,
+            description: None,
+        })
+        .collect()
+}
+
+fn aggregations() -> Vec<QueryCandidate> {
+    AggregationFunction::ALL
+        .iter()
+        .map(|v| QueryCandidate {
+            text: v.name().to_string(),
+            kind: QueryCandidateKind::Function,
+            description: None,
+        })
+        .collect()
+}
+
+fn field_description(typ: &FieldType) -> &'static str {
+    match typ {
+        FieldType::Ip(v)
+        | FieldType::Array(v)
+        | FieldType::Text(v)
+        | FieldType::Numeric(v)
+        | FieldType::Decimal(v)
+        | FieldType::Date(v)
+        | FieldType::TextOptions(_, v) => v,
+    }
+}
+
+/// Field compared with `=` or `!=` at the end of the tokens
+fn option_field(tokens: &[Token]) -> Option<&str> {
+    let mut tokens = tokens.iter().rev();
+    if tokens.next() != Some(&Token::ASSIGN) {
+        return None;
+    }
+    match tokens.next()? {
+        Token::BANG(_) => match tokens.next()? {
+            Token::FIELD(field) | Token::FUNCTION(field) => Some(field),
+            _ => None,
+        },
+        Token::FIELD(field) | Token::FUNCTION(field) => Some(field),
+        _ => None,
+    }
+}
+
+/// Position of the quote of the string that is not closed
+fn open_quote(chars: &[char]) -> Option<usize> {
+    let mut open: Option<usize> = None;
+    let mut escaped = false;
+    for (position, ch) in chars.iter().enumerate() {
+        match open {
+            Some(start) => {
+                if escaped {
+                    escaped = false;
+                } else if *ch == '\\' {
+                    escaped = true;
+                } else if *ch == chars[start] {
+                    open = None;
+                }
+            }
+            None if *ch == '"' || *ch == '\'' => open = Some(position),
+            None => {}
+        }
+    }
+    open
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '.'
+}
+
+/// IP or network in CIDR notation: `10.0.0.0/8`
+pub(crate) fn is_ip_or_network(value: &str) -> bool {
+    match value.split_once('/') {
+        Some((ip, mask)) => SiemIp::from_ip_str(ip).is_ok() && mask.parse::<u8>().is_ok(),
+        None => SiemIp::from_ip_str(value).is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker() -> QueryChecker {
+        let mut schema = FieldSchema::new();
+        schema.insert("source.ip", FieldType::Ip("Source IP"));
+        schema.insert("user.name", FieldType::Text("User name"));
+        schema.insert("destination.port", FieldType::Numeric("Destination port"));
+        let mut options = BTreeMap::new();
+        options.insert("SUCCESS", "The event was successful");
+        options.insert("FAIL", "The event failed");
+        let mut outcome_schema = FieldSchema::new();
+        outcome_schema.insert(
+            "event.outcome",
+            FieldType::TextOptions(options, "Outcome of the event"),
+        );
+        QueryChecker::from_schemas(&[&schema, &outcome_schema])
+    }
+
+    fn texts(completion: &QueryCompletion) -> Vec<&str> {
+        completion
+            .candidates
+            .iter()
+            .map(|v| v.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn should_report_incompatible_queries() {
+        let query = Query::parse("filter source.ip = \"10.0.0.0/8\" AND source.ip = \"not-an-ip\" AND destination.port = \"http\" AND destination.port = \"*80\" AND event.outcome = \"FAILURE\" AND process.name = \"cmd.exe\" AND user.name > 5 | stats sum(user.name) as total, count() by event.outcome | filter total > 1 AND host = \"a\"").unwrap();
+        let message = |level, stage, field: &str, kind| QueryCheckMessage {
+            level,
+            stage,
+            field: field.to_string(),
+            kind,
+        };
+        assert_eq!(
+            vec![
+                message(
+                    LintLevel::Error,
+                    0,
+                    "source.ip",
+                    QueryCheckKind::IncompatibleComparison("=", "ip")
+                ),
+                message(
+                    LintLevel::Error,
+                    0,
+                    "destination.port",
+                    QueryCheckKind::IncompatibleComparison("=", "numeric")
+                ),
+                message(
+                    LintLevel::Error,
+                    0,
+                    "process.name",
+                    QueryCheckKind::UnknownField
+                ),
+                message(LintLevel::Error, 2, "host", QueryCheckKind::UnknownField),
+                message(
+                    LintLevel::Warning,
+                    0,
+                    "destination.port",
+                    QueryCheckKind::IncompatibleComparison("=", "numeric")
+                ),
+                message(
+                    LintLevel::Warning,
+                    0,
+                    "event.outcome",
+                    QueryCheckKind::UnknownOption("FAILURE".to_string())
+                ),
+                message(
+                    LintLevel::Warning,
+                    0,
+                    "user.name",
+                    QueryCheckKind::IncompatibleComparison(">", "text")
+                ),
+                message(
+                    LintLevel::Warning,
+                    1,
+                    "user.name",
+                    QueryCheckKind::IncompatibleAggregation("sum", "text")
+                ),
+            ],
+            checker().check(&query)
+        );
+    }
+
+    #[test]
+    fn should_complete_queries() {
+        let checker = checker();
+        let complete = |query: &str| checker.complete(query, query.chars().count());
+        assert_eq!(
+            vec!["filter", "fields", "stats", "top", "dedup", "sort", "head"],
+            texts(&complete(""))
+        );
+        let completion = complete("filter user.na");
+        assert_eq!(7, completion.start);
+        assert_eq!(
+            vec![QueryCandidate {
+                text: "user.name".to_string(),
+                kind: QueryCandidateKind::Field,
+                description: Some("User name".to_string()),
+            }],
+            completion.candidates
+        );
+        let completion = complete("filter event.outcome = \"F");
+        assert_eq!(24, completion.start);
+        assert_eq!(
+            vec![QueryCandidate {
+                text: "FAIL".to_string(),
+                kind: QueryCandidateKind::Value,
+                description: Some("The event failed".to_string()),
+            }],
+            completion.candidates
+        );
+        assert_eq!(
+            vec!["\"FAIL\"", "\"SUCCESS\""],
+            texts(&complete("filter event.outcome != "))[..2]
+        );
+        assert_eq!(vec!["AND"], texts(&complete("filter user.name = \"a\" a")));
+        // Columns of the previous stages
+        assert_eq!(
+            vec!["size", "strftime"],
+            texts(&complete(
+                "fields user.name as user, len(source.ip) as size | filter s"
+            ))
+        );
+        assert_eq!(vec!["count"], texts(&complete("stats c")));
+        assert_eq!(vec!["as", "by"], texts(&complete("stats count() ")));
+        assert_eq!(vec!["source.ip"], texts(&complete("stats count() by so")));
+        // Only the text before the cursor is used
+        assert_eq!(
+            vec!["user.name"],
+            texts(&checker.complete("filter user = 1 | fields user.name", 11))
+        );
+    }
+}
@@ -0,0 +1,631 @@
+use serde_json::{json, Map, Value};
+
+use crate::prelude::types::LogString;
+use crate::prelude::{FieldSchema, FieldType, QueryInfo};
+
+use super::ast::{
+    Aggregation, AggregationFunction, ComparisonOperator, Query, QueryExpression, QueryLiteral,
+    QueryStage, SortField,
+};
+use super::time::QueryClock;
+use super::QueryError;
+
+/// Field of the logs with the time at which the event was created
+pub const EVENT_CREATED_FIELD: &str = "event.created";
+
+/// Translates queries to the query DSL of Elasticsearch and OpenSearch.
+/// The type of the field in the schema decides the query used: the IP fields accept CIDR ranges
+/// (`source.ip = "10.0.0.0/8"`) and cannot be used with wildcards.
+///
+/// Only one search is made, so the stages must follow the order of a search:
+/// filters, then the fields, sorting, deduplication and limits of the results or a `stats`/`top` aggregation.
+#[derive(Debug, Clone)]
+pub struct ElasticTranslator {
+    schema: FieldSchema,
+    clock: QueryClock,
+}
+
+/// Aggregation stage waiting for the limit of buckets
+enum ElasticAggregation {
+    Stats {
+        aggregations: Vec<Aggregation>,
+        by: Vec<String>,
+    },
+    Top {
+        limit: usize,
+        fields: Vec<String>,
+    },
+}
+
+#[derive(Default)]
+struct ElasticSearch {
+    filters: Vec<Value>,
+    source: Option<Vec<String>>,
+    sort: Vec<Value>,
+    collapse: Option<String>,
+    size: Option<usize>,
+    aggregation: Option<ElasticAggregation>,
+}
+
+impl ElasticTranslator {
+    pub fn new(schema: FieldSchema) -> Self {
+        Self {
+            schema,
+            clock: QueryClock::System,
+        }
+    }
+
+    /// Clock used to resolve `now()` and the time range in milliseconds
+    pub fn clock(mut self, clock: QueryClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Body of the search of a `LOG_QUERY` command: only the logs created between `from` and `to`,
+    /// with the `offset` and `limit` applied and, if not empty, only the `fields`.
+    pub fn translate_query_info(&self, info: &QueryInfo) -> Result<Value, QueryError> {
+        let query = Query::parse(&info.query)?;
+        let mut search = ElasticSearch::default();
+        let (earliest, latest) = query.time_range(self.clock.now());
+        search
+            .filters
+            .push(time_filter(info.from.max(earliest), info.to.min(latest)));
+        for stage in &query.stages {
+            self.translate_stage(&mut search, stage)?;
+        }
+        if !info.fields.is_empty() {
+            search.source = Some(match search.source.take() {
+                Some(source) => source
+                    .into_iter()
+                    .filter(|field| info.fields.contains(field))
+                    .collect(),
+                None => info.fields.clone(),
+            });
+        }
+        if search.aggregation.is_some() && info.offset > 0 {
+            return Err(translation_error(String::from(
+                "The offset cannot be used with aggregations",
+            )));
+        }
+        search.size = Some(search.size.map_or(info.limit, |v| v.min(info.limit)));
+        let mut body = search.build();
+        if info.offset > 0 {
+            body["from"] = json!(info.offset);
+        }
+        Ok(body)
+    }
+
+    /// Body of the search over all the logs of the index
+    pub fn translate(&self, query: &Query) -> Result<Value, QueryError> {
+        let mut search = ElasticSearch::default();
+        if query.earliest.is_some() || query.latest.is_some() {
+            let (earliest, latest) = query.time_range(self.clock.now());
+            search.filters.push(time_filter(earliest, latest));
+        }
+        for stage in &query.stages {
+            self.translate_stage(&mut search, stage)?;
+        }
+        Ok(search.build())
+    }
+
+    fn translate_stage(
+        &self,
+        search: &mut ElasticSearch,
+        stage: &QueryStage,
+    ) -> Result<(), QueryError> {
+        let after = |stages: &[bool], stage: &str, previous: &str| -> Result<(), QueryError> {
+            if stages.iter().any(|v| *v) {
+                return Err(translation_error(format!(
+                    "{} cannot be used after {}",
+                    stage, previous
+                )));
+            }
+            Ok(())
+        };
+        let aggregated = search.aggregation.is_some();
+        let limited = search.size.is_some();
+        match stage {
+            QueryStage::Filter(expression) => {
+                after(
+                    &[aggregated, limited, search.collapse.is_some()],
+                    "filter",
+                    "stats, top, dedup or head",
+                )?;
+                search.filters.push(self.condition(expression)?);
+            }
+            QueryStage::Fields(projections) => {
+                after(&[aggregated], "fields", "stats or top")?;
+                let mut fields = Vec::with_capacity(projections.len());
+                for projection in projections {
+                    match &projection.expression {
+                        QueryExpression::Field(field) if projection.name() == *field => {
+                            self.check_field(field)?;
+                            fields.push(field.clone());
+                        }
+                        _ => {
+                            return Err(translation_error(format!(
+                                "Only fields without alias can be returned: {}",
+                                projection.name()
+                            )))
+                        }
+                    }
+                }
+                search.source = Some(fields);
+            }
+            QueryStage::Sort(fields) => {
+                after(&[aggregated, limited], "sort", "stats, top or head")?;
+                let mut sort = fields
+                    .iter()
+                    .map(|sort| self.sort(sort))
+                    .collect::<Result<Vec<_>, _>>()?;
+                sort.append(&mut search.sort);
+                search.sort = sort;
+            }
+            QueryStage::Dedup(fields) => {
+                after(
+                    &[aggregated, limited, search.collapse.is_some()],
+                    "dedup",
+                    "stats, top, dedup or head",
+                )?;
+                if fields.len() != 1 {
+                    return Err(translation_error(String::from(
+                        "Only one field can be used in dedup",
+                    )));
+                }
+                self.check_field(&fields[0])?;
+                search.collapse = Some(fields[0].clone());
+            }
+            QueryStage::Head(limit) => {
+                search.size = Some(search.size.map_or(*limit, |v| v.min(*limit)));
+            }
+            QueryStage::Stats { aggregations, by } => {
+                after(
+                    &[aggregated, limited, search.collapse.is_some()],
+                    "stats",
+                    "stats, top, dedup or head",
+                )?;
+                for field in by
+                    .iter()
+                    .chain(aggregations.iter().filter_map(|v| v.field.as_ref()))
+                {
+                    self.check_field(field)?;
+                }
+                search.aggregation = Some(ElasticAggregation::Stats {
+                    aggregations: aggregations.clone(),
+                    by: by.clone(),
+                });
+            }
+            QueryStage::Top { limit, fields } => {
+                after(
+                    &[aggregated, limited, search.collapse.is_some()],
+                    "top",
+                    "stats, top, dedup or head",
+                )?;
+                for field in fields {
+                    self.check_field(field)?;
+                }
+                search.aggregation = Some(ElasticAggregation::Top {
+                    limit: *limit,
+                    fields: fields.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Type of the field. None if the field is not in the schema but unknown fields are allowed.
+    fn check_field(&self, field: &str) -> Result<Option<&FieldType>, QueryError> {
+        match self.schema.get_field(field) {
+            Some(kind) => Ok(Some(kind)),
+            None if self.schema.allow_unknown_fields || field == EVENT_CREATED_FIELD => Ok(None),
+            None => Err(translation_error(format!(
+                "The field {} is not in the schema",
+                field
+            ))),
+        }
+    }
+
+    fn sort(&self, sort: &SortField) -> Result<Value, QueryError> {
+        self.check_field(&sort.field)?;
+        let order = if sort.descending { "desc" } else { "asc" };
+        Ok(json!({ &sort.field: { "order": order, "missing": "_last" } }))
+    }
+
+    fn condition(&self, expression: &QueryExpression) -> Result<Value, QueryError> {
+        match expression {
+            QueryExpression::And(list) => {
+                Ok(json!({ "bool": { "filter": self.conditions(list)? } }))
+            }
+            QueryExpression::Or(list) => Ok(json!({
+                "bool": { "should": self.conditions(list)?, "minimum_should_match": 1 }
+            })),
+            QueryExpression::Not(condition) => {
+                Ok(json!({ "bool": { "must_not": [self.condition(condition)?] } }))
+            }
+            QueryExpression::Comparison(left, operator, right) => {
+                self.comparison(left, *operator, right)
+            }
+            QueryExpression::Literal(QueryLiteral::Boolean(true)) => Ok(json!({ "match_all": {} })),
+            QueryExpression::Literal(QueryLiteral::Boolean(false)) => {
+                Ok(json!({ "match_none": {} }))
+            }
+            _ => Err(translation_error(String::from(
+                "Only comparisons can be used as conditions",
+            ))),
+        }
+    }
+
+    fn conditions(&self, list: &[QueryExpression]) -> Result<Vec<Value>, QueryError> {
+        list.iter().map(|v| self.condition(v)).collect()
+    }
+
+    fn comparison(
+        &self,
+        left: &QueryExpression,
+        operator: ComparisonOperator,
+        right: &QueryExpression,
+    ) -> Result<Value, QueryError> {
+        let field = match left {
+            QueryExpression::Field(field) => field,
+            _ => {
+                return Err(translation_error(format!(
+                    "Only fields can be compared: {}",
+                    left.column_name()
+                )))
+            }
+        };
+        let time;
+        let literal = match right {
+            QueryExpression::Literal(literal) => literal,
+            QueryExpression::Time(v) => {
+                time = QueryLiteral::Integer(v.resolve(self.clock.now()));
+                &time
+            }
+            _ => {
+                return Err(translation_error(format!(
+                    "Fields can only be compared with values: {}",
+                    right.column_name()
+                )))
+            }
+        };
+        let kind = self.check_field(field)?;
+        let is_text = matches!(
+            kind,
+            None | Some(FieldType::Text(_))
+                | Some(FieldType::TextOptions(_, _))
+                | Some(FieldType::Array(_))
+        );
+        let query = match literal {
+            QueryLiteral::StartsWith(_)
+            | QueryLiteral::EndsWith(_)
+            | QueryLiteral::Contains(_)
+            | QueryLiteral::Like(_)
+            | QueryLiteral::Regex(_)
+                if !is_text =>
+            {
+                return Err(translation_error(format!(
+                    "Wildcards and regular expressions can only be used with text fields: {}",
+                    field
+                )))
+            }
+            QueryLiteral::StartsWith(v) => json!({ "prefix": { field: { "value": v } } }),
+            QueryLiteral::EndsWith(_) | QueryLiteral::Contains(_) | QueryLiteral::Like(_) => {
+                json!({ "wildcard": { field: { "value": wildcard_pattern(literal) } } })
+            }
+            QueryLiteral::Regex(v) => json!({ "regexp": { field: { "value": lucene_regex(v) } } }),
+            literal => {
+                let value = literal_value(literal);
+                match operator {
+                    ComparisonOperator::Equals | ComparisonOperator::NotEquals => {
+                        // IP fields accept also CIDR ranges
+                        json!({ "term": { field: value } })
+                    }
+                    ComparisonOperator::LessThan => json!({ "range": { field: { "lt": value } } }),
+                    ComparisonOperator::LessOrEqual => {
+                        json!({ "range": { field: { "lte": value } } })
+                    }
+                    ComparisonOperator::GreaterThan => {
+                        json!({ "range": { field: { "gt": value } } })
+                    }
+                    ComparisonOperator::GreaterOrEqual => {
+                        json!({ "range": { field: { "gte": value } } })
+                    }
+                }
+            }
+        };
+        Ok(match operator {
+            // Also true when the field is missing
+            ComparisonOperator::NotEquals => json!({ "bool": { "must_not": [query] } }),
+            _ => query,
+        })
+    }
+}
+
+impl ElasticAggregation {
+    fn build(self, size: Option<usize>) -> Value {
+        match self {
+            ElasticAggregation::Stats { aggregations, by } => {
+                let mut metrics = Map::new();
+                for aggregation in &aggregations {
+                    metrics.insert(aggregation.name(), metric(aggregation));
+                }
+                if by.is_empty() {
+                    return Value::Object(metrics);
+                }
+                let sources: Vec<Value> = by
+                    .iter()
+                    .map(|field| {
+                        json!({ field: { "terms": { "field": field, "missing_bucket": true } } })
+                    })
+                    .collect();
+                let mut composite = json!({ "sources": sources });
+                if let Some(size) = size {
+                    composite["size"] = json!(size);
+                }
+                json!({ "stats": { "composite": composite, "aggs": metrics } })
+            }
+            ElasticAggregation::Top { limit, fields } => {
+                let size = size.map_or(limit, |v| v.min(limit));
+                let terms = if fields.len() == 1 {
+                    json!({ "terms": { "field": fields[0], "size": size } })
+                } else {
+                    let terms: Vec<Value> = fields.iter().map(|v| json!({ "field": v })).collect();
+                    json!({ "multi_terms": { "terms": terms, "size": size } })
+                };
+                json!({ "top": terms })
+            }
+        }
+    }
+}
+
+impl ElasticSearch {
+    fn build(self) -> Value {
+        let mut body = Map::new();
+        let query = match self.filters.len() {
+            0 => json!({ "match_all": {} }),
+            _ => json!({ "bool": { "filter": self.filters } }),
+        };
+        body.insert(String::from("query"), query);
+        if let Some(aggregation) = self.aggregation {
+            // Only the buckets are returned
+            body.insert(String::from("size"), json!(0));
+            body.insert(String::from("aggs"), aggregation.build(self.size));
+            return Value::Object(body);
+        }
+        if let Some(source) = self.source {
+            body.insert(String::from("_source"), json!(source));
+        }
+        if !self.sort.is_empty() {
+            body.insert(String::from("sort"), json!(self.sort));
+        }
+        if let Some(field) = self.collapse {
+            body.insert(String::from("collapse"), json!({ "field": field }));
+        }
+        if let Some(size) = self.size {
+            body.insert(String::from("size"), json!(size));
+        }
+        Value::Object(body)
+    }
+}
+
+/// `count()` is a filter aggregation to have the number of documents with the name of the column
+fn metric(aggregation: &Aggregation) -> Value {
+    let field = match &aggregation.field {
+        Some(field) => field,
+        None => return json!({ "filter": { "match_all": {} } }),
+    };
+    match aggregation.function {
+        AggregationFunction::Count => json!({ "value_count": { "field": field } }),
+        AggregationFunction::DistinctCount => json!({ "cardinality": { "field": field } }),
+        AggregationFunction::Sum => json!({ "sum": { "field": field } }),
+        AggregationFunction::Avg => json!({ "avg": { "field": field } }),
+        AggregationFunction::Min => json!({ "min": { "field": field } }),
+        AggregationFunction::Max => json!({ "max": { "field": field } }),
+        AggregationFunction::Values => json!({ "terms": { "field": field } }),
+    }
+}
+
+/// Logs created between the times, both included
+fn time_filter(from: i64, to: i64) -> Value {
+    json!({ "range": { EVENT_CREATED_FIELD: { "gte": from, "lte": to } } })
+}
+
+fn literal_value(literal: &QueryLiteral) -> Value {
+    match literal {
+        QueryLiteral::Integer(v) => json!(v),
+        QueryLiteral::Float(v) => json!(v),
+        QueryLiteral::Boolean(v) => json!(v),
+        QueryLiteral::Text(v)
+        | QueryLiteral::StartsWith(v)
+        | QueryLiteral::EndsWith(v)
+        | QueryLiteral::Contains(v)
+        | QueryLiteral::Like(v)
+        | QueryLiteral::Regex(v) => json!(v),
+    }
+}
+
+/// Pattern of a wildcard query. `?` and `\` are escaped because only `*` is a wildcard in the queries.
+fn wildcard_pattern(literal: &QueryLiteral) -> String {
+    let escape = |text: &str, pattern: &mut String| {
+        for ch in text.chars() {
+            if ch == '?' || ch == '*' || ch == '\\' {
+                pattern.push('\\');
+            }
+            pattern.push(ch);
+        }
+    };
+    let mut pattern = String::new();
+    match literal {
+        QueryLiteral::EndsWith(v) => {
+            pattern.push('*');
+            escape(v, &mut pattern);
+        }
+        QueryLiteral::Contains(v) => {
+            pattern.push('*');
+            escape(v, &mut pattern);
+            pattern.push('*');
+        }
+        QueryLiteral::Like(v) => {
+            let mut chars = v.chars().peekable();
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '\\' if chars.peek() == Some(&'*') => {
+                        chars.next();
+                        pattern.push_str("\\*");
+                    }
+                    '*' => pattern.push('*'),
+                    ch => escape(&ch.to_string(), &mut pattern),
+                }
+            }
+        }
+        _ => {}
+    }
+    pattern
+}
+
+/// Lucene regular expressions always match the whole text and have no anchors
+fn lucene_regex(regex: &str) -> String {
+    let mut pattern = String::with_capacity(regex.len() + 4);
+    match regex.strip_prefix('^') {
+        Some(rest) => pattern.push_str(rest),
+        None => {
+            pattern.push_str(".*");
+            pattern.push_str(regex);
+        }
+    }
+    let escaped_end = pattern.ends_with("\\$") && !pattern.ends_with("\\\\$");
+    if pattern.ends_with('$') && !escaped_end {
+        pattern.pop();
+    } else {
+        pattern.push_str(".*");
+    }
+    pattern
+}
+
+fn translation_error(message: String) -> QueryError {
+    QueryError::Translation(LogString::Owned(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translator() -> ElasticTranslator {
+        let mut schema = FieldSchema::new();
+        schema.insert("source.ip", FieldType::Ip("Source IP"));
+        schema.insert("user.name", FieldType::Text("User name"));
+        schema.insert("event.code", FieldType::Numeric("Event code"));
+        schema.allow_unknown_fields = true;
+        ElasticTranslator::new(schema)
+    }
+
+    fn golden(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn should_translate_query_info_to_a_search() {
+        let mut info = QueryInfo::new("filter source.ip = \"10.0.0.0/8\" AND (user.name = \"adm*\" OR process.name ~= \"^cmd.*exe$\") AND NOT event.code >= 4625 AND host != \"srv*\" | sort -event.created | dedup user.name | fields user.name, source.ip | head 20");
+        info.is_native = false;
+        info.to = 100;
+        info.limit = 10;
+        info.offset = 5;
+        info.fields = vec!["user.name".to_string()];
+        let expected = golden(
+            r#"{
+                "query": { "bool": { "filter": [
+                    { "range": { "event.created": { "gte": 0, "lte": 100 } } },
+                    { "bool": { "filter": [
+                        { "term": { "source.ip": "10.0.0.0/8" } },
+                        { "bool": { "should": [
+                            { "prefix": { "user.name": { "value": "adm" } } },
+                            { "regexp": { "process.name": { "value": "cmd.*exe" } } }
+                        ], "minimum_should_match": 1 } },
+                        { "bool": { "must_not": [ { "range": { "event.code": { "gte": 4625 } } } ] } },
+                        { "bool": { "must_not": [ { "prefix": { "host": { "value": "srv" } } } ] } }
+                    ] } }
+                ] } },
+                "_source": ["user.name"],
+                "sort": [ { "event.created": { "order": "desc", "missing": "_last" } } ],
+                "collapse": { "field": "user.name" },
+                "size": 10,
+                "from": 5
+            }"#,
+        );
+        assert_eq!(expected, translator().translate_query_info(&info).unwrap());
+    }
+
+    #[test]
+    fn should_translate_aggregations() {
+        let query = Query::parse("filter user.name = \"*ad?min*\" | stats count() as total, distinct_count(source.ip), avg(event.code) by user.name, host").unwrap();
+        let expected = golden(
+            r#"{
+                "query": { "bool": { "filter": [
+                    { "wildcard": { "user.name": { "value": "*ad\\?min*" } } }
+                ] } },
+                "size": 0,
+                "aggs": { "stats": {
+                    "composite": { "sources": [
+                        { "user.name": { "terms": { "field": "user.name", "missing_bucket": true } } },
+                        { "host": { "terms": { "field": "host", "missing_bucket": true } } }
+                    ] },
+                    "aggs": {
+                        "total": { "filter": { "match_all": {} } },
+                        "distinct_count(source.ip)": { "cardinality": { "field": "source.ip" } },
+                        "avg(event.code)": { "avg": { "field": "event.code" } }
+                    }
+                } }
+            }"#,
+        );
+        assert_eq!(expected, translator().translate(&query).unwrap());
+
+        let query = Query::parse("top 3 user.name | head 2").unwrap();
+        let expected = golden(
+            r#"{
+                "query": { "match_all": {} },
+                "size": 0,
+                "aggs": { "top": { "terms": { "field": "user.name", "size": 2 } } }
+            }"#,
+        );
+        assert_eq!(expected, translator().translate(&query).unwrap());
+    }
+
+    #[test]
+    fn should_reject_queries_that_cannot_be_translated() {
+        let error = |query: &str| match translator().translate(&Query::parse(query).unwrap()) {
+            Err(QueryError::Translation(message)) => message.to_string(),
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!(
+            "Wildcards and regular expressions can only be used with text fields: source.ip",
+            error("filter source.ip = \"10.*\"")
+        );
+        assert_eq!(
+            "filter cannot be used after stats, top, dedup or head",
+            error("head 5 | filter host = \"a\"")
+        );
+        assert_eq!(
+            "Only fields without alias can be returned: host",
+            error("fields lowercase(host.name) as host")
+        );
+    }
+
+    #[test]
+    fn should_resolve_the_time_range() {
+        let translator = translator().clock(QueryClock::Fixed(1_700_044_365_123));
+        let mut info =
+            QueryInfo::new("earliest=-1d@d latest=now | filter event.created < now()-1h");
+        info.from = 1_699_000_000_000;
+        info.to = 1_800_000_000_000;
+        let expected = golden(
+            r#"{
+                "query": { "bool": { "filter": [
+                    { "range": { "event.created": { "gte": 1699920000000, "lte": 1700044365123 } } },
+                    { "range": { "event.created": { "lt": 1700040765123 } } }
+                ] } },
+                "size": 128
+            }"#,
+        );
+        assert_eq!(expected, translator.translate_query_info(&info).unwrap());
+    }
+}
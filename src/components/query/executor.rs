@@ -0,0 +1,1042 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use regex::Regex;
+
+use crate::prelude::rule::glob::GlobPattern;
+use crate::prelude::rule::{compare_field, field_equals, text_matches};
+use crate::prelude::types::LogString;
+use crate::prelude::{QueryInfo, SiemField, SiemIp, SiemLog};
+
+use super::ast::{
+    Aggregation, AggregationFunction, ComparisonOperator, FieldProjection, Query, QueryExpression,
+    QueryFunction, QueryLiteral, QueryStage, SortField,
+};
+use super::time::{format_time, QueryClock};
+use super::QueryError;
+
+/// Row returned by a query: column name => value. Same format as the response of `LOG_QUERY`.
+pub type QueryRow = BTreeMap<String, SiemField>;
+
+const EVENT_CREATED: &str = "event.created";
+
+impl Query {
+    /// Runs the query over the logs without an external database
+    pub fn execute<I>(&self, logs: I) -> Result<Vec<QueryRow>, QueryError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<SiemLog>,
+    {
+        self.execute_with_clock(logs, QueryClock::System)
+    }
+
+    /// Runs the query resolving `now()` and the time range with the clock
+    pub fn execute_with_clock<I>(
+        &self,
+        logs: I,
+        clock: QueryClock,
+    ) -> Result<Vec<QueryRow>, QueryError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<SiemLog>,
+    {
+        let rows = logs
+            .into_iter()
+            .map(|log| log_to_row(log.borrow()))
+            .collect();
+        self.execute_rows_with_clock(rows, clock)
+    }
+
+    /// Runs the query over the rows returned by another query
+    pub fn execute_rows(&self, rows: Vec<QueryRow>) -> Result<Vec<QueryRow>, QueryError> {
+        self.execute_rows_with_clock(rows, QueryClock::System)
+    }
+
+    pub fn execute_rows_with_clock(
+        &self,
+        rows: Vec<QueryRow>,
+        clock: QueryClock,
+    ) -> Result<Vec<QueryRow>, QueryError> {
+        let now = clock.now();
+        let executor = QueryExecutor::new(self, now)?;
+        let mut rows = rows;
+        if self.earliest.is_some() || self.latest.is_some() {
+            let (earliest, latest) = self.time_range(now);
+            rows.retain(|row| match row.get(EVENT_CREATED).and_then(created_time) {
+                Some(created) => created >= earliest && created <= latest,
+                None => false,
+            });
+        }
+        for stage in &self.stages {
+            rows = executor.run_stage(stage, rows);
+        }
+        Ok(rows)
+    }
+}
+
+/// Time of creation of a log: `SiemLog::set_event_created` stores it as an integer
+fn created_time(field: &SiemField) -> Option<i64> {
+    match field {
+        SiemField::Date(created) | SiemField::I64(created) => Some(*created),
+        SiemField::U64(created) => i64::try_from(*created).ok(),
+        _ => None,
+    }
+}
+
+/// Runs the query of a `LOG_QUERY` command: only the logs created between `from` and `to` are used,
+/// the `offset` and `limit` are applied to the results and, if not empty, only the `fields` are returned.
+pub fn execute_query_info<I>(info: &QueryInfo, logs: I) -> Result<Vec<QueryRow>, QueryError>
+where
+    I: IntoIterator,
+    I::Item: Borrow<SiemLog>,
+{
+    execute_query_info_with_clock(info, logs, QueryClock::System)
+}
+
+pub fn execute_query_info_with_clock<I>(
+    info: &QueryInfo,
+    logs: I,
+    clock: QueryClock,
+) -> Result<Vec<QueryRow>, QueryError>
+where
+    I: IntoIterator,
+    I::Item: Borrow<SiemLog>,
+{
+    let query = Query::parse(&info.query)?;
+    let logs = logs.into_iter().filter(|log| {
+        let created = log
+            .borrow()
+            .field(EVENT_CREATED)
+            .and_then(created_time)
+            .unwrap_or(0);
+        created >= info.from && created <= info.to
+    });
+    let rows = query.execute_with_clock(logs, clock)?;
+    Ok(rows
+        .into_iter()
+        .skip(info.offset)
+        .take(info.limit)
+        .map(|row| {
+            if info.fields.is_empty() {
+                row
+            } else {
+                row.into_iter()
+                    .filter(|(name, _)| info.fields.contains(name))
+                    .collect()
+            }
+        })
+        .collect())
+}
+
+pub(crate) fn log_to_row(log: &SiemLog) -> QueryRow {
+    log.iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+/// Query with the regular expressions already compiled
+struct QueryExecutor {
+    regexes: BTreeMap<String, Regex>,
+    /// Resolved value of `now()`
+    now: i64,
+}
+
+impl QueryExecutor {
+    fn new(query: &Query, now: i64) -> Result<Self, QueryError> {
+        let mut regexes = BTreeMap::new();
+        for stage in &query.stages {
+            match stage {
+                QueryStage::Filter(expression) => compile_regexes(expression, &mut regexes)?,
+                QueryStage::Fields(projections) => {
+                    for projection in projections {
+                        compile_regexes(&projection.expression, &mut regexes)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { regexes, now })
+    }
+
+    fn run_stage(&self, stage: &QueryStage, rows: Vec<QueryRow>) -> Vec<QueryRow> {
+        match stage {
+            QueryStage::Filter(expression) => rows
+                .into_iter()
+                .filter(|row| self.is_true(expression, row))
+                .collect(),
+            QueryStage::Fields(projections) => rows
+                .into_iter()
+                .map(|row| self.project(projections, &row))
+                .collect(),
+            QueryStage::Stats { aggregations, by } => {
+                let mut groups = group_rows(rows, by);
+                if groups.is_empty() && by.is_empty() {
+                    // Without groups there is always a result: count() = 0
+                    groups.push((QueryRow::new(), Vec::new()));
+                }
+                groups
+                    .into_iter()
+                    .map(|(mut row, rows)| {
+                        for aggregation in aggregations {
+                            row.insert(aggregation.name(), aggregate(aggregation, &rows));
+                        }
+                        row
+                    })
+                    .collect()
+            }
+            QueryStage::Top { limit, fields } => {
+                let mut groups: Vec<(QueryRow, usize)> = group_rows(rows, fields)
+                    .into_iter()
+                    .filter(|(row, _)| row.values().all(|v| !matches!(v, SiemField::Null)))
+                    .map(|(row, rows)| (row, rows.len()))
+                    .collect();
+                // Stable: the values with the same count keep the order of appearance
+                groups.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+                groups
+                    .into_iter()
+                    .take(*limit)
+                    .map(|(mut row, count)| {
+                        row.insert(String::from("count"), SiemField::I64(count as i64));
+                        row
+                    })
+                    .collect()
+            }
+            QueryStage::Dedup(fields) => {
+                let mut seen = BTreeSet::new();
+                rows.into_iter()
+                    .filter(|row| seen.insert(group_key(row, fields)))
+                    .collect()
+            }
+            QueryStage::Sort(fields) => {
+                let mut rows = rows;
+                rows.sort_by(|a, b| compare_rows(a, b, fields));
+                rows
+            }
+            QueryStage::Head(limit) => {
+                let mut rows = rows;
+                rows.truncate(*limit);
+                rows
+            }
+        }
+    }
+
+    fn project(&self, projections: &[FieldProjection], row: &QueryRow) -> QueryRow {
+        projections
+            .iter()
+            .map(|projection| {
+                (
+                    projection.name(),
+                    self.evaluate(&projection.expression, row),
+                )
+            })
+            .collect()
+    }
+
+    fn is_true(&self, expression: &QueryExpression, row: &QueryRow) -> bool {
+        match expression {
+            QueryExpression::And(list) => list.iter().all(|v| self.is_true(v, row)),
+            QueryExpression::Or(list) => list.iter().any(|v| self.is_true(v, row)),
+            QueryExpression::Not(v) => !self.is_true(v, row),
+            QueryExpression::Comparison(left, operator, right) => {
+                self.compare(left, *operator, right, row)
+            }
+            expression => is_truthy(&self.evaluate(expression, row)),
+        }
+    }
+
+    /// Comparisons with a missing field are false, except `!=` that is the negation of `=`
+    fn compare(
+        &self,
+        left: &QueryExpression,
+        operator: ComparisonOperator,
+        right: &QueryExpression,
+        row: &QueryRow,
+    ) -> bool {
+        let value = self.evaluate(left, row);
+        if let QueryExpression::Literal(literal) = right {
+            if let Some(matches) = self.pattern_matches(&value, literal) {
+                return match operator {
+                    ComparisonOperator::NotEquals => !matches,
+                    _ => matches,
+                };
+            }
+        }
+        let expected = self.evaluate(right, row);
+        let equals = || {
+            !matches!(value, SiemField::Null)
+                && !matches!(expected, SiemField::Null)
+                && field_equals(&value, &expected)
+        };
+        let ordering = || match (&value, &expected) {
+            (SiemField::Null, _) | (_, SiemField::Null) => None,
+            _ => compare_field(&value, &expected),
+        };
+        match operator {
+            ComparisonOperator::Equals => equals(),
+            ComparisonOperator::NotEquals => !equals(),
+            ComparisonOperator::LessThan => ordering() == Some(Ordering::Less),
+            ComparisonOperator::LessOrEqual => {
+                matches!(ordering(), Some(Ordering::Less | Ordering::Equal))
+            }
+            ComparisonOperator::GreaterThan => ordering() == Some(Ordering::Greater),
+            ComparisonOperator::GreaterOrEqual => {
+                matches!(ordering(), Some(Ordering::Greater | Ordering::Equal))
+            }
+        }
+    }
+
+    /// None if the literal is not a wildcard or a regex
+    fn pattern_matches(&self, value: &SiemField, literal: &QueryLiteral) -> Option<bool> {
+        let matches = match literal {
+            QueryLiteral::StartsWith(v) => text_matches(value, |txt| txt.starts_with(&v[..])),
+            QueryLiteral::EndsWith(v) => text_matches(value, |txt| txt.ends_with(&v[..])),
+            QueryLiteral::Contains(v) => text_matches(value, |txt| txt.contains(&v[..])),
+            QueryLiteral::Like(v) => {
+                // Only the asterisk is a wildcard in the queries
+                let pattern = GlobPattern::with_case(&v.replace('?', "\\?"), true);
+                text_matches(value, |txt| pattern.is_match(txt))
+            }
+            QueryLiteral::Regex(v) => match self.regexes.get(v) {
+                Some(regex) => text_matches(value, |txt| regex.is_match(txt)),
+                None => false,
+            },
+            _ => return None,
+        };
+        Some(matches)
+    }
+
+    fn evaluate(&self, expression: &QueryExpression, row: &QueryRow) -> SiemField {
+        match expression {
+            QueryExpression::Field(name) => row.get(name).cloned().unwrap_or_default(),
+            QueryExpression::Literal(literal) => literal_value(literal),
+            QueryExpression::Time(time) => SiemField::Date(time.resolve(self.now)),
+            QueryExpression::Function(function, args) => {
+                let args: Vec<SiemField> = args.iter().map(|v| self.evaluate(v, row)).collect();
+                call_function(*function, args)
+            }
+            condition => SiemField::Text(LogString::Borrowed(if self.is_true(condition, row) {
+                "true"
+            } else {
+                "false"
+            })),
+        }
+    }
+}
+
+fn compile_regexes(
+    expression: &QueryExpression,
+    regexes: &mut BTreeMap<String, Regex>,
+) -> Result<(), QueryError> {
+    match expression {
+        QueryExpression::Literal(QueryLiteral::Regex(pattern)) => {
+            if !regexes.contains_key(pattern) {
+                let regex = Regex::new(pattern).map_err(|err| {
+                    QueryError::Execution(LogString::Owned(format!(
+                        "Invalid regular expression {}: {}",
+                        pattern, err
+                    )))
+                })?;
+                regexes.insert(pattern.clone(), regex);
+            }
+        }
+        QueryExpression::Function(_, list)
+        | QueryExpression::And(list)
+        | QueryExpression::Or(list) => {
+            for expression in list {
+                compile_regexes(expression, regexes)?;
+            }
+        }
+        QueryExpression::Comparison(left, _, right) => {
+            compile_regexes(left, regexes)?;
+            compile_regexes(right, regexes)?;
+        }
+        QueryExpression::Not(expression) => compile_regexes(expression, regexes)?,
+        QueryExpression::Field(_) | QueryExpression::Literal(_) | QueryExpression::Time(_) => {}
+    }
+    Ok(())
+}
+
+/// Values of the fields used to group the rows. A missing field is not the same as an empty text.
+fn group_key(row: &QueryRow, fields: &[String]) -> Vec<Option<String>> {
+    fields
+        .iter()
+        .map(|field| row.get(field).and_then(text_value))
+        .collect()
+}
+
+/// Groups the rows in order of appearance: (Values of the fields, Rows of the group)
+fn group_rows(rows: Vec<QueryRow>, fields: &[String]) -> Vec<(QueryRow, Vec<QueryRow>)> {
+    let mut positions: BTreeMap<Vec<Option<String>>, usize> = BTreeMap::new();
+    let mut groups: Vec<(QueryRow, Vec<QueryRow>)> = Vec::new();
+    for row in rows {
+        let key = group_key(&row, fields);
+        let position = match positions.get(&key) {
+            Some(position) => *position,
+            None => {
+                let values = fields
+                    .iter()
+                    .map(|field| (field.clone(), row.get(field).cloned().unwrap_or_default()))
+                    .collect();
+                groups.push((values, Vec::new()));
+                positions.insert(key, groups.len() - 1);
+                groups.len() - 1
+            }
+        };
+        groups[position].1.push(row);
+    }
+    groups
+}
+
+fn compare_rows(a: &QueryRow, b: &QueryRow, fields: &[SortField]) -> Ordering {
+    for sort in fields {
+        let null = SiemField::Null;
+        let first = a.get(&sort.field).unwrap_or(&null);
+        let second = b.get(&sort.field).unwrap_or(&null);
+        let ordering = compare_values(first, second, sort.descending);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Missing values go always at the end. Values of different kinds are ordered by kind: numbers, IPs, texts
+/// and arrays, so the order is total even when a field has values of multiple types.
+fn compare_values(a: &SiemField, b: &SiemField, descending: bool) -> Ordering {
+    let ordering = match (a, b) {
+        (SiemField::Null, SiemField::Null) => return Ordering::Equal,
+        (SiemField::Null, _) => return Ordering::Greater,
+        (_, SiemField::Null) => return Ordering::Less,
+        _ => value_rank(a)
+            .cmp(&value_rank(b))
+            .then_with(|| compare_same_kind(a, b)),
+    };
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+fn compare_same_kind(a: &SiemField, b: &SiemField) -> Ordering {
+    match (a, b) {
+        (SiemField::IP(SiemIp::V4(v1)), SiemField::IP(SiemIp::V4(v2))) => v1.cmp(v2),
+        (SiemField::IP(SiemIp::V6(v1)), SiemField::IP(SiemIp::V6(v2))) => v1.cmp(v2),
+        (SiemField::IP(SiemIp::V4(_)), SiemField::IP(_)) => Ordering::Less,
+        (SiemField::IP(_), SiemField::IP(_)) => Ordering::Greater,
+        (SiemField::F64(v1), SiemField::F64(v2)) => compare_floats(*v1, *v2),
+        (SiemField::F64(v1), _) => compare_integer_float(exact_number(b), *v1).reverse(),
+        (_, SiemField::F64(v2)) => compare_integer_float(exact_number(a), *v2),
+        _ => match (exact_number(a), exact_number(b)) {
+            (Some(v1), Some(v2)) => v1.cmp(&v2),
+            _ => a.to_string().cmp(&b.to_string()),
+        },
+    }
+}
+
+/// NaN goes after the other numbers
+fn compare_floats(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Exact comparison, without casting the integer to a float
+fn compare_integer_float(integer: Option<i128>, float: f64) -> Ordering {
+    let integer = integer.unwrap_or_default();
+    if float.is_nan() || float >= i128::MAX as f64 {
+        return Ordering::Less;
+    }
+    if float < i128::MIN as f64 {
+        return Ordering::Greater;
+    }
+    let truncated = float.trunc();
+    integer
+        .cmp(&(truncated as i128))
+        .then_with(|| truncated.partial_cmp(&float).unwrap_or(Ordering::Equal))
+}
+
+fn value_rank(value: &SiemField) -> u8 {
+    match value {
+        SiemField::I64(_) | SiemField::U64(_) | SiemField::F64(_) | SiemField::Date(_) => 0,
+        SiemField::IP(_) => 1,
+        SiemField::Array(_) => 3,
+        SiemField::Null => 4,
+        _ => 2,
+    }
+}
+
+fn exact_number(value: &SiemField) -> Option<i128> {
+    match value {
+        SiemField::I64(v) | SiemField::Date(v) => Some(*v as i128),
+        SiemField::U64(v) => Some(*v as i128),
+        _ => None,
+    }
+}
+
+fn aggregate(aggregation: &Aggregation, rows: &[QueryRow]) -> SiemField {
+    let values: Vec<&SiemField> = match &aggregation.field {
+        Some(field) => rows
+            .iter()
+            .filter_map(|row| row.get(field))
+            .filter(|v| !matches!(v, SiemField::Null))
+            .collect(),
+        // count()
+        None => return SiemField::I64(rows.len() as i64),
+    };
+    match aggregation.function {
+        AggregationFunction::Count => SiemField::I64(values.len() as i64),
+        AggregationFunction::DistinctCount => SiemField::I64(distinct_values(&values).len() as i64),
+        AggregationFunction::Values => SiemField::Array(
+            distinct_values(&values)
+                .into_iter()
+                .map(LogString::Owned)
+                .collect(),
+        ),
+        AggregationFunction::Min => values
+            .into_iter()
+            .min_by(|a, b| compare_values(a, b, false))
+            .cloned()
+            .unwrap_or_default(),
+        AggregationFunction::Max => values
+            .into_iter()
+            .max_by(|a, b| compare_values(a, b, false))
+            .cloned()
+            .unwrap_or_default(),
+        AggregationFunction::Sum | AggregationFunction::Avg => {
+            let numbers: Vec<SiemField> = values
+                .into_iter()
+                .map(number_value)
+                .filter(|v| !matches!(v, SiemField::Null))
+                .collect();
+            if numbers.is_empty() {
+                return SiemField::Null;
+            }
+            let is_float = numbers.iter().any(|v| matches!(v, SiemField::F64(_)));
+            let total: f64 = numbers
+                .iter()
+                .map(|v| match v {
+                    SiemField::I64(v) => *v as f64,
+                    SiemField::U64(v) => *v as f64,
+                    SiemField::F64(v) => *v,
+                    _ => 0.0,
+                })
+                .sum();
+            if aggregation.function == AggregationFunction::Avg {
+                SiemField::F64(total / numbers.len() as f64)
+            } else if is_float {
+                SiemField::F64(total)
+            } else {
+                // Without floats the sum is exact, even if it does not fit in an I64
+                let total: i128 = numbers.iter().filter_map(exact_number).sum();
+                match (i64::try_from(total), u64::try_from(total)) {
+                    (Ok(v), _) => SiemField::I64(v),
+                    (_, Ok(v)) => SiemField::U64(v),
+                    _ => SiemField::F64(total as f64),
+                }
+            }
+        }
+    }
+}
+
+/// Sorted texts of the values. The elements of the arrays are used as individual values.
+fn distinct_values(values: &[&SiemField]) -> BTreeSet<String> {
+    let mut distinct = BTreeSet::new();
+    for value in values {
+        match value {
+            SiemField::Array(list) => distinct.extend(list.iter().map(|v| v.to_string())),
+            value => {
+                distinct.insert(value.to_string());
+            }
+        }
+    }
+    distinct
+}
+
+fn literal_value(literal: &QueryLiteral) -> SiemField {
+    match literal {
+        QueryLiteral::Integer(v) => SiemField::I64(*v),
+        QueryLiteral::Float(v) => SiemField::F64(*v),
+        QueryLiteral::Boolean(v) => SiemField::Text(LogString::Owned(v.to_string())),
+        QueryLiteral::Text(v)
+        | QueryLiteral::StartsWith(v)
+        | QueryLiteral::EndsWith(v)
+        | QueryLiteral::Contains(v)
+        | QueryLiteral::Like(v)
+        | QueryLiteral::Regex(v) => SiemField::Text(LogString::Owned(v.clone())),
+    }
+}
+
+/// Null, zero, empty texts and `false` are false
+fn is_truthy(value: &SiemField) -> bool {
+    match value {
+        SiemField::Null => false,
+        SiemField::Text(v) => !v.is_empty() && v != "false",
+        SiemField::I64(v) => *v != 0,
+        SiemField::U64(v) => *v != 0,
+        SiemField::F64(v) => *v != 0.0,
+        SiemField::Array(v) => !v.is_empty(),
+        _ => true,
+    }
+}
+
+fn text_value(value: &SiemField) -> Option<String> {
+    match value {
+        SiemField::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+fn number_value(value: &SiemField) -> SiemField {
+    match value {
+        SiemField::I64(_) | SiemField::U64(_) | SiemField::F64(_) => value.clone(),
+        SiemField::Date(v) => SiemField::I64(*v),
+        SiemField::Null => SiemField::Null,
+        value => {
+            let txt = value.to_string();
+            let txt = txt.trim();
+            if let Ok(v) = txt.parse::<i64>() {
+                SiemField::I64(v)
+            } else if let Ok(v) = txt.parse::<f64>() {
+                SiemField::F64(v)
+            } else {
+                SiemField::Null
+            }
+        }
+    }
+}
+
+fn call_function(function: QueryFunction, args: Vec<SiemField>) -> SiemField {
+    let mut args = args.into_iter();
+    let value = args.next().unwrap_or_default();
+    match function {
+        QueryFunction::ToNumber => number_value(&value),
+        QueryFunction::ToInteger => match number_value(&value) {
+            SiemField::F64(v) => SiemField::I64(v.trunc() as i64),
+            SiemField::U64(v) => SiemField::I64(v as i64),
+            v => v,
+        },
+        QueryFunction::ToFloat => match number_value(&value) {
+            SiemField::I64(v) => SiemField::F64(v as f64),
+            SiemField::U64(v) => SiemField::F64(v as f64),
+            v => v,
+        },
+        QueryFunction::Floor => match number_value(&value) {
+            SiemField::F64(v) => SiemField::I64(v.floor() as i64),
+            v => v,
+        },
+        QueryFunction::ToString => match text_value(&value) {
+            Some(v) => SiemField::Text(LogString::Owned(v)),
+            // Default value
+            None => match args.next() {
+                Some(default) => default,
+                None => SiemField::Null,
+            },
+        },
+        QueryFunction::Len => match &value {
+            SiemField::Null => SiemField::Null,
+            SiemField::Array(list) => SiemField::I64(list.len() as i64),
+            value => SiemField::I64(value.to_string().chars().count() as i64),
+        },
+        QueryFunction::Lowercase => text_function(&value, |v| v.to_lowercase()),
+        QueryFunction::Uppercase => text_function(&value, |v| v.to_uppercase()),
+        QueryFunction::Trim => text_function(&value, |v| v.trim().to_string()),
+        QueryFunction::Replace => {
+            let pattern = args.next().and_then(|v| text_value(&v)).unwrap_or_default();
+            let replacement = args.next().and_then(|v| text_value(&v)).unwrap_or_default();
+            if pattern.is_empty() {
+                return value;
+            }
+            text_function(&value, |v| v.replace(&pattern, &replacement))
+        }
+        QueryFunction::Bin => bin_value(&value, &args.next().unwrap_or_default()),
+        QueryFunction::Strftime => {
+            let format = args.next().and_then(|v| text_value(&v)).unwrap_or_default();
+            let time = match number_value(&value) {
+                SiemField::I64(v) => v,
+                SiemField::U64(v) => v as i64,
+                SiemField::F64(v) => v as i64,
+                _ => return SiemField::Null,
+            };
+            match format_time(time, &format) {
+                Some(v) => SiemField::Text(LogString::Owned(v)),
+                None => SiemField::Null,
+            }
+        }
+    }
+}
+
+/// Rounds down the value to a multiple of the span: `bin(event.created, 5m)`. Dates stay dates.
+fn bin_value(value: &SiemField, span: &SiemField) -> SiemField {
+    let span = match number_value(span) {
+        SiemField::I64(v) => v as f64,
+        SiemField::U64(v) => v as f64,
+        SiemField::F64(v) => v,
+        _ => return SiemField::Null,
+    };
+    if span <= 0.0 {
+        return SiemField::Null;
+    }
+    let integer = |v: i64| {
+        if span.fract() == 0.0 && span <= i64::MAX as f64 {
+            let span = span as i64;
+            v.div_euclid(span) * span
+        } else {
+            ((v as f64 / span).floor() * span) as i64
+        }
+    };
+    match value {
+        SiemField::Date(v) => SiemField::Date(integer(*v)),
+        value => match number_value(value) {
+            SiemField::I64(v) => SiemField::I64(integer(v)),
+            SiemField::U64(v) => SiemField::I64(integer(v as i64)),
+            SiemField::F64(v) => SiemField::F64((v / span).floor() * span),
+            _ => SiemField::Null,
+        },
+    }
+}
+
+fn text_function<F>(value: &SiemField, function: F) -> SiemField
+where
+    F: Fn(&str) -> String,
+{
+    match value {
+        SiemField::Null => SiemField::Null,
+        SiemField::Array(list) => {
+            SiemField::Array(list.iter().map(|v| LogString::Owned(function(v))).collect())
+        }
+        value => SiemField::Text(LogString::Owned(function(&value.to_string()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::SiemIp;
+
+    fn logs() -> Vec<SiemLog> {
+        let users = [
+            ("Alice", "10.0.0.1", 4625, " WIN-01 "),
+            ("bob", "10.0.0.2", 4624, "win-02"),
+            ("admin", "192.168.1.1", 4625, "srv-db"),
+        ];
+        users
+            .iter()
+            .enumerate()
+            .map(|(i, (user, ip, code, host))| {
+                let mut log = SiemLog::new("", i as i64 * 1000, "localhost");
+                log.add_field("user.name", (*user).into());
+                log.add_field("source.ip", SiemIp::from_ip_str(ip).unwrap().into());
+                log.add_field(
+                    "event.code",
+                    SiemField::Text(LogString::Owned(code.to_string())),
+                );
+                log.add_field("host.hostname", (*host).into());
+                log
+            })
+            .collect()
+    }
+
+    fn text(value: &str) -> SiemField {
+        SiemField::Text(LogString::Owned(value.to_string()))
+    }
+
+    #[test]
+    fn should_filter_and_project_logs() {
+        let logs = logs();
+        let query = Query::parse(
+            "filter to_number(event.code) = 4625 AND NOT user.name = \"adm*\" | fields lowercase(trim(host.hostname)) as host, source.ip, len(user.name) as size",
+        )
+        .unwrap();
+        let rows = query.execute(&logs).unwrap();
+        assert_eq!(1, rows.len());
+        assert_eq!(Some(&text("win-01")), rows[0].get("host"));
+        assert_eq!(Some(&text("10.0.0.1")), rows[0].get("source.ip"));
+        assert_eq!(Some(&SiemField::I64(5)), rows[0].get("size"));
+        assert_eq!(3, rows[0].len());
+
+        // Aliases can be used by the next stages
+        let query = Query::parse(
+            "fields replace(host.hostname, \"win\", \"WIN\") as host, user.name | filter host ~= \"^WIN-\\\\d+$\" OR user.name = \"*dmi*\"",
+        )
+        .unwrap();
+        let rows = query.execute(&logs).unwrap();
+        let hosts: Vec<String> = rows.iter().map(|v| v["host"].to_string()).collect();
+        assert_eq!(vec!["WIN-02", "srv-db"], hosts);
+
+        let query = Query::parse("filter event.code >= 4625 AND user.name != \"bob\" AND missing != 1 AND NOT missing = 1").unwrap();
+        assert_eq!(2, query.execute(&logs).unwrap().len());
+        let query = Query::parse(
+            "filter floor(to_float(event.code)) < 4625 | fields to_string(missing, 'none') as none",
+        )
+        .unwrap();
+        let rows = query.execute(&logs).unwrap();
+        assert_eq!(
+            vec![text("none")],
+            rows.into_iter()
+                .map(|mut v| v.remove("none").unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_aggregate_and_sort_rows() {
+        let logs = logs();
+        let query = Query::parse("stats count() as total, distinct_count(user.name), sum(event.code), values(user.name) as users by event.code | sort -total").unwrap();
+        let rows = query.execute(&logs).unwrap();
+        assert_eq!(2, rows.len());
+        assert_eq!(Some(&text("4625")), rows[0].get("event.code"));
+        assert_eq!(Some(&SiemField::I64(2)), rows[0].get("total"));
+        assert_eq!(
+            Some(&SiemField::I64(2)),
+            rows[0].get("distinct_count(user.name)")
+        );
+        assert_eq!(Some(&SiemField::I64(9250)), rows[0].get("sum(event.code)"));
+        assert_eq!(
+            Some(&SiemField::Array(vec!["Alice".into(), "admin".into()])),
+            rows[0].get("users")
+        );
+        assert_eq!(Some(&SiemField::I64(1)), rows[1].get("total"));
+
+        let query = Query::parse(
+            "stats avg(event.code) as avg, min(source.ip) as min, max(user.name) as max, count(missing)",
+        )
+        .unwrap();
+        let rows = query.execute(&logs).unwrap();
+        assert_eq!(1, rows.len());
+        assert_eq!(Some(&SiemField::F64(13874.0 / 3.0)), rows[0].get("avg"));
+        assert_eq!("10.0.0.1", rows[0]["min"].to_string());
+        assert_eq!(Some(&text("bob")), rows[0].get("max"));
+        assert_eq!(Some(&SiemField::I64(0)), rows[0].get("count(missing)"));
+
+        // Always a result without groups
+        let query = Query::parse("filter user.name = \"nobody\" | stats count()").unwrap();
+        let rows = query.execute(&logs).unwrap();
+        assert_eq!(
+            vec![SiemField::I64(0)],
+            rows[0].values().cloned().collect::<Vec<_>>()
+        );
+
+        let query = Query::parse("top 1 event.code").unwrap();
+        let rows = query.execute(&logs).unwrap();
+        assert_eq!(1, rows.len());
+        assert_eq!(Some(&text("4625")), rows[0].get("event.code"));
+        assert_eq!(Some(&SiemField::I64(2)), rows[0].get("count"));
+        assert_eq!(
+            0,
+            Query::parse("top missing")
+                .unwrap()
+                .execute(&logs)
+                .unwrap()
+                .len()
+        );
+
+        let users = |query: &str| -> Vec<String> {
+            Query::parse(query)
+                .unwrap()
+                .execute(&logs)
+                .unwrap()
+                .iter()
+                .map(|v| v["user.name"].to_string())
+                .collect()
+        };
+        assert_eq!(vec!["Alice", "bob"], users("dedup event.code"));
+        assert_eq!(vec!["bob", "admin"], users("sort -user.name | head 2"));
+        assert_eq!(
+            vec!["bob", "admin", "Alice"],
+            users("sort event.code, -source.ip")
+        );
+    }
+
+    #[test]
+    fn should_sort_and_sum_values_of_mixed_types() {
+        let sizes = vec![
+            text("9"),
+            SiemField::I64(10),
+            SiemField::Null,
+            SiemField::IP([10, 0, 0, 1].into()),
+            SiemField::F64(9.5),
+            SiemField::U64(u64::MAX),
+            SiemField::F64(f64::NAN),
+            SiemField::I64(-1),
+        ];
+        let logs: Vec<SiemLog> = sizes
+            .into_iter()
+            .map(|size| {
+                let mut log = SiemLog::new("", 0, "localhost");
+                log.add_field("size", size);
+                log
+            })
+            .collect();
+        let sizes = |query: &str| -> Vec<String> {
+            Query::parse(query)
+                .unwrap()
+                .execute(&logs)
+                .unwrap()
+                .iter()
+                .map(|v| v["size"].to_string())
+                .collect()
+        };
+        // Numbers, IPs, texts and missing values
+        let sorted = sizes("sort size");
+        assert_eq!(
+            vec![
+                "-1",
+                "9.5",
+                "10",
+                "18446744073709551615",
+                "NaN",
+                "10.0.0.1",
+                "9",
+                ""
+            ],
+            sorted
+        );
+        // Missing values are always at the end
+        assert_eq!(
+            vec![
+                "9",
+                "10.0.0.1",
+                "NaN",
+                "18446744073709551615",
+                "10",
+                "9.5",
+                "-1",
+                ""
+            ],
+            sizes("sort -size")
+        );
+
+        // The sum of integers does not overflow
+        let sum = |values: Vec<SiemField>| {
+            let logs: Vec<SiemLog> = values
+                .into_iter()
+                .map(|bytes| {
+                    let mut log = SiemLog::new("", 0, "localhost");
+                    log.add_field("bytes", bytes);
+                    log
+                })
+                .collect();
+            let rows = Query::parse("stats sum(bytes) as total")
+                .unwrap()
+                .execute(&logs)
+                .unwrap();
+            rows[0]["total"].clone()
+        };
+        assert_eq!(
+            SiemField::U64(1 << 63),
+            sum(vec![SiemField::I64(i64::MAX), SiemField::U64(1)])
+        );
+        assert_eq!(
+            SiemField::I64(-1),
+            sum(vec![
+                SiemField::U64(u64::MAX),
+                SiemField::I64(i64::MIN),
+                SiemField::I64(i64::MIN)
+            ])
+        );
+        assert_eq!(
+            SiemField::F64(3.0 * u64::MAX as f64),
+            sum(vec![SiemField::U64(u64::MAX); 3])
+        );
+    }
+
+    #[test]
+    fn should_apply_the_query_info() {
+        let logs = logs();
+        let mut info = QueryInfo::new("filter event.code = \"46*\"");
+        info.from = 1000;
+        info.fields = vec!["user.name".to_string()];
+        let rows = execute_query_info(&info, logs.clone()).unwrap();
+        assert_eq!(
+            vec![text("bob"), text("admin")],
+            rows.iter()
+                .map(|v| v["user.name"].clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, rows[0].len());
+        info.offset = 1;
+        info.limit = 1;
+        let rows = execute_query_info(&info, logs).unwrap();
+        assert_eq!(
+            vec![text("admin")],
+            rows.iter()
+                .map(|v| v["user.name"].clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_filter_logs_with_an_integer_creation_time() {
+        let mut logs = logs();
+        for (i, log) in logs.iter_mut().enumerate() {
+            log.set_event_created(10_000 + i as i64 * 1000);
+        }
+        let rows = Query::parse("earliest=-2s")
+            .unwrap()
+            .execute_with_clock(logs.clone(), QueryClock::Fixed(12_500))
+            .unwrap();
+        assert_eq!(
+            vec![text("bob"), text("admin")],
+            rows.iter()
+                .map(|v| v["user.name"].clone())
+                .collect::<Vec<_>>()
+        );
+        let mut info = QueryInfo::new("head 10");
+        info.from = 10_500;
+        info.to = 11_500;
+        let rows = execute_query_info(&info, logs).unwrap();
+        assert_eq!(
+            vec![text("bob")],
+            rows.iter()
+                .map(|v| v["user.name"].clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_resolve_times_with_the_clock() {
+        let logs = logs();
+        let created: Vec<i64> = logs.iter().map(|v| v.event_created()).collect();
+        let clock = QueryClock::Fixed(created[1] + 500);
+        let run = |query: &str| {
+            Query::parse(query)
+                .unwrap()
+                .execute_with_clock(logs.clone(), clock)
+                .unwrap()
+        };
+        // Only the logs created in the last second
+        let rows = run("earliest=-1s latest=now");
+        assert_eq!(
+            created
+                .iter()
+                .filter(|v| **v >= created[1] - 500 && **v <= created[1] + 500)
+                .count(),
+            rows.len()
+        );
+        let rows = run("filter event.created <= now() - 1s | fields bin(event.created, 1m) as created, strftime(event.created, \"%Y-%m-%d\") as day");
+        for row in &rows {
+            match &row["created"] {
+                SiemField::Date(v) => assert_eq!(0, v % 60_000),
+                v => panic!("Expected a date: {:?}", v),
+            }
+            assert!(matches!(&row["day"], SiemField::Text(v) if v.len() == 10));
+        }
+        assert_eq!(
+            created.iter().filter(|v| **v <= created[1] - 500).count(),
+            rows.len()
+        );
+        assert_eq!(
+            SiemField::I64(120),
+            bin_value(&SiemField::I64(125), &SiemField::I64(10))
+        );
+        assert_eq!(
+            SiemField::Null,
+            bin_value(&SiemField::I64(125), &SiemField::I64(0))
+        );
+    }
+}
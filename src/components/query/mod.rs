@@ -1,3 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::types::LogString;
+
+pub mod ast;
+pub mod check;
+pub mod elastic;
+pub mod executor;
+pub mod parser;
+pub mod saved;
+pub mod sql;
+pub mod time;
+
+/// Error found parsing or running a query
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
+pub enum QueryError {
+    /// The query cannot be parsed: (Position of the character, Description)
+    InvalidSyntax(usize, LogString),
+    /// The query cannot be executed
+    Execution(LogString),
+    /// The query cannot be translated to the language of the database
+    Translation(LogString),
+    /// A parameter of a saved query is missing or its value is not valid
+    InvalidParameter(LogString),
+}
+
 pub struct QueryLexer {
     input: Vec<char>,
     pub position: usize,
@@ -5,7 +32,7 @@ pub struct QueryLexer {
     pub ch: char,
 }
 
-fn is_function(name: &str) -> bool {
+pub(crate) fn is_function(name: &str) -> bool {
     matches!(
         name,
         "to_number"
@@ -18,6 +45,9 @@ fn is_function(name: &str) -> bool {
             | "trim"
             | "to_integer"
             | "to_float"
+            | "bin"
+            | "strftime"
+            | "now"
     )
 }
 
@@ -54,13 +84,13 @@ impl QueryLexer {
             input,
             position: 0,
             read_position: 0,
-            ch: '0',
+            ch: '\0',
         }
     }
 
     pub fn read_char(&mut self) {
         if self.read_position >= self.input.len() {
-            self.ch = '0';
+            self.ch = '\0';
         } else {
             self.ch = self.input[self.read_position];
         }
@@ -145,7 +175,18 @@ impl QueryLexer {
 
         let read_number = |l: &mut QueryLexer| -> Vec<char> {
             let position = l.position;
-            while l.position < l.input.len() && l.ch.is_ascii_digit() {
+            let mut decimals = false;
+            while l.position < l.input.len() {
+                let next_is_digit = l
+                    .input
+                    .get(l.read_position)
+                    .map(|c| c.is_ascii_digit())
+                    .unwrap_or(false);
+                if l.ch == '.' && !decimals && next_is_digit {
+                    decimals = true;
+                } else if !l.ch.is_ascii_digit() {
+                    break;
+                }
                 l.read_char();
             }
             l.input[position..l.position].to_vec()
@@ -181,6 +222,21 @@ impl QueryLexer {
             '>' => {
                 tok = Token::GT(self.ch);
             }
+            '~' => {
+                tok = Token::TILDE(self.ch);
+            }
+            '@' => {
+                tok = Token::AT(self.ch);
+            }
+            '$' => {
+                // Parameters of saved queries: $user
+                self.read_char();
+                if !is_letter(self.ch) {
+                    return Token::ILLEGAL;
+                }
+                let ident: Vec<char> = read_identifier(self);
+                return Token::PARAMETER(ident.into_iter().collect());
+            }
             ';' => {
                 tok = Token::SEMICOLON(self.ch);
             }
@@ -199,17 +255,25 @@ impl QueryLexer {
             '}' => {
                 tok = Token::RBRACE(self.ch);
             }
-            '0' => {
+            '\0' => {
                 tok = Token::EOF;
             }
             '\'' => {
                 self.read_char();
                 let data = read_literal_string(self);
+                if self.position >= self.input.len() {
+                    // Unterminated string
+                    return Token::ILLEGAL;
+                }
                 tok = Token::String(data.iter().collect())
             }
             '"' => {
                 self.read_char();
                 let data = read_string(self);
+                if self.position >= self.input.len() {
+                    // Unterminated string
+                    return Token::ILLEGAL;
+                }
                 if data.len() > 1 {
                     let n_asterix = count_asterix(&data);
                     //Test if can be a start_with, contains, ends_with or like
@@ -221,9 +285,9 @@ impl QueryLexer {
                         if starts_astx && ends_astx {
                             tok = Token::Contains(data.iter().filter(|c| *c != &'*').collect())
                         } else if starts_astx {
-                            tok = Token::StartsWith(data.iter().filter(|c| *c != &'*').collect())
-                        } else if ends_astx {
                             tok = Token::EndsWith(data.iter().filter(|c| *c != &'*').collect())
+                        } else if ends_astx {
+                            tok = Token::StartsWith(data.iter().filter(|c| *c != &'*').collect())
                         } else if n_asterix == 0 {
                             tok = Token::String(data.iter().collect())
                         } else {
@@ -246,7 +310,21 @@ impl QueryLexer {
                         }
                     }
                 } else if self.ch.is_ascii_digit() {
-                    let ident: Vec<char> = read_number(self);
+                    let mut ident: Vec<char> = read_number(self);
+                    if ident.contains(&'.') {
+                        return Token::FLOAT(ident.into_iter().collect());
+                    }
+                    let next_is_letter = self
+                        .input
+                        .get(self.read_position)
+                        .map(|c| is_letter(*c) || c.is_ascii_digit())
+                        .unwrap_or(false);
+                    if matches!(self.ch, 's' | 'm' | 'h' | 'd' | 'w') && !next_is_letter {
+                        // Durations: 24h, 5m
+                        ident.push(self.ch);
+                        self.read_char();
+                        return Token::DURATION(ident.into_iter().collect());
+                    }
                     return Token::INT(ident.into_iter().collect());
                 } else {
                     return Token::ILLEGAL;
@@ -286,15 +364,31 @@ pub enum Token {
     SLASH(char),
     LT(char),
     GT(char),
+    TILDE(char),
+    AT(char),
     FILTER,
     FIELDS,
     AS,
+    STATS,
+    BY,
+    TOP,
+    DEDUP,
+    SORT,
+    HEAD,
     String(String),
     RegexField(String),
     StartsWith(String),
     EndsWith(String),
     Like(String),
     Contains(String),
+    /// Number with decimals
+    FLOAT(String),
+    /// Number with a time unit: `24h`
+    DURATION(String),
+    EARLIEST,
+    LATEST,
+    /// Placeholder of a saved query: `$user`
+    PARAMETER(String),
 }
 
 pub fn get_keyword_token(ident: &[char]) -> Result<Token, String> {
@@ -302,12 +396,20 @@ pub fn get_keyword_token(ident: &[char]) -> Result<Token, String> {
     match &identifier[..] {
         "true" => Ok(Token::TRUE),
         "false" => Ok(Token::FALSE),
-        "AND" => Ok(Token::AND),
-        "OR" => Ok(Token::OR),
-        "NOT" => Ok(Token::NOT),
+        "AND" | "and" => Ok(Token::AND),
+        "OR" | "or" => Ok(Token::OR),
+        "NOT" | "not" => Ok(Token::NOT),
         "filter" => Ok(Token::FILTER),
         "fields" => Ok(Token::FIELDS),
         "as" => Ok(Token::AS),
+        "stats" => Ok(Token::STATS),
+        "by" => Ok(Token::BY),
+        "top" => Ok(Token::TOP),
+        "dedup" => Ok(Token::DEDUP),
+        "sort" => Ok(Token::SORT),
+        "head" => Ok(Token::HEAD),
+        "earliest" => Ok(Token::EARLIEST),
+        "latest" => Ok(Token::LATEST),
         _ => {
             if is_function(&identifier) {
                 return Ok(Token::FUNCTION(identifier));
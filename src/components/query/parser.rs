@@ -0,0 +1,966 @@
+use crate::prelude::types::LogString;
+
+use super::ast::{
+    Aggregation, AggregationFunction, ComparisonOperator, FieldProjection, Query, QueryExpression,
+    QueryFunction, QueryLiteral, QueryStage, SortField, DEFAULT_STAGE_LIMIT,
+};
+use super::time::{parse_duration, QueryTime, RelativeTime, TimeUnit};
+use super::{QueryError, QueryLexer, Token};
+
+impl Query {
+    /// Parses the query. `NOT` binds tighter than `AND`, that binds tighter than `OR`.
+    /// The positions of the errors are in characters, not bytes.
+    pub fn parse(query: &str) -> Result<Query, QueryError> {
+        let chars: Vec<char> = query.chars().collect();
+        let tokens = tokenize(&chars)?;
+        let mut parser = QueryParser {
+            chars,
+            tokens,
+            position: 0,
+        };
+        let mut query = Query {
+            stages: Vec::new(),
+            earliest: None,
+            latest: None,
+        };
+        while matches!(parser.peek(), Some(Token::EARLIEST) | Some(Token::LATEST)) {
+            let is_earliest = parser.peek() == Some(&Token::EARLIEST);
+            parser.position += 1;
+            if parser.peek() != Some(&Token::ASSIGN) {
+                return Err(parser.error("Expected '='"));
+            }
+            parser.position += 1;
+            let time = parser.parse_query_time()?;
+            if is_earliest {
+                query.earliest = Some(time);
+            } else {
+                query.latest = Some(time);
+            }
+        }
+        if query.earliest.is_some() || query.latest.is_some() {
+            // Only the time range
+            match parser.peek() {
+                None => return Ok(query),
+                Some(Token::PIPE) => parser.position += 1,
+                _ => {}
+            }
+        }
+        query.stages.push(parser.parse_stage()?);
+        while parser.peek() == Some(&Token::PIPE) {
+            parser.position += 1;
+            query.stages.push(parser.parse_stage()?);
+        }
+        if parser.peek().is_some() {
+            return Err(parser.error("Expected '|' or the end of the query"));
+        }
+        Ok(query)
+    }
+}
+
+pub(super) fn tokenize(chars: &[char]) -> Result<Vec<(usize, Token)>, QueryError> {
+    let mut lexer = QueryLexer::new(chars.to_vec());
+    lexer.read_char();
+    let mut tokens = Vec::new();
+    loop {
+        lexer.skip_whitespace();
+        let position = lexer.position;
+        match lexer.next_token() {
+            Token::EOF => return Ok(tokens),
+            Token::ILLEGAL => {
+                let message = match chars.get(position) {
+                    Some('"') | Some('\'') => String::from("Unterminated string"),
+                    Some(ch) => format!("Unexpected character '{}'", ch),
+                    None => String::from("Unexpected end of the query"),
+                };
+                return Err(QueryError::InvalidSyntax(
+                    position,
+                    LogString::Owned(message),
+                ));
+            }
+            token => tokens.push((position, token)),
+        }
+    }
+}
+
+fn describe(token: Option<&Token>) -> String {
+    let token = match token {
+        Some(v) => v,
+        None => return String::from("the end of the query"),
+    };
+    match token {
+        Token::FIELD(v) => format!("field '{}'", v),
+        Token::FUNCTION(v) => format!("function '{}'", v),
+        Token::INT(v) | Token::FLOAT(v) => format!("number {}", v),
+        Token::String(v) => format!("text '{}'", v),
+        Token::StartsWith(_) | Token::EndsWith(_) | Token::Contains(_) | Token::Like(_) => {
+            String::from("a wildcard text")
+        }
+        Token::ASSIGN => String::from("'='"),
+        Token::PIPE => String::from("'|'"),
+        Token::TRUE => String::from("true"),
+        Token::FALSE => String::from("false"),
+        Token::AND => String::from("AND"),
+        Token::OR => String::from("OR"),
+        Token::NOT => String::from("NOT"),
+        Token::FILTER => String::from("filter"),
+        Token::FIELDS => String::from("fields"),
+        Token::AS => String::from("as"),
+        Token::STATS => String::from("stats"),
+        Token::BY => String::from("by"),
+        Token::TOP => String::from("top"),
+        Token::DEDUP => String::from("dedup"),
+        Token::SORT => String::from("sort"),
+        Token::HEAD => String::from("head"),
+        Token::PLUS(c)
+        | Token::MINUS(c)
+        | Token::COMMA(c)
+        | Token::SEMICOLON(c)
+        | Token::LPAREN(c)
+        | Token::RPAREN(c)
+        | Token::LBRACE(c)
+        | Token::RBRACE(c)
+        | Token::BANG(c)
+        | Token::ASTERISK(c)
+        | Token::SLASH(c)
+        | Token::LT(c)
+        | Token::GT(c)
+        | Token::TILDE(c)
+        | Token::AT(c) => format!("'{}'", c),
+        Token::DURATION(v) => format!("duration {}", v),
+        Token::PARAMETER(v) => format!("parameter '${}'", v),
+        Token::EARLIEST => String::from("earliest"),
+        Token::LATEST => String::from("latest"),
+        token => format!("{:?}", token),
+    }
+}
+
+struct QueryParser {
+    /// Characters of the query. Regex literals are read again without the wildcard processing.
+    chars: Vec<char>,
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    /// Character position of the current token
+    fn token_position(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.chars.len())
+    }
+
+    fn error(&self, message: &str) -> QueryError {
+        QueryError::InvalidSyntax(
+            self.token_position(),
+            LogString::Owned(format!("{} but found {}", message, describe(self.peek()))),
+        )
+    }
+
+    fn parse_stage(&mut self) -> Result<QueryStage, QueryError> {
+        match self.peek() {
+            Some(Token::FILTER) => {
+                self.position += 1;
+                Ok(QueryStage::Filter(self.parse_or()?))
+            }
+            Some(Token::FIELDS) => {
+                self.position += 1;
+                let mut projections = vec![self.parse_projection()?];
+                while matches!(self.peek(), Some(Token::COMMA(_))) {
+                    self.position += 1;
+                    projections.push(self.parse_projection()?);
+                }
+                Ok(QueryStage::Fields(projections))
+            }
+            Some(Token::STATS) => {
+                self.position += 1;
+                let mut aggregations = vec![self.parse_aggregation()?];
+                while matches!(self.peek(), Some(Token::COMMA(_))) {
+                    self.position += 1;
+                    aggregations.push(self.parse_aggregation()?);
+                }
+                let by = if self.peek() == Some(&Token::BY) {
+                    self.position += 1;
+                    self.parse_field_list()?
+                } else {
+                    Vec::new()
+                };
+                Ok(QueryStage::Stats { aggregations, by })
+            }
+            Some(Token::TOP) => {
+                self.position += 1;
+                let limit = self.parse_limit()?;
+                Ok(QueryStage::Top {
+                    limit,
+                    fields: self.parse_field_list()?,
+                })
+            }
+            Some(Token::DEDUP) => {
+                self.position += 1;
+                Ok(QueryStage::Dedup(self.parse_field_list()?))
+            }
+            Some(Token::SORT) => {
+                self.position += 1;
+                let mut fields = vec![self.parse_sort_field()?];
+                while matches!(self.peek(), Some(Token::COMMA(_))) {
+                    self.position += 1;
+                    fields.push(self.parse_sort_field()?);
+                }
+                Ok(QueryStage::Sort(fields))
+            }
+            Some(Token::HEAD) => {
+                self.position += 1;
+                Ok(QueryStage::Head(self.parse_limit()?))
+            }
+            _ => {
+                Err(self.error("Expected a stage (filter, fields, stats, top, dedup, sort, head)"))
+            }
+        }
+    }
+
+    /// Field names can also be the name of a function
+    fn parse_field_name(&mut self) -> Result<String, QueryError> {
+        let name = match self.peek() {
+            Some(Token::FIELD(name)) | Some(Token::FUNCTION(name)) => name.clone(),
+            _ => return Err(self.error("Expected the name of a field")),
+        };
+        self.position += 1;
+        Ok(name)
+    }
+
+    fn parse_field_list(&mut self) -> Result<Vec<String>, QueryError> {
+        let mut fields = vec![self.parse_field_name()?];
+        while matches!(self.peek(), Some(Token::COMMA(_))) {
+            self.position += 1;
+            fields.push(self.parse_field_name()?);
+        }
+        Ok(fields)
+    }
+
+    /// Optional number of rows of `top` and `head`
+    fn parse_limit(&mut self) -> Result<usize, QueryError> {
+        let limit = match self.peek() {
+            Some(Token::INT(v)) => match v.parse::<usize>() {
+                Ok(v) => v,
+                Err(_) => return Err(self.error("Expected a valid number of rows")),
+            },
+            _ => return Ok(DEFAULT_STAGE_LIMIT),
+        };
+        self.position += 1;
+        Ok(limit)
+    }
+
+    fn parse_sort_field(&mut self) -> Result<SortField, QueryError> {
+        let descending = match self.peek() {
+            Some(Token::MINUS(_)) => {
+                self.position += 1;
+                true
+            }
+            Some(Token::PLUS(_)) => {
+                self.position += 1;
+                false
+            }
+            _ => false,
+        };
+        Ok(SortField {
+            field: self.parse_field_name()?,
+            descending,
+        })
+    }
+
+    /// `count()`, `sum(field) as total`...
+    fn parse_aggregation(&mut self) -> Result<Aggregation, QueryError> {
+        let position = self.token_position();
+        let function = match self.peek() {
+            Some(Token::FIELD(name)) => AggregationFunction::from_name(name),
+            _ => None,
+        };
+        let function =
+            match function {
+                Some(v)
+                    if matches!(
+                        self.tokens.get(self.position + 1),
+                        Some((_, Token::LPAREN(_)))
+                    ) =>
+                {
+                    v
+                }
+                _ => return Err(self.error(
+                    "Expected an aggregation (count, distinct_count, sum, avg, min, max, values)",
+                )),
+            };
+        // Name and opening parenthesis
+        self.position += 2;
+        let field = if matches!(self.peek(), Some(Token::RPAREN(_))) {
+            None
+        } else {
+            Some(self.parse_field_name()?)
+        };
+        if !matches!(self.peek(), Some(Token::RPAREN(_))) {
+            return Err(self.error(&format!(
+                "Expected ')' in the aggregation {}",
+                function.name()
+            )));
+        }
+        self.position += 1;
+        if field.is_none() && function != AggregationFunction::Count {
+            return Err(QueryError::InvalidSyntax(
+                position,
+                LogString::Owned(format!("Aggregation {} needs a field", function.name())),
+            ));
+        }
+        let alias = if self.peek() == Some(&Token::AS) {
+            self.position += 1;
+            Some(self.parse_field_name()?)
+        } else {
+            None
+        };
+        Ok(Aggregation {
+            function,
+            field,
+            alias,
+        })
+    }
+
+    fn parse_projection(&mut self) -> Result<FieldProjection, QueryError> {
+        let expression = self.parse_value()?;
+        if self.peek() != Some(&Token::AS) {
+            return Ok(FieldProjection {
+                expression,
+                alias: None,
+            });
+        }
+        self.position += 1;
+        let alias = match self.peek() {
+            Some(Token::FIELD(name)) | Some(Token::FUNCTION(name)) => name.clone(),
+            _ => return Err(self.error("Expected the name of the alias")),
+        };
+        self.position += 1;
+        Ok(FieldProjection {
+            expression,
+            alias: Some(alias),
+        })
+    }
+
+    fn parse_or(&mut self) -> Result<QueryExpression, QueryError> {
+        let mut list = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::OR) {
+            self.position += 1;
+            list.push(self.parse_and()?);
+        }
+        Ok(if list.len() == 1 {
+            list.remove(0)
+        } else {
+            QueryExpression::Or(list)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<QueryExpression, QueryError> {
+        let mut list = vec![self.parse_not()?];
+        while self.peek() == Some(&Token::AND) {
+            self.position += 1;
+            list.push(self.parse_not()?);
+        }
+        Ok(if list.len() == 1 {
+            list.remove(0)
+        } else {
+            QueryExpression::And(list)
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<QueryExpression, QueryError> {
+        if self.peek() == Some(&Token::NOT) {
+            self.position += 1;
+            return Ok(QueryExpression::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<QueryExpression, QueryError> {
+        let left = self.parse_value()?;
+        let operator = match self.peek() {
+            Some(Token::ASSIGN) => ComparisonOperator::Equals,
+            Some(Token::BANG(_)) => {
+                self.position += 1;
+                if self.peek() != Some(&Token::ASSIGN) {
+                    return Err(self.error("Expected '=' after '!'"));
+                }
+                ComparisonOperator::NotEquals
+            }
+            Some(Token::LT(_)) => {
+                if self.next_is_assign() {
+                    self.position += 1;
+                    ComparisonOperator::LessOrEqual
+                } else {
+                    ComparisonOperator::LessThan
+                }
+            }
+            Some(Token::GT(_)) => {
+                if self.next_is_assign() {
+                    self.position += 1;
+                    ComparisonOperator::GreaterOrEqual
+                } else {
+                    ComparisonOperator::GreaterThan
+                }
+            }
+            Some(Token::TILDE(_)) => {
+                self.position += 1;
+                if self.peek() != Some(&Token::ASSIGN) {
+                    return Err(self.error("Expected '=' after '~'"));
+                }
+                self.position += 1;
+                let regex = self.parse_regex()?;
+                return Ok(QueryExpression::Comparison(
+                    Box::new(left),
+                    ComparisonOperator::Equals,
+                    Box::new(regex),
+                ));
+            }
+            // A value by itself, like a boolean field
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let position = self.token_position();
+        let right = self.parse_value()?;
+        if is_pattern(&right)
+            && !matches!(
+                operator,
+                ComparisonOperator::Equals | ComparisonOperator::NotEquals
+            )
+        {
+            return Err(QueryError::InvalidSyntax(
+                position,
+                LogString::Borrowed("Wildcards can only be used with = and !="),
+            ));
+        }
+        Ok(QueryExpression::Comparison(
+            Box::new(left),
+            operator,
+            Box::new(right),
+        ))
+    }
+
+    fn next_is_assign(&self) -> bool {
+        matches!(self.tokens.get(self.position + 1), Some((_, Token::ASSIGN)))
+    }
+
+    /// Text of the string after `~=` without applying the wildcards
+    fn parse_regex(&mut self) -> Result<QueryExpression, QueryError> {
+        let position = self.token_position();
+        let pattern = match self.peek() {
+            Some(Token::String(_))
+            | Some(Token::StartsWith(_))
+            | Some(Token::EndsWith(_))
+            | Some(Token::Contains(_))
+            | Some(Token::Like(_)) => raw_string(&self.chars, position),
+            _ => return Err(self.error("Expected a regular expression between quotes")),
+        };
+        if let Err(err) = regex::Regex::new(&pattern) {
+            return Err(QueryError::InvalidSyntax(
+                position,
+                LogString::Owned(format!("Invalid regular expression: {}", err)),
+            ));
+        }
+        self.position += 1;
+        Ok(QueryExpression::Literal(QueryLiteral::Regex(pattern)))
+    }
+
+    fn parse_value(&mut self) -> Result<QueryExpression, QueryError> {
+        let position = self.token_position();
+        let value = match self.peek() {
+            Some(Token::LPAREN(_)) => {
+                self.position += 1;
+                let expression = self.parse_or()?;
+                if !matches!(self.peek(), Some(Token::RPAREN(_))) {
+                    return Err(self.error(&format!(
+                        "Expected ')' to close the '(' at position {}",
+                        position
+                    )));
+                }
+                self.position += 1;
+                return Ok(expression);
+            }
+            Some(Token::FUNCTION(name)) if name == "now" => {
+                self.position += 1;
+                if matches!(self.peek(), Some(Token::LPAREN(_))) {
+                    self.position += 1;
+                    if !matches!(self.peek(), Some(Token::RPAREN(_))) {
+                        return Err(self.error("Function now expects 0 arguments"));
+                    }
+                    self.position += 1;
+                }
+                return self.parse_time();
+            }
+            Some(Token::MINUS(_)) | Some(Token::PLUS(_))
+                if matches!(
+                    self.tokens.get(self.position + 1),
+                    Some((_, Token::DURATION(_)))
+                ) =>
+            {
+                return self.parse_time();
+            }
+            Some(Token::AT(_)) => return self.parse_time(),
+            Some(Token::DURATION(v)) => match parse_duration(v) {
+                Some(v) => QueryExpression::Literal(QueryLiteral::Integer(v)),
+                None => return Err(self.error("Expected a valid duration")),
+            },
+            Some(Token::FUNCTION(name)) => {
+                if matches!(
+                    self.tokens.get(self.position + 1),
+                    Some((_, Token::LPAREN(_)))
+                ) {
+                    return self.parse_function();
+                }
+                // Fields can have the name of a function
+                QueryExpression::Field(name.clone())
+            }
+            Some(Token::FIELD(name)) => QueryExpression::Field(name.clone()),
+            Some(Token::MINUS(_)) => {
+                self.position += 1;
+                return match self.parse_value()? {
+                    QueryExpression::Literal(QueryLiteral::Integer(v)) => {
+                        Ok(QueryExpression::Literal(QueryLiteral::Integer(-v)))
+                    }
+                    QueryExpression::Literal(QueryLiteral::Float(v)) => {
+                        Ok(QueryExpression::Literal(QueryLiteral::Float(-v)))
+                    }
+                    _ => Err(QueryError::InvalidSyntax(
+                        position,
+                        LogString::Borrowed("Expected a number after '-'"),
+                    )),
+                };
+            }
+            Some(Token::INT(v)) => match v.parse::<i64>() {
+                Ok(v) => QueryExpression::Literal(QueryLiteral::Integer(v)),
+                Err(_) => return Err(self.error("Expected a valid integer")),
+            },
+            Some(Token::FLOAT(v)) => match v.parse::<f64>() {
+                Ok(v) => QueryExpression::Literal(QueryLiteral::Float(v)),
+                Err(_) => return Err(self.error("Expected a valid number")),
+            },
+            Some(Token::String(v)) => QueryExpression::Literal(QueryLiteral::Text(v.clone())),
+            Some(Token::StartsWith(v)) => {
+                QueryExpression::Literal(QueryLiteral::StartsWith(v.clone()))
+            }
+            Some(Token::EndsWith(v)) => QueryExpression::Literal(QueryLiteral::EndsWith(v.clone())),
+            Some(Token::Contains(v)) => QueryExpression::Literal(QueryLiteral::Contains(v.clone())),
+            Some(Token::Like(v)) => QueryExpression::Literal(QueryLiteral::Like(v.clone())),
+            Some(Token::TRUE) => QueryExpression::Literal(QueryLiteral::Boolean(true)),
+            Some(Token::FALSE) => QueryExpression::Literal(QueryLiteral::Boolean(false)),
+            _ => return Err(self.error("Expected a field, a value or a function")),
+        };
+        self.position += 1;
+        Ok(value)
+    }
+
+    /// Offsets and snapping after `now()`: `-1d+2h@h`
+    fn parse_time(&mut self) -> Result<QueryExpression, QueryError> {
+        let mut time = RelativeTime {
+            offset: 0,
+            snap: None,
+        };
+        loop {
+            let sign = match self.peek() {
+                Some(Token::PLUS(_)) => 1,
+                Some(Token::MINUS(_)) => -1,
+                _ => break,
+            };
+            let millis = match self.tokens.get(self.position + 1) {
+                Some((_, Token::DURATION(v))) => parse_duration(v),
+                _ => break,
+            };
+            self.position += 1;
+            match millis {
+                Some(millis) => time.offset = time.offset.saturating_add(sign * millis),
+                None => return Err(self.error("Expected a valid duration")),
+            }
+            self.position += 1;
+        }
+        if matches!(self.peek(), Some(Token::AT(_))) {
+            self.position += 1;
+            let unit = match self.peek() {
+                Some(Token::FIELD(name)) => TimeUnit::from_name(name),
+                _ => None,
+            };
+            match unit {
+                Some(unit) => time.snap = Some(unit),
+                None => return Err(self.error("Expected a time unit (s, m, h, d, w) after '@'")),
+            }
+            self.position += 1;
+        }
+        Ok(QueryExpression::Time(time))
+    }
+
+    /// Value of `earliest` and `latest`
+    fn parse_query_time(&mut self) -> Result<QueryTime, QueryError> {
+        let position = self.token_position();
+        if let Some(Token::INT(v)) = self.peek() {
+            if let Ok(time) = v.parse::<i64>() {
+                self.position += 1;
+                return Ok(QueryTime::Absolute(time));
+            }
+        }
+        match self.parse_value() {
+            Ok(QueryExpression::Time(time)) => Ok(QueryTime::Relative(time)),
+            _ => Err(QueryError::InvalidSyntax(
+                position,
+                LogString::Borrowed("Expected a time: now, -24h, @d or milliseconds from 1970"),
+            )),
+        }
+    }
+
+    fn parse_function(&mut self) -> Result<QueryExpression, QueryError> {
+        let position = self.token_position();
+        let function = match self.peek() {
+            Some(Token::FUNCTION(name)) => match QueryFunction::from_name(name) {
+                Some(v) => v,
+                None => return Err(self.error("Expected a known function")),
+            },
+            _ => return Err(self.error("Expected a function")),
+        };
+        // Name and opening parenthesis
+        self.position += 2;
+        let mut args = Vec::new();
+        if !matches!(self.peek(), Some(Token::RPAREN(_))) {
+            args.push(self.parse_or()?);
+            while matches!(self.peek(), Some(Token::COMMA(_))) {
+                self.position += 1;
+                args.push(self.parse_or()?);
+            }
+        }
+        if !matches!(self.peek(), Some(Token::RPAREN(_))) {
+            return Err(self.error(&format!(
+                "Expected ',' or ')' in the arguments of {}",
+                function.name()
+            )));
+        }
+        self.position += 1;
+        let (min, max) = function.arity();
+        if args.len() < min || args.len() > max {
+            let expected = if min == max {
+                min.to_string()
+            } else {
+                format!("{} to {}", min, max)
+            };
+            return Err(QueryError::InvalidSyntax(
+                position,
+                LogString::Owned(format!(
+                    "Function {} expects {} arguments but got {}",
+                    function.name(),
+                    expected,
+                    args.len()
+                )),
+            ));
+        }
+        Ok(QueryExpression::Function(function, args))
+    }
+}
+
+fn is_pattern(expression: &QueryExpression) -> bool {
+    matches!(
+        expression,
+        QueryExpression::Literal(
+            QueryLiteral::StartsWith(_)
+                | QueryLiteral::EndsWith(_)
+                | QueryLiteral::Contains(_)
+                | QueryLiteral::Like(_)
+                | QueryLiteral::Regex(_)
+        )
+    )
+}
+
+/// Content of the quoted string that starts at the position. Only the quote and the backslash are escaped.
+fn raw_string(chars: &[char], position: usize) -> String {
+    let quote = chars[position];
+    let mut text = String::new();
+    let mut escaped = false;
+    for ch in chars.iter().skip(position + 1) {
+        if escaped {
+            if *ch != quote && *ch != '\\' {
+                text.push('\\');
+            }
+            text.push(*ch);
+            escaped = false;
+        } else if *ch == '\\' {
+            escaped = true;
+        } else if *ch == quote {
+            break;
+        } else {
+            text.push(*ch);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str) -> QueryExpression {
+        QueryExpression::Field(name.to_string())
+    }
+
+    fn literal(literal: QueryLiteral) -> QueryExpression {
+        QueryExpression::Literal(literal)
+    }
+
+    fn compare(
+        left: QueryExpression,
+        operator: ComparisonOperator,
+        right: QueryExpression,
+    ) -> QueryExpression {
+        QueryExpression::Comparison(Box::new(left), operator, Box::new(right))
+    }
+
+    #[test]
+    fn should_parse_queries() {
+        let query = Query::parse("filter field_name2=\"*something\" | fields os.actor_process as osap | filter to_string(osap,'something') = \"12345\"").unwrap();
+        assert_eq!(
+            vec![
+                QueryStage::Filter(compare(
+                    field("field_name2"),
+                    ComparisonOperator::Equals,
+                    literal(QueryLiteral::EndsWith("something".into()))
+                )),
+                QueryStage::Fields(vec![FieldProjection {
+                    expression: field("os.actor_process"),
+                    alias: Some("osap".into()),
+                }]),
+                QueryStage::Filter(compare(
+                    QueryExpression::Function(
+                        QueryFunction::ToString,
+                        vec![
+                            field("osap"),
+                            literal(QueryLiteral::Text("something".into()))
+                        ]
+                    ),
+                    ComparisonOperator::Equals,
+                    literal(QueryLiteral::Text("12345".into()))
+                )),
+            ],
+            query.stages
+        );
+
+        let query = Query::parse(
+            "filter a = 1 OR b >= -2.5 AND NOT (c != \"x*\" or d ~= \"^C:\\\\\\\\.*\\.exe$\") | fields len(e), f",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                QueryStage::Filter(QueryExpression::Or(vec![
+                    compare(
+                        field("a"),
+                        ComparisonOperator::Equals,
+                        literal(QueryLiteral::Integer(1))
+                    ),
+                    QueryExpression::And(vec![
+                        compare(
+                            field("b"),
+                            ComparisonOperator::GreaterOrEqual,
+                            literal(QueryLiteral::Float(-2.5))
+                        ),
+                        QueryExpression::Not(Box::new(QueryExpression::Or(vec![
+                            compare(
+                                field("c"),
+                                ComparisonOperator::NotEquals,
+                                literal(QueryLiteral::StartsWith("x".into()))
+                            ),
+                            compare(
+                                field("d"),
+                                ComparisonOperator::Equals,
+                                literal(QueryLiteral::Regex("^C:\\\\.*\\.exe$".into()))
+                            ),
+                        ]))),
+                    ]),
+                ])),
+                QueryStage::Fields(vec![
+                    FieldProjection {
+                        expression: QueryExpression::Function(QueryFunction::Len, vec![field("e")]),
+                        alias: None,
+                    },
+                    FieldProjection {
+                        expression: field("f"),
+                        alias: None,
+                    },
+                ]),
+            ],
+            query.stages
+        );
+        if let QueryStage::Fields(projections) = &query.stages[1] {
+            assert_eq!("len(e)", projections[0].name());
+        }
+        let query =
+            Query::parse("stats count(), sum(bytes) as total by source.ip, host | sort -total, +host | top 5 host | dedup host | head")
+                .unwrap();
+        assert_eq!(
+            vec![
+                QueryStage::Stats {
+                    aggregations: vec![
+                        Aggregation {
+                            function: AggregationFunction::Count,
+                            field: None,
+                            alias: None,
+                        },
+                        Aggregation {
+                            function: AggregationFunction::Sum,
+                            field: Some("bytes".into()),
+                            alias: Some("total".into()),
+                        },
+                    ],
+                    by: vec!["source.ip".into(), "host".into()],
+                },
+                QueryStage::Sort(vec![
+                    SortField {
+                        field: "total".into(),
+                        descending: true,
+                    },
+                    SortField {
+                        field: "host".into(),
+                        descending: false,
+                    },
+                ]),
+                QueryStage::Top {
+                    limit: 5,
+                    fields: vec!["host".into()],
+                },
+                QueryStage::Dedup(vec!["host".into()]),
+                QueryStage::Head(DEFAULT_STAGE_LIMIT),
+            ],
+            query.stages
+        );
+        if let QueryStage::Stats { aggregations, .. } = &query.stages[0] {
+            assert_eq!("count()", aggregations[0].name());
+        }
+        // Number zero is not the end of the query
+        assert_eq!(
+            QueryStage::Filter(compare(
+                field("a"),
+                ComparisonOperator::LessThan,
+                literal(QueryLiteral::Integer(0))
+            )),
+            Query::parse("filter a < 0").unwrap().stages[0]
+        );
+    }
+
+    #[test]
+    fn should_report_the_position_of_errors() {
+        let error = |query: &str| match Query::parse(query) {
+            Err(QueryError::InvalidSyntax(position, message)) => (position, message.to_string()),
+            Ok(_) => panic!("The query {} must not be valid", query),
+            Err(err) => panic!("Unexpected error {:?}", err),
+        };
+        assert_eq!(
+            (
+                13,
+                "Expected ')' to close the '(' at position 7 but found the end of the query"
+                    .to_string()
+            ),
+            error("filter (a = 1")
+        );
+        assert_eq!(
+            (
+                11,
+                "Expected a field, a value or a function but found '|'".to_string()
+            ),
+            error("filter a = | fields b")
+        );
+        assert_eq!(
+            (11, "Unterminated string".to_string()),
+            error("filter a = \"abc")
+        );
+        assert_eq!(
+            (9, "Unexpected character '#'".to_string()),
+            error("filter a #")
+        );
+        assert_eq!(
+            (
+                7,
+                "Function replace expects 3 arguments but got 1".to_string()
+            ),
+            error("filter replace(a) = 1")
+        );
+        assert_eq!(
+            (11, "Wildcards can only be used with = and !=".to_string()),
+            error("filter a > \"*b\"")
+        );
+        assert_eq!(
+            (
+                0,
+                "Expected a stage (filter, fields, stats, top, dedup, sort, head) but found field 'select'"
+                    .to_string()
+            ),
+            error("select a")
+        );
+        assert_eq!(
+            (6, "Aggregation sum needs a field".to_string()),
+            error("stats sum()")
+        );
+        assert_eq!(
+            (
+                6,
+                "Expected an aggregation (count, distinct_count, sum, avg, min, max, values) but found field 'total'"
+                    .to_string()
+            ),
+            error("stats total by host")
+        );
+        assert!(error("filter a ~= \"(\"")
+            .1
+            .starts_with("Invalid regular expression"));
+    }
+
+    #[test]
+    fn should_parse_time_ranges() {
+        let time = |offset, snap| RelativeTime { offset, snap };
+        let query = Query::parse("earliest=-24h@h latest=now | stats count()").unwrap();
+        assert_eq!(
+            Some(QueryTime::Relative(time(
+                -24 * 3_600_000,
+                Some(TimeUnit::Hour)
+            ))),
+            query.earliest
+        );
+        assert_eq!(Some(QueryTime::Relative(time(0, None))), query.latest);
+        assert_eq!(1, query.stages.len());
+
+        let query = Query::parse("earliest=1700000000000").unwrap();
+        assert_eq!(Some(QueryTime::Absolute(1_700_000_000_000)), query.earliest);
+        assert!(query.stages.is_empty());
+
+        assert_eq!(
+            QueryStage::Filter(compare(
+                field("event.created"),
+                ComparisonOperator::GreaterThan,
+                QueryExpression::Time(time(-86_400_000 + 3_600_000, Some(TimeUnit::Day)))
+            )),
+            Query::parse("filter event.created > now()-1d+1h@d")
+                .unwrap()
+                .stages[0]
+        );
+        assert_eq!(
+            QueryStage::Fields(vec![FieldProjection {
+                expression: QueryExpression::Function(
+                    QueryFunction::Bin,
+                    vec![
+                        field("event.created"),
+                        literal(QueryLiteral::Integer(300_000))
+                    ]
+                ),
+                alias: Some("minute".to_string()),
+            }]),
+            Query::parse("fields bin(event.created, 5m) as minute")
+                .unwrap()
+                .stages[0]
+        );
+        match Query::parse("filter event.created > now()@y") {
+            Err(QueryError::InvalidSyntax(29, message)) => assert_eq!(
+                "Expected a time unit (s, m, h, d, w) after '@' but found field 'y'",
+                message
+            ),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::types::LogString;
+use crate::prelude::{QueryInfo, SiemIp, UserRole};
+
+use super::ast::Query;
+use super::parser::tokenize;
+use super::time::{QueryClock, QueryTime};
+use super::{QueryError, Token};
+
+/// Named query stored by a user, with placeholders for the values: `filter user.name = $user AND source.ip = $ip`
+///
+/// The values are bound as literals of the declared type, so they can never change the structure of the query.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedQuery {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// The user that saved the query
+    pub owner: String,
+    /// Minimum role needed to list and run the query
+    pub required_role: UserRole,
+    /// Query with the parameters as `$name`
+    pub query: String,
+    pub parameters: Vec<QueryParameter>,
+    /// Default time range, used when the `LOG_QUERY` leaves `from` as 0 or `to` as `i64::MAX`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub earliest: Option<QueryTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest: Option<QueryTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueryParameter {
+    /// Name without the `$`
+    pub name: String,
+    pub kind: QueryParameterType,
+    pub description: String,
+    /// Value used when the parameter is not bound
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryParameterType {
+    /// Exact text: quotes and wildcards are not interpreted
+    Text,
+    Integer,
+    Float,
+    Boolean,
+    Ip,
+}
+
+impl SavedQuery {
+    /// Query that can be run by analysts, without parameters or time range
+    pub fn new<S: Into<String>>(id: S, name: S, owner: S, query: S) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+            description: String::new(),
+            owner: owner.into(),
+            required_role: UserRole::Analyst,
+            query: query.into(),
+            parameters: Vec::new(),
+            earliest: None,
+            latest: None,
+        }
+    }
+
+    /// The role can list and run the query
+    pub fn allows(&self, role: &UserRole) -> bool {
+        *role >= self.required_role
+    }
+
+    /// Text of the query with the parameters replaced by their values or defaults.
+    /// Unknown parameters, missing values and values that do not match the type are rejected.
+    pub fn bind(&self, values: &BTreeMap<String, String>) -> Result<String, QueryError> {
+        if let Some(name) = values
+            .keys()
+            .find(|name| !self.parameters.iter().any(|v| v.name == **name))
+        {
+            return Err(parameter_error(format!("Unknown parameter ${}", name)));
+        }
+        let chars: Vec<char> = self.query.chars().collect();
+        let mut bound = String::with_capacity(self.query.len());
+        let mut last = 0;
+        for (position, token) in tokenize(&chars)? {
+            let name = match token {
+                Token::PARAMETER(name) => name,
+                _ => continue,
+            };
+            let parameter = match self.parameters.iter().find(|v| v.name == name) {
+                Some(v) => v,
+                None => {
+                    return Err(parameter_error(format!(
+                        "The parameter ${} is not declared",
+                        name
+                    )))
+                }
+            };
+            let value = match values.get(&name).or(parameter.default.as_ref()) {
+                Some(v) => v,
+                None => {
+                    return Err(parameter_error(format!(
+                        "The parameter ${} has no value",
+                        name
+                    )))
+                }
+            };
+            bound.extend(&chars[last..position]);
+            bound.push_str(&parameter.kind.literal(&name, value)?);
+            last = position + 1 + name.chars().count();
+        }
+        bound.extend(&chars[last..]);
+        Query::parse(&bound)?;
+        Ok(bound)
+    }
+
+    /// `LOG_QUERY` that runs the saved query referenced by the `QueryInfo`: the parameters are bound
+    /// and the default time range replaces the `from` and `to` not set by the caller.
+    pub fn query_info(&self, info: &QueryInfo, clock: QueryClock) -> Result<QueryInfo, QueryError> {
+        if info.saved_query.as_deref() != Some(self.id.as_str()) {
+            return Err(parameter_error(format!(
+                "The query does not reference the saved query {}",
+                self.id
+            )));
+        }
+        let mut bound = info.clone();
+        bound.query = self.bind(&info.parameters)?;
+        // Saved queries are always written in the query language of uSIEM
+        bound.is_native = false;
+        bound.saved_query = None;
+        bound.parameters = BTreeMap::new();
+        let now = clock.now();
+        match &self.earliest {
+            Some(earliest) if bound.from == 0 => bound.from = earliest.resolve(now),
+            _ => {}
+        }
+        match &self.latest {
+            Some(latest) if bound.to == i64::MAX => bound.to = latest.resolve(now),
+            _ => {}
+        }
+        Ok(bound)
+    }
+}
+
+impl QueryParameterType {
+    /// Value written as a literal of the query
+    fn literal(&self, name: &str, value: &str) -> Result<String, QueryError> {
+        let invalid = || {
+            parameter_error(format!(
+                "The value of ${} is not a valid {}",
+                name,
+                self.name()
+            ))
+        };
+        Ok(match self {
+            QueryParameterType::Text => quote(value),
+            QueryParameterType::Integer => value
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| invalid())?
+                .to_string(),
+            // Always written with a decimal point and without exponent, like the query language reads them
+            QueryParameterType::Float => match value.trim().parse::<f64>() {
+                Ok(v) if v.is_finite() => {
+                    let mut literal = v.to_string();
+                    if !literal.contains('.') {
+                        literal.push_str(".0");
+                    }
+                    literal
+                }
+                _ => return Err(invalid()),
+            },
+            QueryParameterType::Boolean => match value.trim() {
+                "true" => String::from("true"),
+                "false" => String::from("false"),
+                _ => return Err(invalid()),
+            },
+            QueryParameterType::Ip => match SiemIp::from_ip_str(value.trim()) {
+                Ok(_) => quote(value.trim()),
+                Err(_) => return Err(invalid()),
+            },
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryParameterType::Text => "text",
+            QueryParameterType::Integer => "integer",
+            QueryParameterType::Float => "number",
+            QueryParameterType::Boolean => "boolean",
+            QueryParameterType::Ip => "IP",
+        }
+    }
+}
+
+/// Single quoted strings are never wildcards
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for ch in value.chars() {
+        if ch == '\\' || ch == '\'' {
+            quoted.push('\\');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+fn parameter_error(message: String) -> QueryError {
+    QueryError::InvalidParameter(LogString::Owned(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::SiemField;
+
+    fn saved_query() -> SavedQuery {
+        let mut saved = SavedQuery::new(
+            "failed-logins",
+            "Failed logins",
+            "admin",
+            "filter user.name = $user AND event.code = $code | fields user.name",
+        );
+        saved.parameters = vec![
+            QueryParameter {
+                name: "user".to_string(),
+                kind: QueryParameterType::Text,
+                description: "User name".to_string(),
+                default: None,
+            },
+            QueryParameter {
+                name: "code".to_string(),
+                kind: QueryParameterType::Integer,
+                description: "Event code".to_string(),
+                default: Some("4625".to_string()),
+            },
+        ];
+        saved
+    }
+
+    fn values(list: &[(&str, &str)]) -> BTreeMap<String, String> {
+        list.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn should_bind_the_parameters() {
+        let saved = saved_query();
+        assert_eq!(
+            "filter user.name = 'bob' AND event.code = 4625 | fields user.name",
+            saved.bind(&values(&[("user", "bob")])).unwrap()
+        );
+        // The values cannot change the query
+        let bound = saved
+            .bind(&values(&[(
+                "user",
+                "a*' OR user.name = \"*\" | stats count() \\",
+            )]))
+            .unwrap();
+        let query = Query::parse(&bound).unwrap();
+        assert_eq!(2, query.stages.len());
+        let mut log = crate::prelude::SiemLog::new("", 0, "");
+        log.add_field("user.name", "bob".into());
+        log.add_field("event.code", SiemField::I64(4625));
+        assert!(query.execute(vec![log]).unwrap().is_empty());
+
+        let error = |list: &[(&str, &str)]| match saved.bind(&values(list)) {
+            Err(QueryError::InvalidParameter(message)) => message.to_string(),
+            other => panic!("Unexpected result {:?}", other),
+        };
+        assert_eq!("The parameter $user has no value", error(&[]));
+        assert_eq!(
+            "The value of $code is not a valid integer",
+            error(&[("user", "bob"), ("code", "1 OR 1=1")])
+        );
+        assert_eq!("Unknown parameter $host", error(&[("host", "a")]));
+    }
+
+    #[test]
+    fn should_bind_float_parameters() {
+        let mut saved = SavedQuery::new("scores", "Scores", "admin", "filter score > $score");
+        saved.parameters = vec![QueryParameter {
+            name: "score".to_string(),
+            kind: QueryParameterType::Float,
+            description: "Minimum score".to_string(),
+            default: None,
+        }];
+        let bind = |value: &str| saved.bind(&values(&[("score", value)]));
+        assert_eq!("filter score > 2.0", bind("2").unwrap());
+        assert_eq!("filter score > -0.25", bind("-0.25").unwrap());
+        let query = Query::parse(&bind("2.0").unwrap()).unwrap();
+        let mut log = crate::prelude::SiemLog::new("", 0, "");
+        log.add_field("score", SiemField::F64(2.5));
+        assert_eq!(1, query.execute(vec![log]).unwrap().len());
+        assert_eq!("filter score > 0.0000001", bind("1e-7").unwrap());
+        assert_eq!("filter score > 10000000000000000.0", bind("1e16").unwrap());
+        for value in ["1e400", "NaN", "inf", "2,5"] {
+            assert_eq!(
+                Err(QueryError::InvalidParameter(LogString::Owned(
+                    "The value of $score is not a valid number".to_string()
+                ))),
+                bind(value)
+            );
+        }
+    }
+
+    #[test]
+    fn should_build_the_query_info_of_a_saved_query() {
+        let mut saved = saved_query();
+        saved.required_role = UserRole::Engineer;
+        saved.earliest = Some(QueryTime::Absolute(1_000));
+        saved.latest = Some(QueryTime::Absolute(8_000));
+        assert!(saved.allows(&UserRole::Administrator));
+        assert!(!saved.allows(&UserRole::Analyst));
+
+        let mut info = QueryInfo::new("");
+        info.saved_query = Some("failed-logins".to_string());
+        info.parameters = values(&[("user", "bob"), ("code", "4624")]);
+        info.to = 5_000;
+        let bound = saved.query_info(&info, QueryClock::Fixed(10_000)).unwrap();
+        assert_eq!(
+            "filter user.name = 'bob' AND event.code = 4624 | fields user.name",
+            bound.query
+        );
+        assert_eq!((1_000, 5_000), (bound.from, bound.to));
+        assert!(info.is_native);
+        assert!(!bound.is_native);
+        assert_eq!(None, bound.saved_query);
+        assert!(bound.parameters.is_empty());
+
+        // The default time range does not limit the one of the caller
+        info.from = 500;
+        info.to = i64::MAX;
+        let bound = saved.query_info(&info, QueryClock::Fixed(10_000)).unwrap();
+        assert_eq!((500, 8_000), (bound.from, bound.to));
+    }
+}
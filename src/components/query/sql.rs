@@ -0,0 +1,1009 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::types::LogString;
+use crate::prelude::{FieldSchema, QueryInfo};
+
+use super::ast::{
+    Aggregation, AggregationFunction, ComparisonOperator, FieldProjection, Query, QueryExpression,
+    QueryFunction, QueryLiteral, QueryStage, SortField,
+};
+use super::time::QueryClock;
+use super::QueryError;
+
+/// Column of the log tables with the time at which the event was created
+pub const EVENT_CREATED_COLUMN: &str = "event_created";
+
+/// Translates queries to SQL for the databases that store the logs in tables.
+/// The fields of the schema are columns (`source.ip` => `source_ip`) and, if the schema allows unknown fields,
+/// the rest are read from a JSON column. The values of the query are always sent as parameters.
+///
+/// ```ignore
+/// let translator = SqlTranslator::new(schema, "logs").dialect(SqlDialect::MySql);
+/// let statement = translator.translate_query_info(&query_info)?;
+/// connection.query(&statement.sql, &statement.parameters);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SqlTranslator {
+    schema: FieldSchema,
+    table: String,
+    dialect: SqlDialect,
+    overflow_column: String,
+    clock: QueryClock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SqlDialect {
+    /// Parameters as `$1`. JSON fields with `->>`
+    PostgreSql,
+    /// Parameters as `?`. JSON fields with `JSON_EXTRACT`
+    MySql,
+}
+
+/// Statement ready to be executed with the parameters in order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SqlStatement {
+    pub sql: String,
+    pub parameters: Vec<SqlParameter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SqlParameter {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+/// Piece of SQL whose parameters are numbered when the statement is complete
+#[derive(Debug, Clone, Default)]
+struct SqlFragment {
+    parts: Vec<SqlPart>,
+}
+
+#[derive(Debug, Clone)]
+enum SqlPart {
+    Sql(String),
+    Parameter(SqlParameter),
+}
+
+/// Columns that can be used by a stage
+#[derive(Debug, Clone)]
+enum SqlScope {
+    /// Columns of the table of logs
+    Table,
+    /// Columns returned by a previous stage
+    Columns(Vec<String>),
+}
+
+struct SqlSelect {
+    columns: Option<Vec<SqlFragment>>,
+    from: SqlFragment,
+    /// Name of the table or subquery, already quoted
+    source: String,
+    conditions: Vec<SqlFragment>,
+    group_by: Vec<SqlFragment>,
+    order_by: Vec<SqlFragment>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+struct SqlContext {
+    select: SqlSelect,
+    scope: SqlScope,
+    subqueries: usize,
+}
+
+impl SqlTranslator {
+    /// Translator for PostgreSQL that stores the unknown fields in the column `extra`
+    pub fn new<S: Into<String>>(schema: FieldSchema, table: S) -> Self {
+        Self {
+            schema,
+            table: table.into(),
+            dialect: SqlDialect::PostgreSql,
+            overflow_column: String::from("extra"),
+            clock: QueryClock::System,
+        }
+    }
+
+    pub fn dialect(mut self, dialect: SqlDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// JSON column with the fields that are not in the schema
+    pub fn overflow_column<S: Into<String>>(mut self, column: S) -> Self {
+        self.overflow_column = column.into();
+        self
+    }
+
+    /// Clock used to resolve `now()` and the time range as parameters
+    pub fn clock(mut self, clock: QueryClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Translates the query of a `LOG_QUERY` command: only the logs created between `from` and `to`,
+    /// with the `offset` and `limit` applied and, if not empty, only the `fields`.
+    pub fn translate_query_info(&self, info: &QueryInfo) -> Result<SqlStatement, QueryError> {
+        let query = Query::parse(&info.query)?;
+        let mut context = self.context();
+        let (earliest, latest) = query.time_range(self.clock.now());
+        context
+            .select
+            .conditions
+            .push(self.time_condition(info.from.max(earliest), info.to.min(latest)));
+        for stage in &query.stages {
+            self.translate_stage(&mut context, stage)?;
+        }
+        if !info.fields.is_empty() {
+            let projections: Vec<FieldProjection> = info
+                .fields
+                .iter()
+                .map(|field| FieldProjection {
+                    expression: QueryExpression::Field(field.clone()),
+                    alias: None,
+                })
+                .collect();
+            self.translate_stage(&mut context, &QueryStage::Fields(projections))?;
+        }
+        if context.select.limit.is_some() {
+            context.wrap(self.dialect);
+        }
+        context.select.limit = Some(info.limit);
+        context.select.offset = info.offset;
+        Ok(context.select.render().build(self.dialect))
+    }
+
+    /// Translates the query over all the logs of the table
+    pub fn translate(&self, query: &Query) -> Result<SqlStatement, QueryError> {
+        let mut context = self.context();
+        if query.earliest.is_some() || query.latest.is_some() {
+            let (earliest, latest) = query.time_range(self.clock.now());
+            context
+                .select
+                .conditions
+                .push(self.time_condition(earliest, latest));
+        }
+        for stage in &query.stages {
+            self.translate_stage(&mut context, stage)?;
+        }
+        Ok(context.select.render().build(self.dialect))
+    }
+
+    /// Name of the column of a field: `source.ip` => `source_ip`
+    pub fn column_name(field: &str) -> String {
+        field.replace('.', "_")
+    }
+
+    /// Logs created between the times, both included
+    fn time_condition(&self, from: i64, to: i64) -> SqlFragment {
+        let created = self.dialect.quote(EVENT_CREATED_COLUMN);
+        SqlFragment::concat(vec![
+            SqlFragment::sql(format!("{} >= ", created)),
+            SqlFragment::parameter(SqlParameter::Integer(from)),
+            SqlFragment::sql(format!(" AND {} <= ", created)),
+            SqlFragment::parameter(SqlParameter::Integer(to)),
+        ])
+    }
+
+    fn context(&self) -> SqlContext {
+        let source = self.dialect.quote(&self.table);
+        SqlContext {
+            select: SqlSelect::new(SqlFragment::sql(source.clone()), source),
+            scope: SqlScope::Table,
+            subqueries: 0,
+        }
+    }
+
+    fn translate_stage(
+        &self,
+        context: &mut SqlContext,
+        stage: &QueryStage,
+    ) -> Result<(), QueryError> {
+        let dialect = self.dialect;
+        match stage {
+            QueryStage::Filter(expression) => {
+                if !context.select.is_simple() {
+                    context.wrap(dialect);
+                }
+                let condition = self.condition(&context.scope, expression)?;
+                context.select.conditions.push(condition);
+            }
+            QueryStage::Fields(projections) => {
+                if !context.select.is_simple() {
+                    context.wrap(dialect);
+                }
+                let mut columns = Vec::with_capacity(projections.len());
+                let mut names = Vec::with_capacity(projections.len());
+                for projection in projections {
+                    let name = projection.name();
+                    columns.push(SqlFragment::concat(vec![
+                        self.value(&context.scope, &projection.expression)?,
+                        SqlFragment::sql(format!(" AS {}", dialect.quote(&name))),
+                    ]));
+                    names.push(name);
+                }
+                context.select.columns = Some(columns);
+                context.scope = SqlScope::Columns(names);
+            }
+            QueryStage::Stats { aggregations, by } => {
+                if !context.select.is_simple() || !context.select.order_by.is_empty() {
+                    context.wrap(dialect);
+                }
+                let mut columns = Vec::with_capacity(by.len() + aggregations.len());
+                let mut group_by = Vec::with_capacity(by.len());
+                let mut names = Vec::with_capacity(by.len() + aggregations.len());
+                for field in by {
+                    let value = self.field(&context.scope, field)?;
+                    columns.push(SqlFragment::concat(vec![
+                        value.clone(),
+                        SqlFragment::sql(format!(" AS {}", dialect.quote(field))),
+                    ]));
+                    group_by.push(self.group_key(&context.scope, field, value, columns.len()));
+                    names.push(field.clone());
+                }
+                for aggregation in aggregations {
+                    let name = aggregation.name();
+                    columns.push(SqlFragment::concat(vec![
+                        self.aggregation(&context.scope, aggregation)?,
+                        SqlFragment::sql(format!(" AS {}", dialect.quote(&name))),
+                    ]));
+                    names.push(name);
+                }
+                context.select.columns = Some(columns);
+                context.select.group_by = group_by;
+                context.scope = SqlScope::Columns(names);
+            }
+            QueryStage::Top { limit, fields } => {
+                if !context.select.is_simple() || !context.select.order_by.is_empty() {
+                    context.wrap(dialect);
+                }
+                let mut columns = Vec::with_capacity(fields.len() + 1);
+                let mut group_by = Vec::with_capacity(fields.len());
+                for field in fields {
+                    let value = self.field(&context.scope, field)?;
+                    columns.push(SqlFragment::concat(vec![
+                        value.clone(),
+                        SqlFragment::sql(format!(" AS {}", dialect.quote(field))),
+                    ]));
+                    context.select.conditions.push(SqlFragment::concat(vec![
+                        value.clone(),
+                        SqlFragment::sql(" IS NOT NULL"),
+                    ]));
+                    group_by.push(self.group_key(&context.scope, field, value, columns.len()));
+                }
+                let count = dialect.quote("count");
+                columns.push(SqlFragment::sql(format!("COUNT(*) AS {}", count)));
+                context.select.columns = Some(columns);
+                context.select.group_by = group_by;
+                context.select.order_by = vec![SqlFragment::sql(format!("{} DESC", count))];
+                context.select.limit = Some(*limit);
+                let mut names = fields.clone();
+                names.push(String::from("count"));
+                context.scope = SqlScope::Columns(names);
+            }
+            QueryStage::Dedup(fields) => {
+                // The row kept of each group is decided by the database
+                let columns = match &context.scope {
+                    SqlScope::Table => self.table_columns(),
+                    SqlScope::Columns(columns) => columns.clone(),
+                };
+                let partition = fields
+                    .iter()
+                    .map(|field| self.field(&context.scope, field))
+                    .collect::<Result<Vec<_>, _>>()?;
+                context.wrap(dialect);
+                let row = dialect.quote("_row");
+                context.select.columns = Some(vec![SqlFragment::concat(vec![
+                    SqlFragment::sql(format!(
+                        "{}.*, ROW_NUMBER() OVER (PARTITION BY ",
+                        context.select.source
+                    )),
+                    SqlFragment::join(partition, ", "),
+                    SqlFragment::sql(format!(") AS {}", row)),
+                ])]);
+                context.wrap(dialect);
+                context.select.columns = Some(
+                    columns
+                        .iter()
+                        .map(|column| SqlFragment::sql(dialect.quote(column)))
+                        .collect(),
+                );
+                context
+                    .select
+                    .conditions
+                    .push(SqlFragment::sql(format!("{} = 1", row)));
+            }
+            QueryStage::Sort(fields) => {
+                if context.select.limit.is_some() {
+                    context.wrap(dialect);
+                }
+                let mut order_by = fields
+                    .iter()
+                    .map(|sort| self.sort(&context.scope, sort))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // The previous order is kept for the rows with the same values
+                order_by.append(&mut context.select.order_by);
+                context.select.order_by = order_by;
+            }
+            QueryStage::Head(limit) => {
+                context.select.limit = Some(match context.select.limit {
+                    Some(previous) => previous.min(*limit),
+                    None => *limit,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Columns of the table: the fields of the schema and the JSON column
+    fn table_columns(&self) -> Vec<String> {
+        let mut columns: BTreeSet<String> = self
+            .schema
+            .fields
+            .keys()
+            .map(|field| Self::column_name(field))
+            .collect();
+        if self.schema.allow_unknown_fields {
+            columns.insert(self.overflow_column.clone());
+        }
+        columns.into_iter().collect()
+    }
+
+    fn is_column(&self, scope: &SqlScope, field: &str) -> bool {
+        match scope {
+            SqlScope::Table => {
+                self.schema.get_field(field).is_some()
+                    || self.schema.get_field(&Self::column_name(field)).is_some()
+            }
+            SqlScope::Columns(columns) => columns.iter().any(|v| v == field),
+        }
+    }
+
+    fn field(&self, scope: &SqlScope, field: &str) -> Result<SqlFragment, QueryError> {
+        if self.is_column(scope, field) {
+            return Ok(SqlFragment::sql(match scope {
+                SqlScope::Table => self.dialect.quote(&Self::column_name(field)),
+                SqlScope::Columns(_) => self.dialect.quote(field),
+            }));
+        }
+        match scope {
+            SqlScope::Table if self.schema.allow_unknown_fields => {
+                let column = self.dialect.quote(&self.overflow_column);
+                Ok(match self.dialect {
+                    SqlDialect::PostgreSql => SqlFragment::concat(vec![
+                        SqlFragment::sql(format!("({} ->> ", column)),
+                        SqlFragment::parameter(SqlParameter::Text(field.to_string())),
+                        SqlFragment::sql(")"),
+                    ]),
+                    SqlDialect::MySql => SqlFragment::concat(vec![
+                        SqlFragment::sql(format!("JSON_UNQUOTE(JSON_EXTRACT({}, ", column)),
+                        SqlFragment::parameter(SqlParameter::Text(format!(
+                            "$.\"{}\"",
+                            field.replace('\\', "\\\\").replace('"', "\\\"")
+                        ))),
+                        SqlFragment::sql("))"),
+                    ]),
+                })
+            }
+            SqlScope::Table => Err(translation_error(format!(
+                "The field {} is not in the schema",
+                field
+            ))),
+            SqlScope::Columns(_) => Err(translation_error(format!(
+                "The field {} is not returned by the previous stages",
+                field
+            ))),
+        }
+    }
+
+    /// Fields read from the overflow column are grouped by their position in the select
+    /// list, as each extraction binds its own parameter and the database would not
+    /// recognize both expressions as the same one.
+    fn group_key(
+        &self,
+        scope: &SqlScope,
+        field: &str,
+        value: SqlFragment,
+        position: usize,
+    ) -> SqlFragment {
+        if self.is_column(scope, field) {
+            value
+        } else {
+            SqlFragment::sql(position.to_string())
+        }
+    }
+
+    fn sort(&self, scope: &SqlScope, sort: &SortField) -> Result<SqlFragment, QueryError> {
+        let field = self.field(scope, &sort.field)?;
+        let direction = if sort.descending { "DESC" } else { "ASC" };
+        // Missing values at the end like the in-memory executor
+        Ok(match self.dialect {
+            SqlDialect::PostgreSql => SqlFragment::concat(vec![
+                field,
+                SqlFragment::sql(format!(" {} NULLS LAST", direction)),
+            ]),
+            SqlDialect::MySql => SqlFragment::concat(vec![
+                field.clone(),
+                SqlFragment::sql(" IS NULL, "),
+                field,
+                SqlFragment::sql(format!(" {}", direction)),
+            ]),
+        })
+    }
+
+    fn aggregation(
+        &self,
+        scope: &SqlScope,
+        aggregation: &Aggregation,
+    ) -> Result<SqlFragment, QueryError> {
+        let field = match &aggregation.field {
+            Some(field) => self.field(scope, field)?,
+            None => return Ok(SqlFragment::sql("COUNT(*)")),
+        };
+        let (start, end) = match aggregation.function {
+            AggregationFunction::Count => ("COUNT(", ")"),
+            AggregationFunction::DistinctCount => ("COUNT(DISTINCT ", ")"),
+            AggregationFunction::Sum => ("SUM(", ")"),
+            AggregationFunction::Avg => ("AVG(", ")"),
+            AggregationFunction::Min => ("MIN(", ")"),
+            AggregationFunction::Max => ("MAX(", ")"),
+            AggregationFunction::Values => match self.dialect {
+                SqlDialect::PostgreSql => ("ARRAY_AGG(DISTINCT ", ")"),
+                SqlDialect::MySql => ("GROUP_CONCAT(DISTINCT ", ")"),
+            },
+        };
+        Ok(SqlFragment::concat(vec![
+            SqlFragment::sql(start),
+            field,
+            SqlFragment::sql(end),
+        ]))
+    }
+
+    /// Expression used as a condition. Comparisons with missing values are false like in the in-memory executor.
+    fn condition(
+        &self,
+        scope: &SqlScope,
+        expression: &QueryExpression,
+    ) -> Result<SqlFragment, QueryError> {
+        match expression {
+            QueryExpression::And(list) | QueryExpression::Or(list) => {
+                let separator = if matches!(expression, QueryExpression::And(_)) {
+                    " AND "
+                } else {
+                    " OR "
+                };
+                let list = list
+                    .iter()
+                    .map(|v| self.condition(scope, v))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SqlFragment::concat(vec![
+                    SqlFragment::sql("("),
+                    SqlFragment::join(list, separator),
+                    SqlFragment::sql(")"),
+                ]))
+            }
+            QueryExpression::Not(condition) => Ok(not_true(self.condition(scope, condition)?)),
+            QueryExpression::Comparison(left, operator, right) => {
+                self.comparison(scope, left, *operator, right)
+            }
+            QueryExpression::Literal(QueryLiteral::Boolean(v)) => {
+                Ok(SqlFragment::parameter(SqlParameter::Boolean(*v)))
+            }
+            _ => Err(translation_error(String::from(
+                "Only comparisons can be used as conditions",
+            ))),
+        }
+    }
+
+    fn comparison(
+        &self,
+        scope: &SqlScope,
+        left: &QueryExpression,
+        operator: ComparisonOperator,
+        right: &QueryExpression,
+    ) -> Result<SqlFragment, QueryError> {
+        let mut value = self.value(scope, left)?;
+        let (symbol, expected) = match right {
+            QueryExpression::Literal(QueryLiteral::Regex(pattern)) => (
+                match self.dialect {
+                    SqlDialect::PostgreSql => " ~ ",
+                    SqlDialect::MySql => " REGEXP ",
+                },
+                SqlFragment::parameter(SqlParameter::Text(pattern.clone())),
+            ),
+            QueryExpression::Literal(literal @ QueryLiteral::StartsWith(_))
+            | QueryExpression::Literal(literal @ QueryLiteral::EndsWith(_))
+            | QueryExpression::Literal(literal @ QueryLiteral::Contains(_))
+            | QueryExpression::Literal(literal @ QueryLiteral::Like(_)) => (
+                " LIKE ",
+                SqlFragment::parameter(SqlParameter::Text(like_pattern(literal))),
+            ),
+            right => {
+                if matches!(
+                    right,
+                    QueryExpression::Literal(QueryLiteral::Integer(_) | QueryLiteral::Float(_))
+                ) && self.is_json_field(scope, left)
+                {
+                    // The JSON values are texts
+                    value = SqlFragment::concat(vec![
+                        SqlFragment::sql("CAST("),
+                        value,
+                        SqlFragment::sql(format!(" AS {})", self.dialect.float_type())),
+                    ]);
+                }
+                let symbol = match operator {
+                    ComparisonOperator::Equals | ComparisonOperator::NotEquals => " = ",
+                    ComparisonOperator::LessThan => " < ",
+                    ComparisonOperator::LessOrEqual => " <= ",
+                    ComparisonOperator::GreaterThan => " > ",
+                    ComparisonOperator::GreaterOrEqual => " >= ",
+                };
+                (symbol, self.value(scope, right)?)
+            }
+        };
+        let comparison = SqlFragment::concat(vec![value, SqlFragment::sql(symbol), expected]);
+        Ok(match operator {
+            ComparisonOperator::NotEquals => not_true(comparison),
+            _ => SqlFragment::concat(vec![
+                SqlFragment::sql("("),
+                comparison,
+                SqlFragment::sql(")"),
+            ]),
+        })
+    }
+
+    fn is_json_field(&self, scope: &SqlScope, expression: &QueryExpression) -> bool {
+        match expression {
+            QueryExpression::Field(field) => {
+                matches!(scope, SqlScope::Table) && !self.is_column(scope, field)
+            }
+            _ => false,
+        }
+    }
+
+    fn value(
+        &self,
+        scope: &SqlScope,
+        expression: &QueryExpression,
+    ) -> Result<SqlFragment, QueryError> {
+        match expression {
+            QueryExpression::Field(field) => self.field(scope, field),
+            QueryExpression::Literal(literal) => Ok(SqlFragment::parameter(match literal {
+                QueryLiteral::Integer(v) => SqlParameter::Integer(*v),
+                QueryLiteral::Float(v) => SqlParameter::Float(*v),
+                QueryLiteral::Boolean(v) => SqlParameter::Boolean(*v),
+                QueryLiteral::Text(v)
+                | QueryLiteral::StartsWith(v)
+                | QueryLiteral::EndsWith(v)
+                | QueryLiteral::Contains(v)
+                | QueryLiteral::Like(v)
+                | QueryLiteral::Regex(v) => SqlParameter::Text(v.clone()),
+            })),
+            QueryExpression::Time(time) => Ok(SqlFragment::parameter(SqlParameter::Integer(
+                time.resolve(self.clock.now()),
+            ))),
+            QueryExpression::Function(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|v| self.value(scope, v))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.function(*function, args)
+            }
+            condition => self.condition(scope, condition),
+        }
+    }
+
+    /// The conversions follow the rules of the database
+    fn function(
+        &self,
+        function: QueryFunction,
+        args: Vec<SqlFragment>,
+    ) -> Result<SqlFragment, QueryError> {
+        let cast = |args: Vec<SqlFragment>, kind: &str| {
+            SqlFragment::concat(vec![
+                SqlFragment::sql("CAST("),
+                SqlFragment::join(args, ", "),
+                SqlFragment::sql(format!(" AS {})", kind)),
+            ])
+        };
+        let call = |name: &str, args: Vec<SqlFragment>| {
+            SqlFragment::concat(vec![
+                SqlFragment::sql(format!("{}(", name)),
+                SqlFragment::join(args, ", "),
+                SqlFragment::sql(")"),
+            ])
+        };
+        Ok(match function {
+            QueryFunction::ToNumber | QueryFunction::ToFloat => {
+                cast(args, self.dialect.float_type())
+            }
+            QueryFunction::ToInteger => cast(args, self.dialect.integer_type()),
+            QueryFunction::ToString => {
+                let mut args = args.into_iter();
+                let value = cast(args.next().into_iter().collect(), self.dialect.text_type());
+                match args.next() {
+                    Some(default) => call("COALESCE", vec![value, default]),
+                    None => value,
+                }
+            }
+            QueryFunction::Lowercase => call("LOWER", args),
+            QueryFunction::Uppercase => call("UPPER", args),
+            QueryFunction::Replace => call("REPLACE", args),
+            QueryFunction::Len => call("CHAR_LENGTH", args),
+            QueryFunction::Floor => call("FLOOR", args),
+            QueryFunction::Trim => call("TRIM", args),
+            QueryFunction::Bin => {
+                let mut args = args.into_iter();
+                let value = args.next().unwrap_or_default();
+                let span = args.next().unwrap_or_default();
+                SqlFragment::concat(vec![
+                    SqlFragment::sql("(FLOOR("),
+                    value,
+                    SqlFragment::sql(" / "),
+                    span.clone(),
+                    SqlFragment::sql(") * "),
+                    span,
+                    SqlFragment::sql(")"),
+                ])
+            }
+            QueryFunction::Strftime => {
+                return Err(translation_error(
+                    "strftime cannot be translated to SQL, format the rows after the query"
+                        .to_string(),
+                ))
+            }
+        })
+    }
+}
+
+impl SqlDialect {
+    /// Quotes the name of a table or column
+    pub fn quote(&self, identifier: &str) -> String {
+        let quote = match self {
+            SqlDialect::PostgreSql => '"',
+            SqlDialect::MySql => '`',
+        };
+        let mut quoted = String::with_capacity(identifier.len() + 2);
+        quoted.push(quote);
+        for ch in identifier.chars() {
+            if ch == quote {
+                quoted.push(quote);
+            }
+            quoted.push(ch);
+        }
+        quoted.push(quote);
+        quoted
+    }
+
+    fn placeholder(&self, position: usize) -> String {
+        match self {
+            SqlDialect::PostgreSql => format!("${}", position),
+            SqlDialect::MySql => String::from("?"),
+        }
+    }
+
+    fn float_type(&self) -> &'static str {
+        match self {
+            SqlDialect::PostgreSql => "DOUBLE PRECISION",
+            SqlDialect::MySql => "DOUBLE",
+        }
+    }
+
+    fn integer_type(&self) -> &'static str {
+        match self {
+            SqlDialect::PostgreSql => "BIGINT",
+            SqlDialect::MySql => "SIGNED",
+        }
+    }
+
+    fn text_type(&self) -> &'static str {
+        match self {
+            SqlDialect::PostgreSql => "TEXT",
+            SqlDialect::MySql => "CHAR",
+        }
+    }
+}
+
+impl SqlFragment {
+    fn sql<S: Into<String>>(sql: S) -> Self {
+        Self {
+            parts: vec![SqlPart::Sql(sql.into())],
+        }
+    }
+
+    fn parameter(parameter: SqlParameter) -> Self {
+        Self {
+            parts: vec![SqlPart::Parameter(parameter)],
+        }
+    }
+
+    fn concat(list: Vec<SqlFragment>) -> Self {
+        Self {
+            parts: list.into_iter().flat_map(|v| v.parts).collect(),
+        }
+    }
+
+    fn join(list: Vec<SqlFragment>, separator: &str) -> Self {
+        let mut parts = Vec::new();
+        for (i, fragment) in list.into_iter().enumerate() {
+            if i > 0 {
+                parts.push(SqlPart::Sql(separator.to_string()));
+            }
+            parts.extend(fragment.parts);
+        }
+        Self { parts }
+    }
+
+    /// Numbers the parameters in the order they appear in the statement
+    fn build(self, dialect: SqlDialect) -> SqlStatement {
+        let mut sql = String::new();
+        let mut parameters = Vec::new();
+        for part in self.parts {
+            match part {
+                SqlPart::Sql(v) => sql.push_str(&v),
+                SqlPart::Parameter(v) => {
+                    parameters.push(v);
+                    sql.push_str(&dialect.placeholder(parameters.len()));
+                }
+            }
+        }
+        SqlStatement { sql, parameters }
+    }
+}
+
+impl SqlSelect {
+    fn new(from: SqlFragment, source: String) -> Self {
+        Self {
+            columns: None,
+            from,
+            source,
+            conditions: Vec::new(),
+            group_by: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+            offset: 0,
+        }
+    }
+
+    /// Returns all the columns of the source without groups or limits
+    fn is_simple(&self) -> bool {
+        self.columns.is_none() && self.group_by.is_empty() && self.limit.is_none()
+    }
+
+    fn render(self) -> SqlFragment {
+        let mut list = vec![SqlFragment::sql("SELECT ")];
+        match self.columns {
+            Some(columns) => list.push(SqlFragment::join(columns, ", ")),
+            None => list.push(SqlFragment::sql(format!("{}.*", self.source))),
+        }
+        list.push(SqlFragment::sql(" FROM "));
+        list.push(self.from);
+        if !self.conditions.is_empty() {
+            list.push(SqlFragment::sql(" WHERE "));
+            list.push(SqlFragment::join(self.conditions, " AND "));
+        }
+        if !self.group_by.is_empty() {
+            list.push(SqlFragment::sql(" GROUP BY "));
+            list.push(SqlFragment::join(self.group_by, ", "));
+        }
+        if !self.order_by.is_empty() {
+            list.push(SqlFragment::sql(" ORDER BY "));
+            list.push(SqlFragment::join(self.order_by, ", "));
+        }
+        if let Some(limit) = self.limit {
+            list.push(SqlFragment::sql(" LIMIT "));
+            list.push(SqlFragment::parameter(SqlParameter::Integer(limit as i64)));
+            if self.offset > 0 {
+                list.push(SqlFragment::sql(" OFFSET "));
+                list.push(SqlFragment::parameter(SqlParameter::Integer(
+                    self.offset as i64,
+                )));
+            }
+        }
+        SqlFragment::concat(list)
+    }
+}
+
+impl SqlContext {
+    /// Uses the current select as the source of a new one
+    fn wrap(&mut self, dialect: SqlDialect) {
+        self.subqueries += 1;
+        let source = dialect.quote(&format!("q{}", self.subqueries));
+        let select = std::mem::replace(
+            &mut self.select,
+            SqlSelect::new(SqlFragment::default(), source.clone()),
+        );
+        self.select.from = SqlFragment::concat(vec![
+            SqlFragment::sql("("),
+            select.render(),
+            SqlFragment::sql(format!(") AS {}", source)),
+        ]);
+    }
+}
+
+/// NOT that is also true when the condition is NULL
+fn not_true(condition: SqlFragment) -> SqlFragment {
+    SqlFragment::concat(vec![
+        SqlFragment::sql("NOT COALESCE("),
+        condition,
+        SqlFragment::sql(", FALSE)"),
+    ])
+}
+
+/// Pattern of LIKE with the special characters escaped with a backslash
+fn like_pattern(literal: &QueryLiteral) -> String {
+    let escape = |text: &str, pattern: &mut String| {
+        for ch in text.chars() {
+            if ch == '%' || ch == '_' || ch == '\\' {
+                pattern.push('\\');
+            }
+            pattern.push(ch);
+        }
+    };
+    let mut pattern = String::new();
+    match literal {
+        QueryLiteral::StartsWith(v) => {
+            escape(v, &mut pattern);
+            pattern.push('%');
+        }
+        QueryLiteral::EndsWith(v) => {
+            pattern.push('%');
+            escape(v, &mut pattern);
+        }
+        QueryLiteral::Contains(v) => {
+            pattern.push('%');
+            escape(v, &mut pattern);
+            pattern.push('%');
+        }
+        QueryLiteral::Like(v) => {
+            let mut chars = v.chars().peekable();
+            while let Some(ch) = chars.next() {
+                match ch {
+                    '\\' if chars.peek() == Some(&'*') => {
+                        chars.next();
+                        pattern.push('*');
+                    }
+                    '*' => pattern.push('%'),
+                    ch => escape(&ch.to_string(), &mut pattern),
+                }
+            }
+        }
+        _ => {}
+    }
+    pattern
+}
+
+fn translation_error(message: String) -> QueryError {
+    QueryError::Translation(LogString::Owned(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::FieldType;
+
+    fn schema(allow_unknown_fields: bool) -> FieldSchema {
+        let mut schema = FieldSchema::new();
+        schema.insert("source.ip", FieldType::Ip("Source IP"));
+        schema.insert("user.name", FieldType::Text("User name"));
+        schema.allow_unknown_fields = allow_unknown_fields;
+        schema
+    }
+
+    fn text(value: &str) -> SqlParameter {
+        SqlParameter::Text(value.to_string())
+    }
+
+    #[test]
+    fn should_translate_query_info_to_postgresql() {
+        let translator = SqlTranslator::new(schema(true), "logs");
+        let mut info = QueryInfo::new("filter source.ip = \"10.0.0.1\" AND user.name != \"adm*\" AND process.name ~= \"^cmd\" | stats count() as total by user.name | sort -total | head 5");
+        info.is_native = false;
+        info.to = 100;
+        info.limit = 10;
+        info.offset = 2;
+        let statement = translator.translate_query_info(&info).unwrap();
+        assert_eq!(
+            "SELECT \"q1\".* FROM (SELECT \"user_name\" AS \"user.name\", COUNT(*) AS \"total\" FROM \"logs\" WHERE \"event_created\" >= $1 AND \"event_created\" <= $2 AND ((\"source_ip\" = $3) AND NOT COALESCE(\"user_name\" LIKE $4, FALSE) AND ((\"extra\" ->> $5) ~ $6)) GROUP BY \"user_name\" ORDER BY \"total\" DESC NULLS LAST LIMIT $7) AS \"q1\" LIMIT $8 OFFSET $9",
+            statement.sql
+        );
+        assert_eq!(
+            vec![
+                SqlParameter::Integer(0),
+                SqlParameter::Integer(100),
+                text("10.0.0.1"),
+                text("adm%"),
+                text("process.name"),
+                text("^cmd"),
+                SqlParameter::Integer(5),
+                SqlParameter::Integer(10),
+                SqlParameter::Integer(2),
+            ],
+            statement.parameters
+        );
+    }
+
+    #[test]
+    fn should_translate_queries_to_mysql() {
+        let translator = SqlTranslator::new(schema(true), "logs").dialect(SqlDialect::MySql);
+        let query = Query::parse(
+            "filter event.code > 4000 | fields lowercase(user.name) as user | dedup user",
+        )
+        .unwrap();
+        let statement = translator.translate(&query).unwrap();
+        assert_eq!(
+            "SELECT `user` FROM (SELECT `q1`.*, ROW_NUMBER() OVER (PARTITION BY `user`) AS `_row` FROM (SELECT LOWER(`user_name`) AS `user` FROM `logs` WHERE (CAST(JSON_UNQUOTE(JSON_EXTRACT(`extra`, ?)) AS DOUBLE) > ?)) AS `q1`) AS `q2` WHERE `_row` = 1",
+            statement.sql
+        );
+        assert_eq!(
+            vec![text("$.\"event.code\""), SqlParameter::Integer(4000)],
+            statement.parameters
+        );
+
+        // User values are never part of the statement
+        let query = Query::parse("filter user.name = \"a_b%' OR 1=1 --*\"").unwrap();
+        let statement = translator.translate(&query).unwrap();
+        assert_eq!(
+            "SELECT `logs`.* FROM `logs` WHERE (`user_name` LIKE ?)",
+            statement.sql
+        );
+        assert_eq!(vec![text("a\\_b\\%' OR 1=1 --%")], statement.parameters);
+
+        // Times are resolved with the clock
+        let translator = translator.clock(QueryClock::Fixed(1_700_044_365_123));
+        let query = Query::parse("earliest=-1d@d | fields bin(event.created, 1h) as hour").unwrap();
+        let statement = translator.translate(&query).unwrap();
+        assert_eq!(
+            "SELECT (FLOOR(`event_created` / ?) * ?) AS `hour` FROM `logs` WHERE `event_created` >= ? AND `event_created` <= ?",
+            statement.sql
+        );
+        assert_eq!(
+            vec![
+                SqlParameter::Integer(3_600_000),
+                SqlParameter::Integer(3_600_000),
+                SqlParameter::Integer(1_699_920_000_000),
+                SqlParameter::Integer(i64::MAX),
+            ],
+            statement.parameters
+        );
+
+        let translator = SqlTranslator::new(schema(false), "logs");
+        assert_eq!(
+            Err(QueryError::Translation(LogString::Borrowed(
+                "The field event.code is not in the schema"
+            ))),
+            translator.translate(&Query::parse("filter event.code = 1").unwrap())
+        );
+    }
+
+    #[test]
+    fn should_group_by_fields_outside_the_schema() {
+        let translator = SqlTranslator::new(schema(true), "logs");
+        let query = Query::parse("stats count() as total by user.name, process.name").unwrap();
+        let statement = translator.translate(&query).unwrap();
+        assert_eq!(
+            "SELECT \"user_name\" AS \"user.name\", (\"extra\" ->> $1) AS \"process.name\", COUNT(*) AS \"total\" FROM \"logs\" GROUP BY \"user_name\", 2",
+            statement.sql
+        );
+        assert_eq!(vec![text("process.name")], statement.parameters);
+
+        let translator = translator.dialect(SqlDialect::MySql);
+        let query = Query::parse("top 3 process.name").unwrap();
+        let statement = translator.translate(&query).unwrap();
+        assert_eq!(
+            "SELECT JSON_UNQUOTE(JSON_EXTRACT(`extra`, ?)) AS `process.name`, COUNT(*) AS `count` FROM `logs` WHERE JSON_UNQUOTE(JSON_EXTRACT(`extra`, ?)) IS NOT NULL GROUP BY 1 ORDER BY `count` DESC LIMIT ?",
+            statement.sql
+        );
+        assert_eq!(
+            vec![
+                text("$.\"process.name\""),
+                text("$.\"process.name\""),
+                SqlParameter::Integer(3),
+            ],
+            statement.parameters
+        );
+    }
+}
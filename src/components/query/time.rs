@@ -0,0 +1,179 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::ast::Query;
+
+const SECOND: i64 = 1_000;
+const MINUTE: i64 = 60 * SECOND;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+
+/// Time used to resolve `now()` and the relative times of the queries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryClock {
+    /// Current time of the system
+    #[default]
+    System,
+    /// Fixed time in milliseconds. Useful for tests and for repeating a query.
+    Fixed(i64),
+}
+
+impl QueryClock {
+    /// Current time in milliseconds from 1970
+    pub fn now(&self) -> i64 {
+        match self {
+            QueryClock::System => Utc::now().timestamp_millis(),
+            QueryClock::Fixed(now) => *now,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeUnit {
+    /// `s`
+    Second,
+    /// `m`
+    Minute,
+    /// `h`
+    Hour,
+    /// `d`
+    Day,
+    /// `w`: weeks start on Monday
+    Week,
+}
+
+/// Time relative to the moment the query is executed: `now()-1d@d`, `-24h`, `@h`.
+/// All the times are in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelativeTime {
+    /// Milliseconds added to the current time. Negative for the past.
+    pub offset: i64,
+    /// The time is rounded down to the start of the unit after applying the offset
+    pub snap: Option<TimeUnit>,
+}
+
+/// Limit of the time range of a query: `earliest=-24h`, `latest=now`, `earliest=1700000000000`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryTime {
+    /// Milliseconds from 1970
+    Absolute(i64),
+    Relative(RelativeTime),
+}
+
+impl TimeUnit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "s" => Self::Second,
+            "m" => Self::Minute,
+            "h" => Self::Hour,
+            "d" => Self::Day,
+            "w" => Self::Week,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Second => "s",
+            Self::Minute => "m",
+            Self::Hour => "h",
+            Self::Day => "d",
+            Self::Week => "w",
+        }
+    }
+
+    pub fn millis(&self) -> i64 {
+        match self {
+            Self::Second => SECOND,
+            Self::Minute => MINUTE,
+            Self::Hour => HOUR,
+            Self::Day => DAY,
+            Self::Week => WEEK,
+        }
+    }
+
+    /// Start of the unit that contains the time
+    pub fn snap(&self, time: i64) -> i64 {
+        match self {
+            // 1970-01-01 was Thursday, three days after a Monday
+            Self::Week => (time + 3 * DAY).div_euclid(WEEK) * WEEK - 3 * DAY,
+            unit => time.div_euclid(unit.millis()) * unit.millis(),
+        }
+    }
+}
+
+impl RelativeTime {
+    /// Milliseconds of the time
+    pub fn resolve(&self, now: i64) -> i64 {
+        let time = now.saturating_add(self.offset);
+        match &self.snap {
+            Some(unit) => unit.snap(time),
+            None => time,
+        }
+    }
+}
+
+impl QueryTime {
+    pub fn resolve(&self, now: i64) -> i64 {
+        match self {
+            QueryTime::Absolute(time) => *time,
+            QueryTime::Relative(time) => time.resolve(now),
+        }
+    }
+}
+
+impl Query {
+    /// Limits of `earliest` and `latest`, both included. Unbounded if not set.
+    pub fn time_range(&self, now: i64) -> (i64, i64) {
+        (
+            self.earliest.map(|v| v.resolve(now)).unwrap_or(i64::MIN),
+            self.latest.map(|v| v.resolve(now)).unwrap_or(i64::MAX),
+        )
+    }
+}
+
+/// Milliseconds of a duration like `24h` or `5m`
+pub fn parse_duration(duration: &str) -> Option<i64> {
+    let split = duration.len().checked_sub(1)?;
+    let unit = TimeUnit::from_name(duration.get(split..)?)?;
+    let amount: i64 = duration.get(..split)?.parse().ok()?;
+    amount.checked_mul(unit.millis())
+}
+
+/// Formats the time in UTC. None if the format is not valid.
+pub fn format_time(time: i64, format: &str) -> Option<String> {
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return None;
+    }
+    let date = Utc.timestamp_millis_opt(time).single()?;
+    Some(date.format_with_items(items.into_iter()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_resolve_relative_times() {
+        // 2023-11-15T10:32:45.123Z, a Wednesday
+        let now = 1_700_044_365_123;
+        let time = |offset, snap| RelativeTime { offset, snap }.resolve(now);
+        assert_eq!(now - DAY, time(-DAY, None));
+        // 2023-11-14T00:00:00Z
+        assert_eq!(1_699_920_000_000, time(-DAY, Some(TimeUnit::Day)));
+        // 2023-11-15T10:00:00Z
+        assert_eq!(1_700_042_400_000, time(0, Some(TimeUnit::Hour)));
+        // Monday 2023-11-13T00:00:00Z
+        assert_eq!(1_699_833_600_000, time(0, Some(TimeUnit::Week)));
+        assert_eq!(Some(5 * MINUTE), parse_duration("5m"));
+        assert_eq!(None, parse_duration("5y"));
+        assert_eq!(
+            Some("2023-11-15 10:32".to_string()),
+            format_time(now, "%Y-%m-%d %H:%M")
+        );
+        assert_eq!(None, format_time(now, "%Q"));
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::holder::DatasetHolder;
+use crate::prelude::i18n::Language;
+use crate::prelude::types::LogString;
+use crate::prelude::{AlertAggregation, SiemAlert, SiemLog};
+
+use super::exception::is_excepted;
+use super::render::AlertRenderer;
+use super::state::RuleStateStore;
+use super::SiemRule;
+
+/// Groups the logs that match a rule and only triggers when the threshold is reached inside the window.
+/// Ex: 5 failed logins for the same user.name within 10 minutes.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RuleAggregation {
+    /// Fields used to build the group key. Logs without any of them are ignored.
+    pub group_by: Vec<LogString>,
+    pub threshold: AggregationThreshold,
+    /// Size of the sliding window in milliseconds
+    pub window: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregationThreshold {
+    /// Number of matching logs
+    Count(usize),
+    /// Number of distinct values of a field: (Field, Count)
+    DistinctCount(LogString, usize),
+    /// Number of distinct subrules matched by the logs of the group. All the subrules must match in any order.
+    DistinctSubrules(usize),
+}
+
+impl AggregationThreshold {
+    pub fn limit(&self) -> usize {
+        match self {
+            AggregationThreshold::Count(v) => *v,
+            AggregationThreshold::DistinctCount(_, v) => *v,
+            AggregationThreshold::DistinctSubrules(v) => *v,
+        }
+    }
+}
+
+impl RuleAggregation {
+    /// Key of the group the log belongs to: the values of the `group_by` fields joined by "|"
+    pub fn group_key(&self, log: &SiemLog) -> Option<LogString> {
+        let mut values = Vec::with_capacity(self.group_by.len());
+        for field in &self.group_by {
+            values.push(log.field(field)?.to_string());
+        }
+        Some(LogString::Owned(values.join("|")))
+    }
+}
+
+/// Hits of a group inside the window
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct WindowCounter {
+    /// Time of each hit. Used by Count
+    hits: VecDeque<i64>,
+    /// Value => Last time seen. Used by DistinctCount and DistinctSubrules
+    values: BTreeMap<LogString, i64>,
+    last_seen: i64,
+}
+
+impl WindowCounter {
+    fn expire(&mut self, since: i64) {
+        while let Some(time) = self.hits.front() {
+            if *time > since {
+                break;
+            }
+            self.hits.pop_front();
+        }
+        self.values.retain(|_, time| *time > since);
+    }
+    fn is_empty(&self) -> bool {
+        self.hits.is_empty() && self.values.is_empty()
+    }
+}
+
+/// In-memory engine that counts the logs matching aggregation rules using a sliding window per group.
+/// Rules without aggregation generate an alert with each log that matches.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AggregationEngine {
+    /// Rule ID => Group key => Counter
+    groups: BTreeMap<LogString, BTreeMap<LogString, WindowCounter>>,
+    /// Not included in the snapshots
+    #[serde(skip)]
+    renderer: AlertRenderer,
+}
+
+impl AggregationEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Language used in the content of the alerts
+    pub fn set_language(&mut self, language: Language) {
+        self.renderer = AlertRenderer::new(language);
+    }
+
+    /// Evaluates the log against the rule and returns an alert if the threshold has been reached.
+    /// The counter of the group is reset after generating the alert.
+    /// Logs that match an exception of the rule are ignored.
+    /// The rule is evaluated with `SiemRule::matches_with_state`, so the rule states are checked and recorded in the store.
+    pub fn process(
+        &mut self,
+        rule: &SiemRule,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        states: &mut RuleStateStore,
+    ) -> Option<SiemAlert> {
+        let matched = rule.matches_with_state(log, datasets, states)?;
+        let now = log.i64_field("event.created").unwrap_or(0);
+        // Excepted logs are not counted
+        if is_excepted(&rule.id, log, datasets, now) {
+            return None;
+        }
+        let aggregation = match &rule.aggregation {
+            Some(v) => v,
+            None => return Some(self.renderer.render(rule, log, &matched, datasets, now)),
+        };
+        let key = aggregation.group_key(log)?;
+        let distinct_values = match &aggregation.threshold {
+            AggregationThreshold::Count(_) => None,
+            AggregationThreshold::DistinctCount(field, _) => {
+                Some(vec![LogString::Owned(log.field(field)?.to_string())])
+            }
+            AggregationThreshold::DistinctSubrules(_) => Some(matched.clone()),
+        };
+        let groups = self.groups.entry(rule.id.clone()).or_default();
+        let counter = groups.entry(key.clone()).or_default();
+        counter.expire(now.saturating_sub(aggregation.window));
+        counter.last_seen = counter.last_seen.max(now);
+        let limit = aggregation.threshold.limit();
+        let count = match distinct_values {
+            Some(values) => {
+                for value in values {
+                    let time = counter.values.entry(value).or_insert(now);
+                    *time = (*time).max(now);
+                }
+                counter.values.len()
+            }
+            None => {
+                counter.hits.push_back(now);
+                // Older hits are not needed to reach the threshold
+                while counter.hits.len() > limit {
+                    counter.hits.pop_front();
+                }
+                counter.hits.len()
+            }
+        };
+        if count < limit {
+            return None;
+        }
+        groups.remove(&key);
+        let aggregation = AlertAggregation {
+            limit: now.saturating_add(aggregation.window),
+            key: key.to_string(),
+        };
+        let mut alert = self.renderer.render(rule, log, &matched, datasets, now);
+        alert.aggregation = Some(aggregation);
+        Some(alert)
+    }
+
+    /// Removes the groups without hits inside the window of their rule
+    pub fn clean(&mut self, now: i64, rules: &[SiemRule]) {
+        for rule in rules {
+            let window = match &rule.aggregation {
+                Some(v) => v.window,
+                None => continue,
+            };
+            if let Some(groups) = self.groups.get_mut(&rule.id) {
+                for counter in groups.values_mut() {
+                    counter.expire(now.saturating_sub(window));
+                }
+                groups.retain(|_, counter| !counter.is_empty());
+            }
+        }
+        self.groups.retain(|_, groups| !groups.is_empty());
+    }
+
+    /// Removes the groups not updated since the given time. Useful when the rules are no longer available.
+    pub fn clean_older_than(&mut self, since: i64) {
+        for groups in self.groups.values_mut() {
+            groups.retain(|_, counter| counter.last_seen > since);
+        }
+        self.groups.retain(|_, groups| !groups.is_empty());
+    }
+
+    /// Forgets the counters of a rule
+    pub fn remove_rule(&mut self, id: &str) {
+        self.groups.remove(id);
+    }
+
+    /// Number of active groups
+    pub fn len(&self) -> usize {
+        self.groups.values().map(|groups| groups.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::prelude::rule::exception::RuleException;
+    use crate::prelude::rule::{
+        AlertGenerator, MitreInfo, RuleCondition, RuleOperator, RuleState, RuleStateValue,
+        SiemSubRule,
+    };
+    use crate::prelude::rule_exceptions::{RuleExceptionsDataset, RuleExceptionsSynDataset};
+    use crate::prelude::{AlertSeverity, SiemDataset};
+    use std::sync::Arc;
+
+    fn failed_login_rule(threshold: AggregationThreshold) -> SiemRule {
+        let mut subrules = BTreeMap::new();
+        subrules.insert(
+            LogString::Borrowed("login_failure"),
+            SiemSubRule {
+                conditions: vec![RuleCondition {
+                    field: LogString::Borrowed("event.outcome"),
+                    operator: RuleOperator::Equals("FAIL".into()),
+                }],
+                rule_state: None,
+            },
+        );
+        SiemRule {
+            id: LogString::Borrowed("id004"),
+            name: LogString::Borrowed("Brute force"),
+            description: LogString::Borrowed(""),
+            mitre: Cow::Owned(MitreInfo {
+                tactics: vec![],
+                techniques: vec![],
+            }),
+            needed_datasets: vec![],
+            subrules: Cow::Owned(subrules),
+            conditions: Cow::Owned(vec![]),
+            alert: Cow::Owned(AlertGenerator {
+                content: vec![],
+                severity: AlertSeverity::HIGH,
+                tags: vec![],
+                aggregation: None,
+            }),
+            aggregation: Some(Box::new(RuleAggregation {
+                group_by: vec![LogString::Borrowed("user.name")],
+                threshold,
+                window: 60_000,
+            })),
+            sequence: None,
+            expression: None,
+        }
+    }
+
+    fn login(user: &str, source: &str, time: i64) -> SiemLog {
+        let mut log = SiemLog::new("", time, "localhost");
+        log.set_event_created(time);
+        log.add_field("user.name", user.to_string().into());
+        log.add_field("source.ip", source.to_string().into());
+        log.add_field("event.outcome", "FAIL".into());
+        log
+    }
+
+    #[test]
+    fn should_trigger_when_count_is_reached() {
+        let rule = failed_login_rule(AggregationThreshold::Count(3));
+        let datasets = DatasetHolder::new();
+        let mut engine = AggregationEngine::new();
+        let mut states = RuleStateStore::new();
+        let mut process = |user: &str, time: i64| {
+            engine.process(
+                &rule,
+                &mut login(user, "10.0.0.1", time),
+                &datasets,
+                &mut states,
+            )
+        };
+        assert!(process("alice", 0).is_none());
+        assert!(process("bob", 1_000).is_none());
+        assert!(process("alice", 2_000).is_none());
+        // The first hit is outside the window
+        assert!(process("alice", 61_000).is_none());
+        let alert = process("alice", 61_500).unwrap();
+        let aggregation = alert.aggregation.unwrap();
+        assert_eq!("alice", aggregation.key);
+        assert_eq!(121_500, aggregation.limit);
+        assert_eq!("id004", alert.rule);
+        // The alert has the time of the log that triggered it
+        assert_eq!(61_500, alert.date);
+        // Counter is reset after the alert
+        assert!(process("alice", 63_000).is_none());
+        assert_eq!(2, engine.len());
+        engine.clean(200_000, std::slice::from_ref(&rule));
+        assert!(engine.is_empty());
+    }
+
+    #[test]
+    fn should_trigger_when_distinct_count_is_reached() {
+        let rule = failed_login_rule(AggregationThreshold::DistinctCount(
+            LogString::Borrowed("source.ip"),
+            2,
+        ));
+        let datasets = DatasetHolder::new();
+        let mut engine = AggregationEngine::new();
+        let mut states = RuleStateStore::new();
+        let mut process = |source: &str, time: i64| {
+            engine.process(
+                &rule,
+                &mut login("alice", source, time),
+                &datasets,
+                &mut states,
+            )
+        };
+        assert!(process("10.0.0.1", 0).is_none());
+        assert!(process("10.0.0.1", 1_000).is_none());
+        assert_eq!(
+            "alice",
+            process("10.0.0.2", 2_000).unwrap().aggregation.unwrap().key
+        );
+    }
+
+    #[test]
+    fn should_ignore_excepted_logs() {
+        let rule = failed_login_rule(AggregationThreshold::Count(2));
+        let mut exceptions = RuleExceptionsDataset::new();
+        exceptions.insert(RuleException {
+            id: LogString::Borrowed("exc001"),
+            rule: LogString::Borrowed("id004"),
+            conditions: vec![RuleCondition {
+                field: LogString::Borrowed("source.ip"),
+                operator: RuleOperator::Equals("10.0.0.99".into()),
+            }],
+            author: LogString::Borrowed("secops"),
+            reason: LogString::Borrowed("Password audit"),
+            expires: Some(10_000),
+        });
+        let (sender, _receiver) = crossbeam_channel::unbounded();
+        let datasets = DatasetHolder::from_datasets(vec![SiemDataset::RuleExceptions(
+            RuleExceptionsSynDataset::new(Arc::new(exceptions), sender),
+        )]);
+        let mut engine = AggregationEngine::new();
+        let mut states = RuleStateStore::new();
+        let mut process = |source: &str, time: i64| {
+            engine.process(
+                &rule,
+                &mut login("alice", source, time),
+                &datasets,
+                &mut states,
+            )
+        };
+        assert!(process("10.0.0.99", 0).is_none());
+        assert!(process("10.0.0.99", 1_000).is_none());
+        assert!(process("10.0.0.1", 2_000).is_none());
+        // The exception has expired
+        assert!(process("10.0.0.99", 20_000).is_some());
+    }
+
+    #[test]
+    fn should_check_and_record_rule_states() {
+        let source_state = |name: &'static str| RuleState {
+            name: LogString::Borrowed(name),
+            states: RuleStateValue::Field(LogString::Borrowed("source.ip")),
+            ttl: None,
+        };
+        let mut rule = failed_login_rule(AggregationThreshold::Count(2));
+        if let Some(subrule) = rule.subrules.to_mut().get_mut("login_failure") {
+            subrule.conditions.push(RuleCondition {
+                field: LogString::Borrowed("source.ip"),
+                operator: RuleOperator::Not(Box::new(RuleOperator::ExistsRuleState(vec![
+                    source_state("trusted_source"),
+                ]))),
+            });
+            subrule.rule_state = Some(source_state("failed_login"));
+        }
+        let datasets = DatasetHolder::new();
+        let mut engine = AggregationEngine::new();
+        let mut states = RuleStateStore::new();
+        states.insert(
+            LogString::Borrowed("trusted_source"),
+            LogString::Borrowed("10.0.0.99"),
+            i64::MAX,
+        );
+        let mut process = |source: &str, time: i64, states: &mut RuleStateStore| {
+            engine.process(&rule, &mut login("alice", source, time), &datasets, states)
+        };
+        // Trusted sources are not counted
+        assert!(process("10.0.0.99", 0, &mut states).is_none());
+        assert!(process("10.0.0.99", 1_000, &mut states).is_none());
+        assert!(process("10.0.0.1", 2_000, &mut states).is_none());
+        assert!(states.contains("failed_login", "10.0.0.1", 2_000));
+        assert!(!states.contains("failed_login", "10.0.0.99", 2_000));
+        assert!(process("10.0.0.1", 3_000, &mut states).is_some());
+    }
+}
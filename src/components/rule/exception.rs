@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::holder::DatasetHolder;
+use crate::prelude::types::LogString;
+use crate::prelude::SiemLog;
+
+use super::RuleCondition;
+
+/// Allows a known and accepted activity that triggers a rule. The alerts of the rule are not generated for
+/// the logs that match all the conditions of the exception.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RuleException {
+    pub id: LogString,
+    /// ID of the rule this exception belongs to
+    pub rule: LogString,
+    /// All the conditions must match for the log to be excluded
+    pub conditions: Vec<RuleCondition>,
+    /// Who created the exception
+    pub author: LogString,
+    /// Why the activity is allowed
+    pub reason: LogString,
+    /// Timestamp in milliseconds after which the exception no longer applies. Never expires if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+}
+
+impl RuleException {
+    pub fn is_expired(&self, now: i64) -> bool {
+        match self.expires {
+            Some(expires) => now > expires,
+            None => false,
+        }
+    }
+
+    /// Checks if the exception is active and the log matches all of its conditions.
+    /// Exceptions without conditions never match, to avoid silencing the whole rule by mistake.
+    pub fn matches(&self, log: &mut SiemLog, datasets: &DatasetHolder, now: i64) -> bool {
+        if self.conditions.is_empty() || self.is_expired(now) {
+            return false;
+        }
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(log, datasets))
+    }
+}
+
+/// Checks the exceptions of the rule stored in the RuleExceptions dataset. `now` is the time of the log.
+pub fn is_excepted(rule_id: &str, log: &mut SiemLog, datasets: &DatasetHolder, now: i64) -> bool {
+    match datasets.rule_exceptions() {
+        Some(exceptions) => exceptions.find(rule_id, log, datasets, now).is_some(),
+        None => false,
+    }
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::types::LogString;
+
+/// Maximum number of groups generated when converting an expression into groups of subrules
+pub const MAX_EXPRESSION_GROUPS: usize = 64;
+
+/// Boolean combination of subrules. Used when the rule cannot be expressed as groups of subrules, like with negations.
+/// Ex: `all of selection_* and not 1 of filter_*`
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleExpression {
+    Subrule(LogString),
+    And(Vec<RuleExpression>),
+    Or(Vec<RuleExpression>),
+    Not(Box<RuleExpression>),
+}
+
+/// Groups of subrules joined by OR. Each subrule is marked as negated (false) or not (true).
+type Groups<'a> = Vec<Vec<(&'a LogString, bool)>>;
+
+impl RuleExpression {
+    /// Evaluates the expression. The function tells if a subrule matches and is only called when needed.
+    pub fn evaluate<'a, F>(&'a self, subrule: &mut F) -> bool
+    where
+        F: FnMut(&'a LogString) -> bool,
+    {
+        match self {
+            RuleExpression::Subrule(name) => subrule(name),
+            RuleExpression::And(list) => list.iter().all(|v| v.evaluate(subrule)),
+            RuleExpression::Or(list) => list.iter().any(|v| v.evaluate(subrule)),
+            RuleExpression::Not(v) => !v.evaluate(subrule),
+        }
+    }
+
+    /// Names of all the subrules used by the expression
+    pub fn subrules(&self) -> Vec<&LogString> {
+        let mut to_ret = Vec::new();
+        self.collect_subrules(false, &mut to_ret);
+        to_ret
+    }
+
+    /// Subrules that are not negated. Only these ones are reported as matched.
+    pub fn positive_subrules(&self) -> Vec<&LogString> {
+        let mut to_ret = Vec::new();
+        self.collect_subrules(true, &mut to_ret);
+        to_ret
+    }
+
+    fn collect_subrules<'a>(&'a self, only_positive: bool, list: &mut Vec<&'a LogString>) {
+        match self {
+            RuleExpression::Subrule(name) => {
+                if !list.contains(&name) {
+                    list.push(name);
+                }
+            }
+            RuleExpression::And(children) | RuleExpression::Or(children) => {
+                for child in children {
+                    child.collect_subrules(only_positive, list);
+                }
+            }
+            RuleExpression::Not(child) => {
+                if !only_positive {
+                    child.collect_subrules(only_positive, list);
+                }
+            }
+        }
+    }
+
+    /// Equivalent groups of subrules for `SiemRule.conditions`.
+    /// None if the expression has negations or more groups than the limit.
+    pub fn to_groups(&self, limit: usize) -> Option<Vec<Vec<LogString>>> {
+        self.groups(false, limit)?
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(|(name, positive)| positive.then(|| name.clone()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Groups of subrules that must match for the expression to match, ignoring the negated ones.
+    /// None if there are more groups than the limit.
+    pub fn required_groups(&self, limit: usize) -> Option<Vec<Vec<LogString>>> {
+        let groups = self.groups(false, limit)?;
+        Some(
+            groups
+                .into_iter()
+                .map(|group| {
+                    group
+                        .into_iter()
+                        .filter(|(_, positive)| *positive)
+                        .map(|(name, _)| name.clone())
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    /// Disjunctive normal form of the expression with the negations pushed to the subrules
+    fn groups(&self, negated: bool, limit: usize) -> Option<Groups<'_>> {
+        let (children, is_and) = match self {
+            RuleExpression::Subrule(name) => return Some(vec![vec![(name, !negated)]]),
+            RuleExpression::Not(child) => return child.groups(!negated, limit),
+            RuleExpression::And(children) => (children, !negated),
+            RuleExpression::Or(children) => (children, negated),
+        };
+        let mut to_ret: Groups = if is_and { vec![vec![]] } else { vec![] };
+        for child in children {
+            let child = child.groups(negated, limit)?;
+            if is_and {
+                if to_ret.len() * child.len() > limit {
+                    return None;
+                }
+                let mut product = Vec::with_capacity(to_ret.len() * child.len());
+                for group in &to_ret {
+                    for other in &child {
+                        let mut group = group.clone();
+                        for item in other {
+                            if !group.contains(item) {
+                                group.push(*item);
+                            }
+                        }
+                        product.push(group);
+                    }
+                }
+                to_ret = product;
+            } else {
+                to_ret.extend(child);
+                if to_ret.len() > limit {
+                    return None;
+                }
+            }
+        }
+        Some(to_ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subrule(name: &'static str) -> RuleExpression {
+        RuleExpression::Subrule(LogString::Borrowed(name))
+    }
+
+    #[test]
+    fn should_convert_expressions_into_groups() {
+        // (a or b) and c
+        let expression = RuleExpression::And(vec![
+            RuleExpression::Or(vec![subrule("a"), subrule("b")]),
+            subrule("c"),
+        ]);
+        assert_eq!(
+            Some(vec![
+                vec!["a".into(), "c".into()],
+                vec!["b".into(), "c".into()]
+            ]),
+            expression.to_groups(MAX_EXPRESSION_GROUPS)
+        );
+        assert_eq!(None, expression.to_groups(1));
+
+        // a and not (b or c)
+        let expression = RuleExpression::And(vec![
+            subrule("a"),
+            RuleExpression::Not(Box::new(RuleExpression::Or(vec![
+                subrule("b"),
+                subrule("c"),
+            ]))),
+        ]);
+        assert_eq!(None, expression.to_groups(MAX_EXPRESSION_GROUPS));
+        assert_eq!(
+            Some(vec![vec![LogString::Borrowed("a")]]),
+            expression.required_groups(MAX_EXPRESSION_GROUPS)
+        );
+        assert_eq!(vec!["a"], expression.positive_subrules());
+        assert_eq!(vec!["a", "b", "c"], expression.subrules());
+        let evaluate = |matched: &[&str]| {
+            expression.evaluate(&mut |name: &LogString| matched.contains(&&name[..]))
+        };
+        assert!(evaluate(&["a"]));
+        assert!(!evaluate(&["a", "c"]));
+        assert!(!evaluate(&["b"]));
+    }
+}
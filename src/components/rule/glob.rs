@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+
+/// Wildcard pattern with the Sigma syntax: `*` matches any number of characters and `?` a single one.
+/// Wildcards are escaped with a backslash (`\*`, `\?`, `\\`). Any other backslash is a literal one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "GlobDefinition", into = "GlobDefinition")]
+pub struct GlobPattern {
+    pattern: String,
+    case_sensitive: bool,
+    tokens: Vec<GlobToken>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct GlobDefinition {
+    pattern: String,
+    #[serde(default)]
+    case_sensitive: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum GlobToken {
+    /// Lowercase if the pattern is case insensitive
+    Char(char),
+    AnyChar,
+    AnyString,
+}
+
+impl From<GlobDefinition> for GlobPattern {
+    fn from(value: GlobDefinition) -> Self {
+        GlobPattern::with_case(&value.pattern, value.case_sensitive)
+    }
+}
+
+impl From<GlobPattern> for GlobDefinition {
+    fn from(value: GlobPattern) -> Self {
+        GlobDefinition {
+            pattern: value.pattern,
+            case_sensitive: value.case_sensitive,
+        }
+    }
+}
+
+impl PartialEq for GlobPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern && self.case_sensitive == other.case_sensitive
+    }
+}
+
+impl GlobPattern {
+    /// Case insensitive pattern
+    pub fn new(pattern: &str) -> Self {
+        Self::with_case(pattern, false)
+    }
+
+    pub fn with_case(pattern: &str, case_sensitive: bool) -> Self {
+        let mut tokens = Vec::with_capacity(pattern.len());
+        let mut chars = pattern.chars().peekable();
+        while let Some(ch) = chars.next() {
+            let token = match ch {
+                '*' => {
+                    // Consecutive wildcards are the same as one
+                    if tokens.last() == Some(&GlobToken::AnyString) {
+                        continue;
+                    }
+                    GlobToken::AnyString
+                }
+                '?' => GlobToken::AnyChar,
+                '\\' => match chars.peek() {
+                    Some(next @ ('*' | '?' | '\\')) => {
+                        let next = *next;
+                        chars.next();
+                        GlobToken::Char(next)
+                    }
+                    _ => GlobToken::Char('\\'),
+                },
+                ch => GlobToken::Char(ch),
+            };
+            tokens.push(token);
+        }
+        if !case_sensitive {
+            for token in tokens.iter_mut() {
+                if let GlobToken::Char(ch) = token {
+                    *ch = lowercase(*ch);
+                }
+            }
+        }
+        Self {
+            pattern: pattern.to_string(),
+            case_sensitive,
+            tokens,
+        }
+    }
+
+    /// Pattern with the Sigma escaping
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
+
+    /// The unescaped text if the pattern has no wildcards
+    pub fn literal(&self) -> Option<String> {
+        let mut to_ret = String::with_capacity(self.pattern.len());
+        for token in &self.tokens {
+            match token {
+                GlobToken::Char(ch) => to_ret.push(*ch),
+                _ => return None,
+            }
+        }
+        if self.case_sensitive {
+            Some(to_ret)
+        } else {
+            // Recover the original case of the characters
+            Self::with_case(&self.pattern, true).literal()
+        }
+    }
+
+    /// Longest text without wildcards that any matching text must contain. Lowercase if the pattern is case insensitive.
+    pub fn longest_literal(&self) -> Option<String> {
+        let mut longest = String::new();
+        let mut current = String::new();
+        for token in &self.tokens {
+            match token {
+                GlobToken::Char(ch) => current.push(*ch),
+                _ => {
+                    if current.len() > longest.len() {
+                        longest = std::mem::take(&mut current);
+                    }
+                    current.clear();
+                }
+            }
+        }
+        if current.len() > longest.len() {
+            longest = current;
+        }
+        (!longest.is_empty()).then_some(longest)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let tokens = &self.tokens[..];
+        // Byte position in the text and position in the tokens
+        let (mut t, mut p) = (0, 0);
+        // Position of the last AnyString and the text position it is covering
+        let mut backtrack: Option<(usize, usize)> = None;
+        while let Some(ch) = text[t..].chars().next() {
+            let next = t + ch.len_utf8();
+            let ch = if self.case_sensitive {
+                ch
+            } else {
+                lowercase(ch)
+            };
+            match tokens.get(p) {
+                Some(GlobToken::AnyString) => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                Some(GlobToken::AnyChar) => {
+                    p += 1;
+                    t = next;
+                    continue;
+                }
+                Some(GlobToken::Char(expected)) if *expected == ch => {
+                    p += 1;
+                    t = next;
+                    continue;
+                }
+                _ => {}
+            }
+            match backtrack {
+                Some((star, covered)) => {
+                    let covered =
+                        covered + text[covered..].chars().next().map_or(1, char::len_utf8);
+                    p = star + 1;
+                    t = covered;
+                    backtrack = Some((star, covered));
+                }
+                None => return false,
+            }
+        }
+        tokens[p..].iter().all(|v| *v == GlobToken::AnyString)
+    }
+}
+
+/// Simple lowercase that keeps a single character
+pub(crate) fn lowercase(ch: char) -> char {
+    if ch.is_ascii() {
+        ch.to_ascii_lowercase()
+    } else {
+        ch.to_lowercase().next().unwrap_or(ch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_sigma_wildcards() {
+        // A backslash before a wildcard must be escaped
+        let glob = GlobPattern::new("*\\Windows\\\\*\\cmd.exe");
+        assert!(glob.is_match("C:\\Windows\\System32\\CMD.EXE"));
+        assert!(!glob.is_match("C:\\Windows\\cmd.exe"));
+        assert!(!GlobPattern::with_case("*\\Windows\\\\*\\cmd.exe", true)
+            .is_match("C:\\Windows\\System32\\CMD.EXE"));
+
+        let glob = GlobPattern::new("p?ng *.exe*");
+        assert!(glob.is_match("ping evil.exe -n 1"));
+        assert!(glob.is_match("PONG .exe"));
+        assert!(!glob.is_match("pinng evil.exe"));
+
+        // Escaped wildcards
+        let glob = GlobPattern::new("what\\?\\*\\\\*");
+        assert!(glob.is_match("what?*\\ever"));
+        assert!(!glob.is_match("whatX*\\ever"));
+        assert_eq!(None, glob.literal());
+        assert_eq!(
+            Some("Cmd.Exe?".to_string()),
+            GlobPattern::new("Cmd.Exe\\?").literal()
+        );
+
+        // Multibyte characters
+        let glob = GlobPattern::new("*ÁRBOL?ñ*");
+        assert!(glob.is_match("el árbolÑñ"));
+        assert!(!glob.is_match("el árbolñ"));
+        assert_eq!(Some("árbol".to_string()), glob.longest_literal());
+        assert_eq!(
+            Some("\\Windows\\".to_string()),
+            GlobPattern::with_case("*\\Windows\\\\*\\cmd.exe", true).longest_literal()
+        );
+        assert_eq!(None, GlobPattern::new("*?*").longest_literal());
+
+        assert!(GlobPattern::new("*").is_match(""));
+        assert!(GlobPattern::new("**a**").is_match("bab"));
+        assert!(!GlobPattern::new("?").is_match(""));
+    }
+}
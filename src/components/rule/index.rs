@@ -0,0 +1,492 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::prelude::types::LogString;
+use crate::prelude::{SiemField, SiemLog};
+use crate::utilities::aho_corasick::AhoCorasick;
+
+use super::expression::MAX_EXPRESSION_GROUPS;
+use super::glob::lowercase;
+use super::{RuleOperator, SiemRule};
+
+/// Values of product, service and category required by a group of subrules
+type LogSourceKey = (Option<LogString>, Option<LogString>, Option<LogString>);
+
+/// Text that must appear in a field and if it is case sensitive. Case insensitive texts are lowercase.
+type IndexedLiteral = (String, bool);
+
+/// Selects the rules that can be triggered by a log without evaluating all of them.
+/// The rules are bucketed by the product, service and category they require and
+/// the literals of Contains/StartsWith/EndsWith and wildcard patterns are searched in a single pass with an Aho-Corasick automaton.
+#[derive(Debug, Clone, Default)]
+pub struct RuleIndex {
+    /// Rule ID => Requirements of each group of subrules
+    rules: BTreeMap<LogString, Vec<IndexedGroup>>,
+    buckets: BTreeMap<LogSourceKey, BTreeSet<LogString>>,
+    /// Field => Literal => Number of groups that need it
+    literals: BTreeMap<LogString, BTreeMap<IndexedLiteral, usize>>,
+    /// Field => Automatons with the case sensitive and the case insensitive literals of the field
+    automatons: BTreeMap<LogString, FieldAutomatons>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FieldAutomatons {
+    cased: Option<(AhoCorasick, Vec<String>)>,
+    /// Searched in the lowercase text
+    uncased: Option<(AhoCorasick, Vec<String>)>,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedGroup {
+    source: LogSourceKey,
+    /// A text that must appear in a field for the group to match: (Field, Literal)
+    literal: Option<(LogString, IndexedLiteral)>,
+}
+
+impl RuleIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces a rule in the index
+    pub fn insert(&mut self, rule: &SiemRule) {
+        self.remove(&rule.id);
+        let groups = index_groups(rule);
+        let mut changed = BTreeSet::new();
+        for group in &groups {
+            self.buckets
+                .entry(group.source.clone())
+                .or_default()
+                .insert(rule.id.clone());
+            if let Some((field, literal)) = &group.literal {
+                let count = self
+                    .literals
+                    .entry(field.clone())
+                    .or_default()
+                    .entry(literal.clone())
+                    .or_default();
+                *count += 1;
+                if *count == 1 {
+                    changed.insert(field.clone());
+                }
+            }
+        }
+        self.rules.insert(rule.id.clone(), groups);
+        self.rebuild(changed);
+    }
+
+    /// Removes a rule from the index
+    pub fn remove(&mut self, id: &str) {
+        let groups = match self.rules.remove(id) {
+            Some(v) => v,
+            None => return,
+        };
+        let mut changed = BTreeSet::new();
+        for group in groups {
+            if let Some(rules) = self.buckets.get_mut(&group.source) {
+                rules.remove(id);
+                if rules.is_empty() {
+                    self.buckets.remove(&group.source);
+                }
+            }
+            if let Some((field, literal)) = group.literal {
+                if let Some(literals) = self.literals.get_mut(&field) {
+                    if let Some(count) = literals.get_mut(&literal) {
+                        *count -= 1;
+                        if *count == 0 {
+                            literals.remove(&literal);
+                            changed.insert(field);
+                        }
+                    }
+                }
+            }
+        }
+        self.rebuild(changed);
+    }
+
+    /// Only the automatons of the fields whose literals changed are built again
+    fn rebuild(&mut self, fields: BTreeSet<LogString>) {
+        for field in fields {
+            let literals = match self.literals.get(&field) {
+                Some(v) if !v.is_empty() => v,
+                _ => {
+                    self.literals.remove(&field);
+                    self.automatons.remove(&field);
+                    continue;
+                }
+            };
+            let automaton = |case_sensitive: bool| {
+                let literals: Vec<String> = literals
+                    .keys()
+                    .filter(|(_, cased)| *cased == case_sensitive)
+                    .map(|(literal, _)| literal.clone())
+                    .collect();
+                (!literals.is_empty()).then(|| (AhoCorasick::new(&literals), literals))
+            };
+            let automatons = FieldAutomatons {
+                cased: automaton(true),
+                uncased: automaton(false),
+            };
+            self.automatons.insert(field, automatons);
+        }
+    }
+
+    /// Number of indexed rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// IDs of the rules that may be triggered by the log
+    pub fn candidates(&self, log: &SiemLog) -> Vec<&LogString> {
+        let mut found: BTreeSet<(&str, &str, bool)> = BTreeSet::new();
+        for (field, automatons) in &self.automatons {
+            let value = match log.field(field) {
+                Some(v) => v,
+                None => continue,
+            };
+            for_each_text(value, |txt| {
+                if let Some((automaton, literals)) = &automatons.cased {
+                    for id in automaton.find_all(txt) {
+                        found.insert((&field[..], &literals[id][..], true));
+                    }
+                }
+                if let Some((automaton, literals)) = &automatons.uncased {
+                    let txt: String = txt.chars().map(lowercase).collect();
+                    for id in automaton.find_all(&txt) {
+                        found.insert((&field[..], &literals[id][..], false));
+                    }
+                }
+            });
+        }
+        let product = log_source_value(log, "product");
+        let service = log_source_value(log, "service");
+        let category = log_source_value(log, "category");
+        let mut to_ret = BTreeSet::new();
+        for product in [None, product.clone()] {
+            for service in [None, service.clone()] {
+                for category in [None, category.clone()] {
+                    let source = (product.clone(), service.clone(), category);
+                    let rules = match self.buckets.get(&source) {
+                        Some(v) => v,
+                        None => continue,
+                    };
+                    for id in rules {
+                        if to_ret.contains(id) {
+                            continue;
+                        }
+                        let groups = match self.rules.get(id) {
+                            Some(v) => v,
+                            None => continue,
+                        };
+                        let matches = groups.iter().any(|group| {
+                            group.source == source
+                                && match &group.literal {
+                                    Some((field, (literal, cased))) => {
+                                        found.contains(&(&field[..], &literal[..], *cased))
+                                    }
+                                    None => true,
+                                }
+                        });
+                        if matches {
+                            to_ret.insert(id);
+                        }
+                    }
+                }
+            }
+        }
+        to_ret.into_iter().collect()
+    }
+}
+
+fn log_source_value(log: &SiemLog, field: &str) -> Option<LogString> {
+    match log.field(field) {
+        Some(SiemField::Text(v)) if !v.is_empty() => Some(v.clone()),
+        _ => None,
+    }
+}
+
+/// Applies the function to the text content of the field, like the text operators do
+fn for_each_text<F>(value: &SiemField, mut function: F)
+where
+    F: FnMut(&str),
+{
+    let txt: Result<&str, _> = value.try_into();
+    if let Ok(txt) = txt {
+        return function(txt);
+    }
+    let list: Result<Vec<LogString>, _> = value.try_into();
+    if let Ok(list) = list {
+        for txt in list {
+            function(&txt);
+        }
+    }
+}
+
+/// Requirements of each group of subrules that triggers the rule.
+/// The negated subrules of an expression do not add requirements.
+/// The steps of a sequence are matched by different logs, so each subrule is a group.
+fn index_groups(rule: &SiemRule) -> Vec<IndexedGroup> {
+    let groups: Vec<Vec<LogString>> = if rule.sequence.is_some() {
+        rule.subrules
+            .keys()
+            .map(|name| vec![name.clone()])
+            .collect()
+    } else if let Some(expression) = &rule.expression {
+        // An empty group can be triggered by any log
+        expression
+            .required_groups(MAX_EXPRESSION_GROUPS)
+            .unwrap_or_else(|| vec![vec![]])
+    } else if rule.conditions.is_empty() {
+        vec![rule.subrules.keys().cloned().collect()]
+    } else {
+        rule.conditions.to_vec()
+    };
+    groups
+        .iter()
+        .map(|group| {
+            let mut source: LogSourceKey = (None, None, None);
+            let mut literal: Option<(LogString, IndexedLiteral)> = None;
+            let conditions = group
+                .iter()
+                .filter_map(|name| rule.subrules.get(name))
+                .flat_map(|subrule| subrule.conditions.iter());
+            for condition in conditions {
+                let slot = match &condition.field[..] {
+                    "product" => Some(&mut source.0),
+                    "service" => Some(&mut source.1),
+                    "category" => Some(&mut source.2),
+                    _ => None,
+                };
+                if let (Some(slot), RuleOperator::Equals(SiemField::Text(v))) =
+                    (slot, &condition.operator)
+                {
+                    if slot.is_none() {
+                        *slot = Some(v.clone());
+                    }
+                    continue;
+                }
+                // The longest literal is the most selective
+                if let Some(required) = required_literal(&condition.operator) {
+                    let is_longer = match &literal {
+                        Some((_, (v, _))) => required.0.len() > v.len(),
+                        None => true,
+                    };
+                    if is_longer {
+                        literal = Some((condition.field.clone(), required));
+                    }
+                }
+            }
+            IndexedGroup { source, literal }
+        })
+        .collect()
+}
+
+/// A literal that must appear in the field for the operator to match
+fn required_literal(operator: &RuleOperator) -> Option<IndexedLiteral> {
+    match operator {
+        RuleOperator::Contains(v) | RuleOperator::StartsWith(v) | RuleOperator::EndsWith(v)
+            if !v.is_empty() =>
+        {
+            Some((v.to_string(), true))
+        }
+        RuleOperator::Glob(glob) => glob
+            .longest_literal()
+            .map(|v| (v, glob.is_case_sensitive())),
+        RuleOperator::All(list) => list
+            .iter()
+            .filter_map(|op| required_literal(op))
+            .max_by_key(|(v, _)| v.len()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::prelude::rule::glob::GlobPattern;
+    use crate::prelude::rule::sequence::{RuleSequence, SequenceStep};
+    use crate::prelude::rule::{AlertGenerator, MitreInfo, RuleCondition, SiemSubRule};
+    use crate::prelude::AlertSeverity;
+
+    fn rule(id: &'static str, conditions: Vec<(&'static str, RuleOperator)>) -> SiemRule {
+        let mut subrules = BTreeMap::new();
+        subrules.insert(
+            LogString::Borrowed("selection"),
+            SiemSubRule {
+                conditions: conditions
+                    .into_iter()
+                    .map(|(field, operator)| RuleCondition {
+                        field: LogString::Borrowed(field),
+                        operator,
+                    })
+                    .collect(),
+                rule_state: None,
+            },
+        );
+        SiemRule {
+            id: LogString::Borrowed(id),
+            name: LogString::Borrowed(id),
+            description: LogString::Borrowed(""),
+            mitre: Cow::Owned(MitreInfo {
+                tactics: vec![],
+                techniques: vec![],
+            }),
+            needed_datasets: vec![],
+            subrules: Cow::Owned(subrules),
+            conditions: Cow::Owned(vec![]),
+            alert: Cow::Owned(AlertGenerator {
+                content: vec![],
+                severity: AlertSeverity::LOW,
+                tags: vec![],
+                aggregation: None,
+            }),
+            aggregation: None,
+            sequence: None,
+            expression: None,
+        }
+    }
+
+    #[test]
+    fn should_select_candidate_rules() {
+        let mut index = RuleIndex::new();
+        index.insert(&rule(
+            "windows_encoded",
+            vec![
+                ("product", RuleOperator::Equals("windows".into())),
+                (
+                    "process.command_line",
+                    RuleOperator::Contains("-EncodedCommand".into()),
+                ),
+            ],
+        ));
+        index.insert(&rule(
+            "linux_shadow",
+            vec![
+                ("product", RuleOperator::Equals("linux".into())),
+                ("file.path", RuleOperator::EndsWith("/shadow".into())),
+            ],
+        ));
+        index.insert(&rule(
+            "any_admin",
+            vec![("user.name", RuleOperator::Equals("admin".into()))],
+        ));
+        assert_eq!(3, index.len());
+
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.set_product("windows");
+        log.add_field(
+            "process.command_line",
+            "powershell -EncodedCommand AAAA".into(),
+        );
+        log.add_field("file.path", "/etc/shadow".into());
+        assert_eq!(vec!["any_admin", "windows_encoded"], index.candidates(&log));
+
+        log.add_field("process.command_line", "cmd.exe".into());
+        assert_eq!(vec!["any_admin"], index.candidates(&log));
+
+        log.set_product("linux");
+        assert_eq!(vec!["any_admin", "linux_shadow"], index.candidates(&log));
+
+        index.remove("linux_shadow");
+        assert_eq!(vec!["any_admin"], index.candidates(&log));
+        assert!(!index.automatons.contains_key("file.path"));
+    }
+
+    #[test]
+    fn should_index_each_step_of_a_sequence() {
+        let mut sequence = rule(
+            "download_and_run",
+            vec![("url.full", RuleOperator::EndsWith(".exe".into()))],
+        );
+        sequence.subrules.to_mut().insert(
+            LogString::Borrowed("execution"),
+            SiemSubRule {
+                conditions: vec![RuleCondition {
+                    field: LogString::Borrowed("process.command_line"),
+                    operator: RuleOperator::Contains("Downloads".into()),
+                }],
+                rule_state: None,
+            },
+        );
+        sequence.sequence = Some(Box::new(RuleSequence {
+            steps: vec![
+                SequenceStep {
+                    subrule: LogString::Borrowed("selection"),
+                    count: 1,
+                },
+                SequenceStep {
+                    subrule: LogString::Borrowed("execution"),
+                    count: 1,
+                },
+            ],
+            join_by: vec![LogString::Borrowed("host.hostname")],
+            max_span: 60_000,
+        }));
+        let mut index = RuleIndex::new();
+        index.insert(&sequence);
+
+        // Each log only matches one of the steps
+        let mut download = SiemLog::new("", 0, "localhost");
+        download.add_field("url.full", "http://example.com/setup.exe".into());
+        assert_eq!(vec!["download_and_run"], index.candidates(&download));
+        let mut execution = SiemLog::new("", 0, "localhost");
+        execution.add_field(
+            "process.command_line",
+            "C:\\Users\\bob\\Downloads\\setup.exe".into(),
+        );
+        assert_eq!(vec!["download_and_run"], index.candidates(&execution));
+        assert!(index
+            .candidates(&SiemLog::new("", 0, "localhost"))
+            .is_empty());
+    }
+
+    #[test]
+    fn should_index_wildcard_patterns() {
+        let mut index = RuleIndex::new();
+        index.insert(&rule(
+            "encoded_glob",
+            vec![(
+                "process.command_line",
+                RuleOperator::Glob(GlobPattern::new("* -EncodedCommand *")),
+            )],
+        ));
+        index.insert(&rule(
+            "cased_glob",
+            vec![(
+                "file.path",
+                RuleOperator::Glob(GlobPattern::with_case("*/etc/Shadow?", true)),
+            )],
+        ));
+        index.insert(&rule(
+            "only_wildcards",
+            vec![("user.name", RuleOperator::Glob(GlobPattern::new("*?*")))],
+        ));
+        assert_eq!(
+            Some(&(" -encodedcommand ".to_string(), false)),
+            index.rules["encoded_glob"][0]
+                .literal
+                .as_ref()
+                .map(|(_, v)| v)
+        );
+
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.add_field(
+            "process.command_line",
+            "POWERSHELL.EXE -ENCODEDCOMMAND AAAA".into(),
+        );
+        log.add_field("file.path", "/etc/shadow1".into());
+        assert_eq!(
+            vec!["encoded_glob", "only_wildcards"],
+            index.candidates(&log)
+        );
+
+        log.add_field("process.command_line", "cmd.exe".into());
+        log.add_field("file.path", "/etc/Shadow1".into());
+        assert_eq!(vec!["cased_glob", "only_wildcards"], index.candidates(&log));
+    }
+}
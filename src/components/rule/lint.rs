@@ -0,0 +1,476 @@
+use std::cmp::Reverse;
+
+use serde::Serialize;
+
+use crate::components::query::check::is_ip_or_network;
+use crate::prelude::dataset::SiemDatasetType;
+use crate::prelude::types::LogString;
+use crate::prelude::{FieldSchema, FieldType, SiemField};
+
+use super::aggregation::AggregationThreshold;
+use super::{RuleError, RuleOperator, SiemRule};
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LintLevel {
+    /// The rule works but probably not as expected
+    Warning,
+    /// The rule has a condition that can never match
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    /// The rule cannot be loaded
+    InvalidRule(RuleError),
+    /// The field does not exist in the schema of the parsers
+    UnknownField,
+    /// The operator cannot be used with the type of the field: (Operator, Field type)
+    IncompatibleOperator(&'static str, &'static str),
+    /// The value is not one of the options of the field
+    UnknownOption(LogString),
+    /// The dataset is used by an operator but it's not in `needed_datasets`
+    MissingDataset(SiemDatasetType),
+}
+
+/// Problem found in a rule
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct LintMessage {
+    pub level: LintLevel,
+    /// Subrule where the problem was found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subrule: Option<LogString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<LogString>,
+    pub kind: LintKind,
+}
+
+/// Checks the rules against the fields generated by the parsers
+#[derive(Clone, Debug, Default)]
+pub struct RuleLinter {
+    schema: FieldSchema,
+}
+
+impl RuleLinter {
+    pub fn new(schema: FieldSchema) -> Self {
+        Self { schema }
+    }
+
+    /// Merges the schemas of all the parsers
+    pub fn from_schemas(schemas: &[&FieldSchema]) -> Self {
+        let mut schema = FieldSchema::new();
+        for other in schemas {
+            schema.add_schema(other);
+            schema.allow_unknown_fields |= other.allow_unknown_fields;
+        }
+        Self { schema }
+    }
+
+    pub fn schema(&self) -> &FieldSchema {
+        &self.schema
+    }
+
+    /// Finds the problems of the rule, sorted by level with errors first
+    pub fn lint(&self, rule: &SiemRule) -> Vec<LintMessage> {
+        let mut messages = Vec::new();
+        if let Err(err) = rule.validate() {
+            messages.push(LintMessage {
+                level: LintLevel::Error,
+                subrule: None,
+                field: None,
+                kind: LintKind::InvalidRule(err),
+            });
+        }
+        for (name, subrule) in rule.subrules.iter() {
+            for condition in &subrule.conditions {
+                let mut context = LintContext {
+                    messages: &mut messages,
+                    subrule: Some(name),
+                    field: &condition.field,
+                };
+                match self.schema.get_field(&condition.field) {
+                    Some(typ) => check_operator(&condition.operator, typ, &mut context),
+                    None => self.unknown_field(&mut context),
+                }
+            }
+        }
+        let mut fields: Vec<&LogString> = Vec::new();
+        if let Some(aggregation) = &rule.aggregation {
+            fields.extend(aggregation.group_by.iter());
+            if let AggregationThreshold::DistinctCount(field, _) = &aggregation.threshold {
+                fields.push(field);
+            }
+        }
+        if let Some(sequence) = &rule.sequence {
+            fields.extend(sequence.join_by.iter());
+        }
+        for field in fields {
+            if self.schema.get_field(field).is_none() {
+                self.unknown_field(&mut LintContext {
+                    messages: &mut messages,
+                    subrule: None,
+                    field,
+                });
+            }
+        }
+        for dataset in needed_datasets(rule) {
+            if !rule.needed_datasets.contains(&dataset) {
+                messages.push(LintMessage {
+                    level: LintLevel::Warning,
+                    subrule: None,
+                    field: None,
+                    kind: LintKind::MissingDataset(dataset),
+                });
+            }
+        }
+        messages.sort_by_key(|message| Reverse(message.level));
+        messages
+    }
+
+    fn unknown_field(&self, context: &mut LintContext) {
+        let level = if self.schema.allow_unknown_fields {
+            LintLevel::Warning
+        } else {
+            LintLevel::Error
+        };
+        context.push(level, LintKind::UnknownField);
+    }
+}
+
+struct LintContext<'a> {
+    messages: &'a mut Vec<LintMessage>,
+    subrule: Option<&'a LogString>,
+    field: &'a LogString,
+}
+
+impl<'a> LintContext<'a> {
+    fn push(&mut self, level: LintLevel, kind: LintKind) {
+        self.messages.push(LintMessage {
+            level,
+            subrule: self.subrule.cloned(),
+            field: Some(self.field.clone()),
+            kind,
+        });
+    }
+}
+
+/// Datasets used by the operators of the rule
+pub fn needed_datasets(rule: &SiemRule) -> Vec<SiemDatasetType> {
+    let mut to_ret = Vec::new();
+    for subrule in rule.subrules.values() {
+        for condition in &subrule.conditions {
+            operator_datasets(&condition.operator, &mut to_ret);
+        }
+    }
+    to_ret
+}
+
+fn operator_datasets(operator: &RuleOperator, datasets: &mut Vec<SiemDatasetType>) {
+    let dataset = match operator {
+        RuleOperator::All(list) | RuleOperator::Any(list) => {
+            for op in list {
+                operator_datasets(op, datasets);
+            }
+            return;
+        }
+        RuleOperator::Not(op) | RuleOperator::B64(op) => return operator_datasets(op, datasets),
+        RuleOperator::InDataset(dataset) => dataset.clone(),
+        RuleOperator::InCountry(_) => SiemDatasetType::GeoIp,
+        _ => return,
+    };
+    if !datasets.contains(&dataset) {
+        datasets.push(dataset);
+    }
+}
+
+impl SiemRule {
+    /// Adds to `needed_datasets` the datasets used by the operators of the rule
+    pub fn fill_needed_datasets(&mut self) {
+        for dataset in needed_datasets(self) {
+            if !self.needed_datasets.contains(&dataset) {
+                self.needed_datasets.push(dataset);
+            }
+        }
+    }
+}
+
+pub(crate) fn type_name(typ: &FieldType) -> &'static str {
+    match typ {
+        FieldType::Ip(_) => "ip",
+        FieldType::Array(_) => "array",
+        FieldType::Text(_) => "text",
+        FieldType::Numeric(_) => "numeric",
+        FieldType::Decimal(_) => "decimal",
+        FieldType::Date(_) => "date",
+        FieldType::TextOptions(_, _) => "text_options",
+    }
+}
+
+pub(crate) fn operator_name(operator: &RuleOperator) -> &'static str {
+    match operator {
+        RuleOperator::All(_) => "all",
+        RuleOperator::Any(_) => "any",
+        RuleOperator::Not(_) => "not",
+        RuleOperator::Equals(_) => "equals",
+        RuleOperator::StartsWith(_) => "starts_with",
+        RuleOperator::EndsWith(_) => "ends_with",
+        RuleOperator::Contains(_) => "contains",
+        RuleOperator::GT(_) => "gt",
+        RuleOperator::LT(_) => "lt",
+        RuleOperator::GTE(_) => "gte",
+        RuleOperator::LTE(_) => "lte",
+        RuleOperator::Matches(_) => "matches",
+        RuleOperator::SameNet(_) => "same_net",
+        RuleOperator::IsLocalIp(_) => "is_local_ip",
+        RuleOperator::IsExternalIp(_) => "is_external_ip",
+        RuleOperator::Exists(_) => "exists",
+        RuleOperator::IsNull(_) => "is_null",
+        RuleOperator::B64(_) => "b64",
+        RuleOperator::InDataset(_) => "in_dataset",
+        RuleOperator::ExistsRuleState(_) => "exists_rule_state",
+        RuleOperator::InCountry(_) => "in_country",
+        RuleOperator::EqualsField(_) => "equals_field",
+        RuleOperator::Glob(_) => "glob",
+    }
+}
+
+/// Datasets whose keys are IPs
+fn is_ip_dataset(dataset: &SiemDatasetType) -> bool {
+    matches!(
+        dataset,
+        SiemDatasetType::GeoIp
+            | SiemDatasetType::IpMac
+            | SiemDatasetType::IpDNS
+            | SiemDatasetType::BlockIp
+            | SiemDatasetType::IpCloudService
+            | SiemDatasetType::IpCloudProvider
+            | SiemDatasetType::IpHeadquarters
+            | SiemDatasetType::CustomMapIpNet(_)
+            | SiemDatasetType::CustomIpList(_)
+            | SiemDatasetType::CustomIpMap(_)
+    )
+}
+
+fn check_operator(operator: &RuleOperator, typ: &FieldType, context: &mut LintContext) {
+    let is_text = matches!(typ, FieldType::Text(_) | FieldType::TextOptions(_, _));
+    let is_number = matches!(
+        typ,
+        FieldType::Numeric(_) | FieldType::Decimal(_) | FieldType::Date(_)
+    );
+    let is_ip = matches!(typ, FieldType::Ip(_));
+    // Level of the problem when the operator is used with this type
+    let level = match operator {
+        RuleOperator::All(list) | RuleOperator::Any(list) => {
+            for op in list {
+                check_operator(op, typ, context);
+            }
+            None
+        }
+        RuleOperator::Not(op) => {
+            check_operator(op, typ, context);
+            None
+        }
+        RuleOperator::B64(op) => {
+            if is_text {
+                // The decoded content is always a text
+                check_operator(op, &FieldType::Text(""), context);
+                None
+            } else {
+                Some(LintLevel::Error)
+            }
+        }
+        // The text representation of the value is used
+        RuleOperator::StartsWith(_)
+        | RuleOperator::EndsWith(_)
+        | RuleOperator::Contains(_)
+        | RuleOperator::Matches(_)
+        | RuleOperator::Glob(_) => (is_ip || is_number).then_some(LintLevel::Warning),
+        RuleOperator::SameNet(_)
+        | RuleOperator::IsLocalIp(_)
+        | RuleOperator::IsExternalIp(_)
+        | RuleOperator::InCountry(_) => (!is_ip).then_some(LintLevel::Error),
+        RuleOperator::InDataset(dataset) => {
+            (is_ip_dataset(dataset) && !is_ip).then_some(LintLevel::Error)
+        }
+        RuleOperator::Equals(expected) => {
+            if let (SiemField::Text(value), FieldType::TextOptions(options, _)) = (expected, typ) {
+                if !options.contains_key(&value[..]) {
+                    context.push(LintLevel::Warning, LintKind::UnknownOption(value.clone()));
+                }
+            }
+            check_value(expected, is_ip, is_number)
+        }
+        RuleOperator::GT(expected)
+        | RuleOperator::LT(expected)
+        | RuleOperator::GTE(expected)
+        | RuleOperator::LTE(expected) => match check_value(expected, is_ip, is_number) {
+            // Numbers stored as text are parsed before comparing them
+            None if is_text
+                && (expected.is_numeric() || matches!(expected, SiemField::Date(_))) =>
+            {
+                Some(LintLevel::Warning)
+            }
+            level => level,
+        },
+        RuleOperator::Exists(_)
+        | RuleOperator::IsNull(_)
+        | RuleOperator::ExistsRuleState(_)
+        | RuleOperator::EqualsField(_) => None,
+    };
+    if let Some(level) = level {
+        context.push(
+            level,
+            LintKind::IncompatibleOperator(operator_name(operator), type_name(typ)),
+        );
+    }
+}
+
+/// Values that can never be equal to the content of the field
+fn check_value(expected: &SiemField, is_ip: bool, is_number: bool) -> Option<LintLevel> {
+    match expected {
+        // Text values are compared with the text representation of the field
+        SiemField::Text(value) if is_ip => (!is_ip_or_network(value)).then_some(LintLevel::Error),
+        SiemField::Text(value) if is_number => value
+            .trim()
+            .parse::<f64>()
+            .is_err()
+            .then_some(LintLevel::Error),
+        SiemField::IP(_) if is_number => Some(LintLevel::Error),
+        SiemField::I64(_) | SiemField::U64(_) | SiemField::F64(_) | SiemField::Date(_) if is_ip => {
+            Some(LintLevel::Error)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::prelude::rule::{AlertGenerator, MitreInfo, RuleCondition, SiemSubRule};
+    use crate::prelude::{AlertSeverity, SiemIp};
+
+    #[test]
+    fn should_report_incompatible_conditions() {
+        let mut schema = FieldSchema::new();
+        schema.insert("source.ip", FieldType::Ip("Source IP"));
+        schema.insert("user.name", FieldType::Text("User name"));
+        schema.insert("destination.port", FieldType::Numeric("Destination port"));
+        let mut options = BTreeMap::new();
+        options.insert("SUCCESS", "The event was successful");
+        options.insert("FAIL", "The event failed");
+        let mut outcome_schema = FieldSchema::new();
+        outcome_schema.insert(
+            "event.outcome",
+            FieldType::TextOptions(options, "Outcome of the event"),
+        );
+        let linter = RuleLinter::from_schemas(&[&schema, &outcome_schema]);
+
+        let condition = |field: &'static str, operator: RuleOperator| RuleCondition {
+            field: LogString::Borrowed(field),
+            operator,
+        };
+        let mut subrules = BTreeMap::new();
+        subrules.insert(
+            LogString::Borrowed("selection"),
+            SiemSubRule {
+                conditions: vec![
+                    condition(
+                        "user.name",
+                        RuleOperator::SameNet((SiemIp::from_ip_str("10.0.0.0").unwrap(), 8)),
+                    ),
+                    condition("source.ip", RuleOperator::InCountry("ES".into())),
+                    condition(
+                        "source.ip",
+                        RuleOperator::Not(Box::new(RuleOperator::InDataset(
+                            SiemDatasetType::BlockIp,
+                        ))),
+                    ),
+                    // Text values are compared with the text of the IP and the number
+                    condition("source.ip", RuleOperator::Equals("192.168.1.10".into())),
+                    condition("source.ip", RuleOperator::Equals("localhost".into())),
+                    condition("destination.port", RuleOperator::Equals("443".into())),
+                    condition("destination.port", RuleOperator::Equals("http".into())),
+                    condition("destination.port", RuleOperator::Contains("80".into())),
+                    condition("event.outcome", RuleOperator::Equals("FAILURE".into())),
+                    condition("process.name", RuleOperator::Equals("cmd.exe".into())),
+                ],
+                rule_state: None,
+            },
+        );
+        let mut rule = SiemRule {
+            id: LogString::Borrowed("id010"),
+            name: LogString::Borrowed("Lint"),
+            description: LogString::Borrowed(""),
+            mitre: Cow::Owned(MitreInfo {
+                tactics: vec![],
+                techniques: vec![],
+            }),
+            needed_datasets: vec![SiemDatasetType::BlockIp],
+            subrules: Cow::Owned(subrules),
+            conditions: Cow::Owned(vec![]),
+            alert: Cow::Owned(AlertGenerator {
+                content: vec![],
+                severity: AlertSeverity::LOW,
+                tags: vec![],
+                aggregation: None,
+            }),
+            aggregation: None,
+            sequence: None,
+            expression: None,
+        };
+        let selection = Some(LogString::Borrowed("selection"));
+        let message = |level, field: &'static str, kind| LintMessage {
+            level,
+            subrule: selection.clone(),
+            field: Some(LogString::Borrowed(field)),
+            kind,
+        };
+        assert_eq!(
+            vec![
+                message(
+                    LintLevel::Error,
+                    "user.name",
+                    LintKind::IncompatibleOperator("same_net", "text")
+                ),
+                message(
+                    LintLevel::Error,
+                    "source.ip",
+                    LintKind::IncompatibleOperator("equals", "ip")
+                ),
+                message(
+                    LintLevel::Error,
+                    "destination.port",
+                    LintKind::IncompatibleOperator("equals", "numeric")
+                ),
+                message(LintLevel::Error, "process.name", LintKind::UnknownField),
+                message(
+                    LintLevel::Warning,
+                    "destination.port",
+                    LintKind::IncompatibleOperator("contains", "numeric")
+                ),
+                message(
+                    LintLevel::Warning,
+                    "event.outcome",
+                    LintKind::UnknownOption(LogString::Borrowed("FAILURE"))
+                ),
+                LintMessage {
+                    level: LintLevel::Warning,
+                    subrule: None,
+                    field: None,
+                    kind: LintKind::MissingDataset(SiemDatasetType::GeoIp),
+                },
+            ],
+            linter.lint(&rule)
+        );
+
+        rule.fill_needed_datasets();
+        assert_eq!(
+            vec![SiemDatasetType::BlockIp, SiemDatasetType::GeoIp],
+            rule.needed_datasets
+        );
+    }
+}
@@ -1,16 +1,38 @@
 use crate::prelude::holder::DatasetHolder;
 use crate::prelude::{AlertAggregation, AlertSeverity, SiemField, SiemIp, SiemLog};
 
-use super::dataset::SiemDatasetType;
+use super::dataset::{SiemDataset, SiemDatasetType};
 use super::mitre::{MitreTactics, MitreTechniques};
 use crate::prelude::types::LogString;
+use crate::utilities::base64;
+use aggregation::RuleAggregation;
+use expression::RuleExpression;
+use glob::GlobPattern;
 use regex::Regex;
+use sequence::RuleSequence;
 use serde::{de, Deserialize, Serialize, Serializer};
+use state::{RuleStateStore, StateContext};
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::str::FromStr;
 
+pub mod aggregation;
+pub mod exception;
+pub mod expression;
+pub mod glob;
+pub mod index;
+pub mod lint;
+pub mod plan;
+pub mod render;
+pub mod sequence;
 pub mod sigma;
+pub mod sigma_condition;
+pub mod sigma_correlation;
+pub mod sigma_export;
+pub mod sigma_loader;
+pub mod sigma_pipeline;
+pub mod state;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct SiemRule {
@@ -29,6 +51,15 @@ pub struct SiemRule {
     pub conditions: Cow<'static, Vec<Vec<LogString>>>,
     /// Generates the content of the alert
     pub alert: Cow<'static, AlertGenerator>,
+    /// Count the matching logs by group and trigger only when the threshold is reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<Box<RuleAggregation>>,
+    /// Ordered list of subrules that must match logs of the same entity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<Box<RuleSequence>>,
+    /// Boolean combination of subrules that triggers this rule. Takes precedence over `conditions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<Box<RuleExpression>>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -99,6 +130,10 @@ pub enum RuleOperator {
     InDataset(SiemDatasetType),
     ExistsRuleState(Vec<RuleState>),
     InCountry(String),
+    /// The value is equal to the value of another field of the log
+    EqualsField(LogString),
+    /// Wildcard pattern with `*` and `?` anywhere. Case insensitive by default.
+    Glob(GlobPattern),
 }
 
 impl PartialEq for RuleOperator {
@@ -123,6 +158,8 @@ impl PartialEq for RuleOperator {
             (Self::B64(v1), Self::B64(v2)) => v1 == v2,
             (Self::InDataset(v1), Self::InDataset(v2)) => v1 == v2,
             (Self::ExistsRuleState(v1), Self::ExistsRuleState(v2)) => v1 == v2,
+            (Self::EqualsField(v1), Self::EqualsField(v2)) => v1 == v2,
+            (Self::Glob(v1), Self::Glob(v2)) => v1 == v2,
             (Self::InCountry(v1), Self::InCountry(v2)) => v1 == v2,
             (Self::IsNull(v1), Self::IsNull(v2)) => v1 == v2,
             _ => false,
@@ -168,7 +205,12 @@ where
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct RuleState {
+    /// Name of the state, shared between the rules that create or check it. Cannot be empty.
+    pub name: LogString,
     pub states: RuleStateValue,
+    /// Time to live of the state in milliseconds. Without TTL the state never expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -191,53 +233,588 @@ impl AlertDictionary {
     }
 }
 
+/// Error found when loading a rule
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
+pub enum RuleError {
+    /// A condition references a subrule that does not exist: (Rule ID, Subrule name)
+    UnknownSubrule(LogString, LogString),
+    /// A condition without subrules would trigger with any log: Rule ID
+    EmptyCondition(LogString),
+    /// The aggregation needs a threshold and a window greater than 0: Rule ID
+    InvalidAggregation(LogString),
+    /// The sequence needs steps with a count and a max span greater than 0: Rule ID
+    InvalidSequence(LogString),
+    /// A rule state without name would share the key with all the other unnamed states: (Rule ID, Subrule name)
+    UnnamedState(LogString, LogString),
+}
+
 impl SiemRule {
-    pub fn matches(&self, log: &mut SiemLog, datasets: &DatasetHolder) -> bool {
-        for rule in self.subrules.as_ref().values() {
-            for condition in &rule.conditions {
-                if !condition.matches(log, datasets) {
-                    return false;
+    /// Checks if the log triggers this rule. The rule triggers when all the subrules of any of the `conditions` match.
+    /// If there are no conditions, all the subrules must match. When the rule has an `expression`, the conditions are ignored.
+    ///
+    /// Returns the list of subrules that matched.
+    pub fn matches(&self, log: &mut SiemLog, datasets: &DatasetHolder) -> Option<Vec<LogString>> {
+        let mut evaluated = BTreeMap::new();
+        self.evaluate(log, datasets, None, &mut evaluated)
+    }
+
+    /// Like `matches` but checking the states with `ExistsRuleState` against the store.
+    /// All the subrules with a `rule_state` are evaluated and, if they match, the state is recorded once the log has been evaluated.
+    pub fn matches_with_state(
+        &self,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        states: &mut RuleStateStore,
+    ) -> Option<Vec<LogString>> {
+        let now = log.i64_field("event.created").unwrap_or(0);
+        let mut evaluated = BTreeMap::new();
+        let matched = {
+            let context = StateContext { store: states, now };
+            self.evaluate_stateful(log, datasets, context, &mut evaluated);
+            self.evaluate(log, datasets, Some(context), &mut evaluated)
+        };
+        self.record_states(&evaluated, log, states, now);
+        matched
+    }
+
+    /// Evaluates the subrules with a `rule_state`, as their state is recorded even if the rule does not need them
+    fn evaluate_stateful<'a>(
+        &'a self,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        context: StateContext,
+        evaluated: &mut BTreeMap<&'a str, bool>,
+    ) {
+        for (name, rule) in self.subrules.iter() {
+            if rule.rule_state.is_some() {
+                let matched = rule.evaluate(log, datasets, Some(context));
+                evaluated.insert(&name[..], matched);
+            }
+        }
+    }
+
+    /// Records the state of the evaluated subrules that matched
+    fn record_states(
+        &self,
+        evaluated: &BTreeMap<&str, bool>,
+        log: &SiemLog,
+        states: &mut RuleStateStore,
+        now: i64,
+    ) {
+        for (name, rule) in self.subrules.iter() {
+            if let Some(state) = &rule.rule_state {
+                if evaluated.get(&name[..]) == Some(&true) {
+                    states.record(state, log, now);
+                }
+            }
+        }
+    }
+
+    fn evaluate<'a>(
+        &'a self,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        state: Option<StateContext>,
+        evaluated: &mut BTreeMap<&'a str, bool>,
+    ) -> Option<Vec<LogString>> {
+        if let Some(expression) = &self.expression {
+            let mut subrule_matches = |name: &'a LogString| -> bool {
+                if let Some(matched) = evaluated.get(&name[..]) {
+                    return *matched;
+                }
+                let matched = self
+                    .subrules
+                    .get(name)
+                    .map(|rule| rule.evaluate(log, datasets, state))
+                    .unwrap_or(false);
+                evaluated.insert(name, matched);
+                matched
+            };
+            if !expression.evaluate(&mut subrule_matches) {
+                return None;
+            }
+            let matched = expression
+                .positive_subrules()
+                .into_iter()
+                .filter(|name| evaluated.get(&name[..]) == Some(&true))
+                .cloned()
+                .collect();
+            return Some(matched);
+        }
+        if self.conditions.is_empty() {
+            for (name, rule) in self.subrules.iter() {
+                let matched = match evaluated.get(&name[..]) {
+                    Some(v) => *v,
+                    None => rule.evaluate(log, datasets, state),
+                };
+                if !matched {
+                    return None;
                 }
             }
+            return Some(self.subrules.keys().cloned().collect());
         }
-        true
+        // A subrule can be used in multiple conditions, but must be evaluated only once
+        for group in self.conditions.iter() {
+            let mut group_matches = true;
+            for name in group {
+                let matched = match evaluated.get(&name[..]) {
+                    Some(v) => *v,
+                    None => {
+                        let matched = self
+                            .subrules
+                            .get(name)
+                            .map(|rule| rule.evaluate(log, datasets, state))
+                            .unwrap_or(false);
+                        evaluated.insert(name, matched);
+                        matched
+                    }
+                };
+                if !matched {
+                    group_matches = false;
+                    break;
+                }
+            }
+            if group_matches {
+                return Some(group.clone());
+            }
+        }
+        None
+    }
+
+    /// Checks that the conditions, the expression and the sequence only reference existing subrules and that the aggregation is usable
+    pub fn validate(&self) -> Result<(), RuleError> {
+        if let Some(aggregation) = &self.aggregation {
+            if aggregation.window <= 0 || aggregation.threshold.limit() == 0 {
+                return Err(RuleError::InvalidAggregation(self.id.clone()));
+            }
+        }
+        if let Some(sequence) = &self.sequence {
+            if sequence.steps.is_empty()
+                || sequence.max_span <= 0
+                || sequence.steps.iter().any(|step| step.count == 0)
+            {
+                return Err(RuleError::InvalidSequence(self.id.clone()));
+            }
+            for step in &sequence.steps {
+                if !self.subrules.contains_key(&step.subrule) {
+                    return Err(RuleError::UnknownSubrule(
+                        self.id.clone(),
+                        step.subrule.clone(),
+                    ));
+                }
+            }
+        }
+        if let Some(expression) = &self.expression {
+            for name in expression.subrules() {
+                if !self.subrules.contains_key(name) {
+                    return Err(RuleError::UnknownSubrule(self.id.clone(), name.clone()));
+                }
+            }
+        }
+        for (name, subrule) in self.subrules.iter() {
+            let unnamed = subrule
+                .rule_state
+                .iter()
+                .chain(
+                    subrule
+                        .conditions
+                        .iter()
+                        .flat_map(|condition| condition.operator.rule_states()),
+                )
+                .any(|state| state.name.is_empty());
+            if unnamed {
+                return Err(RuleError::UnnamedState(self.id.clone(), name.clone()));
+            }
+        }
+        for group in self.conditions.iter() {
+            if group.is_empty() {
+                return Err(RuleError::EmptyCondition(self.id.clone()));
+            }
+            for name in group {
+                if !self.subrules.contains_key(name) {
+                    return Err(RuleError::UnknownSubrule(self.id.clone(), name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SiemSubRule {
+    /// A subrule matches when all its conditions match
+    pub fn matches(&self, log: &mut SiemLog, datasets: &DatasetHolder) -> bool {
+        self.evaluate(log, datasets, None)
+    }
+
+    fn evaluate(
+        &self,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        state: Option<StateContext>,
+    ) -> bool {
+        self.conditions.iter().all(|condition| {
+            condition
+                .operator
+                .evaluate(Operand::Field(&condition.field), log, datasets, state)
+        })
     }
 }
 
 impl RuleCondition {
-    pub fn matches(&self, log: &mut SiemLog, _datasets: &DatasetHolder) -> bool {
-        let field = log.field(&self.field);
-        if field.is_none() {
-            match &self.operator {
-                RuleOperator::Exists(cond) => return !*cond,
-                RuleOperator::IsNull(cond) => return *cond,
-                _ => return false,
+    pub fn matches(&self, log: &mut SiemLog, datasets: &DatasetHolder) -> bool {
+        self.operator.matches(&self.field, log, datasets)
+    }
+}
+
+impl RuleOperator {
+    /// States checked by this operator and the operators inside it
+    fn rule_states(&self) -> Vec<&RuleState> {
+        match self {
+            RuleOperator::ExistsRuleState(list) => list.iter().collect(),
+            RuleOperator::All(list) | RuleOperator::Any(list) => {
+                list.iter().flat_map(|op| op.rule_states()).collect()
+            }
+            RuleOperator::Not(op) | RuleOperator::B64(op) => op.rule_states(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Checks if the content of a field of the log satisfies this operator
+    pub fn matches(&self, field_name: &str, log: &mut SiemLog, datasets: &DatasetHolder) -> bool {
+        self.evaluate(Operand::Field(field_name), log, datasets, None)
+    }
+
+    pub(crate) fn evaluate(
+        &self,
+        operand: Operand,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        state: Option<StateContext>,
+    ) -> bool {
+        match self {
+            RuleOperator::All(list) => list
+                .iter()
+                .all(|op| op.evaluate(operand, log, datasets, state)),
+            RuleOperator::Any(list) => list
+                .iter()
+                .any(|op| op.evaluate(operand, log, datasets, state)),
+            RuleOperator::Not(op) => !op.evaluate(operand, log, datasets, state),
+            RuleOperator::Exists(cond) => operand.value(log).is_some() == *cond,
+            RuleOperator::IsNull(cond) => {
+                matches!(operand.value(log), None | Some(SiemField::Null)) == *cond
             }
+            // Without the field, no other operator can match
+            _ => match operand.value(log) {
+                Some(_) => self.evaluate_field(operand, log, datasets, state),
+                None => false,
+            },
+        }
+    }
+
+    fn evaluate_field(
+        &self,
+        operand: Operand,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        state: Option<StateContext>,
+    ) -> bool {
+        match self {
+            RuleOperator::Equals(expected) => operand
+                .value(log)
+                .map(|value| field_equals(value, expected))
+                .unwrap_or(false),
+            RuleOperator::StartsWith(txt) => operand.text_matches(log, |v| v.starts_with(&txt[..])),
+            RuleOperator::EndsWith(txt) => operand.text_matches(log, |v| v.ends_with(&txt[..])),
+            RuleOperator::Contains(txt) => operand.text_matches(log, |v| v.contains(&txt[..])),
+            RuleOperator::GT(expected) => {
+                matches!(operand.compare(log, expected), Some(Ordering::Greater))
+            }
+            RuleOperator::LT(expected) => {
+                matches!(operand.compare(log, expected), Some(Ordering::Less))
+            }
+            RuleOperator::GTE(expected) => matches!(
+                operand.compare(log, expected),
+                Some(Ordering::Greater) | Some(Ordering::Equal)
+            ),
+            RuleOperator::LTE(expected) => matches!(
+                operand.compare(log, expected),
+                Some(Ordering::Less) | Some(Ordering::Equal)
+            ),
+            RuleOperator::Matches(regex) => operand.text_matches(log, |v| regex.is_match(v)),
+            RuleOperator::Glob(glob) => operand.text_matches(log, |v| glob.is_match(v)),
+            RuleOperator::SameNet((ip, net)) => operand
+                .ip(log)
+                .map(|v| v.same_net(ip, *net))
+                .unwrap_or(false),
+            RuleOperator::IsLocalIp(cond) => operand
+                .ip(log)
+                .map(|v| v.is_local() == *cond)
+                .unwrap_or(false),
+            RuleOperator::IsExternalIp(cond) => operand
+                .ip(log)
+                .map(|v| v.is_local() != *cond)
+                .unwrap_or(false),
+            RuleOperator::B64(op) => match operand.value(log).and_then(decode_b64) {
+                Some(decoded) => op.evaluate(Operand::Value(&decoded), log, datasets, state),
+                None => false,
+            },
+            RuleOperator::InDataset(dataset) => operand
+                .value(log)
+                .map(|value| in_dataset(value, dataset, datasets))
+                .unwrap_or(false),
+            RuleOperator::InCountry(country) => operand
+                .ip(log)
+                .map(|ip| in_country(&ip, country, datasets))
+                .unwrap_or(false),
+            RuleOperator::EqualsField(field) => match (operand.value(log), log.field(field)) {
+                (Some(value), Some(other)) => field_equals(value, other),
+                _ => false,
+            },
+            // Without a state store no state exists
+            RuleOperator::ExistsRuleState(list) => match state {
+                Some(state) => list
+                    .iter()
+                    .all(|rule_state| state.store.exists(rule_state, log, state.now)),
+                None => false,
+            },
+            RuleOperator::All(_)
+            | RuleOperator::Any(_)
+            | RuleOperator::Not(_)
+            | RuleOperator::Exists(_)
+            | RuleOperator::IsNull(_) => self.evaluate(operand, log, datasets, state),
+        }
+    }
+}
+
+/// Value checked by an operator
+#[derive(Clone, Copy)]
+pub(crate) enum Operand<'a> {
+    /// Field of the log. The IP, numeric and text values are read with the cached accessors of `SiemLog`.
+    Field(&'a str),
+    /// Value that is not stored in the log, like the decoded content of a base64 field
+    Value(&'a SiemField),
+}
+
+impl<'a> Operand<'a> {
+    pub(crate) fn value<'b>(self, log: &'b SiemLog) -> Option<&'b SiemField>
+    where
+        'a: 'b,
+    {
+        match self {
+            Operand::Field(name) => log.field(name),
+            Operand::Value(value) => Some(value),
+        }
+    }
+
+    pub(crate) fn ip(self, log: &mut SiemLog) -> Option<SiemIp> {
+        match self {
+            Operand::Field(name) => log.ip_field(name),
+            Operand::Value(value) => field_ip(value),
+        }
+    }
+
+    /// Content of a text value. None for the other types.
+    pub(crate) fn text<'b>(self, log: &'b mut SiemLog) -> Option<&'b str>
+    where
+        'a: 'b,
+    {
+        match self {
+            Operand::Field(name) => log.txt_field(name).map(|v| &v[..]),
+            Operand::Value(value) => value.try_into().ok(),
+        }
+    }
+
+    /// Same as `text_matches`
+    pub(crate) fn text_matches<F>(self, log: &mut SiemLog, predicate: F) -> bool
+    where
+        F: Fn(&str) -> bool,
+    {
+        if let Some(txt) = self.text(log) {
+            return predicate(txt);
         }
-        let _field = field.unwrap();
-        match &self.operator {
-            RuleOperator::All(_) => todo!(),
-            RuleOperator::Any(_) => todo!(),
-            RuleOperator::Not(_) => todo!(),
-            RuleOperator::Equals(_) => todo!(),
-            RuleOperator::StartsWith(_) => todo!(),
-            RuleOperator::EndsWith(_) => todo!(),
-            RuleOperator::Contains(_) => todo!(),
-            RuleOperator::GT(_) => todo!(),
-            RuleOperator::LT(_) => todo!(),
-            RuleOperator::GTE(_) => todo!(),
-            RuleOperator::LTE(_) => todo!(),
-            RuleOperator::Matches(_) => todo!(),
-            RuleOperator::SameNet(_) => todo!(),
-            RuleOperator::IsLocalIp(_) => todo!(),
-            RuleOperator::IsExternalIp(_) => todo!(),
-            RuleOperator::Exists(cond) => *cond,
-            RuleOperator::IsNull(cond) => !*cond,
-            RuleOperator::B64(_) => todo!(),
-            RuleOperator::InDataset(_) => todo!(),
-            RuleOperator::ExistsRuleState(_) => todo!(),
-            RuleOperator::InCountry(_) => todo!(),
+        self.value(log)
+            .map(|value| text_matches(value, predicate))
+            .unwrap_or(false)
+    }
+
+    /// Same as `compare_field`
+    pub(crate) fn compare(self, log: &mut SiemLog, expected: &SiemField) -> Option<Ordering> {
+        if let (Operand::Field(name), SiemField::I64(v) | SiemField::Date(v)) = (self, expected) {
+            // Floats and texts are compared as such
+            if matches!(
+                log.field(name)?,
+                SiemField::I64(_) | SiemField::U64(_) | SiemField::Date(_)
+            ) {
+                return Some(log.i64_field(name)?.cmp(v));
+            }
         }
+        compare_field(self.value(log)?, expected)
+    }
+}
+
+/// Applies the predicate to the text content of a field. Non text fields are casted to text.
+pub(crate) fn text_matches<F>(value: &SiemField, predicate: F) -> bool
+where
+    F: Fn(&str) -> bool,
+{
+    let txt: Result<&str, _> = value.try_into();
+    if let Ok(txt) = txt {
+        return predicate(txt);
+    }
+    if let SiemField::Array(list) = value {
+        return list.iter().any(|v| predicate(v));
+    }
+    let list: Result<Vec<LogString>, _> = value.try_into();
+    match list {
+        Ok(list) => list.iter().any(|v| predicate(v)),
+        Err(_) => false,
+    }
+}
+
+pub(crate) fn field_ip(value: &SiemField) -> Option<SiemIp> {
+    value.try_into().ok()
+}
+
+/// Decodes the base64 content of a text field
+pub(crate) fn decode_b64(value: &SiemField) -> Option<SiemField> {
+    let txt: &str = value.try_into().ok()?;
+    let decoded = base64::decode(txt).ok()?;
+    Some(SiemField::Text(LogString::Owned(
+        String::from_utf8_lossy(&decoded).to_string(),
+    )))
+}
+
+fn parse_txt_field<T: FromStr>(value: &SiemField) -> Option<T> {
+    let txt: &str = value.try_into().ok()?;
+    txt.trim().parse().ok()
+}
+
+pub(crate) fn compare_field(value: &SiemField, expected: &SiemField) -> Option<Ordering> {
+    if let SiemField::F64(v) = value {
+        let expected: f64 = match expected {
+            SiemField::Text(txt) => txt.trim().parse().ok()?,
+            _ => expected.try_into().ok()?,
+        };
+        return v.partial_cmp(&expected);
+    }
+    let is_numeric = value.is_numeric() || matches!(value, SiemField::Date(_));
+    match expected {
+        SiemField::I64(v) | SiemField::Date(v) => {
+            let field: Option<i64> = value.try_into().ok();
+            Some(field.or_else(|| parse_txt_field(value))?.cmp(v))
+        }
+        SiemField::U64(v) => {
+            let field: Option<u64> = value.try_into().ok();
+            Some(field.or_else(|| parse_txt_field(value))?.cmp(v))
+        }
+        SiemField::F64(v) => {
+            let field: Option<f64> = value.try_into().ok();
+            field.or_else(|| parse_txt_field(value))?.partial_cmp(v)
+        }
+        SiemField::IP(ip) => match (field_ip(value)?, ip) {
+            (SiemIp::V4(v1), SiemIp::V4(v2)) => Some(v1.cmp(v2)),
+            (SiemIp::V6(v1), SiemIp::V6(v2)) => Some(v1.cmp(v2)),
+            _ => None,
+        },
+        SiemField::Text(txt) => {
+            if is_numeric {
+                let expected: f64 = txt.trim().parse().ok()?;
+                let field: f64 = value.try_into().ok()?;
+                field.partial_cmp(&expected)
+            } else {
+                let field: &str = value.try_into().ok()?;
+                Some(field.cmp(&txt[..]))
+            }
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn field_equals(value: &SiemField, expected: &SiemField) -> bool {
+    if expected.is_numeric() || matches!(expected, SiemField::Date(_)) {
+        if let Some(ord) = compare_field(value, expected) {
+            return ord == Ordering::Equal;
+        }
+    }
+    if let SiemField::IP(ip) = expected {
+        if let Some(v) = field_ip(value) {
+            return v == *ip;
+        }
+    }
+    match value {
+        SiemField::Array(list) => match expected {
+            SiemField::Text(txt) => list.iter().any(|v| v == txt),
+            _ => {
+                let txt = expected.to_string();
+                list.iter().any(|v| *v == txt)
+            }
+        },
+        field => field == expected,
+    }
+}
+
+pub(crate) fn in_country(ip: &SiemIp, country: &str, datasets: &DatasetHolder) -> bool {
+    let geoip = match datasets.geoip() {
+        Some(v) => v,
+        None => return false,
+    };
+    geoip
+        .get(ip)
+        .map(|info| {
+            info.country_iso.eq_ignore_ascii_case(country)
+                || info.country.eq_ignore_ascii_case(country)
+        })
+        .unwrap_or(false)
+}
+
+pub(crate) fn in_dataset(
+    value: &SiemField,
+    dataset: &SiemDatasetType,
+    datasets: &DatasetHolder,
+) -> bool {
+    let dataset = match datasets.get(dataset) {
+        Some(v) => v,
+        None => return false,
+    };
+    let txt = || -> Option<&str> { value.try_into().ok() };
+    match dataset {
+        SiemDataset::BlockIp(d) | SiemDataset::CustomIpList((_, d)) => {
+            field_ip(value).map(|ip| d.contains(&ip)).unwrap_or(false)
+        }
+        SiemDataset::IpMac(d) | SiemDataset::CustomIpMap((_, d)) => field_ip(value)
+            .map(|ip| d.get(&ip).is_some())
+            .unwrap_or(false),
+        SiemDataset::IpDNS(d) => field_ip(value)
+            .map(|ip| d.get(&ip).is_some())
+            .unwrap_or(false),
+        SiemDataset::IpCloudService(d)
+        | SiemDataset::IpCloudProvider(d)
+        | SiemDataset::IpHeadquarters(d)
+        | SiemDataset::CustomMapIpNet((_, d)) => field_ip(value)
+            .map(|ip| d.get(&ip).is_some())
+            .unwrap_or(false),
+        SiemDataset::GeoIp(d) => field_ip(value)
+            .map(|ip| d.get(&ip).is_some())
+            .unwrap_or(false),
+        SiemDataset::BlockDomain(d)
+        | SiemDataset::BlockEmailSender(d)
+        | SiemDataset::BlockCountry(d)
+        | SiemDataset::CustomTextList((_, d)) => {
+            let txt: Option<LogString> = value.try_into().ok();
+            txt.map(|v| d.contains(&v)).unwrap_or(false)
+        }
+        SiemDataset::MacHost(d)
+        | SiemDataset::HostUser(d)
+        | SiemDataset::UserHeadquarters(d)
+        | SiemDataset::CustomMapText((_, d)) => txt().map(|v| d.get(v).is_some()).unwrap_or(false),
+        SiemDataset::HostVulnerable(d)
+        | SiemDataset::UserTag(d)
+        | SiemDataset::AssetTag(d)
+        | SiemDataset::CustomMapTextList((_, d)) => {
+            txt().map(|v| d.get(v).is_some()).unwrap_or(false)
+        }
+        _ => false,
     }
 }
 
@@ -298,6 +875,9 @@ fn should_be_serialized_and_deserialize() {
             tags: vec![LogString::Borrowed("external_attack")],
             aggregation: None,
         }),
+        aggregation: None,
+        sequence: None,
+        expression: None,
     };
     let json_txt = serde_json::to_string_pretty(&superrule).unwrap();
     let _v: SiemRule = serde_json::from_str(&json_txt).unwrap();
@@ -310,3 +890,370 @@ fn should_be_serialized_and_deserialize() {
         }
     };
 }
+
+#[test]
+fn should_evaluate_rule_operators() {
+    use super::dataset::ip_set::{IpSetDataset, IpSetSynDataset};
+    use std::sync::Arc;
+
+    let mut log = SiemLog::new("powershell -enc", 0, "localhost");
+    log.add_field("source.ip", SiemField::IP([192, 168, 1, 10].into()));
+    log.add_field("destination.ip", SiemField::IP([8, 8, 8, 8].into()));
+    log.add_field("destination.port", SiemField::U64(443));
+    log.add_field("user.name", SiemField::from_str_slice("Administrator"));
+    log.add_field(
+        "process.args",
+        SiemField::from_str_slice("d2hvYW1pIC9hbGw="),
+    );
+    log.add_field("tags", SiemField::Array(vec![LogString::Borrowed("vip")]));
+
+    let mut block_ip = IpSetDataset::new();
+    block_ip.insert([8, 8, 8, 8].into());
+    let (sender, _receiver) = crossbeam_channel::bounded(1);
+    let datasets = DatasetHolder::from_datasets(vec![SiemDataset::BlockIp(IpSetSynDataset::new(
+        Arc::new(block_ip),
+        sender,
+    ))]);
+
+    let check = |field: &str, operator: RuleOperator, log: &mut SiemLog| {
+        RuleCondition {
+            field: LogString::Owned(field.to_string()),
+            operator,
+        }
+        .matches(log, &datasets)
+    };
+    assert!(check(
+        "source.ip",
+        RuleOperator::Equals("192.168.1.10".into()),
+        &mut log
+    ));
+    assert!(check(
+        "destination.port",
+        RuleOperator::Equals(SiemField::I64(443)),
+        &mut log
+    ));
+    assert!(check(
+        "destination.port",
+        RuleOperator::GT(SiemField::I64(100)),
+        &mut log
+    ));
+    assert!(check(
+        "destination.port",
+        RuleOperator::LTE(SiemField::U64(443)),
+        &mut log
+    ));
+    assert!(!check(
+        "destination.port",
+        RuleOperator::LT(SiemField::F64(443.0)),
+        &mut log
+    ));
+    assert!(check(
+        "user.name",
+        RuleOperator::StartsWith("Admin".into()),
+        &mut log
+    ));
+    assert!(check(
+        "user.name",
+        RuleOperator::EndsWith("tor".into()),
+        &mut log
+    ));
+    assert!(check(
+        "user.name",
+        RuleOperator::Contains("minis".into()),
+        &mut log
+    ));
+    assert!(check("tags", RuleOperator::Equals("vip".into()), &mut log));
+    assert!(check(
+        "user.name",
+        RuleOperator::Matches(Regex::new("^Adm.+r$").unwrap()),
+        &mut log
+    ));
+    assert!(check(
+        "source.ip",
+        RuleOperator::SameNet(([192, 168, 1, 0].into(), 24)),
+        &mut log
+    ));
+    assert!(check("source.ip", RuleOperator::IsLocalIp(true), &mut log));
+    assert!(check(
+        "destination.ip",
+        RuleOperator::IsExternalIp(true),
+        &mut log
+    ));
+    assert!(check(
+        "destination.ip",
+        RuleOperator::InDataset(SiemDatasetType::BlockIp),
+        &mut log
+    ));
+    assert!(!check(
+        "source.ip",
+        RuleOperator::InDataset(SiemDatasetType::BlockIp),
+        &mut log
+    ));
+    assert!(check(
+        "process.args",
+        RuleOperator::B64(Box::new(RuleOperator::Contains("whoami".into()))),
+        &mut log
+    ));
+    assert!(check(
+        "source.ip",
+        RuleOperator::All(vec![
+            Box::new(RuleOperator::IsLocalIp(true)),
+            Box::new(RuleOperator::Not(Box::new(RuleOperator::Equals(
+                "192.168.1.11".into()
+            )))),
+        ]),
+        &mut log
+    ));
+    assert!(check(
+        "user.name",
+        RuleOperator::Any(vec![
+            Box::new(RuleOperator::Equals("root".into())),
+            Box::new(RuleOperator::Equals("Administrator".into())),
+        ]),
+        &mut log
+    ));
+    assert!(check("host.name", RuleOperator::IsNull(true), &mut log));
+    assert!(check("host.name", RuleOperator::Exists(false), &mut log));
+    assert!(!check(
+        "host.name",
+        RuleOperator::Contains("".into()),
+        &mut log
+    ));
+}
+
+#[cfg(not(feature = "slow_geoip"))]
+#[test]
+fn should_find_ip_country() {
+    use super::dataset::geo_ip::{GeoIpDataset, GeoIpInfo, GeoIpSynDataset};
+    use std::sync::Arc;
+
+    let mut log = SiemLog::new("", 0, "localhost");
+    log.add_field("source.ip", SiemField::IP([1, 1, 1, 1].into()));
+    let mut geoip = GeoIpDataset::new();
+    geoip.insert(
+        [1, 1, 1, 0].into(),
+        24,
+        GeoIpInfo {
+            country: LogString::Borrowed("Australia"),
+            country_iso: LogString::Borrowed("AU"),
+            ..Default::default()
+        },
+    );
+    let (sender, _receiver) = crossbeam_channel::bounded(1);
+    let datasets = DatasetHolder::from_datasets(vec![SiemDataset::GeoIp(GeoIpSynDataset::new(
+        Arc::new(geoip),
+        sender,
+    ))]);
+    let condition = RuleCondition {
+        field: LogString::Borrowed("source.ip"),
+        operator: RuleOperator::InCountry("au".into()),
+    };
+    assert!(condition.matches(&mut log, &datasets));
+    let condition = RuleCondition {
+        field: LogString::Borrowed("source.ip"),
+        operator: RuleOperator::InCountry("ES".into()),
+    };
+    assert!(!condition.matches(&mut log, &datasets));
+}
+
+#[test]
+fn should_match_any_condition_group() {
+    let subrule = |field: &'static str, value: &'static str| SiemSubRule {
+        conditions: vec![RuleCondition {
+            field: LogString::Borrowed(field),
+            operator: RuleOperator::Equals(value.into()),
+        }],
+        rule_state: None,
+    };
+    let mut subrules = BTreeMap::new();
+    subrules.insert(
+        LogString::Borrowed("is_admin"),
+        subrule("user.name", "admin"),
+    );
+    subrules.insert(LogString::Borrowed("is_root"), subrule("user.name", "root"));
+    subrules.insert(
+        LogString::Borrowed("is_failure"),
+        subrule("event.outcome", "FAIL"),
+    );
+    let mut rule = SiemRule {
+        id: LogString::Borrowed("id002"),
+        name: LogString::Borrowed("Privileged login failure"),
+        description: LogString::Borrowed(""),
+        mitre: Cow::Owned(MitreInfo {
+            tactics: vec![],
+            techniques: vec![],
+        }),
+        needed_datasets: vec![],
+        subrules: Cow::Owned(subrules),
+        conditions: Cow::Owned(vec![
+            vec![
+                LogString::Borrowed("is_admin"),
+                LogString::Borrowed("is_failure"),
+            ],
+            vec![
+                LogString::Borrowed("is_root"),
+                LogString::Borrowed("is_failure"),
+            ],
+        ]),
+        alert: Cow::Owned(AlertGenerator {
+            content: vec![],
+            severity: AlertSeverity::MEDIUM,
+            tags: vec![],
+            aggregation: None,
+        }),
+        aggregation: None,
+        sequence: None,
+        expression: None,
+    };
+    assert!(rule.validate().is_ok());
+    let datasets = DatasetHolder::new();
+    let mut log = SiemLog::new("", 0, "localhost");
+    log.add_field("user.name", "root".into());
+    log.add_field("event.outcome", "FAIL".into());
+    assert_eq!(
+        Some(vec![
+            LogString::Borrowed("is_root"),
+            LogString::Borrowed("is_failure")
+        ]),
+        rule.matches(&mut log, &datasets)
+    );
+    log.add_field("event.outcome", "SUCCESS".into());
+    assert_eq!(None, rule.matches(&mut log, &datasets));
+
+    rule.conditions
+        .to_mut()
+        .push(vec![LogString::Borrowed("is_guest")]);
+    assert_eq!(
+        Err(RuleError::UnknownSubrule(
+            LogString::Borrowed("id002"),
+            LogString::Borrowed("is_guest")
+        )),
+        rule.validate()
+    );
+    let mut dataset = super::dataset::rules::RulesDataset::new();
+    assert!(dataset.insert(rule).is_err());
+}
+
+#[test]
+fn should_correlate_logs_using_rule_state() {
+    let host_state = RuleState {
+        name: LogString::Borrowed("downloaded_file"),
+        states: RuleStateValue::Field(LogString::Borrowed("host.hostname")),
+        ttl: Some(60_000),
+    };
+    let mut subrules = BTreeMap::new();
+    subrules.insert(
+        LogString::Borrowed("download"),
+        SiemSubRule {
+            conditions: vec![RuleCondition {
+                field: LogString::Borrowed("url.path"),
+                operator: RuleOperator::EndsWith(".exe".into()),
+            }],
+            rule_state: Some(host_state.clone()),
+        },
+    );
+    subrules.insert(
+        LogString::Borrowed("powershell"),
+        SiemSubRule {
+            conditions: vec![
+                RuleCondition {
+                    field: LogString::Borrowed("process.name"),
+                    operator: RuleOperator::Equals("powershell.exe".into()),
+                },
+                RuleCondition {
+                    field: LogString::Borrowed("host.hostname"),
+                    operator: RuleOperator::ExistsRuleState(vec![host_state]),
+                },
+            ],
+            rule_state: None,
+        },
+    );
+    let rule = SiemRule {
+        id: LogString::Borrowed("id003"),
+        name: LogString::Borrowed("Powershell after download"),
+        description: LogString::Borrowed(""),
+        mitre: Cow::Owned(MitreInfo {
+            tactics: vec![],
+            techniques: vec![],
+        }),
+        needed_datasets: vec![],
+        subrules: Cow::Owned(subrules),
+        conditions: Cow::Owned(vec![vec![LogString::Borrowed("powershell")]]),
+        alert: Cow::Owned(AlertGenerator {
+            content: vec![],
+            severity: AlertSeverity::HIGH,
+            tags: vec![],
+            aggregation: None,
+        }),
+        aggregation: None,
+        sequence: None,
+        expression: None,
+    };
+    let datasets = DatasetHolder::new();
+    let mut states = RuleStateStore::new();
+    let powershell_log = |host: &str, time: i64| {
+        let mut log = SiemLog::new("", time, "localhost");
+        log.add_field("host.hostname", SiemField::from(host.to_string()));
+        log.add_field("process.name", "powershell.exe".into());
+        log
+    };
+    assert!(rule
+        .matches_with_state(
+            &mut powershell_log("host001", 1_000),
+            &datasets,
+            &mut states
+        )
+        .is_none());
+
+    let mut download = SiemLog::new("", 2_000, "localhost");
+    download.add_field("host.hostname", "host001".into());
+    download.add_field("url.path", "/files/setup.exe".into());
+    assert!(rule
+        .matches_with_state(&mut download, &datasets, &mut states)
+        .is_none());
+    assert_eq!(1, states.len());
+
+    assert!(rule
+        .matches_with_state(
+            &mut powershell_log("host002", 3_000),
+            &datasets,
+            &mut states
+        )
+        .is_none());
+    assert!(rule
+        .matches_with_state(
+            &mut powershell_log("host001", 3_000),
+            &datasets,
+            &mut states
+        )
+        .is_some());
+    assert!(rule
+        .matches_with_state(
+            &mut powershell_log("host001", 70_000),
+            &datasets,
+            &mut states
+        )
+        .is_none());
+    // Stateless evaluation never finds states
+    assert!(rule
+        .matches(&mut powershell_log("host001", 3_000), &datasets)
+        .is_none());
+
+    // All the unnamed states would share the same key
+    assert!(rule.validate().is_ok());
+    let mut unnamed = rule.clone();
+    if let Some(subrule) = unnamed.subrules.to_mut().get_mut("powershell") {
+        subrule.conditions[1].operator =
+            RuleOperator::Not(Box::new(RuleOperator::ExistsRuleState(vec![RuleState {
+                name: LogString::Borrowed(""),
+                states: RuleStateValue::Field(LogString::Borrowed("host.hostname")),
+                ttl: None,
+            }])));
+    }
+    assert!(matches!(
+        unnamed.validate(),
+        Err(RuleError::UnnamedState(_, subrule)) if subrule == "powershell"
+    ));
+    // The name is required
+    assert!(serde_json::from_str::<RuleState>(r#"{"states":{"Field":"host.hostname"}}"#).is_err());
+}
@@ -0,0 +1,616 @@
+use std::cmp::Ordering;
+
+use regex::Regex;
+
+use crate::prelude::dataset::SiemDatasetType;
+use crate::prelude::holder::DatasetHolder;
+use crate::prelude::types::LogString;
+use crate::prelude::{SiemField, SiemIp, SiemLog};
+
+use super::expression::RuleExpression;
+use super::glob::GlobPattern;
+use super::state::{RuleStateStore, StateContext};
+use super::{
+    decode_b64, field_equals, in_country, in_dataset, Operand, RuleError, RuleOperator, RuleState,
+    SiemRule, SiemSubRule,
+};
+
+/// Immutable evaluation plan of a rule.
+/// The field names are collected in advance, regexes and network masks are built beforehand
+/// and the conditions are sorted so the cheapest ones are evaluated first.
+/// The values are read with the cached accessors of the log, so each field is casted only once.
+#[derive(Debug, Clone)]
+pub struct CompiledRule {
+    rule: SiemRule,
+    /// Distinct fields used by the rule. The conditions reference them by position.
+    fields: Vec<LogString>,
+    subrules: Vec<CompiledSubRule>,
+    /// Groups of subrules that trigger the rule, in the same order as `SiemRule.conditions`
+    groups: Vec<CompiledGroup>,
+    /// Used instead of the groups when the rule has an expression
+    expression: Option<CompiledExpression>,
+    /// Subrules of the expression that are not negated
+    positive: Vec<(usize, LogString)>,
+}
+
+#[derive(Debug, Clone)]
+enum CompiledExpression {
+    Subrule(usize),
+    /// The children are sorted by cost
+    And(Vec<CompiledExpression>),
+    Or(Vec<CompiledExpression>),
+    Not(Box<CompiledExpression>),
+}
+
+#[derive(Debug, Clone)]
+struct CompiledGroup {
+    names: Vec<LogString>,
+    /// Position of the subrules sorted by cost
+    subrules: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledSubRule {
+    conditions: Vec<CompiledCondition>,
+    rule_state: Option<RuleState>,
+    cost: u32,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledCondition {
+    field: usize,
+    operator: CompiledOperator,
+}
+
+#[derive(Debug, Clone)]
+enum CompiledOperator {
+    All(Vec<CompiledOperator>),
+    Any(Vec<CompiledOperator>),
+    Not(Box<CompiledOperator>),
+    Exists(bool),
+    IsNull(bool),
+    EqualsText(LogString),
+    Equals(SiemField),
+    StartsWith(String),
+    EndsWith(String),
+    Contains(String),
+    Compare(SiemField, fn(Ordering) -> bool),
+    Matches(Regex),
+    Glob(GlobPattern),
+    NetV4(u32, u32),
+    NetV6(u128, u128),
+    IsLocalIp(bool),
+    B64(Box<CompiledOperator>),
+    InDataset(SiemDatasetType),
+    InCountry(String),
+    EqualsField(LogString),
+    ExistsRuleState(Vec<RuleState>),
+}
+
+impl CompiledOperator {
+    fn compile(operator: &RuleOperator) -> CompiledOperator {
+        match operator {
+            RuleOperator::All(list) => {
+                let mut list: Vec<CompiledOperator> =
+                    list.iter().map(|op| Self::compile(op)).collect();
+                list.sort_by_key(|op| op.cost());
+                CompiledOperator::All(list)
+            }
+            RuleOperator::Any(list) => {
+                let mut list: Vec<CompiledOperator> =
+                    list.iter().map(|op| Self::compile(op)).collect();
+                list.sort_by_key(|op| op.cost());
+                CompiledOperator::Any(list)
+            }
+            RuleOperator::Not(op) => CompiledOperator::Not(Box::new(Self::compile(op))),
+            RuleOperator::Exists(v) => CompiledOperator::Exists(*v),
+            RuleOperator::IsNull(v) => CompiledOperator::IsNull(*v),
+            RuleOperator::Equals(SiemField::Text(v)) => CompiledOperator::EqualsText(v.clone()),
+            RuleOperator::Equals(v) => CompiledOperator::Equals(v.clone()),
+            RuleOperator::StartsWith(v) => CompiledOperator::StartsWith(v.clone()),
+            RuleOperator::EndsWith(v) => CompiledOperator::EndsWith(v.clone()),
+            RuleOperator::Contains(v) => CompiledOperator::Contains(v.clone()),
+            RuleOperator::GT(v) => CompiledOperator::Compare(v.clone(), Ordering::is_gt),
+            RuleOperator::LT(v) => CompiledOperator::Compare(v.clone(), Ordering::is_lt),
+            RuleOperator::GTE(v) => CompiledOperator::Compare(v.clone(), Ordering::is_ge),
+            RuleOperator::LTE(v) => CompiledOperator::Compare(v.clone(), Ordering::is_le),
+            RuleOperator::Matches(v) => CompiledOperator::Matches(v.clone()),
+            RuleOperator::Glob(v) => CompiledOperator::Glob(v.clone()),
+            RuleOperator::SameNet((SiemIp::V4(ip), net)) => {
+                let mask = u32::MAX
+                    .checked_shl(32u32.saturating_sub(*net as u32))
+                    .unwrap_or(0);
+                CompiledOperator::NetV4(ip & mask, mask)
+            }
+            RuleOperator::SameNet((SiemIp::V6(ip), net)) => {
+                let mask = u128::MAX
+                    .checked_shl(128u32.saturating_sub(*net as u32))
+                    .unwrap_or(0);
+                CompiledOperator::NetV6(ip & mask, mask)
+            }
+            RuleOperator::IsLocalIp(v) => CompiledOperator::IsLocalIp(*v),
+            RuleOperator::IsExternalIp(v) => CompiledOperator::IsLocalIp(!*v),
+            RuleOperator::B64(op) => CompiledOperator::B64(Box::new(Self::compile(op))),
+            RuleOperator::InDataset(v) => CompiledOperator::InDataset(v.clone()),
+            RuleOperator::InCountry(v) => CompiledOperator::InCountry(v.clone()),
+            RuleOperator::EqualsField(v) => CompiledOperator::EqualsField(v.clone()),
+            RuleOperator::ExistsRuleState(v) => CompiledOperator::ExistsRuleState(v.clone()),
+        }
+    }
+
+    /// Estimated cost of the evaluation
+    fn cost(&self) -> u32 {
+        match self {
+            CompiledOperator::All(list) | CompiledOperator::Any(list) => {
+                list.iter().map(|op| op.cost()).sum()
+            }
+            CompiledOperator::Not(op) => op.cost(),
+            CompiledOperator::Exists(_) | CompiledOperator::IsNull(_) => 1,
+            CompiledOperator::EqualsText(_)
+            | CompiledOperator::NetV4(_, _)
+            | CompiledOperator::NetV6(_, _)
+            | CompiledOperator::IsLocalIp(_) => 2,
+            CompiledOperator::Equals(_)
+            | CompiledOperator::Compare(_, _)
+            | CompiledOperator::StartsWith(_)
+            | CompiledOperator::EndsWith(_)
+            | CompiledOperator::EqualsField(_) => 3,
+            CompiledOperator::Contains(_) => 5,
+            CompiledOperator::Glob(_) => 7,
+            CompiledOperator::InDataset(_) | CompiledOperator::ExistsRuleState(_) => 8,
+            CompiledOperator::InCountry(_) => 10,
+            CompiledOperator::Matches(_) => 20,
+            CompiledOperator::B64(op) => 10 + op.cost(),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        operand: Operand,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        state: Option<StateContext>,
+    ) -> bool {
+        match self {
+            CompiledOperator::All(list) => {
+                return list
+                    .iter()
+                    .all(|op| op.evaluate(operand, log, datasets, state))
+            }
+            CompiledOperator::Any(list) => {
+                return list
+                    .iter()
+                    .any(|op| op.evaluate(operand, log, datasets, state))
+            }
+            CompiledOperator::Not(op) => return !op.evaluate(operand, log, datasets, state),
+            CompiledOperator::Exists(cond) => return operand.value(log).is_some() == *cond,
+            CompiledOperator::IsNull(cond) => {
+                return matches!(operand.value(log), None | Some(SiemField::Null)) == *cond
+            }
+            _ => {
+                if operand.value(log).is_none() {
+                    return false;
+                }
+            }
+        };
+        match self {
+            CompiledOperator::EqualsText(expected) => match operand.text(log) {
+                Some(txt) => txt == &expected[..],
+                None => operand
+                    .value(log)
+                    .map(|value| field_equals(value, &SiemField::Text(expected.clone())))
+                    .unwrap_or(false),
+            },
+            CompiledOperator::Equals(expected) => operand
+                .value(log)
+                .map(|value| field_equals(value, expected))
+                .unwrap_or(false),
+            CompiledOperator::StartsWith(txt) => {
+                operand.text_matches(log, |v| v.starts_with(&txt[..]))
+            }
+            CompiledOperator::EndsWith(txt) => operand.text_matches(log, |v| v.ends_with(&txt[..])),
+            CompiledOperator::Contains(txt) => operand.text_matches(log, |v| v.contains(&txt[..])),
+            CompiledOperator::Compare(expected, accept) => {
+                operand.compare(log, expected).map(accept).unwrap_or(false)
+            }
+            CompiledOperator::Matches(regex) => operand.text_matches(log, |v| regex.is_match(v)),
+            CompiledOperator::Glob(glob) => operand.text_matches(log, |v| glob.is_match(v)),
+            CompiledOperator::NetV4(net, mask) => match operand.ip(log) {
+                Some(SiemIp::V4(ip)) => ip & mask == *net,
+                _ => false,
+            },
+            CompiledOperator::NetV6(net, mask) => match operand.ip(log) {
+                Some(SiemIp::V6(ip)) => ip & mask == *net,
+                _ => false,
+            },
+            CompiledOperator::IsLocalIp(cond) => operand
+                .ip(log)
+                .map(|ip| ip.is_local() == *cond)
+                .unwrap_or(false),
+            CompiledOperator::B64(op) => match operand.value(log).and_then(decode_b64) {
+                Some(decoded) => op.evaluate(Operand::Value(&decoded), log, datasets, state),
+                None => false,
+            },
+            CompiledOperator::InDataset(dataset) => operand
+                .value(log)
+                .map(|value| in_dataset(value, dataset, datasets))
+                .unwrap_or(false),
+            CompiledOperator::InCountry(country) => operand
+                .ip(log)
+                .map(|ip| in_country(&ip, country, datasets))
+                .unwrap_or(false),
+            CompiledOperator::EqualsField(field) => match (operand.value(log), log.field(field)) {
+                (Some(value), Some(other)) => field_equals(value, other),
+                _ => false,
+            },
+            CompiledOperator::ExistsRuleState(list) => match state {
+                Some(state) => list
+                    .iter()
+                    .all(|rule_state| state.store.exists(rule_state, log, state.now)),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl CompiledSubRule {
+    fn evaluate(
+        &self,
+        fields: &[LogString],
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        state: Option<StateContext>,
+    ) -> bool {
+        self.conditions.iter().all(|condition| {
+            let operand = Operand::Field(&fields[condition.field]);
+            condition.operator.evaluate(operand, log, datasets, state)
+        })
+    }
+}
+
+impl CompiledRule {
+    /// Validates and compiles the rule
+    pub fn new(rule: &SiemRule) -> Result<CompiledRule, RuleError> {
+        rule.validate()?;
+        let mut fields: Vec<LogString> = Vec::new();
+        let mut positions = std::collections::BTreeMap::new();
+        for (position, name) in rule.subrules.keys().enumerate() {
+            positions.insert(name.clone(), position);
+        }
+        let subrules: Vec<CompiledSubRule> = rule
+            .subrules
+            .values()
+            .map(|subrule| compile_subrule(subrule, &mut fields))
+            .collect();
+        let groups = if rule.conditions.is_empty() {
+            vec![rule.subrules.keys().cloned().collect::<Vec<LogString>>()]
+        } else {
+            rule.conditions.to_vec()
+        };
+        let groups = groups
+            .into_iter()
+            .map(|names| {
+                let mut order: Vec<usize> = names.iter().map(|name| positions[name]).collect();
+                order.sort_by_key(|position| subrules[*position].cost);
+                order.dedup();
+                CompiledGroup {
+                    names,
+                    subrules: order,
+                }
+            })
+            .collect();
+        let expression = rule
+            .expression
+            .as_ref()
+            .map(|expression| compile_expression(expression, &positions, &subrules));
+        let positive = match &rule.expression {
+            Some(expression) => expression
+                .positive_subrules()
+                .into_iter()
+                .map(|name| (positions[name], name.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(CompiledRule {
+            rule: rule.clone(),
+            fields,
+            subrules,
+            groups,
+            expression,
+            positive,
+        })
+    }
+
+    /// Rule used to build this plan
+    pub fn rule(&self) -> &SiemRule {
+        &self.rule
+    }
+
+    /// Same as `SiemRule::matches`
+    pub fn matches(&self, log: &mut SiemLog, datasets: &DatasetHolder) -> Option<Vec<LogString>> {
+        let mut evaluated = vec![None; self.subrules.len()];
+        self.evaluate(log, datasets, None, &mut evaluated)
+    }
+
+    /// Same as `SiemRule::matches_with_state`
+    pub fn matches_with_state(
+        &self,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        states: &mut RuleStateStore,
+    ) -> Option<Vec<LogString>> {
+        let now = log.i64_field("event.created").unwrap_or(0);
+        let mut evaluated = vec![None; self.subrules.len()];
+        let matched = {
+            let context = StateContext { store: states, now };
+            for (position, subrule) in self.subrules.iter().enumerate() {
+                if subrule.rule_state.is_some() {
+                    evaluated[position] =
+                        Some(subrule.evaluate(&self.fields, log, datasets, Some(context)));
+                }
+            }
+            self.evaluate(log, datasets, Some(context), &mut evaluated)
+        };
+        for (position, subrule) in self.subrules.iter().enumerate() {
+            if let Some(state) = &subrule.rule_state {
+                if evaluated[position] == Some(true) {
+                    states.record(state, log, now);
+                }
+            }
+        }
+        matched
+    }
+
+    fn evaluate(
+        &self,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        state: Option<StateContext>,
+        evaluated: &mut [Option<bool>],
+    ) -> Option<Vec<LogString>> {
+        if let Some(expression) = &self.expression {
+            let mut subrule_matches = |position: usize| -> bool {
+                *evaluated[position].get_or_insert_with(|| {
+                    self.subrules[position].evaluate(&self.fields, log, datasets, state)
+                })
+            };
+            if !expression.evaluate(&mut subrule_matches) {
+                return None;
+            }
+            let matched = self
+                .positive
+                .iter()
+                .filter(|(position, _)| evaluated[*position] == Some(true))
+                .map(|(_, name)| name.clone())
+                .collect();
+            return Some(matched);
+        }
+        for group in &self.groups {
+            let matched = group.subrules.iter().all(|position| {
+                *evaluated[*position].get_or_insert_with(|| {
+                    self.subrules[*position].evaluate(&self.fields, log, datasets, state)
+                })
+            });
+            if matched {
+                return Some(group.names.clone());
+            }
+        }
+        None
+    }
+}
+
+impl CompiledExpression {
+    fn evaluate<F>(&self, subrule: &mut F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        match self {
+            CompiledExpression::Subrule(position) => subrule(*position),
+            CompiledExpression::And(list) => list.iter().all(|v| v.evaluate(subrule)),
+            CompiledExpression::Or(list) => list.iter().any(|v| v.evaluate(subrule)),
+            CompiledExpression::Not(v) => !v.evaluate(subrule),
+        }
+    }
+
+    fn cost(&self, subrules: &[CompiledSubRule]) -> u32 {
+        match self {
+            CompiledExpression::Subrule(position) => subrules[*position].cost,
+            CompiledExpression::And(list) | CompiledExpression::Or(list) => {
+                list.iter().map(|v| v.cost(subrules)).sum()
+            }
+            CompiledExpression::Not(v) => v.cost(subrules),
+        }
+    }
+}
+
+fn compile_expression(
+    expression: &RuleExpression,
+    positions: &std::collections::BTreeMap<LogString, usize>,
+    subrules: &[CompiledSubRule],
+) -> CompiledExpression {
+    let compile_list = |list: &[RuleExpression]| {
+        let mut list: Vec<CompiledExpression> = list
+            .iter()
+            .map(|v| compile_expression(v, positions, subrules))
+            .collect();
+        list.sort_by_key(|v| v.cost(subrules));
+        list
+    };
+    match expression {
+        RuleExpression::Subrule(name) => CompiledExpression::Subrule(positions[name]),
+        RuleExpression::And(list) => CompiledExpression::And(compile_list(list)),
+        RuleExpression::Or(list) => CompiledExpression::Or(compile_list(list)),
+        RuleExpression::Not(v) => {
+            CompiledExpression::Not(Box::new(compile_expression(v, positions, subrules)))
+        }
+    }
+}
+
+fn compile_subrule(subrule: &SiemSubRule, fields: &mut Vec<LogString>) -> CompiledSubRule {
+    let mut conditions: Vec<CompiledCondition> = subrule
+        .conditions
+        .iter()
+        .map(|condition| {
+            let field = match fields.iter().position(|v| *v == condition.field) {
+                Some(v) => v,
+                None => {
+                    fields.push(condition.field.clone());
+                    fields.len() - 1
+                }
+            };
+            CompiledCondition {
+                field,
+                operator: CompiledOperator::compile(&condition.operator),
+            }
+        })
+        .collect();
+    conditions.sort_by_key(|condition| condition.operator.cost());
+    CompiledSubRule {
+        cost: conditions.iter().map(|c| c.operator.cost()).sum(),
+        conditions,
+        rule_state: subrule.rule_state.clone(),
+    }
+}
+
+impl SiemRule {
+    /// Builds an immutable evaluation plan of the rule
+    pub fn compile(&self) -> Result<CompiledRule, RuleError> {
+        CompiledRule::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::prelude::rule::{AlertGenerator, MitreInfo, RuleCondition};
+    use crate::prelude::AlertSeverity;
+
+    fn condition(field: &'static str, operator: RuleOperator) -> RuleCondition {
+        RuleCondition {
+            field: LogString::Borrowed(field),
+            operator,
+        }
+    }
+
+    fn suspicious_process_rule() -> SiemRule {
+        let mut subrules = BTreeMap::new();
+        subrules.insert(
+            LogString::Borrowed("encoded_powershell"),
+            SiemSubRule {
+                conditions: vec![
+                    condition(
+                        "process.command_line",
+                        RuleOperator::Matches(
+                            Regex::from_str(r"(?i)\s-e(nc|ncodedcommand)?\s").unwrap(),
+                        ),
+                    ),
+                    condition(
+                        "process.command_line",
+                        RuleOperator::Not(Box::new(RuleOperator::Contains("-NoProfile".into()))),
+                    ),
+                    condition(
+                        "process.name",
+                        RuleOperator::Equals("powershell.exe".into()),
+                    ),
+                ],
+                rule_state: None,
+            },
+        );
+        subrules.insert(
+            LogString::Borrowed("internal_network"),
+            SiemSubRule {
+                conditions: vec![
+                    condition(
+                        "source.ip",
+                        RuleOperator::SameNet(([10, 0, 0, 0].into(), 8)),
+                    ),
+                    condition(
+                        "destination.port",
+                        RuleOperator::Any(vec![
+                            Box::new(RuleOperator::GTE(SiemField::I64(1024))),
+                            Box::new(RuleOperator::Equals(SiemField::U64(445))),
+                        ]),
+                    ),
+                ],
+                rule_state: None,
+            },
+        );
+        SiemRule {
+            id: LogString::Borrowed("id006"),
+            name: LogString::Borrowed("Encoded powershell"),
+            description: LogString::Borrowed(""),
+            mitre: Cow::Owned(MitreInfo {
+                tactics: vec![],
+                techniques: vec![],
+            }),
+            needed_datasets: vec![],
+            subrules: Cow::Owned(subrules),
+            conditions: Cow::Owned(vec![]),
+            alert: Cow::Owned(AlertGenerator {
+                content: vec![],
+                severity: AlertSeverity::HIGH,
+                tags: vec![],
+                aggregation: None,
+            }),
+            aggregation: None,
+            sequence: None,
+            expression: None,
+        }
+    }
+
+    fn process_log(name: &str, command: &str, source: [u32; 4], port: u64) -> SiemLog {
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.add_field("process.name", SiemField::from(name.to_string()));
+        log.add_field("process.command_line", SiemField::from(command.to_string()));
+        log.add_field("source.ip", SiemField::IP(source.into()));
+        log.add_field("destination.port", SiemField::U64(port));
+        log
+    }
+
+    #[test]
+    fn should_match_like_the_interpreter() {
+        let rule = suspicious_process_rule();
+        let plan = rule.compile().unwrap();
+        let datasets = DatasetHolder::new();
+        let logs = vec![
+            process_log(
+                "powershell.exe",
+                "powershell -enc AAAA",
+                [10, 1, 1, 1],
+                3389,
+            ),
+            process_log("powershell.exe", "powershell -enc AAAA", [10, 1, 1, 1], 445),
+            process_log("powershell.exe", "powershell -enc AAAA", [10, 1, 1, 1], 80),
+            process_log(
+                "powershell.exe",
+                "powershell -NoProfile -enc AAAA",
+                [10, 1, 1, 1],
+                445,
+            ),
+            process_log(
+                "powershell.exe",
+                "powershell -enc AAAA",
+                [192, 168, 1, 1],
+                445,
+            ),
+            process_log("cmd.exe", "cmd /c whoami", [10, 1, 1, 1], 445),
+        ];
+        let expected = [true, true, false, false, false, false];
+        for (mut log, expected) in logs.into_iter().zip(expected) {
+            let compiled = plan.matches(&mut log, &datasets);
+            assert_eq!(expected, compiled.is_some());
+            assert_eq!(rule.matches(&mut log, &datasets), compiled);
+        }
+        let mut invalid = rule.clone();
+        invalid
+            .conditions
+            .to_mut()
+            .push(vec![LogString::Borrowed("unknown")]);
+        assert!(invalid.compile().is_err());
+    }
+}
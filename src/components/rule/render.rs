@@ -0,0 +1,170 @@
+use crate::prelude::holder::DatasetHolder;
+use crate::prelude::i18n::Language;
+use crate::prelude::types::LogString;
+use crate::prelude::{SiemAlert, SiemLog};
+
+use super::{AlertContent, SiemRule};
+
+/// Builds the alert of a rule triggered by a log.
+/// The texts of the rule are translated using the I18n dataset when available.
+#[derive(Debug, Clone, Default)]
+pub struct AlertRenderer {
+    language: Language,
+}
+
+impl AlertRenderer {
+    pub fn new(language: Language) -> Self {
+        Self { language }
+    }
+
+    pub fn language(&self) -> &Language {
+        &self.language
+    }
+
+    /// Generates the alert using the `AlertGenerator` of the rule. `matched` is the list of subrules that matched the log.
+    /// `date` is the time of the alert in milliseconds, the creation time of the log for the rule engines.
+    pub fn render(
+        &self,
+        rule: &SiemRule,
+        log: &SiemLog,
+        matched: &[LogString],
+        datasets: &DatasetHolder,
+        date: i64,
+    ) -> SiemAlert {
+        let description = if rule.alert.content.is_empty() {
+            self.translate(&rule.description, datasets)
+        } else {
+            self.render_content(&rule.alert.content, log, matched, datasets)
+        };
+        SiemAlert {
+            title: self.translate(&rule.name, datasets),
+            description,
+            severity: rule.alert.severity.clone(),
+            date,
+            tags: rule.alert.tags.iter().map(|v| v.to_string()).collect(),
+            techniques: rule.mitre.techniques.clone(),
+            rule: rule.id.to_string(),
+            log: log.clone(),
+            aggregation: rule.alert.aggregation.clone(),
+        }
+    }
+
+    /// Joins the content of the alert: texts are translated, fields are replaced with the value in the log
+    /// and the matched subrules are joined with the separator.
+    pub fn render_content(
+        &self,
+        content: &[AlertContent],
+        log: &SiemLog,
+        matched: &[LogString],
+        datasets: &DatasetHolder,
+    ) -> String {
+        let mut to_ret = String::with_capacity(128);
+        for piece in content {
+            match piece {
+                AlertContent::Text(txt) => to_ret.push_str(&self.translate(txt, datasets)),
+                AlertContent::Field(field) => {
+                    if let Some(value) = log.field(field) {
+                        to_ret.push_str(&value.to_string());
+                    }
+                }
+                AlertContent::MatchedRules(separator) => to_ret.push_str(&matched.join(separator)),
+            }
+        }
+        to_ret
+    }
+
+    fn translate(&self, text: &str, datasets: &DatasetHolder) -> String {
+        datasets
+            .i18n()
+            .and_then(|i18n| i18n.get_or_default(text, &self.language))
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| text.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::prelude::i18n::{I18nDataset, I18nSynDataset};
+    use crate::prelude::mitre::{MitreTactics, MitreTechniques};
+    use crate::prelude::rule::{AlertGenerator, MitreInfo};
+    use crate::prelude::{AlertSeverity, SiemDataset, SiemField};
+
+    #[test]
+    fn should_render_localized_alert() {
+        let rule = SiemRule {
+            id: LogString::Borrowed("id008"),
+            name: LogString::Borrowed("rule.malicious_ip.title"),
+            description: LogString::Borrowed(""),
+            mitre: Cow::Owned(MitreInfo {
+                tactics: vec![MitreTactics::TA0011],
+                techniques: vec![MitreTechniques::T1071],
+            }),
+            needed_datasets: vec![],
+            subrules: Cow::Owned(BTreeMap::new()),
+            conditions: Cow::Owned(vec![]),
+            alert: Cow::Owned(AlertGenerator {
+                content: vec![
+                    AlertContent::Text(LogString::Borrowed("rule.malicious_ip.connection")),
+                    AlertContent::Field(LogString::Borrowed("source.ip")),
+                    AlertContent::Text(LogString::Borrowed(" => ")),
+                    AlertContent::Field(LogString::Borrowed("destination.ip")),
+                    AlertContent::Text(LogString::Borrowed(" (")),
+                    AlertContent::MatchedRules(LogString::Borrowed(", ")),
+                    AlertContent::Text(LogString::Borrowed(")")),
+                ],
+                severity: AlertSeverity::HIGH,
+                tags: vec![LogString::Borrowed("c2")],
+                aggregation: None,
+            }),
+            aggregation: None,
+            sequence: None,
+            expression: None,
+        };
+        let mut dataset = I18nDataset::new();
+        dataset.insert(
+            Language::ES,
+            LogString::Borrowed("rule.malicious_ip.title"),
+            LogString::Borrowed("Conexión a IP maliciosa"),
+        );
+        dataset.insert(
+            Language::ES,
+            LogString::Borrowed("rule.malicious_ip.connection"),
+            LogString::Borrowed("Conexión desde "),
+        );
+        let (sender, _receiver) = crossbeam_channel::bounded(1);
+        let datasets = DatasetHolder::from_datasets(vec![SiemDataset::I18n(I18nSynDataset::new(
+            Arc::new(dataset),
+            sender,
+        ))]);
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.add_field("source.ip", SiemField::IP([192, 168, 1, 10].into()));
+        log.add_field("destination.ip", SiemField::IP([8, 8, 8, 8].into()));
+        let matched = vec![
+            LogString::Borrowed("local_source"),
+            LogString::Borrowed("blocked_destination"),
+        ];
+
+        let alert =
+            AlertRenderer::new(Language::ES).render(&rule, &log, &matched, &datasets, 1_000);
+        assert_eq!("Conexión a IP maliciosa", alert.title);
+        assert_eq!(
+            "Conexión desde 192.168.1.10 => 8.8.8.8 (local_source, blocked_destination)",
+            alert.description
+        );
+        assert_eq!(vec![MitreTechniques::T1071], alert.techniques);
+        assert_eq!(vec!["c2".to_string()], alert.tags);
+        assert_eq!("id008", alert.rule);
+        assert_eq!(log.field("source.ip"), alert.log.field("source.ip"));
+        assert_eq!(1_000, alert.date);
+
+        // Without translations the keys are used as they are
+        let alert =
+            AlertRenderer::default().render(&rule, &log, &matched, &DatasetHolder::new(), 1_000);
+        assert_eq!("rule.malicious_ip.title", alert.title);
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::holder::DatasetHolder;
+use crate::prelude::i18n::Language;
+use crate::prelude::types::LogString;
+use crate::prelude::{AlertAggregation, SiemAlert, SiemLog};
+
+use super::exception::is_excepted;
+use super::render::AlertRenderer;
+use super::state::{RuleStateStore, StateContext};
+use super::SiemRule;
+
+/// Maximum number of sequences in progress kept by default
+pub const DEFAULT_MAX_SEQUENCES: usize = 10_000;
+
+/// Ordered list of subrules that must match logs sharing the same join keys.
+/// Ex: multiple failed logins followed by a successful one for the same user.name and source.ip within 5 minutes.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RuleSequence {
+    pub steps: Vec<SequenceStep>,
+    /// Fields that must have the same value in all the logs of the sequence
+    pub join_by: Vec<LogString>,
+    /// Maximum time between the first and the last log of the sequence in milliseconds
+    pub max_span: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SequenceStep {
+    /// Name of the subrule that must match
+    pub subrule: LogString,
+    /// Minimum number of logs that must match the subrule before moving to the next step
+    #[serde(default = "default_step_count")]
+    pub count: usize,
+}
+
+fn default_step_count() -> usize {
+    1
+}
+
+impl RuleSequence {
+    /// Key of the sequence the log belongs to: the values of the `join_by` fields joined by "|"
+    pub fn join_key(&self, log: &SiemLog) -> Option<LogString> {
+        let mut values = Vec::with_capacity(self.join_by.len());
+        for field in &self.join_by {
+            values.push(log.field(field)?.to_string());
+        }
+        Some(LogString::Owned(values.join("|")))
+    }
+}
+
+/// Position of a sequence in progress
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SequenceProgress {
+    step: usize,
+    hits: usize,
+    started: i64,
+}
+
+/// State machine engine that tracks the sequences in progress for each join key.
+/// The memory is bounded: when the limit is reached the oldest sequence is discarded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SequenceEngine {
+    max_sequences: usize,
+    /// Rule ID => Join key => Progress
+    sequences: BTreeMap<LogString, BTreeMap<LogString, SequenceProgress>>,
+    /// (Start time, Rule ID, Join key) of each sequence, so the oldest one is found without a full scan
+    by_start: BTreeSet<(i64, LogString, LogString)>,
+    len: usize,
+    /// Not included in the snapshots
+    #[serde(skip)]
+    renderer: AlertRenderer,
+}
+
+impl Default for SequenceEngine {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SEQUENCES)
+    }
+}
+
+impl SequenceEngine {
+    pub fn new(max_sequences: usize) -> Self {
+        Self {
+            max_sequences,
+            sequences: BTreeMap::new(),
+            by_start: BTreeSet::new(),
+            len: 0,
+            renderer: AlertRenderer::default(),
+        }
+    }
+
+    /// Language used in the content of the alerts
+    pub fn set_language(&mut self, language: Language) {
+        self.renderer = AlertRenderer::new(language);
+    }
+
+    /// Feeds the log to the sequence of the rule. Returns an alert when the last step is completed.
+    /// Rules without sequence never generate alerts, neither do sequences ending with a log that matches an exception of the rule.
+    /// The rule states are checked and recorded in the store like in `SiemRule::matches_with_state`.
+    pub fn process(
+        &mut self,
+        rule: &SiemRule,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        states: &mut RuleStateStore,
+    ) -> Option<SiemAlert> {
+        let sequence = rule.sequence.as_ref()?;
+        if sequence.steps.is_empty() {
+            return None;
+        }
+        let key = sequence.join_key(log)?;
+        let now = log.i64_field("event.created").unwrap_or(0);
+        let mut evaluated: BTreeMap<&str, bool> = BTreeMap::new();
+        let alert = {
+            let context = StateContext { store: states, now };
+            rule.evaluate_stateful(log, datasets, context, &mut evaluated);
+            self.advance(rule, key, log, datasets, context, &mut evaluated)
+        };
+        rule.record_states(&evaluated, log, states, now);
+        alert
+    }
+
+    /// Moves the sequence of the join key with the log
+    fn advance<'a>(
+        &mut self,
+        rule: &'a SiemRule,
+        key: LogString,
+        log: &mut SiemLog,
+        datasets: &DatasetHolder,
+        context: StateContext,
+        evaluated: &mut BTreeMap<&'a str, bool>,
+    ) -> Option<SiemAlert> {
+        let sequence = rule.sequence.as_ref()?;
+        let now = context.now;
+        let mut step_matches = |step: usize, log: &mut SiemLog| -> bool {
+            let name = &sequence.steps[step].subrule[..];
+            if let Some(matched) = evaluated.get(name) {
+                return *matched;
+            }
+            let matched = rule
+                .subrules
+                .get(name)
+                .map(|subrule| subrule.evaluate(log, datasets, Some(context)))
+                .unwrap_or(false);
+            evaluated.insert(name, matched);
+            matched
+        };
+
+        let mut progress = match self.take(&rule.id, &key) {
+            Some(progress) if now.saturating_sub(progress.started) <= sequence.max_span => progress,
+            _ => {
+                if !step_matches(0, log) {
+                    return None;
+                }
+                SequenceProgress {
+                    step: 0,
+                    hits: 0,
+                    started: now,
+                }
+            }
+        };
+        let current = progress.step;
+        let can_advance =
+            progress.hits >= sequence.steps[current].count && current + 1 < sequence.steps.len();
+        if can_advance && step_matches(current + 1, log) {
+            progress.step = current + 1;
+            progress.hits = 1;
+        } else if step_matches(current, log) {
+            progress.hits += 1;
+        }
+        let last = sequence.steps.len() - 1;
+        if progress.step == last && progress.hits >= sequence.steps[last].count {
+            // The sequence is completed even if the last log is excepted
+            if is_excepted(&rule.id, log, datasets, now) {
+                return None;
+            }
+            let aggregation = AlertAggregation {
+                limit: now.saturating_add(sequence.max_span),
+                key: key.to_string(),
+            };
+            let mut matched: Vec<LogString> = Vec::with_capacity(sequence.steps.len());
+            for step in &sequence.steps {
+                if !matched.contains(&step.subrule) {
+                    matched.push(step.subrule.clone());
+                }
+            }
+            let mut alert = self.renderer.render(rule, log, &matched, datasets, now);
+            alert.aggregation = Some(aggregation);
+            return Some(alert);
+        }
+        self.store(rule.id.clone(), key, progress);
+        None
+    }
+
+    fn take(&mut self, rule_id: &str, key: &str) -> Option<SequenceProgress> {
+        let sequences = self.sequences.get_mut(rule_id)?;
+        let progress = sequences.remove(key)?;
+        if sequences.is_empty() {
+            self.sequences.remove(rule_id);
+        }
+        self.by_start.remove(&(
+            progress.started,
+            LogString::Owned(rule_id.to_string()),
+            LogString::Owned(key.to_string()),
+        ));
+        self.len -= 1;
+        Some(progress)
+    }
+
+    fn store(&mut self, rule_id: LogString, key: LogString, progress: SequenceProgress) {
+        if self.max_sequences == 0 {
+            return;
+        }
+        if self.len >= self.max_sequences {
+            self.remove_oldest();
+        }
+        self.by_start
+            .insert((progress.started, rule_id.clone(), key.clone()));
+        self.sequences
+            .entry(rule_id)
+            .or_default()
+            .insert(key, progress);
+        self.len += 1;
+    }
+
+    fn remove_oldest(&mut self) {
+        if let Some((_, rule_id, key)) = self.by_start.iter().next().cloned() {
+            self.take(&rule_id, &key);
+        }
+    }
+
+    /// Removes the sequences that can no longer be completed
+    pub fn clean(&mut self, now: i64, rules: &[SiemRule]) {
+        for rule in rules {
+            let max_span = match &rule.sequence {
+                Some(v) => v.max_span,
+                None => continue,
+            };
+            if let Some(sequences) = self.sequences.get_mut(&rule.id) {
+                sequences.retain(|_, progress| now.saturating_sub(progress.started) <= max_span);
+            }
+        }
+        self.sequences.retain(|_, sequences| !sequences.is_empty());
+        self.reindex();
+    }
+
+    /// Forgets the sequences of a rule
+    pub fn remove_rule(&mut self, id: &str) {
+        if self.sequences.remove(id).is_some() {
+            self.by_start.retain(|(_, rule_id, _)| rule_id != id);
+            self.len = self.by_start.len();
+        }
+    }
+
+    fn reindex(&mut self) {
+        self.by_start = self
+            .sequences
+            .iter()
+            .flat_map(|(rule_id, sequences)| {
+                sequences
+                    .iter()
+                    .map(move |(key, progress)| (progress.started, rule_id.clone(), key.clone()))
+            })
+            .collect();
+        self.len = self.by_start.len();
+    }
+
+    /// Number of sequences in progress
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+    use crate::prelude::rule::{
+        AlertGenerator, MitreInfo, RuleCondition, RuleOperator, RuleState, RuleStateValue,
+        SiemSubRule,
+    };
+    use crate::prelude::AlertSeverity;
+
+    fn outcome(value: &'static str) -> SiemSubRule {
+        SiemSubRule {
+            conditions: vec![RuleCondition {
+                field: LogString::Borrowed("event.outcome"),
+                operator: RuleOperator::Equals(value.into()),
+            }],
+            rule_state: None,
+        }
+    }
+
+    fn brute_force_rule() -> SiemRule {
+        let mut subrules = BTreeMap::new();
+        subrules.insert(LogString::Borrowed("failure"), outcome("FAIL"));
+        subrules.insert(LogString::Borrowed("success"), outcome("SUCCESS"));
+        SiemRule {
+            id: LogString::Borrowed("id005"),
+            name: LogString::Borrowed("Successful brute force"),
+            description: LogString::Borrowed(""),
+            mitre: Cow::Owned(MitreInfo {
+                tactics: vec![],
+                techniques: vec![],
+            }),
+            needed_datasets: vec![],
+            subrules: Cow::Owned(subrules),
+            conditions: Cow::Owned(vec![]),
+            alert: Cow::Owned(AlertGenerator {
+                content: vec![],
+                severity: AlertSeverity::CRITICAL,
+                tags: vec![],
+                aggregation: None,
+            }),
+            aggregation: None,
+            sequence: Some(Box::new(RuleSequence {
+                steps: vec![
+                    SequenceStep {
+                        subrule: LogString::Borrowed("failure"),
+                        count: 3,
+                    },
+                    SequenceStep {
+                        subrule: LogString::Borrowed("success"),
+                        count: 1,
+                    },
+                ],
+                join_by: vec![
+                    LogString::Borrowed("user.name"),
+                    LogString::Borrowed("source.ip"),
+                ],
+                max_span: 300_000,
+            })),
+            expression: None,
+        }
+    }
+
+    fn login(user: &str, source: &str, result: &'static str, time: i64) -> SiemLog {
+        let mut log = SiemLog::new("", time, "localhost");
+        log.set_event_created(time);
+        log.add_field("user.name", user.to_string().into());
+        log.add_field("source.ip", source.to_string().into());
+        log.add_field("event.outcome", result.into());
+        log
+    }
+
+    #[test]
+    fn should_detect_failures_followed_by_success() {
+        let rule = brute_force_rule();
+        assert!(rule.validate().is_ok());
+        let datasets = DatasetHolder::new();
+        let mut engine = SequenceEngine::default();
+        let mut states = RuleStateStore::new();
+        let mut process = |source: &str, result: &'static str, time: i64| {
+            engine.process(
+                &rule,
+                &mut login("alice", source, result, time),
+                &datasets,
+                &mut states,
+            )
+        };
+        assert!(process("10.0.0.1", "SUCCESS", 0).is_none());
+        assert!(process("10.0.0.1", "FAIL", 1_000).is_none());
+        assert!(process("10.0.0.1", "FAIL", 2_000).is_none());
+        // Not enough failures yet
+        assert!(process("10.0.0.1", "SUCCESS", 3_000).is_none());
+        assert!(process("10.0.0.2", "FAIL", 3_000).is_none());
+        assert!(process("10.0.0.1", "FAIL", 4_000).is_none());
+        assert!(process("10.0.0.1", "FAIL", 5_000).is_none());
+        let alert = process("10.0.0.1", "SUCCESS", 6_000).unwrap();
+        assert_eq!("alice|10.0.0.1", alert.aggregation.unwrap().key);
+        assert_eq!(1, engine.len());
+
+        // Out of the max span
+        let rule = brute_force_rule();
+        let mut engine = SequenceEngine::default();
+        let mut states = RuleStateStore::new();
+        let mut process = |result: &'static str, time: i64| {
+            engine.process(
+                &rule,
+                &mut login("bob", "10.0.0.3", result, time),
+                &datasets,
+                &mut states,
+            )
+        };
+        assert!(process("FAIL", 0).is_none());
+        assert!(process("FAIL", 1_000).is_none());
+        assert!(process("FAIL", 2_000).is_none());
+        assert!(process("SUCCESS", 400_000).is_none());
+        assert!(engine.is_empty());
+    }
+
+    #[test]
+    fn should_bound_the_sequences_in_progress() {
+        let rule = brute_force_rule();
+        let datasets = DatasetHolder::new();
+        let mut engine = SequenceEngine::new(2);
+        let mut states = RuleStateStore::new();
+        for (i, user) in ["alice", "bob", "carol"].iter().enumerate() {
+            engine.process(
+                &rule,
+                &mut login(user, "10.0.0.1", "FAIL", i as i64),
+                &datasets,
+                &mut states,
+            );
+        }
+        assert_eq!(2, engine.len());
+        // The oldest sequence is discarded
+        let keys: Vec<&str> = engine.sequences["id005"].keys().map(|v| &v[..]).collect();
+        assert_eq!(vec!["bob|10.0.0.1", "carol|10.0.0.1"], keys);
+        assert_eq!(2, engine.by_start.len());
+        engine.clean(1_000_000, &[rule]);
+        assert!(engine.is_empty());
+    }
+
+    #[test]
+    fn should_check_and_record_rule_states() {
+        let source_state = |name: &'static str| RuleState {
+            name: LogString::Borrowed(name),
+            states: RuleStateValue::Field(LogString::Borrowed("source.ip")),
+            ttl: None,
+        };
+        let mut rule = brute_force_rule();
+        let subrules = rule.subrules.to_mut();
+        if let Some(subrule) = subrules.get_mut("failure") {
+            subrule.rule_state = Some(source_state("failed_login"));
+        }
+        if let Some(subrule) = subrules.get_mut("success") {
+            subrule.conditions.push(RuleCondition {
+                field: LogString::Borrowed("source.ip"),
+                operator: RuleOperator::Not(Box::new(RuleOperator::ExistsRuleState(vec![
+                    source_state("trusted_source"),
+                ]))),
+            });
+        }
+        let datasets = DatasetHolder::new();
+        let mut engine = SequenceEngine::default();
+        let mut states = RuleStateStore::new();
+        states.insert(
+            LogString::Borrowed("trusted_source"),
+            LogString::Borrowed("10.0.0.99"),
+            i64::MAX,
+        );
+        let mut process =
+            |source: &str, result: &'static str, time: i64, states: &mut RuleStateStore| {
+                engine.process(
+                    &rule,
+                    &mut login("alice", source, result, time),
+                    &datasets,
+                    states,
+                )
+            };
+        for source in ["10.0.0.1", "10.0.0.99"] {
+            for time in 0..3 {
+                assert!(process(source, "FAIL", time, &mut states).is_none());
+            }
+        }
+        assert!(states.contains("failed_login", "10.0.0.1", 3));
+        assert!(states.contains("failed_login", "10.0.0.99", 3));
+        // The success from a trusted source does not complete the sequence
+        assert!(process("10.0.0.99", "SUCCESS", 4, &mut states).is_none());
+        assert!(process("10.0.0.1", "SUCCESS", 4, &mut states).is_some());
+    }
+}
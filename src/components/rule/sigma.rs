@@ -2,16 +2,48 @@ use std::{borrow::Cow, collections::BTreeMap, fmt::Display};
 
 use serde::{Deserialize, Serialize};
 
+use regex::Regex;
+
 use crate::prelude::{
+    dataset::SiemDatasetType,
     mitre::{MitreTactics, MitreTechniques},
     types::LogString,
-    AlertSeverity, SiemField,
+    AlertSeverity, SiemField, SiemIp,
 };
+use crate::utilities::base64;
 
+use super::expression::{RuleExpression, MAX_EXPRESSION_GROUPS};
+use super::glob::GlobPattern;
+use super::sigma_condition::SigmaCondition;
+use super::sigma_correlation::SigmaCorrelation;
+use super::sigma_pipeline::{SigmaPipeline, LOGSOURCE_SUBRULE};
 use super::{
-    AlertContent, AlertGenerator, MitreInfo, RuleCondition, RuleOperator, SiemRule, SiemSubRule,
+    AlertContent, AlertGenerator, MitreInfo, RuleCondition, RuleError, RuleOperator, SiemRule,
+    SiemSubRule,
 };
 
+/// Error found when translating a Sigma rule
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
+pub enum SigmaError {
+    /// The condition of the detection cannot be parsed: (Position, Description)
+    InvalidCondition(usize, LogString),
+    /// The condition references a search identifier that does not exist
+    UnknownIdentifier(LogString),
+    /// The translated rule is not valid
+    InvalidRule(RuleError),
+    /// The modifier is not supported or cannot be combined with the previous ones: (Field, Modifier)
+    UnsupportedModifier(LogString, LogString),
+    /// The value cannot be used with the modifiers of the field: (Field, Description)
+    InvalidValue(LogString, LogString),
+    /// A correlation references a rule that does not exist
+    UnknownRule(LogString),
+    /// The correlation cannot be translated
+    UnsupportedCorrelation(LogString),
+    /// The detection uses a search identifier reserved for the translation
+    ReservedIdentifier(LogString),
+}
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct SigmaRule {
     /// A brief title for the rule that should contain what the rules is supposed to detect (max. 256 characters)
@@ -44,6 +76,9 @@ pub struct SigmaRule {
     ///
     #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<LogString>,
+    /// Unique name that can be used instead of the id to reference the rule in correlations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<LogString>,
     /// A short description of the rule and the malicious activity that can be detected (max. 65,535 characters)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<LogString>,
@@ -81,8 +116,10 @@ pub struct SigmaRule {
     ///The "definition" can be used to describe the log source, including some information on the log verbosity level or configurations that have to be applied. It is not automatically evaluated by the converters but gives useful advice to readers on how to configure the source to provide the necessary events used in the detection.
     ///
     ///You can use the values of 'category, 'product' and 'service' to point the converters to a certain index. You could define in the configuration files that the category 'firewall' converts to ( index=fw1* OR index=asa* ) during Splunk search conversion or the product 'windows' converts to "_index":"logstash-windows*" in ElasticSearch queries.
+    #[serde(default)]
     pub logsource: Cow<'static, SigmaRuleLogSource>,
-    /// A set of search-identifiers that represent searches on log data
+    /// A set of search-identifiers that represent searches on log data. Not used by correlation rules.
+    #[serde(default)]
     pub detection: Cow<'static, SigmaRuleDetection>,
     /// A list of log fields that could be interesting in further analysis of the event and should be displayed to the analyst.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -93,6 +130,9 @@ pub struct SigmaRule {
     pub level: Option<LogString>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tags: Option<Vec<LogString>>,
+    /// Correlation of other rules. See `sigma_correlation`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation: Option<SigmaCorrelation>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
@@ -111,112 +151,426 @@ pub enum SigmaRuleCondition {
     None,
 }
 
-impl From<SigmaRuleCondition> for SiemSubRule {
-    fn from(val: SigmaRuleCondition) -> Self {
-        match val {
-            SigmaRuleCondition::Map(condition_list) => {
-                let mut conditions = Vec::with_capacity(16);
-                for (field, value) in condition_list {
-                    conditions.push(parse_rule_condition(field, value));
-                }
-                SiemSubRule {
-                    conditions,
-                    rule_state: None,
-                }
+impl TryFrom<SigmaRuleCondition> for SiemSubRule {
+    type Error = SigmaError;
+
+    fn try_from(val: SigmaRuleCondition) -> Result<Self, Self::Error> {
+        parse_search_identifier(val, &BTreeMap::new())
+    }
+}
+
+/// Translates a search identifier renaming the fields with the mapping of a pipeline
+fn parse_search_identifier(
+    val: SigmaRuleCondition,
+    fields: &BTreeMap<LogString, LogString>,
+) -> Result<SiemSubRule, SigmaError> {
+    let mut conditions = Vec::with_capacity(16);
+    match val {
+        SigmaRuleCondition::Map(condition_list) => {
+            for (field, value) in condition_list {
+                conditions.push(parse_rule_condition(field, value, fields)?);
             }
-            SigmaRuleCondition::List(condition_list) => {
-                let mut conditions = Vec::with_capacity(16);
-                for condition in condition_list {
-                    for (field, value) in condition {
-                        conditions.push(parse_rule_condition(field, value))
-                    }
-                }
-                SiemSubRule {
-                    conditions,
-                    rule_state: None,
+        }
+        SigmaRuleCondition::List(condition_list) => {
+            for condition in condition_list {
+                for (field, value) in condition {
+                    conditions.push(parse_rule_condition(field, value, fields)?);
                 }
             }
-            SigmaRuleCondition::None => SiemSubRule {
-                conditions: vec![],
-                rule_state: None,
-            },
         }
+        SigmaRuleCondition::None => {}
     }
+    Ok(SiemSubRule {
+        conditions,
+        rule_state: None,
+    })
 }
 
-fn parse_rule_condition(field: LogString, value: SigmaValue) -> RuleCondition {
+/// Translates a field with its modifiers and the value. The field is renamed if it's in the field mapping.
+pub(crate) fn parse_rule_condition(
+    field: LogString,
+    value: SigmaValue,
+    fields: &BTreeMap<LogString, LogString>,
+) -> Result<RuleCondition, SigmaError> {
     let mut iter = field.split('|');
     let field_name = iter.next().unwrap_or("");
-    let operator = iter.next();
-    let extra = iter.next();
-    if let Some(val) = operator {
-        RuleCondition {
-            field: Cow::Owned(field_name.to_string()),
-            operator: translate_operator(val, extra, value),
-        }
+    let modifiers: Vec<&str> = iter.collect();
+    let operator = if modifiers.is_empty() {
+        translate_content_to_operator(value)
     } else {
-        RuleCondition {
-            field: Cow::Owned(field_name.to_string()),
-            operator: translate_content_to_operator(value),
-        }
+        translate_operator(field_name, &modifiers, value, fields)?
+    };
+    Ok(RuleCondition {
+        field: rename_field(field_name, fields),
+        operator,
+    })
+}
+
+pub(crate) fn rename_field(field: &str, fields: &BTreeMap<LogString, LogString>) -> LogString {
+    match fields.get(field) {
+        Some(renamed) => renamed.clone(),
+        None => Cow::Owned(field.to_string()),
     }
 }
 
 fn translate_content_to_operator(value: SigmaValue) -> RuleOperator {
     match value {
-        SigmaValue::Text(v) => {
-            let starts = v.starts_with('*');
-            let ends = v.ends_with('*') && !v.ends_with("\\*");
-            if starts && ends && v.len() > 2 {
-                RuleOperator::Contains(v[1..v.len() - 2].to_string())
-            } else if starts {
-                RuleOperator::StartsWith(v[1..].to_string())
-            } else if ends && v.len() > 1 {
-                RuleOperator::StartsWith(v[..v.len() - 1].to_string())
-            } else {
-                RuleOperator::Equals(SiemField::Text(Cow::Owned(v.to_string())))
-            }
-        }
+        SigmaValue::Text(v) => text_operator(SigmaMatch::Equals, &v, false),
         SigmaValue::Int(v) => RuleOperator::Equals(SiemField::I64(v)),
         SigmaValue::Float(v) => RuleOperator::Equals(SiemField::F64(v)),
+        SigmaValue::Bool(v) => RuleOperator::Equals(SiemField::Text(Cow::Owned(v.to_string()))),
         SigmaValue::Array(v) => RuleOperator::Any(
             v.into_iter()
-                .map(|v| Box::new(RuleOperator::Equals(v.into())))
+                .map(|v| Box::new(translate_content_to_operator(v)))
                 .collect(),
         ),
         SigmaValue::None => RuleOperator::IsNull(true),
     }
 }
 
-fn translate_operator(operator: &str, extra: Option<&str>, value: SigmaValue) -> RuleOperator {
-    match operator {
-        "equals" => RuleOperator::Equals(value.into()),
-        "contains" => match value {
-            SigmaValue::Text(v) => RuleOperator::Contains(v.to_string()),
-            SigmaValue::Int(v) => RuleOperator::Contains(format!("{}", v)),
-            SigmaValue::Float(v) => RuleOperator::Contains(format!("{}", v)),
-            SigmaValue::Array(v) => {
-                if let Some(extra) = extra {
-                    if extra == "all" {
-                        return RuleOperator::All(
-                            v.iter()
-                                .map(|v| Box::new(RuleOperator::Contains(v.to_string())))
-                                .collect(),
-                        );
-                    }
-                }
-                RuleOperator::Any(
-                    v.iter()
-                        .map(|v| Box::new(RuleOperator::Contains(v.to_string())))
-                        .collect(),
-                )
+/// Sigma strings are case insensitive, unless `cased` is used, and can have the wildcards `*` and `?` anywhere.
+/// Values without wildcards nor letters keep the exact operators, that are faster.
+fn text_operator(matcher: SigmaMatch, value: &str, cased: bool) -> RuleOperator {
+    if let Some(literal) = GlobPattern::new(value).literal() {
+        if cased || !literal.chars().any(char::is_alphabetic) {
+            return match matcher {
+                SigmaMatch::Contains => RuleOperator::Contains(literal),
+                SigmaMatch::StartsWith => RuleOperator::StartsWith(literal),
+                SigmaMatch::EndsWith => RuleOperator::EndsWith(literal),
+                _ => RuleOperator::Equals(SiemField::Text(LogString::Owned(literal))),
+            };
+        }
+    }
+    let mut value = value.to_string();
+    // A trailing backslash must not escape the wildcard added after it
+    let backslashes = value.chars().rev().take_while(|v| *v == '\\').count();
+    if backslashes % 2 == 1 && !matches!(matcher, SigmaMatch::Equals | SigmaMatch::EndsWith) {
+        value.push('\\');
+    }
+    let pattern = match matcher {
+        SigmaMatch::Contains => format!("*{}*", value),
+        SigmaMatch::StartsWith => format!("{}*", value),
+        SigmaMatch::EndsWith => format!("*{}", value),
+        _ => value,
+    };
+    RuleOperator::Glob(GlobPattern::with_case(&pattern, cased))
+}
+
+/// How the value of a Sigma condition is compared with the field
+#[derive(Clone, Copy)]
+enum SigmaMatch {
+    Equals,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Regex,
+    Cidr,
+    Compare(fn(SiemField) -> RuleOperator),
+    Exists,
+    FieldRef,
+}
+
+/// Transformation applied to the value before the comparison
+#[derive(Clone, Copy, PartialEq)]
+enum SigmaTransform {
+    Base64,
+    Base64Offset,
+    Utf16Le,
+    Utf16Be,
+    Windash,
+}
+
+/// Characters that can be used instead of a dash in the parameters of Windows commands
+const WINDASH_CHARS: [char; 5] = ['-', '/', '\u{2013}', '\u{2014}', '\u{2015}'];
+
+fn translate_operator(
+    field: &str,
+    modifiers: &[&str],
+    value: SigmaValue,
+    fields: &BTreeMap<LogString, LogString>,
+) -> Result<RuleOperator, SigmaError> {
+    let unsupported = |modifier: &str| {
+        SigmaError::UnsupportedModifier(
+            LogString::Owned(field.to_string()),
+            LogString::Owned(modifier.to_string()),
+        )
+    };
+    let mut matcher = SigmaMatch::Equals;
+    let mut transforms = Vec::new();
+    let mut all = false;
+    let mut expand = false;
+    let mut cased = false;
+    let mut regex_flags = String::new();
+    for modifier in modifiers {
+        let new_matcher = match *modifier {
+            "equals" => SigmaMatch::Equals,
+            "contains" => SigmaMatch::Contains,
+            "startswith" => SigmaMatch::StartsWith,
+            "endswith" => SigmaMatch::EndsWith,
+            "re" => SigmaMatch::Regex,
+            "cidr" => SigmaMatch::Cidr,
+            "gt" => SigmaMatch::Compare(RuleOperator::GT),
+            "gte" => SigmaMatch::Compare(RuleOperator::GTE),
+            "lt" => SigmaMatch::Compare(RuleOperator::LT),
+            "lte" => SigmaMatch::Compare(RuleOperator::LTE),
+            "exists" => SigmaMatch::Exists,
+            "fieldref" => SigmaMatch::FieldRef,
+            "i" | "m" | "s" if matches!(matcher, SigmaMatch::Regex) => {
+                regex_flags.push_str(modifier);
+                continue;
+            }
+            "all" => {
+                all = true;
+                continue;
+            }
+            "expand" => {
+                expand = true;
+                continue;
+            }
+            "cased" => {
+                cased = true;
+                continue;
+            }
+            "base64" | "base64offset" | "utf16le" | "wide" | "utf16be" | "windash" => {
+                transforms.push(match *modifier {
+                    "base64" => SigmaTransform::Base64,
+                    "base64offset" => SigmaTransform::Base64Offset,
+                    "utf16be" => SigmaTransform::Utf16Be,
+                    "windash" => SigmaTransform::Windash,
+                    _ => SigmaTransform::Utf16Le,
+                });
+                continue;
             }
-            SigmaValue::None => RuleOperator::Contains(String::new()),
+            _ => return Err(unsupported(modifier)),
+        };
+        // Only one comparison can be applied
+        if !matches!(matcher, SigmaMatch::Equals) {
+            return Err(unsupported(modifier));
+        }
+        matcher = new_matcher;
+    }
+    let values = match value {
+        SigmaValue::Array(list) => list,
+        value => vec![value],
+    };
+    let mut operators = Vec::with_capacity(values.len());
+    for value in values {
+        // The referenced field is renamed like the field of the condition
+        let value = match (matcher, value) {
+            (SigmaMatch::FieldRef, SigmaValue::Text(v)) => {
+                SigmaValue::Text(rename_field(&v, fields))
+            }
+            (_, value) => value,
+        };
+        let operator = if expand {
+            expand_placeholder(field, value)?
+        } else {
+            translate_value(field, matcher, &transforms, &regex_flags, cased, value)?
+        };
+        operators.push(Box::new(operator));
+    }
+    Ok(if operators.len() == 1 {
+        *operators.remove(0)
+    } else if all {
+        RuleOperator::All(operators)
+    } else {
+        RuleOperator::Any(operators)
+    })
+}
+
+fn invalid_value(field: &str, description: String) -> SigmaError {
+    SigmaError::InvalidValue(
+        LogString::Owned(field.to_string()),
+        LogString::Owned(description),
+    )
+}
+
+/// Placeholders like `%admin_users%` are replaced by the list of values stored in a CustomTextList dataset with the same name
+fn expand_placeholder(field: &str, value: SigmaValue) -> Result<RuleOperator, SigmaError> {
+    let name = match &value {
+        SigmaValue::Text(v) if v.len() > 2 && v.starts_with('%') && v.ends_with('%') => {
+            &v[1..v.len() - 1]
+        }
+        SigmaValue::None => {
+            return Err(invalid_value(
+                field,
+                String::from("Null values cannot be expanded"),
+            ))
+        }
+        _ => {
+            return Err(invalid_value(
+                field,
+                format!("Only whole placeholders can be expanded: {}", value),
+            ))
+        }
+    };
+    Ok(RuleOperator::InDataset(SiemDatasetType::CustomTextList(
+        LogString::Owned(name.to_string()),
+    )))
+}
+
+fn translate_value(
+    field: &str,
+    matcher: SigmaMatch,
+    transforms: &[SigmaTransform],
+    regex_flags: &str,
+    cased: bool,
+    value: SigmaValue,
+) -> Result<RuleOperator, SigmaError> {
+    let operator = match (matcher, value) {
+        (SigmaMatch::Exists, SigmaValue::Bool(v)) => RuleOperator::Exists(v),
+        (SigmaMatch::FieldRef, SigmaValue::Text(v)) => RuleOperator::EqualsField(v),
+        (SigmaMatch::Compare(operator), SigmaValue::Int(v)) => operator(SiemField::I64(v)),
+        (SigmaMatch::Compare(operator), SigmaValue::Float(v)) => operator(SiemField::F64(v)),
+        (SigmaMatch::Compare(operator), SigmaValue::Text(v)) => match v.trim().parse::<i64>() {
+            Ok(number) => operator(SiemField::I64(number)),
+            Err(_) => match v.trim().parse::<f64>() {
+                Ok(number) => operator(SiemField::F64(number)),
+                Err(_) => return Err(invalid_value(field, format!("Not a number: {}", v))),
+            },
         },
-        "endswith" => RuleOperator::EndsWith(value.to_string()),
-        "startswith" => RuleOperator::StartsWith(value.to_string()),
-        _ => RuleOperator::All(vec![]),
+        (SigmaMatch::Cidr, SigmaValue::Text(v)) => {
+            let (ip, net) = v.split_once('/').unwrap_or((&v[..], ""));
+            let ip = SiemIp::from_ip_str(ip)
+                .map_err(|_| invalid_value(field, format!("Invalid CIDR: {}", v)))?;
+            let max = if matches!(ip, SiemIp::V4(_)) { 32 } else { 128 };
+            let net = match net {
+                "" => max,
+                net => match net.parse::<u8>() {
+                    Ok(net) if net <= max => net,
+                    _ => return Err(invalid_value(field, format!("Invalid CIDR: {}", v))),
+                },
+            };
+            RuleOperator::SameNet((ip, net))
+        }
+        (SigmaMatch::Regex, SigmaValue::Text(v)) => {
+            let pattern = if regex_flags.is_empty() {
+                v.to_string()
+            } else {
+                format!("(?{}){}", regex_flags, v)
+            };
+            match Regex::new(&pattern) {
+                Ok(regex) => RuleOperator::Matches(regex),
+                Err(err) => return Err(invalid_value(field, format!("Invalid regex: {}", err))),
+            }
+        }
+        (SigmaMatch::Equals, SigmaValue::None) if transforms.is_empty() => {
+            RuleOperator::IsNull(true)
+        }
+        (SigmaMatch::Equals, SigmaValue::Text(v)) if transforms.is_empty() => {
+            text_operator(matcher, &v, cased)
+        }
+        (SigmaMatch::Equals, value) if transforms.is_empty() => RuleOperator::Equals(value.into()),
+        (
+            SigmaMatch::Equals
+            | SigmaMatch::Contains
+            | SigmaMatch::StartsWith
+            | SigmaMatch::EndsWith,
+            value @ (SigmaValue::Text(_) | SigmaValue::Int(_) | SigmaValue::Float(_)),
+        ) => {
+            // Base64 is case sensitive and has no wildcards
+            let encoded = transforms
+                .iter()
+                .any(|v| matches!(v, SigmaTransform::Base64 | SigmaTransform::Base64Offset));
+            let mut variants: Vec<String> = transform_value(&value.to_string(), transforms);
+            let mut list: Vec<Box<RuleOperator>> = variants
+                .drain(..)
+                .map(|v| {
+                    Box::new(match matcher {
+                        _ if !encoded => text_operator(matcher, &v, cased),
+                        SigmaMatch::Contains => RuleOperator::Contains(v),
+                        SigmaMatch::StartsWith => RuleOperator::StartsWith(v),
+                        SigmaMatch::EndsWith => RuleOperator::EndsWith(v),
+                        _ => RuleOperator::Equals(SiemField::Text(LogString::Owned(v))),
+                    })
+                })
+                .collect();
+            if list.len() == 1 {
+                *list.remove(0)
+            } else {
+                RuleOperator::Any(list)
+            }
+        }
+        (_, value) => {
+            return Err(invalid_value(
+                field,
+                format!("Value not valid for the modifiers: {:?}", value),
+            ))
+        }
+    };
+    Ok(operator)
+}
+
+/// Applies the transformations in order. Some of them generate multiple variants of the value.
+fn transform_value(value: &str, transforms: &[SigmaTransform]) -> Vec<String> {
+    let mut variants: Vec<Vec<u8>> = vec![value.as_bytes().to_vec()];
+    for transform in transforms {
+        variants = variants
+            .into_iter()
+            .flat_map(|value| match transform {
+                SigmaTransform::Base64 => vec![base64::encode(&value).into_bytes()],
+                SigmaTransform::Base64Offset => base64_offsets(&value),
+                SigmaTransform::Utf16Le => vec![String::from_utf8_lossy(&value)
+                    .encode_utf16()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect()],
+                SigmaTransform::Utf16Be => vec![String::from_utf8_lossy(&value)
+                    .encode_utf16()
+                    .flat_map(|v| v.to_be_bytes())
+                    .collect()],
+                SigmaTransform::Windash => windash(&String::from_utf8_lossy(&value))
+                    .into_iter()
+                    .map(|v| v.into_bytes())
+                    .collect(),
+            })
+            .collect();
+    }
+    let mut to_ret: Vec<String> = Vec::with_capacity(variants.len());
+    for variant in variants {
+        let variant = String::from_utf8_lossy(&variant).to_string();
+        if !to_ret.contains(&variant) {
+            to_ret.push(variant);
+        }
+    }
+    to_ret
+}
+
+/// The three possible encodings of the value depending on its position inside a base64 text
+fn base64_offsets(value: &[u8]) -> Vec<Vec<u8>> {
+    let mut to_ret = Vec::with_capacity(3);
+    for offset in 0..3 {
+        let mut padded = vec![b' '; offset];
+        padded.extend_from_slice(value);
+        let encoded = base64::encode(&padded);
+        let start = [0, 2, 3][offset];
+        let end = encoded.len() - [0, 3, 2][(value.len() + offset) % 3];
+        if start < end {
+            to_ret.push(encoded.as_bytes()[start..end].to_vec());
+        }
     }
+    to_ret
+}
+
+/// Variants of the value replacing the dash or slash at the start of each parameter
+fn windash(value: &str) -> Vec<String> {
+    WINDASH_CHARS
+        .iter()
+        .map(|dash| {
+            let mut previous = ' ';
+            value
+                .chars()
+                .map(|ch| {
+                    let replaced = if previous.is_whitespace() && WINDASH_CHARS.contains(&ch) {
+                        *dash
+                    } else {
+                        ch
+                    };
+                    previous = ch;
+                    replaced
+                })
+                .collect()
+        })
+        .collect()
 }
 
 impl Display for SigmaValue {
@@ -225,6 +579,7 @@ impl Display for SigmaValue {
             SigmaValue::Text(v) => f.write_str(v),
             SigmaValue::Int(v) => f.write_fmt(format_args!("{}", v)),
             SigmaValue::Float(v) => f.write_fmt(format_args!("{}", v)),
+            SigmaValue::Bool(v) => f.write_fmt(format_args!("{}", v)),
             SigmaValue::Array(list) => {
                 f.write_str("[")?;
                 for value in list {
@@ -232,7 +587,7 @@ impl Display for SigmaValue {
                 }
                 f.write_str("]")
             }
-            SigmaValue::None => return Err(std::fmt::Error::default()),
+            SigmaValue::None => f.write_str("null"),
         }?;
         Ok(())
     }
@@ -244,15 +599,16 @@ impl From<SigmaValue> for SiemField {
             SigmaValue::Text(v) => SiemField::Text(v),
             SigmaValue::Int(v) => SiemField::I64(v),
             SigmaValue::Float(v) => SiemField::F64(v),
+            SigmaValue::Bool(v) => SiemField::Text(LogString::Owned(v.to_string())),
             SigmaValue::Array(v) => {
                 SiemField::Array(v.iter().map(|v| LogString::Owned(v.to_string())).collect())
             }
-            SigmaValue::None => todo!(),
+            SigmaValue::None => SiemField::Null,
         }
     }
 }
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq)]
 pub struct SigmaRuleLogSource {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub category: Option<LogString>,
@@ -270,65 +626,203 @@ pub enum SigmaValue {
     Text(LogString),
     Int(i64),
     Float(f64),
+    Bool(bool),
     Array(Vec<SigmaValue>),
     #[default]
     None,
 }
 
-impl From<SigmaRule> for SiemRule {
-    fn from(val: SigmaRule) -> Self {
-        let mut slf = val;
-        let subrules = parse_subrules(&mut slf);
-        let conditions = Vec::with_capacity(16);
-        let description = slf.description.unwrap_or_default();
-        let alert_content = transform_alert_content(&description);
-        SiemRule {
-            id: slf.id.unwrap_or_default(),
-            name: slf.title,
-            mitre: Cow::Owned(MitreInfo {
-                tactics: slf
-                    .tags
-                    .as_ref()
-                    .map(|v| {
-                        v.iter()
-                            .filter(|t| t.starts_with("attack."))
-                            .map(|t| MitreTactics::try_from(&t[7..]))
-                            .filter_map(|v| v.ok())
-                            .collect()
-                    })
-                    .unwrap_or_default(),
-                techniques: slf
-                    .tags
-                    .as_ref()
-                    .map(|v| {
-                        v.iter()
-                            .filter(|t| t.starts_with("attack."))
-                            .map(|t| MitreTechniques::try_from(&t[7..]))
-                            .filter_map(|v| v.ok())
-                            .collect()
-                    })
-                    .unwrap_or_default(),
-            }),
-            description,
-            needed_datasets: vec![],
-            subrules: Cow::Owned(subrules),
-            conditions: Cow::Owned(conditions),
-            alert: Cow::Owned(AlertGenerator {
-                content: alert_content,
-                severity: level_to_severity(&slf.level.unwrap_or_default()),
-                tags: slf.tags.unwrap_or_default(),
-                aggregation: None,
-            }),
+impl TryFrom<SigmaRule> for SiemRule {
+    type Error = SigmaError;
+
+    fn try_from(val: SigmaRule) -> Result<Self, Self::Error> {
+        val.into_siem_rule(&SigmaPipeline::default())
+    }
+}
+
+impl SigmaRule {
+    /// Converts the rule applying the field and logsource mappings of the pipeline.
+    /// The conditions of the logsource are added as a new subrule required by the rule.
+    pub fn into_siem_rule(self, pipeline: &SigmaPipeline) -> Result<SiemRule, SigmaError> {
+        if self.correlation.is_some() {
+            return Err(SigmaError::UnsupportedCorrelation(LogString::Borrowed(
+                "Correlation rules must be converted with the rules they reference",
+            )));
+        }
+        let mut slf = self;
+        let resolved = pipeline.resolve(&slf.logsource)?;
+        if slf
+            .detection
+            .search_identifiers
+            .contains_key(LOGSOURCE_SUBRULE)
+        {
+            return Err(SigmaError::ReservedIdentifier(LogString::Borrowed(
+                LOGSOURCE_SUBRULE,
+            )));
         }
+        let (mut subrules, alternatives) = parse_subrules(&mut slf, &resolved.fields)?;
+        let identifiers: Vec<&LogString> = slf.detection.search_identifiers.keys().collect();
+        let expression =
+            SigmaCondition::parse(&slf.detection.condition)?.to_expression(&identifiers)?;
+        let mut expression = expand_alternatives(expression, &alternatives);
+        if !resolved.conditions.is_empty() {
+            let name = LogString::Borrowed(LOGSOURCE_SUBRULE);
+            subrules.insert(
+                name.clone(),
+                SiemSubRule {
+                    conditions: resolved.conditions,
+                    rule_state: None,
+                },
+            );
+            expression = RuleExpression::And(vec![RuleExpression::Subrule(name), expression]);
+        }
+        // Groups of subrules are preferred as they are supported everywhere
+        let (conditions, expression) = match expression.to_groups(MAX_EXPRESSION_GROUPS) {
+            Some(groups) => (groups, None),
+            None => (Vec::new(), Some(Box::new(expression))),
+        };
+        let mut rule = rule_metadata(slf, &resolved.fields);
+        rule.subrules = Cow::Owned(subrules);
+        rule.conditions = Cow::Owned(conditions);
+        rule.expression = expression;
+        rule.validate().map_err(SigmaError::InvalidRule)?;
+        Ok(rule)
     }
 }
 
-fn parse_subrules(rule: &mut SigmaRule) -> BTreeMap<LogString, SiemSubRule> {
+/// Rule with the name, description, MITRE tags and level of the Sigma rule but without subrules
+pub(crate) fn rule_metadata(rule: SigmaRule, fields: &BTreeMap<LogString, LogString>) -> SiemRule {
+    let slf = rule;
+    let description = slf.description.unwrap_or_default();
+    let alert_content = transform_alert_content(&description)
+        .into_iter()
+        .map(|content| match content {
+            AlertContent::Field(field) => AlertContent::Field(rename_field(&field, fields)),
+            content => content,
+        })
+        .collect();
+    SiemRule {
+        id: slf.id.unwrap_or_default(),
+        name: slf.title,
+        mitre: Cow::Owned(MitreInfo {
+            tactics: slf
+                .tags
+                .as_ref()
+                .map(|v| v.iter().filter_map(|t| tag_to_tactic(t)).collect())
+                .unwrap_or_default(),
+            techniques: slf
+                .tags
+                .as_ref()
+                .map(|v| v.iter().filter_map(|t| tag_to_technique(t)).collect())
+                .unwrap_or_default(),
+        }),
+        description,
+        needed_datasets: vec![],
+        subrules: Cow::Owned(BTreeMap::new()),
+        conditions: Cow::Owned(Vec::new()),
+        alert: Cow::Owned(AlertGenerator {
+            content: alert_content,
+            severity: level_to_severity(&slf.level.unwrap_or_default()),
+            tags: slf.tags.unwrap_or_default(),
+            aggregation: None,
+        }),
+        aggregation: None,
+        sequence: None,
+        expression: None,
+    }
+}
+
+/// Search identifiers with a list of maps => Names of the subrules of each map
+type Alternatives = BTreeMap<LogString, Vec<LogString>>;
+
+/// Translates the search identifiers. Each map of a list is a different subrule named `identifier.N`, as any of them can match.
+fn parse_subrules(
+    rule: &mut SigmaRule,
+    fields: &BTreeMap<LogString, LogString>,
+) -> Result<(BTreeMap<LogString, SiemSubRule>, Alternatives), SigmaError> {
     let mut ret = BTreeMap::new();
+    let mut alternatives = BTreeMap::new();
     for (id, condition) in &rule.detection.search_identifiers {
-        ret.insert(Cow::Owned(id.to_string()), condition.clone().into());
+        match condition {
+            SigmaRuleCondition::List(list) if list.len() > 1 => {
+                let mut names = Vec::with_capacity(list.len());
+                for (position, map) in list.iter().enumerate() {
+                    let name: LogString = Cow::Owned(format!("{}.{}", id, position));
+                    ret.insert(
+                        name.clone(),
+                        parse_search_identifier(SigmaRuleCondition::Map(map.clone()), fields)?,
+                    );
+                    names.push(name);
+                }
+                alternatives.insert(id.clone(), names);
+            }
+            _ => {
+                ret.insert(
+                    Cow::Owned(id.to_string()),
+                    parse_search_identifier(condition.clone(), fields)?,
+                );
+            }
+        }
+    }
+    Ok((ret, alternatives))
+}
+
+/// Replaces the search identifiers with a list of maps by any of the subrules of the maps
+fn expand_alternatives(expression: RuleExpression, alternatives: &Alternatives) -> RuleExpression {
+    match expression {
+        RuleExpression::Subrule(name) => match alternatives.get(&name) {
+            Some(names) => {
+                RuleExpression::Or(names.iter().cloned().map(RuleExpression::Subrule).collect())
+            }
+            None => RuleExpression::Subrule(name),
+        },
+        RuleExpression::And(list) => RuleExpression::And(
+            list.into_iter()
+                .map(|v| expand_alternatives(v, alternatives))
+                .collect(),
+        ),
+        RuleExpression::Or(list) => RuleExpression::Or(
+            list.into_iter()
+                .map(|v| expand_alternatives(v, alternatives))
+                .collect(),
+        ),
+        RuleExpression::Not(v) => {
+            RuleExpression::Not(Box::new(expand_alternatives(*v, alternatives)))
+        }
+    }
+}
+
+/// Names of the enterprise tactics used in the Sigma tags. Ex: `attack.execution`
+pub(crate) const SIGMA_TACTICS: [(&str, MitreTactics); 14] = [
+    ("reconnaissance", MitreTactics::TA0043),
+    ("resource_development", MitreTactics::TA0042),
+    ("initial_access", MitreTactics::TA0001),
+    ("execution", MitreTactics::TA0002),
+    ("persistence", MitreTactics::TA0003),
+    ("privilege_escalation", MitreTactics::TA0004),
+    ("defense_evasion", MitreTactics::TA0005),
+    ("credential_access", MitreTactics::TA0006),
+    ("discovery", MitreTactics::TA0007),
+    ("lateral_movement", MitreTactics::TA0008),
+    ("collection", MitreTactics::TA0009),
+    ("command_and_control", MitreTactics::TA0011),
+    ("exfiltration", MitreTactics::TA0010),
+    ("impact", MitreTactics::TA0040),
+];
+
+/// Tactic of a tag like `attack.execution` or `attack.ta0002`
+fn tag_to_tactic(tag: &str) -> Option<MitreTactics> {
+    let name = tag.strip_prefix("attack.")?;
+    if let Some((_, tactic)) = SIGMA_TACTICS.iter().find(|(v, _)| *v == name) {
+        return Some(tactic.clone());
     }
-    ret
+    MitreTactics::try_from(&name.to_uppercase()[..]).ok()
+}
+
+/// Technique of a tag like `attack.t1059.001`
+fn tag_to_technique(tag: &str) -> Option<MitreTechniques> {
+    let name = tag.strip_prefix("attack.")?;
+    MitreTechniques::try_from(&name.to_uppercase()[..]).ok()
 }
 
 fn level_to_severity(level: &str) -> AlertSeverity {
@@ -497,7 +991,7 @@ fn should_be_deserialized() {
 fn should_transform_c2_sigma_to_siem_rule() {
     let rule = include_str!("c2_sigma_rule.yml");
     let yml_test: SigmaRule = serde_yaml::from_str(&rule).unwrap();
-    let siem_rule: SiemRule = yml_test.into();
+    let siem_rule: SiemRule = yml_test.try_into().unwrap();
     assert_eq!(
         &MitreTechniques::T1041,
         siem_rule.mitre.techniques.get(0).unwrap()
@@ -537,37 +1031,48 @@ fn should_transform_c2_sigma_to_siem_rule() {
         ]),
         select_outgoing.operator
     );
+    // 1 of select*
+    assert_eq!(
+        vec![
+            vec![LogString::Borrowed("select_incoming")],
+            vec![LogString::Borrowed("select_outgoing")]
+        ],
+        siem_rule.conditions.to_vec()
+    );
+    assert!(siem_rule.expression.is_none());
 }
 
 #[test]
 fn should_transform_7zip_sigma_to_siem_rule() {
     let rule = include_str!("7zip_sigma_rule.yml");
     let yml_test: SigmaRule = serde_yaml::from_str(&rule).unwrap();
-    let siem_rule: SiemRule = yml_test.into();
+    let siem_rule: SiemRule = yml_test.try_into().unwrap();
     assert_eq!(&AlertContent::Text(LogString::Borrowed("7-Zip through 21.07 on Windows allows privilege escalation (CVE-2022-29072) and command execution when a file with the .7z extension is dragged to the Help>Contents area. This is caused by misconfiguration of 7z.dll and a heap overflow. The command runs in a child process under the 7zFM.exe process.")), siem_rule.alert.content.get(0).unwrap());
 
+    // Each map of the list is a different subrule
+    assert!(!siem_rule.subrules.contains_key("selection_img"));
     let img_ends_with = siem_rule
         .subrules
-        .get("selection_img")
+        .get("selection_img.0")
         .unwrap()
         .conditions
         .get(0)
         .unwrap();
     assert_eq!("Image", img_ends_with.field);
     assert_eq!(
-        RuleOperator::EndsWith(format!("\\cmd.exe")),
+        RuleOperator::Glob(GlobPattern::new("*\\cmd.exe")),
         img_ends_with.operator
     );
     let original_file_name = siem_rule
         .subrules
-        .get("selection_img")
+        .get("selection_img.1")
         .unwrap()
         .conditions
-        .get(1)
+        .first()
         .unwrap();
     assert_eq!("OriginalFileName", original_file_name.field);
     assert_eq!(
-        RuleOperator::Equals("Cmd.Exe".into()),
+        RuleOperator::Glob(GlobPattern::new("Cmd.Exe")),
         original_file_name.operator
     );
 
@@ -580,7 +1085,7 @@ fn should_transform_7zip_sigma_to_siem_rule() {
         .unwrap();
     assert_eq!("ParentImage", parent_image.field);
     assert_eq!(
-        RuleOperator::EndsWith(format!("\\7zFM.exe")),
+        RuleOperator::Glob(GlobPattern::new("*\\7zFM.exe")),
         parent_image.operator
     );
 
@@ -594,9 +1099,9 @@ fn should_transform_7zip_sigma_to_siem_rule() {
     assert_eq!("CommandLine", bat_command_line.field);
     assert_eq!(
         RuleOperator::Any(vec![
-            Box::new(RuleOperator::Contains(format!(" /c "))),
-            Box::new(RuleOperator::Contains(format!(" /k "))),
-            Box::new(RuleOperator::Contains(format!(" /r "))),
+            Box::new(RuleOperator::Glob(GlobPattern::new("* /c *"))),
+            Box::new(RuleOperator::Glob(GlobPattern::new("* /k *"))),
+            Box::new(RuleOperator::Glob(GlobPattern::new("* /r *"))),
         ]),
         bat_command_line.operator
     );
@@ -611,3 +1116,272 @@ fn should_transform_7zip_sigma_to_siem_rule() {
     assert_eq!("CommandLine", filter_null.field);
     assert_eq!(RuleOperator::IsNull(true), filter_null.operator);
 }
+
+#[test]
+fn should_apply_negated_sigma_filters() {
+    use super::expression::RuleExpression;
+    use crate::prelude::holder::DatasetHolder;
+    use crate::prelude::SiemLog;
+
+    let rule = include_str!("7zip_sigma_rule.yml");
+    let yml_test: SigmaRule = serde_yaml::from_str(rule).unwrap();
+    let siem_rule: SiemRule = yml_test.try_into().unwrap();
+    let subrule = |name: &'static str| RuleExpression::Subrule(LogString::Borrowed(name));
+    // all of selection_* and not 1 of filter_*
+    assert!(siem_rule.conditions.is_empty());
+    assert_eq!(
+        Some(Box::new(RuleExpression::And(vec![
+            RuleExpression::And(vec![
+                RuleExpression::Or(vec![subrule("selection_img.0"), subrule("selection_img.1")]),
+                subrule("selection_parent")
+            ]),
+            RuleExpression::Not(Box::new(RuleExpression::Or(vec![
+                subrule("filter_bat"),
+                subrule("filter_null")
+            ])))
+        ]))),
+        siem_rule.expression
+    );
+
+    let datasets = DatasetHolder::new();
+    let mut log = SiemLog::new("", 0, "localhost");
+    log.add_field("Image", "C:\\Windows\\System32\\cmd.exe".into());
+    log.add_field("OriginalFileName", "Cmd.Exe".into());
+    log.add_field("ParentImage", "C:\\Program Files\\7-Zip\\7zFM.exe".into());
+    log.add_field("CommandLine", "cmd.exe".into());
+    assert_eq!(
+        Some(vec![
+            LogString::Borrowed("selection_img.0"),
+            LogString::Borrowed("selection_parent")
+        ]),
+        siem_rule.matches(&mut log, &datasets)
+    );
+    let compiled = siem_rule.compile().unwrap();
+    assert!(compiled.matches(&mut log, &datasets).is_some());
+
+    // Any of the maps of selection_img is enough
+    let mut renamed = log.clone();
+    renamed.add_field("Image", "C:\\Users\\Public\\shell.exe".into());
+    assert_eq!(
+        Some(vec![
+            LogString::Borrowed("selection_img.1"),
+            LogString::Borrowed("selection_parent")
+        ]),
+        siem_rule.matches(&mut renamed, &datasets)
+    );
+    assert!(compiled.matches(&mut renamed, &datasets).is_some());
+
+    // Filtered by filter_bat
+    log.add_field("CommandLine", "cmd.exe /c whoami".into());
+    assert!(siem_rule.matches(&mut log, &datasets).is_none());
+    assert!(compiled.matches(&mut log, &datasets).is_none());
+
+    // Unknown search identifiers are rejected
+    let mut yml_test: SigmaRule = serde_yaml::from_str(rule).unwrap();
+    yml_test.detection.to_mut().condition = LogString::Borrowed("selection_img and not keywords");
+    let err: Result<SiemRule, _> = yml_test.try_into();
+    assert_eq!(
+        SigmaError::UnknownIdentifier(LogString::Borrowed("keywords")),
+        err.unwrap_err()
+    );
+}
+
+#[test]
+fn should_translate_sigma_modifiers() {
+    let rule = r#"
+title: Modifiers
+id: modifiers
+logsource:
+    product: windows
+detection:
+    selection:
+        CommandLine|base64offset|contains: 'http'
+        ParentCommandLine|windash|contains: ' -enc '
+        ScriptBlock|utf16le|base64|contains: 'ping'
+        DestinationIp|cidr: '10.0.0.0/8'
+        Image|re|i: '.*\\cmd\.exe$'
+        DestinationPort|gte: 1024
+        User|exists: true
+        TargetUser|fieldref: SourceUser
+        Group|expand: '%admins%'
+        Keywords|contains|all:
+            - 'invoke'
+            - 'expression'
+    condition: selection
+"#;
+    let yml_test: SigmaRule = serde_yaml::from_str(rule).unwrap();
+    let siem_rule: SiemRule = yml_test.try_into().unwrap();
+    let operators: BTreeMap<&str, &RuleOperator> = siem_rule.subrules["selection"]
+        .conditions
+        .iter()
+        .map(|condition| (&condition.field[..], &condition.operator))
+        .collect();
+    let any_contains = |list: &[&str]| {
+        RuleOperator::Any(
+            list.iter()
+                .map(|v| Box::new(RuleOperator::Contains(v.to_string())))
+                .collect(),
+        )
+    };
+    assert_eq!(
+        &any_contains(&["aHR0c", "h0dH", "odHRw"]),
+        operators["CommandLine"]
+    );
+    assert_eq!(
+        &RuleOperator::Any(
+            [
+                "* -enc *",
+                "* /enc *",
+                "* \u{2013}enc *",
+                "* \u{2014}enc *",
+                "* \u{2015}enc *"
+            ]
+            .iter()
+            .map(|v| Box::new(RuleOperator::Glob(GlobPattern::new(v))))
+            .collect()
+        ),
+        operators["ParentCommandLine"]
+    );
+    assert_eq!(
+        &RuleOperator::Contains("cABpAG4AZwA=".to_string()),
+        operators["ScriptBlock"]
+    );
+    assert_eq!(
+        &RuleOperator::SameNet((SiemIp::V4(0x0a000000), 8)),
+        operators["DestinationIp"]
+    );
+    assert_eq!(
+        &RuleOperator::Matches(Regex::new("(?i).*\\\\cmd\\.exe$").unwrap()),
+        operators["Image"]
+    );
+    assert_eq!(
+        &RuleOperator::GTE(SiemField::I64(1024)),
+        operators["DestinationPort"]
+    );
+    assert_eq!(&RuleOperator::Exists(true), operators["User"]);
+    assert_eq!(
+        &RuleOperator::EqualsField(LogString::Borrowed("SourceUser")),
+        operators["TargetUser"]
+    );
+    assert_eq!(
+        &RuleOperator::InDataset(SiemDatasetType::CustomTextList(LogString::Borrowed(
+            "admins"
+        ))),
+        operators["Group"]
+    );
+    assert_eq!(
+        &RuleOperator::All(vec![
+            Box::new(RuleOperator::Glob(GlobPattern::new("*invoke*"))),
+            Box::new(RuleOperator::Glob(GlobPattern::new("*expression*"))),
+        ]),
+        operators["Keywords"]
+    );
+
+    let translate = |detection: &str| -> Result<SiemRule, SigmaError> {
+        let rule = format!(
+            "title: Error\nlogsource:\n    product: windows\ndetection:\n    selection:\n        {}\n    condition: selection\n",
+            detection
+        );
+        let yml_test: SigmaRule = serde_yaml::from_str(&rule).unwrap();
+        yml_test.try_into()
+    };
+    assert_eq!(
+        SigmaError::UnsupportedModifier(
+            LogString::Borrowed("CommandLine"),
+            LogString::Borrowed("unknown")
+        ),
+        translate("CommandLine|unknown: 'x'").unwrap_err()
+    );
+    assert_eq!(
+        SigmaError::UnsupportedModifier(
+            LogString::Borrowed("Image"),
+            LogString::Borrowed("endswith")
+        ),
+        translate("Image|contains|endswith: 'x'").unwrap_err()
+    );
+    assert!(matches!(
+        translate("Image|re: '(unclosed'"),
+        Err(SigmaError::InvalidValue(_, _))
+    ));
+    assert!(matches!(
+        translate("DestinationIp|cidr: '10.0.0.0/40'"),
+        Err(SigmaError::InvalidValue(_, _))
+    ));
+    assert!(matches!(
+        translate("User|expand: null"),
+        Err(SigmaError::InvalidValue(_, _))
+    ));
+    let rule = translate("EventID|equals: [4624, null, [4625, null]]").unwrap();
+    assert_eq!(
+        RuleOperator::Any(vec![
+            Box::new(RuleOperator::Equals(SiemField::I64(4624))),
+            Box::new(RuleOperator::IsNull(true)),
+            Box::new(RuleOperator::Equals(SiemField::Array(vec![
+                LogString::Borrowed("4625"),
+                LogString::Borrowed("null")
+            ]))),
+        ]),
+        rule.subrules["selection"].conditions[0].operator
+    );
+}
+
+#[test]
+fn should_translate_sigma_wildcards() {
+    use crate::prelude::holder::DatasetHolder;
+    use crate::prelude::SiemLog;
+
+    let rule = r#"
+title: Wildcards
+id: wildcards
+logsource:
+    product: windows
+detection:
+    selection:
+        Image: 'C:\Users\\*\AppData\\*.ex?'
+        CommandLine|contains: ' -w hidden '
+        ParentImage|startswith: 'C:\'
+        Literal: 'what\?'
+        DestinationPort: '443'
+    condition: selection
+"#;
+    let yml_test: SigmaRule = serde_yaml::from_str(rule).unwrap();
+    let siem_rule: SiemRule = yml_test.try_into().unwrap();
+    let operators: BTreeMap<&str, &RuleOperator> = siem_rule.subrules["selection"]
+        .conditions
+        .iter()
+        .map(|condition| (&condition.field[..], &condition.operator))
+        .collect();
+    assert_eq!(
+        &RuleOperator::Glob(GlobPattern::new("C:\\Users\\\\*\\AppData\\\\*.ex?")),
+        operators["Image"]
+    );
+    assert_eq!(
+        &RuleOperator::Glob(GlobPattern::new("* -w hidden *")),
+        operators["CommandLine"]
+    );
+    // The trailing backslash does not escape the wildcard
+    assert_eq!(
+        &RuleOperator::Glob(GlobPattern::new("C:\\\\*")),
+        operators["ParentImage"]
+    );
+    // Without wildcards nor letters the value is compared as is
+    assert_eq!(
+        &RuleOperator::Equals("443".into()),
+        operators["DestinationPort"]
+    );
+
+    let datasets = DatasetHolder::new();
+    let mut log = SiemLog::new("", 0, "localhost");
+    log.add_field("Image", "c:\\users\\bob\\appdata\\EVIL.EXE".into());
+    log.add_field("CommandLine", "PowerShell.exe -W Hidden -enc AAAA".into());
+    log.add_field("ParentImage", "C:\\Windows\\explorer.exe".into());
+    log.add_field("Literal", "WHAT?".into());
+    log.add_field("DestinationPort", "443".into());
+    assert!(siem_rule.matches(&mut log, &datasets).is_some());
+    let compiled = siem_rule.compile().unwrap();
+    assert!(compiled.matches(&mut log, &datasets).is_some());
+
+    log.add_field("Literal", "whats".into());
+    assert!(siem_rule.matches(&mut log, &datasets).is_none());
+    assert!(compiled.matches(&mut log, &datasets).is_none());
+}
@@ -0,0 +1,337 @@
+use crate::prelude::types::LogString;
+
+use super::expression::RuleExpression;
+use super::glob::GlobPattern;
+use super::sigma::SigmaError;
+
+/// Condition of a Sigma detection.
+/// Supports `and`, `or`, `not`, parentheses, `1 of pattern`, `all of pattern` and `them`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SigmaCondition {
+    /// Name of a search identifier
+    Identifier(LogString),
+    /// `1 of pattern`: any of the search identifiers that match the pattern. `them` matches all the search identifiers.
+    OneOf(LogString),
+    /// `all of pattern`: all the search identifiers that match the pattern
+    AllOf(LogString),
+    And(Vec<SigmaCondition>),
+    Or(Vec<SigmaCondition>),
+    Not(Box<SigmaCondition>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ConditionToken {
+    And,
+    Or,
+    Not,
+    Of,
+    OpenParen,
+    CloseParen,
+    Word(String),
+}
+
+impl SigmaCondition {
+    /// Parses the condition. The operators are case insensitive and `not` binds tighter than `and`, that binds tighter than `or`.
+    pub fn parse(condition: &str) -> Result<SigmaCondition, SigmaError> {
+        let tokens = tokenize(condition)?;
+        let mut parser = ConditionParser {
+            tokens,
+            position: 0,
+            length: condition.len(),
+        };
+        let parsed = parser.parse_or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(parsed),
+            Some((position, _)) => Err(SigmaError::InvalidCondition(
+                *position,
+                LogString::Borrowed("Unexpected token"),
+            )),
+        }
+    }
+
+    /// Replaces the identifiers and patterns with the search identifiers of the detection
+    pub fn to_expression(&self, identifiers: &[&LogString]) -> Result<RuleExpression, SigmaError> {
+        let expression = match self {
+            SigmaCondition::Identifier(name) => match identifiers.iter().find(|v| **v == name) {
+                Some(v) => RuleExpression::Subrule((*v).clone()),
+                None => return Err(SigmaError::UnknownIdentifier(name.clone())),
+            },
+            SigmaCondition::OneOf(pattern) => {
+                let mut list = matching_identifiers(pattern, identifiers)?;
+                if list.len() == 1 {
+                    list.remove(0)
+                } else {
+                    RuleExpression::Or(list)
+                }
+            }
+            SigmaCondition::AllOf(pattern) => {
+                let mut list = matching_identifiers(pattern, identifiers)?;
+                if list.len() == 1 {
+                    list.remove(0)
+                } else {
+                    RuleExpression::And(list)
+                }
+            }
+            SigmaCondition::And(list) => RuleExpression::And(
+                list.iter()
+                    .map(|v| v.to_expression(identifiers))
+                    .collect::<Result<_, _>>()?,
+            ),
+            SigmaCondition::Or(list) => RuleExpression::Or(
+                list.iter()
+                    .map(|v| v.to_expression(identifiers))
+                    .collect::<Result<_, _>>()?,
+            ),
+            SigmaCondition::Not(v) => RuleExpression::Not(Box::new(v.to_expression(identifiers)?)),
+        };
+        Ok(expression)
+    }
+}
+
+fn matching_identifiers(
+    pattern: &str,
+    identifiers: &[&LogString],
+) -> Result<Vec<RuleExpression>, SigmaError> {
+    let list: Vec<RuleExpression> = identifiers
+        .iter()
+        .filter(|name| {
+            if pattern == "them" {
+                // Identifiers starting with an underscore are excluded from "them"
+                !name.starts_with('_')
+            } else {
+                GlobPattern::with_case(pattern, true).is_match(name)
+            }
+        })
+        .map(|name| RuleExpression::Subrule((*name).clone()))
+        .collect();
+    if list.is_empty() {
+        return Err(SigmaError::UnknownIdentifier(LogString::Owned(
+            pattern.to_string(),
+        )));
+    }
+    Ok(list)
+}
+
+fn tokenize(condition: &str) -> Result<Vec<(usize, ConditionToken)>, SigmaError> {
+    let mut tokens = Vec::new();
+    let mut chars = condition.char_indices().peekable();
+    while let Some((position, ch)) = chars.next() {
+        let token = match ch {
+            '(' => ConditionToken::OpenParen,
+            ')' => ConditionToken::CloseParen,
+            '|' => {
+                return Err(SigmaError::InvalidCondition(
+                    position,
+                    LogString::Borrowed("Aggregations in the condition are not supported"),
+                ))
+            }
+            ch if ch.is_whitespace() => continue,
+            ch => {
+                let mut word = String::from(ch);
+                while let Some((_, next)) = chars.peek() {
+                    if next.is_whitespace() || matches!(next, '(' | ')' | '|') {
+                        break;
+                    }
+                    word.push(*next);
+                    chars.next();
+                }
+                match &word.to_lowercase()[..] {
+                    "and" => ConditionToken::And,
+                    "or" => ConditionToken::Or,
+                    "not" => ConditionToken::Not,
+                    "of" => ConditionToken::Of,
+                    _ => ConditionToken::Word(word),
+                }
+            }
+        };
+        tokens.push((position, token));
+    }
+    Ok(tokens)
+}
+
+struct ConditionParser {
+    tokens: Vec<(usize, ConditionToken)>,
+    position: usize,
+    /// Length of the condition. Position of the errors at the end.
+    length: usize,
+}
+
+impl ConditionParser {
+    fn peek(&self) -> Option<&ConditionToken> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn error(&self, message: &'static str) -> SigmaError {
+        let position = self
+            .tokens
+            .get(self.position)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.length);
+        SigmaError::InvalidCondition(position, LogString::Borrowed(message))
+    }
+
+    fn parse_or(&mut self) -> Result<SigmaCondition, SigmaError> {
+        let mut list = vec![self.parse_and()?];
+        while self.peek() == Some(&ConditionToken::Or) {
+            self.position += 1;
+            list.push(self.parse_and()?);
+        }
+        Ok(if list.len() == 1 {
+            list.remove(0)
+        } else {
+            SigmaCondition::Or(list)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<SigmaCondition, SigmaError> {
+        let mut list = vec![self.parse_not()?];
+        while self.peek() == Some(&ConditionToken::And) {
+            self.position += 1;
+            list.push(self.parse_not()?);
+        }
+        Ok(if list.len() == 1 {
+            list.remove(0)
+        } else {
+            SigmaCondition::And(list)
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<SigmaCondition, SigmaError> {
+        if self.peek() == Some(&ConditionToken::Not) {
+            self.position += 1;
+            return Ok(SigmaCondition::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<SigmaCondition, SigmaError> {
+        let word = match self.peek() {
+            Some(ConditionToken::OpenParen) => {
+                self.position += 1;
+                let condition = self.parse_or()?;
+                if self.peek() != Some(&ConditionToken::CloseParen) {
+                    return Err(self.error("Expected a closing parenthesis"));
+                }
+                self.position += 1;
+                return Ok(condition);
+            }
+            Some(ConditionToken::Word(word)) => word.clone(),
+            _ => return Err(self.error("Expected a search identifier")),
+        };
+        self.position += 1;
+        if self.peek() != Some(&ConditionToken::Of) {
+            return Ok(SigmaCondition::Identifier(LogString::Owned(word)));
+        }
+        let quantifier = word.to_lowercase();
+        if quantifier != "1" && quantifier != "all" && quantifier != "any" {
+            self.position -= 1;
+            return Err(self.error("Expected 1 or all before of"));
+        }
+        self.position += 1;
+        let pattern = match self.peek() {
+            Some(ConditionToken::Word(pattern)) => LogString::Owned(pattern.clone()),
+            _ => return Err(self.error("Expected a pattern or them after of")),
+        };
+        self.position += 1;
+        Ok(if quantifier == "all" {
+            SigmaCondition::AllOf(pattern)
+        } else {
+            SigmaCondition::OneOf(pattern)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identifier(name: &'static str) -> SigmaCondition {
+        SigmaCondition::Identifier(LogString::Borrowed(name))
+    }
+
+    #[test]
+    fn should_parse_sigma_conditions() {
+        assert_eq!(
+            SigmaCondition::Or(vec![
+                SigmaCondition::And(vec![identifier("a"), identifier("b")]),
+                SigmaCondition::And(vec![
+                    identifier("c"),
+                    SigmaCondition::Not(Box::new(identifier("d"))),
+                ]),
+            ]),
+            SigmaCondition::parse("a and b or c AND not d").unwrap()
+        );
+        assert_eq!(
+            SigmaCondition::And(vec![
+                SigmaCondition::AllOf(LogString::Borrowed("selection_*")),
+                SigmaCondition::Not(Box::new(SigmaCondition::Or(vec![
+                    SigmaCondition::OneOf(LogString::Borrowed("filter_*")),
+                    identifier("keywords"),
+                ]))),
+            ]),
+            SigmaCondition::parse("all of selection_* and not (1 of filter_* or keywords)")
+                .unwrap()
+        );
+        assert_eq!(
+            SigmaCondition::OneOf(LogString::Borrowed("them")),
+            SigmaCondition::parse("1 of them").unwrap()
+        );
+        assert_eq!(
+            Err(SigmaError::InvalidCondition(
+                13,
+                LogString::Borrowed("Expected a closing parenthesis")
+            )),
+            SigmaCondition::parse("(a and not b c")
+        );
+        assert_eq!(
+            Err(SigmaError::InvalidCondition(
+                10,
+                LogString::Borrowed("Aggregations in the condition are not supported")
+            )),
+            SigmaCondition::parse("selection | count() > 5")
+        );
+        assert!(SigmaCondition::parse("a and").is_err());
+    }
+
+    #[test]
+    fn should_resolve_patterns_with_the_search_identifiers() {
+        let names: Vec<LogString> = vec![
+            "_internal".into(),
+            "filter_a".into(),
+            "filter_b".into(),
+            "selection".into(),
+        ];
+        let identifiers: Vec<&LogString> = names.iter().collect();
+        let subrule = |name: &'static str| RuleExpression::Subrule(LogString::Borrowed(name));
+        assert_eq!(
+            RuleExpression::And(vec![
+                subrule("filter_a"),
+                subrule("filter_b"),
+                subrule("selection"),
+            ]),
+            SigmaCondition::parse("all of them")
+                .unwrap()
+                .to_expression(&identifiers)
+                .unwrap()
+        );
+        assert_eq!(
+            RuleExpression::And(vec![
+                subrule("selection"),
+                RuleExpression::Not(Box::new(RuleExpression::Or(vec![
+                    subrule("filter_a"),
+                    subrule("filter_b"),
+                ]))),
+            ]),
+            SigmaCondition::parse("selection and not 1 of filter_*")
+                .unwrap()
+                .to_expression(&identifiers)
+                .unwrap()
+        );
+        assert_eq!(
+            Err(SigmaError::UnknownIdentifier(LogString::Borrowed("other*"))),
+            SigmaCondition::parse("1 of other*")
+                .unwrap()
+                .to_expression(&identifiers)
+        );
+    }
+}
@@ -0,0 +1,545 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::types::LogString;
+
+use super::aggregation::{AggregationThreshold, RuleAggregation};
+use super::expression::{RuleExpression, MAX_EXPRESSION_GROUPS};
+use super::sequence::{RuleSequence, SequenceStep};
+use super::sigma::{rename_field, rule_metadata, SigmaError, SigmaRule};
+use super::sigma_pipeline::SigmaPipeline;
+use super::{SiemRule, SiemSubRule};
+
+/// Correlation of other Sigma rules referenced by id or name (Sigma v2).
+/// The logs matched by the rules are grouped by the `group-by` fields inside the `timespan`.
+///
+/// ```yaml
+/// title: Many failed logins
+/// id: many_failed_logins
+/// correlation:
+///     type: event_count
+///     rules:
+///         - failed_login
+///     group-by:
+///         - TargetUserName
+///     timespan: 5m
+///     condition:
+///         gte: 10
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SigmaCorrelation {
+    #[serde(rename = "type")]
+    pub correlation_type: SigmaCorrelationType,
+    /// Ids or names of the correlated rules
+    pub rules: Vec<LogString>,
+    #[serde(default, rename = "group-by", skip_serializing_if = "Vec::is_empty")]
+    pub group_by: Vec<LogString>,
+    /// Size of the window. Ex: `30s`, `5m`, `1h`, `1d`
+    pub timespan: LogString,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<SigmaCorrelationCondition>,
+    /// The correlated rules also generate alerts by themselves
+    #[serde(default)]
+    pub generate: bool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SigmaCorrelationType {
+    /// Number of logs matched by the rules
+    EventCount,
+    /// Number of distinct values of a field in the logs matched by the rules
+    ValueCount,
+    /// All the rules match in any order
+    Temporal,
+    /// All the rules match in the order they are listed
+    TemporalOrdered,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SigmaCorrelationCondition {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gt: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gte: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lt: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lte: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eq: Option<i64>,
+    /// Field whose distinct values are counted by `value_count`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<LogString>,
+}
+
+impl SigmaCorrelationCondition {
+    /// Minimum count that triggers the correlation. Only lower bounds are supported.
+    pub fn threshold(&self) -> Result<usize, SigmaError> {
+        if self.lt.is_some() || self.lte.is_some() || self.eq.is_some() {
+            return Err(unsupported(
+                "Only gt and gte conditions are supported".to_string(),
+            ));
+        }
+        let threshold = match (self.gte, self.gt) {
+            (Some(gte), _) => gte,
+            (None, Some(gt)) => gt.saturating_add(1),
+            (None, None) => return Err(unsupported("The condition has no limit".to_string())),
+        };
+        Ok(threshold.max(0) as usize)
+    }
+}
+
+fn unsupported(description: String) -> SigmaError {
+    SigmaError::UnsupportedCorrelation(LogString::Owned(description))
+}
+
+/// Converts a collection of Sigma rules, like the documents of a multi-document YAML file.
+/// Correlations are converted into aggregations or sequences over the rules they reference, with the
+/// `group-by` fields as the key of the alerts. The referenced rules are only returned when the
+/// correlation has `generate: true`. Correlations of other correlations are not supported.
+pub fn convert_sigma_rules(
+    rules: Vec<SigmaRule>,
+    pipeline: &SigmaPipeline,
+) -> Result<Vec<SiemRule>, SigmaError> {
+    // Referenced rules that only feed correlations
+    let mut hidden: BTreeSet<LogString> = BTreeSet::new();
+    // Ids and names of the correlations
+    let mut correlation_references: BTreeSet<LogString> = BTreeSet::new();
+    for rule in &rules {
+        if let Some(correlation) = &rule.correlation {
+            if !correlation.generate {
+                hidden.extend(correlation.rules.iter().cloned());
+            }
+            correlation_references.extend(rule.id.iter().chain(rule.name.iter()).cloned());
+        }
+    }
+    let mut base_rules: Vec<BaseRule> = Vec::new();
+    let mut correlations = Vec::new();
+    for rule in rules {
+        if rule.correlation.is_some() {
+            correlations.push((base_rules.len(), rule));
+            continue;
+        }
+        let fields = pipeline.resolve(&rule.logsource)?.fields;
+        let references: Vec<LogString> = rule.id.iter().chain(rule.name.iter()).cloned().collect();
+        let converted = rule.into_siem_rule(pipeline)?;
+        base_rules.push(BaseRule {
+            references,
+            rule: converted,
+            fields,
+        });
+    }
+    let mut to_ret = Vec::with_capacity(base_rules.len() + correlations.len());
+    let mut converted = Vec::with_capacity(correlations.len());
+    for (position, rule) in correlations {
+        converted.push((
+            position,
+            convert_correlation(rule, &base_rules, &correlation_references)?,
+        ));
+    }
+    let mut converted = converted.into_iter().peekable();
+    for (position, base) in base_rules.into_iter().enumerate() {
+        while let Some((_, rule)) = converted.next_if(|(v, _)| *v == position) {
+            to_ret.push(rule);
+        }
+        if !base.references.iter().any(|v| hidden.contains(v)) {
+            to_ret.push(base.rule);
+        }
+    }
+    to_ret.extend(converted.map(|(_, rule)| rule));
+    Ok(to_ret)
+}
+
+struct BaseRule {
+    /// Id and name of the rule
+    references: Vec<LogString>,
+    rule: SiemRule,
+    /// Field mapping of the pipeline for the logsource of the rule
+    fields: BTreeMap<LogString, LogString>,
+}
+
+/// Correlations can only reference base rules, as the result of a correlation is an alert and not a log
+fn convert_correlation(
+    rule: SigmaRule,
+    base_rules: &[BaseRule],
+    correlations: &BTreeSet<LogString>,
+) -> Result<SiemRule, SigmaError> {
+    let mut rule = rule;
+    let correlation = match rule.correlation.take() {
+        Some(v) => v,
+        None => return Err(unsupported("Not a correlation rule".to_string())),
+    };
+    let mut references: Vec<(&LogString, &BaseRule)> = Vec::with_capacity(correlation.rules.len());
+    for reference in &correlation.rules {
+        match base_rules.iter().find(|v| v.references.contains(reference)) {
+            Some(base) => {
+                if !references.iter().any(|(v, _)| *v == reference) {
+                    references.push((reference, base));
+                }
+            }
+            None if correlations.contains(reference) => {
+                return Err(unsupported(
+                    "Correlations of correlations are not supported".to_string(),
+                ))
+            }
+            None => return Err(SigmaError::UnknownRule(reference.clone())),
+        }
+    }
+    if references.is_empty() {
+        return Err(unsupported("The correlation has no rules".to_string()));
+    }
+    // The group-by fields are renamed like the fields of the first rule
+    let fields = &references[0].1.fields;
+    let group_by: Vec<LogString> = correlation
+        .group_by
+        .iter()
+        .map(|field| rename_field(field, fields))
+        .collect();
+    let window = parse_timespan(&correlation.timespan)?;
+    let mut subrules = BTreeMap::new();
+    let mut siem_rule = rule_metadata(rule, fields);
+    match correlation.correlation_type {
+        SigmaCorrelationType::EventCount | SigmaCorrelationType::ValueCount => {
+            let mut alternatives = Vec::with_capacity(references.len());
+            for (reference, base) in &references {
+                for (name, subrule) in base.rule.subrules.iter() {
+                    subrules.insert(prefixed(reference, name), subrule.clone());
+                }
+                alternatives.push(prefix_expression(&base_expression(&base.rule), reference));
+            }
+            let expression = if alternatives.len() == 1 {
+                alternatives.remove(0)
+            } else {
+                RuleExpression::Or(alternatives)
+            };
+            match expression.to_groups(MAX_EXPRESSION_GROUPS) {
+                Some(groups) => siem_rule.conditions = Cow::Owned(groups),
+                None => siem_rule.expression = Some(Box::new(expression)),
+            }
+            let condition = correlation.condition.unwrap_or_default();
+            let limit = condition.threshold()?;
+            let threshold = match correlation.correlation_type {
+                SigmaCorrelationType::ValueCount => match &condition.field {
+                    Some(field) => {
+                        AggregationThreshold::DistinctCount(rename_field(field, fields), limit)
+                    }
+                    None => {
+                        return Err(unsupported(
+                            "value_count needs the field of the condition".to_string(),
+                        ))
+                    }
+                },
+                _ => AggregationThreshold::Count(limit),
+            };
+            siem_rule.aggregation = Some(Box::new(RuleAggregation {
+                group_by,
+                threshold,
+                window,
+            }));
+        }
+        SigmaCorrelationType::Temporal | SigmaCorrelationType::TemporalOrdered => {
+            // Each rule must be a single subrule to know which one matched each log
+            for (reference, base) in &references {
+                subrules.insert((*reference).clone(), single_subrule(reference, &base.rule)?);
+            }
+            siem_rule.conditions = Cow::Owned(
+                references
+                    .iter()
+                    .map(|(reference, _)| vec![(*reference).clone()])
+                    .collect(),
+            );
+            if correlation.correlation_type == SigmaCorrelationType::Temporal {
+                siem_rule.aggregation = Some(Box::new(RuleAggregation {
+                    group_by,
+                    threshold: AggregationThreshold::DistinctSubrules(references.len()),
+                    window,
+                }));
+            } else {
+                siem_rule.sequence = Some(Box::new(RuleSequence {
+                    steps: references
+                        .iter()
+                        .map(|(reference, _)| SequenceStep {
+                            subrule: (*reference).clone(),
+                            count: 1,
+                        })
+                        .collect(),
+                    join_by: group_by,
+                    max_span: window,
+                }));
+            }
+        }
+    }
+    siem_rule.subrules = Cow::Owned(subrules);
+    siem_rule.validate().map_err(SigmaError::InvalidRule)?;
+    Ok(siem_rule)
+}
+
+fn prefixed(reference: &str, name: &str) -> LogString {
+    LogString::Owned(format!("{}:{}", reference, name))
+}
+
+/// Condition of the rule as an expression over its subrules
+fn base_expression(rule: &SiemRule) -> RuleExpression {
+    if let Some(expression) = &rule.expression {
+        return expression.as_ref().clone();
+    }
+    let and = |names: Vec<LogString>| {
+        RuleExpression::And(names.into_iter().map(RuleExpression::Subrule).collect())
+    };
+    if rule.conditions.is_empty() {
+        return and(rule.subrules.keys().cloned().collect());
+    }
+    RuleExpression::Or(
+        rule.conditions
+            .iter()
+            .map(|group| and(group.clone()))
+            .collect(),
+    )
+}
+
+fn prefix_expression(expression: &RuleExpression, reference: &str) -> RuleExpression {
+    match expression {
+        RuleExpression::Subrule(name) => RuleExpression::Subrule(prefixed(reference, name)),
+        RuleExpression::And(list) => RuleExpression::And(
+            list.iter()
+                .map(|v| prefix_expression(v, reference))
+                .collect(),
+        ),
+        RuleExpression::Or(list) => RuleExpression::Or(
+            list.iter()
+                .map(|v| prefix_expression(v, reference))
+                .collect(),
+        ),
+        RuleExpression::Not(v) => RuleExpression::Not(Box::new(prefix_expression(v, reference))),
+    }
+}
+
+/// Joins the conditions of a rule that is a single group of subrules
+fn single_subrule(reference: &str, rule: &SiemRule) -> Result<SiemSubRule, SigmaError> {
+    let groups = base_expression(rule).to_groups(MAX_EXPRESSION_GROUPS);
+    let group = match groups {
+        Some(mut groups) if groups.len() == 1 => groups.remove(0),
+        _ => {
+            return Err(unsupported(format!(
+                "The rule {} must be a single group of search identifiers without negations to be used in a temporal correlation",
+                reference
+            )))
+        }
+    };
+    let mut conditions = Vec::new();
+    for name in group {
+        if let Some(subrule) = rule.subrules.get(&name) {
+            conditions.extend(subrule.conditions.iter().cloned());
+        }
+    }
+    Ok(SiemSubRule {
+        conditions,
+        rule_state: None,
+    })
+}
+
+/// Timespan in milliseconds
+fn parse_timespan(timespan: &str) -> Result<i64, SigmaError> {
+    let invalid = || {
+        SigmaError::InvalidValue(
+            LogString::Borrowed("timespan"),
+            LogString::Owned(format!("Invalid timespan: {}", timespan)),
+        )
+    };
+    let timespan = timespan.trim();
+    let unit = timespan.chars().last().ok_or_else(invalid)?;
+    let multiplier = match unit {
+        's' => 1_000,
+        'm' => 60_000,
+        'h' => 3_600_000,
+        'd' => 86_400_000,
+        'w' => 604_800_000,
+        _ => return Err(invalid()),
+    };
+    let amount: i64 = timespan[..timespan.len() - 1]
+        .parse()
+        .map_err(|_| invalid())?;
+    Ok(amount.saturating_mul(multiplier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::holder::DatasetHolder;
+    use crate::prelude::rule::aggregation::AggregationEngine;
+    use crate::prelude::rule::sequence::SequenceEngine;
+    use crate::prelude::rule::state::RuleStateStore;
+    use crate::prelude::{SiemField, SiemLog};
+
+    const RULES: &str = r#"
+title: Failed login
+id: 5d2d1f2c-0001
+name: failed_login
+logsource:
+    product: windows
+    service: security
+detection:
+    selection:
+        EventID: 4625
+    condition: selection
+---
+title: Successful login
+id: 5d2d1f2c-0002
+name: successful_login
+logsource:
+    product: windows
+    service: security
+detection:
+    selection:
+        EventID: 4624
+    condition: selection
+---
+title: Many failed logins
+id: many_failed_logins
+level: high
+correlation:
+    type: event_count
+    rules:
+        - failed_login
+    group-by:
+        - TargetUserName
+    timespan: 5m
+    condition:
+        gte: 3
+---
+title: Brute force followed by a login
+id: brute_force_success
+correlation:
+    type: temporal_ordered
+    rules:
+        - failed_login
+        - 5d2d1f2c-0002
+    group-by:
+        - TargetUserName
+    timespan: 10m
+    generate: true
+"#;
+
+    fn parse(documents: &str) -> Vec<SigmaRule> {
+        serde_yaml::Deserializer::from_str(documents)
+            .map(|document| SigmaRule::deserialize(document).unwrap())
+            .collect()
+    }
+
+    fn login(user: &'static str, event_id: i64, time: i64) -> SiemLog {
+        let mut log = SiemLog::new("", time, "localhost");
+        log.add_field("TargetUserName", user.into());
+        log.add_field("EventID", SiemField::I64(event_id));
+        log.add_field("event.created", SiemField::I64(time));
+        log
+    }
+
+    #[test]
+    fn should_convert_correlations() {
+        let rules = convert_sigma_rules(parse(RULES), &SigmaPipeline::default()).unwrap();
+        let ids: Vec<&str> = rules.iter().map(|v| &v.id[..]).collect();
+        // failed_login only feeds the first correlation
+        assert_eq!(
+            vec!["5d2d1f2c-0002", "many_failed_logins", "brute_force_success"],
+            ids
+        );
+        let event_count = &rules[1];
+        assert_eq!(
+            Some(Box::new(RuleAggregation {
+                group_by: vec![LogString::Borrowed("TargetUserName")],
+                threshold: AggregationThreshold::Count(3),
+                window: 300_000,
+            })),
+            event_count.aggregation
+        );
+        let datasets = DatasetHolder::new();
+        let mut engine = AggregationEngine::new();
+        let mut states = RuleStateStore::new();
+        for time in 0..2 {
+            let mut log = login("bob", 4625, time * 1_000);
+            assert!(engine
+                .process(event_count, &mut log, &datasets, &mut states)
+                .is_none());
+        }
+        let mut log = login("alice", 4625, 3_000);
+        assert!(engine
+            .process(event_count, &mut log, &datasets, &mut states)
+            .is_none());
+        let mut log = login("bob", 4625, 4_000);
+        let alert = engine
+            .process(event_count, &mut log, &datasets, &mut states)
+            .unwrap();
+        assert_eq!("bob", alert.aggregation.unwrap().key);
+
+        let ordered = &rules[2];
+        let sequence = ordered.sequence.as_ref().unwrap();
+        assert_eq!(600_000, sequence.max_span);
+        let mut engine = SequenceEngine::default();
+        let mut states = RuleStateStore::new();
+        let mut log = login("bob", 4624, 0);
+        assert!(engine
+            .process(ordered, &mut log, &datasets, &mut states)
+            .is_none());
+        let mut log = login("bob", 4625, 1_000);
+        assert!(engine
+            .process(ordered, &mut log, &datasets, &mut states)
+            .is_none());
+        let mut log = login("bob", 4624, 2_000);
+        assert!(engine
+            .process(ordered, &mut log, &datasets, &mut states)
+            .is_some());
+
+        // Any order
+        let documents = RULES.replace("temporal_ordered", "temporal");
+        let rules = convert_sigma_rules(parse(&documents), &SigmaPipeline::default()).unwrap();
+        let temporal = &rules[2];
+        assert_eq!(
+            AggregationThreshold::DistinctSubrules(2),
+            temporal.aggregation.as_ref().unwrap().threshold
+        );
+        let mut engine = AggregationEngine::new();
+        let mut states = RuleStateStore::new();
+        let mut log = login("bob", 4624, 0);
+        assert!(engine
+            .process(temporal, &mut log, &datasets, &mut states)
+            .is_none());
+        let mut log = login("bob", 4624, 500);
+        assert!(engine
+            .process(temporal, &mut log, &datasets, &mut states)
+            .is_none());
+        let mut log = login("bob", 4625, 1_000);
+        assert!(engine
+            .process(temporal, &mut log, &datasets, &mut states)
+            .is_some());
+
+        // References must exist
+        let unknown = RULES.replace("        - 5d2d1f2c-0002", "        - unknown_rule");
+        let err = convert_sigma_rules(parse(&unknown), &SigmaPipeline::default()).unwrap_err();
+        assert_eq!(
+            SigmaError::UnknownRule(LogString::Borrowed("unknown_rule")),
+            err
+        );
+        // and cannot be other correlations
+        let nested = RULES.replace(
+            "        - failed_login\n        - 5d2d1f2c-0002",
+            "        - many_failed_logins\n        - 5d2d1f2c-0002",
+        );
+        let err = convert_sigma_rules(parse(&nested), &SigmaPipeline::default()).unwrap_err();
+        assert_eq!(
+            SigmaError::UnsupportedCorrelation(LogString::Borrowed(
+                "Correlations of correlations are not supported"
+            )),
+            err
+        );
+        let documents = documents.replace("gte: 3", "lt: 3");
+        assert!(matches!(
+            convert_sigma_rules(parse(&documents), &SigmaPipeline::default()),
+            Err(SigmaError::UnsupportedCorrelation(_))
+        ));
+        assert_eq!(Ok(90_000), parse_timespan("90s"));
+        assert!(parse_timespan("5y").is_err());
+    }
+}
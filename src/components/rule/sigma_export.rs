@@ -0,0 +1,456 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::dataset::SiemDatasetType;
+use crate::prelude::types::LogString;
+use crate::prelude::{AlertSeverity, SiemField};
+
+use super::expression::RuleExpression;
+use super::lint::operator_name;
+use super::sigma::{SigmaRule, SigmaRuleCondition, SigmaRuleDetection, SigmaValue, SIGMA_TACTICS};
+use super::{RuleOperator, SiemRule};
+
+/// A construct of the rule that cannot be represented in Sigma
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SigmaExportError {
+    pub subrule: Option<LogString>,
+    pub field: Option<LogString>,
+    /// Operator or feature of the rule. Ex: `in_dataset`, `rule_state`, `aggregation`
+    pub construct: LogString,
+}
+
+impl SigmaExportError {
+    fn new(
+        subrule: Option<&LogString>,
+        field: Option<&LogString>,
+        construct: &'static str,
+    ) -> Self {
+        Self {
+            subrule: subrule.cloned(),
+            field: field.cloned(),
+            construct: LogString::Borrowed(construct),
+        }
+    }
+}
+
+/// Exports the rule to share it with tools that use Sigma.
+/// The operators are mapped to modifiers, the MITRE tactics and techniques to `attack.*` tags and the severity to the level.
+/// Case sensitive operators use the `cased` modifier when the value has letters.
+/// All the constructs that cannot be represented are reported, like `InDataset`, `ExistsRuleState` or aggregations.
+/// Negated subrules of the expression are exported in the condition, but the `Not` operator of a field is reported.
+impl TryFrom<&SiemRule> for SigmaRule {
+    type Error = Vec<SigmaExportError>;
+
+    fn try_from(rule: &SiemRule) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+        if rule.aggregation.is_some() {
+            errors.push(SigmaExportError::new(None, None, "aggregation"));
+        }
+        if rule.sequence.is_some() {
+            errors.push(SigmaExportError::new(None, None, "sequence"));
+        }
+        let mut search_identifiers = BTreeMap::new();
+        for (name, subrule) in rule.subrules.iter() {
+            if name.is_empty() || name.contains(|v: char| v.is_whitespace() || "()|".contains(v)) {
+                errors.push(SigmaExportError::new(Some(name), None, "subrule_name"));
+            }
+            if subrule.rule_state.is_some() {
+                errors.push(SigmaExportError::new(Some(name), None, "rule_state"));
+            }
+            let mut conditions = BTreeMap::new();
+            for condition in &subrule.conditions {
+                let field = &condition.field;
+                match export_operator(&condition.operator) {
+                    Ok((modifiers, value)) => {
+                        let mut key = field.to_string();
+                        for modifier in modifiers {
+                            key.push('|');
+                            key.push_str(modifier);
+                        }
+                        // Sigma cannot check the same field twice with the same modifiers
+                        if conditions.insert(LogString::Owned(key), value).is_some() {
+                            errors.push(SigmaExportError::new(
+                                Some(name),
+                                Some(field),
+                                "repeated_field",
+                            ));
+                        }
+                    }
+                    Err(construct) => {
+                        errors.push(SigmaExportError::new(Some(name), Some(field), construct))
+                    }
+                }
+            }
+            search_identifiers.insert(name.clone(), SigmaRuleCondition::Map(conditions));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(SigmaRule {
+            title: rule.name.clone(),
+            id: Some(rule.id.clone()),
+            description: (!rule.description.is_empty()).then(|| rule.description.clone()),
+            level: Some(LogString::Borrowed(severity_to_level(&rule.alert.severity))),
+            tags: Some(export_tags(rule)).filter(|v| !v.is_empty()),
+            detection: Cow::Owned(SigmaRuleDetection {
+                search_identifiers,
+                condition: LogString::Owned(export_condition(rule)),
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+fn severity_to_level(severity: &AlertSeverity) -> &'static str {
+    match severity {
+        AlertSeverity::INFORMATIONAL => "informational",
+        AlertSeverity::LOW => "low",
+        AlertSeverity::MEDIUM => "medium",
+        AlertSeverity::HIGH => "high",
+        AlertSeverity::CRITICAL => "critical",
+    }
+}
+
+/// MITRE tags followed by the tags of the alerts
+fn export_tags(rule: &SiemRule) -> Vec<LogString> {
+    let mut tags: Vec<LogString> = Vec::new();
+    for tactic in &rule.mitre.tactics {
+        let tag = match SIGMA_TACTICS.iter().find(|(_, v)| v == tactic) {
+            Some((name, _)) => format!("attack.{}", name),
+            None => format!("attack.{:?}", tactic).to_lowercase(),
+        };
+        tags.push(LogString::Owned(tag));
+    }
+    for technique in &rule.mitre.techniques {
+        let tag = format!("attack.{:?}", technique).replace('_', ".");
+        tags.push(LogString::Owned(tag.to_lowercase()));
+    }
+    for tag in &rule.alert.tags {
+        if !tags.iter().any(|v| v.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.clone());
+        }
+    }
+    tags
+}
+
+fn export_condition(rule: &SiemRule) -> String {
+    if let Some(expression) = &rule.expression {
+        return export_expression(expression);
+    }
+    let and = |names: Vec<&str>, nested: bool| {
+        let joined = names.join(" and ");
+        if nested && names.len() > 1 {
+            format!("({})", joined)
+        } else {
+            joined
+        }
+    };
+    if rule.conditions.is_empty() {
+        return and(rule.subrules.keys().map(|v| &v[..]).collect(), false);
+    }
+    let nested = rule.conditions.len() > 1;
+    rule.conditions
+        .iter()
+        .map(|group| and(group.iter().map(|v| &v[..]).collect(), nested))
+        .collect::<Vec<String>>()
+        .join(" or ")
+}
+
+fn export_expression(expression: &RuleExpression) -> String {
+    let child = |expression: &RuleExpression| match expression {
+        RuleExpression::And(list) | RuleExpression::Or(list) if list.len() > 1 => {
+            format!("({})", export_expression(expression))
+        }
+        _ => export_expression(expression),
+    };
+    match expression {
+        RuleExpression::Subrule(name) => name.to_string(),
+        RuleExpression::And(list) => list.iter().map(child).collect::<Vec<_>>().join(" and "),
+        RuleExpression::Or(list) => list.iter().map(child).collect::<Vec<_>>().join(" or "),
+        RuleExpression::Not(v) => format!("not {}", child(v)),
+    }
+}
+
+/// Modifiers and value of the operator. The name of the operator if it cannot be represented.
+fn export_operator(
+    operator: &RuleOperator,
+) -> Result<(Vec<&'static str>, SigmaValue), &'static str> {
+    let text = |matcher: Option<&'static str>, value: &str| {
+        let mut modifiers: Vec<&'static str> = matcher.into_iter().collect();
+        if value.chars().any(char::is_alphabetic) {
+            modifiers.push("cased");
+        }
+        (
+            modifiers,
+            SigmaValue::Text(LogString::Owned(escape_wildcards(value))),
+        )
+    };
+    let number = |value: &SiemField| match value {
+        SiemField::I64(v) => Some(SigmaValue::Int(*v)),
+        SiemField::U64(v) => i64::try_from(*v).ok().map(SigmaValue::Int),
+        SiemField::F64(v) => Some(SigmaValue::Float(*v)),
+        _ => None,
+    };
+    let exported = match operator {
+        RuleOperator::Equals(SiemField::Null) | RuleOperator::IsNull(true) => {
+            (vec![], SigmaValue::None)
+        }
+        RuleOperator::Equals(
+            value @ (SiemField::Text(_)
+            | SiemField::IP(_)
+            | SiemField::Domain(_)
+            | SiemField::User(_)
+            | SiemField::AssetID(_)),
+        ) => text(None, &value.to_string()),
+        RuleOperator::Equals(value) => match number(value) {
+            Some(value) => (vec![], value),
+            None => return Err(operator_name(operator)),
+        },
+        RuleOperator::StartsWith(v) => text(Some("startswith"), v),
+        RuleOperator::EndsWith(v) => text(Some("endswith"), v),
+        RuleOperator::Contains(v) => text(Some("contains"), v),
+        RuleOperator::Glob(glob) => {
+            let modifiers = if glob.is_case_sensitive() {
+                vec!["cased"]
+            } else {
+                vec![]
+            };
+            (
+                modifiers,
+                SigmaValue::Text(LogString::Owned(glob.pattern().to_string())),
+            )
+        }
+        RuleOperator::GT(v) | RuleOperator::GTE(v) | RuleOperator::LT(v) | RuleOperator::LTE(v) => {
+            let modifier = match operator {
+                RuleOperator::GT(_) => "gt",
+                RuleOperator::GTE(_) => "gte",
+                RuleOperator::LT(_) => "lt",
+                _ => "lte",
+            };
+            match number(v) {
+                Some(value) => (vec![modifier], value),
+                None => return Err(operator_name(operator)),
+            }
+        }
+        RuleOperator::Matches(regex) => (
+            vec!["re"],
+            SigmaValue::Text(LogString::Owned(regex.as_str().to_string())),
+        ),
+        RuleOperator::SameNet((ip, net)) => (
+            vec!["cidr"],
+            SigmaValue::Text(LogString::Owned(format!("{}/{}", ip, net))),
+        ),
+        RuleOperator::Exists(v) => (vec!["exists"], SigmaValue::Bool(*v)),
+        RuleOperator::EqualsField(field) => (vec!["fieldref"], SigmaValue::Text(field.clone())),
+        RuleOperator::InDataset(SiemDatasetType::CustomTextList(name)) => (
+            vec!["expand"],
+            SigmaValue::Text(LogString::Owned(format!("%{}%", name))),
+        ),
+        // The decoded field is compared with the value, like the field with the encoded value
+        RuleOperator::B64(inner) => match inner.as_ref() {
+            RuleOperator::Contains(v) => (
+                vec!["base64offset", "contains"],
+                SigmaValue::Text(LogString::Owned(v.clone())),
+            ),
+            RuleOperator::Equals(SiemField::Text(v)) => {
+                (vec!["base64"], SigmaValue::Text(v.clone()))
+            }
+            _ => return Err(operator_name(operator)),
+        },
+        RuleOperator::Any(list) | RuleOperator::All(list) => {
+            let mut modifiers = None;
+            // `cased` is decided once for the list, as it does not change values without letters
+            let mut cased = None;
+            let mut values = Vec::with_capacity(list.len());
+            for operator in list {
+                let (mut item_modifiers, value) = export_operator(operator)?;
+                let item_cased = item_modifiers.contains(&"cased");
+                item_modifiers.retain(|v| *v != "cased");
+                let has_letters =
+                    matches!(&value, SigmaValue::Text(v) if v.chars().any(char::is_alphabetic));
+                if matches!(value, SigmaValue::Array(_))
+                    || modifiers.get_or_insert_with(|| item_modifiers.clone()) != &item_modifiers
+                    || (has_letters && *cased.get_or_insert(item_cased) != item_cased)
+                {
+                    return Err(operator_name(operator));
+                }
+                values.push(value);
+            }
+            let mut modifiers = match modifiers {
+                Some(v) => v,
+                None => return Err(operator_name(operator)),
+            };
+            if cased == Some(true) {
+                modifiers.push("cased");
+            }
+            if matches!(operator, RuleOperator::All(_)) && values.len() > 1 {
+                modifiers.push("all");
+            }
+            (modifiers, SigmaValue::Array(values))
+        }
+        _ => return Err(operator_name(operator)),
+    };
+    Ok(exported)
+}
+
+/// Escapes the wildcards and the backslashes that would escape them
+fn escape_wildcards(value: &str) -> String {
+    let mut to_ret = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '*' | '?' => to_ret.push('\\'),
+            '\\' if matches!(chars.peek(), Some('*' | '?' | '\\')) => to_ret.push('\\'),
+            _ => {}
+        }
+        to_ret.push(ch);
+    }
+    to_ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::holder::DatasetHolder;
+    use crate::prelude::mitre::{MitreTactics, MitreTechniques};
+    use crate::prelude::rule::{RuleCondition, RuleState, RuleStateValue, SiemSubRule};
+    use crate::prelude::SiemLog;
+
+    const RULE: &str = r#"
+title: Encoded PowerShell
+id: encoded_powershell
+description: Encoded command in $CommandLine
+level: high
+tags:
+    - attack.execution
+    - attack.t1059.001
+    - custom
+logsource:
+    product: windows
+detection:
+    selection:
+        Image|endswith: '\powershell.exe'
+        CommandLine|contains|all:
+            - ' -enc'
+            - ' -w hidden'
+        DestinationIp|cidr: '10.0.0.0/8'
+        Group|expand: '%admins%'
+        ParentCommandLine|contains|cased:
+            - 'abc'
+            - '123'
+    filter:
+        User|cased: 'SYSTEM'
+        ParentImage: 'C:\Program Files\\*'
+    condition: selection and not filter
+"#;
+
+    #[test]
+    fn should_export_rules_to_sigma() {
+        let sigma: SigmaRule = serde_yaml::from_str(RULE).unwrap();
+        let rule: SiemRule = sigma.try_into().unwrap();
+        assert_eq!(vec![MitreTactics::TA0002], rule.mitre.tactics);
+        assert_eq!(vec![MitreTechniques::T1059_001], rule.mitre.techniques);
+
+        let exported = SigmaRule::try_from(&rule).unwrap();
+        assert_eq!(Some(LogString::Borrowed("high")), exported.level);
+        assert_eq!(
+            Some(vec![
+                LogString::Borrowed("attack.execution"),
+                LogString::Borrowed("attack.t1059.001"),
+                LogString::Borrowed("custom"),
+            ]),
+            exported.tags
+        );
+        assert_eq!("selection and not filter", exported.detection.condition);
+        let yaml = serde_yaml::to_string(&exported).unwrap();
+        assert!(yaml.contains("User|cased: SYSTEM"));
+        assert!(yaml.contains("DestinationIp|cidr: 10.0.0.0/8"));
+        assert!(yaml.contains("ParentCommandLine|contains|cased:"));
+
+        // The exported rule is translated into the same rule
+        let imported: SigmaRule = serde_yaml::from_str(&yaml).unwrap();
+        let imported: SiemRule = imported.try_into().unwrap();
+        let operators = |rule: &SiemRule| -> Vec<(LogString, LogString, RuleOperator)> {
+            rule.subrules
+                .iter()
+                .flat_map(|(name, subrule)| {
+                    subrule.conditions.iter().map(move |condition| {
+                        (
+                            name.clone(),
+                            condition.field.clone(),
+                            condition.operator.clone(),
+                        )
+                    })
+                })
+                .collect()
+        };
+        assert_eq!(operators(&rule), operators(&imported));
+        assert_eq!(rule.expression, imported.expression);
+        assert_eq!(rule.mitre.tactics, imported.mitre.tactics);
+        assert_eq!(rule.mitre.techniques, imported.mitre.techniques);
+
+        let datasets = DatasetHolder::new();
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.add_field("User", "system".into());
+        assert!(!imported.subrules["filter"].conditions[1]
+            .operator
+            .matches("User", &mut log, &datasets));
+        log.add_field("User", "SYSTEM".into());
+        assert!(imported.subrules["filter"].conditions[1]
+            .operator
+            .matches("User", &mut log, &datasets));
+    }
+
+    #[test]
+    fn should_report_constructs_not_supported_by_sigma() {
+        let sigma: SigmaRule = serde_yaml::from_str(RULE).unwrap();
+        let mut rule: SiemRule = sigma.try_into().unwrap();
+        let mut subrules = rule.subrules.to_mut();
+        subrules.insert(
+            LogString::Borrowed("blocked"),
+            SiemSubRule {
+                conditions: vec![
+                    RuleCondition {
+                        field: LogString::Borrowed("source.ip"),
+                        operator: RuleOperator::InDataset(SiemDatasetType::BlockIp),
+                    },
+                    RuleCondition {
+                        field: LogString::Borrowed("user.name"),
+                        operator: RuleOperator::Not(Box::new(RuleOperator::Equals("root".into()))),
+                    },
+                ],
+                rule_state: Some(RuleState {
+                    name: LogString::Borrowed("blocked"),
+                    states: RuleStateValue::Field(LogString::Borrowed("source.ip")),
+                    ttl: None,
+                }),
+            },
+        );
+        subrules = rule.subrules.to_mut();
+        subrules
+            .get_mut("selection")
+            .unwrap()
+            .conditions
+            .push(RuleCondition {
+                field: LogString::Borrowed("source.ip"),
+                operator: RuleOperator::ExistsRuleState(vec![]),
+            });
+        let errors = SigmaRule::try_from(&rule).unwrap_err();
+        let constructs: Vec<(&str, &str)> = errors
+            .iter()
+            .map(|v| (&v.subrule.as_ref().unwrap()[..], &v.construct[..]))
+            .collect();
+        assert_eq!(
+            vec![
+                ("blocked", "rule_state"),
+                ("blocked", "in_dataset"),
+                ("blocked", "not"),
+                ("selection", "exists_rule_state"),
+            ],
+            constructs
+        );
+        assert_eq!("\\\\\\*a\\?\\", escape_wildcards("\\*a?\\"));
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::dataset::rules::{CorrelationRulesDataset, RulesDataset};
+use crate::prelude::types::LogString;
+
+use super::sigma::{SigmaError, SigmaRule, SigmaRuleLogSource};
+use super::sigma_correlation::convert_sigma_rules;
+use super::sigma_pipeline::SigmaPipeline;
+use super::SiemRule;
+
+/// Loads all the Sigma rules (`.yml` and `.yaml` files) of a directory tree.
+/// The rules that cannot be loaded are reported without stopping the load of the rest.
+///
+/// ```ignore
+/// let loader = SigmaLoader::new(pipeline).skip_status(vec!["experimental".into()]);
+/// let report = loader.load_into("rules/windows", &correlation_dataset);
+/// for diagnostic in &report.diagnostics {
+///     warn!("{:?}", diagnostic);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct SigmaLoader {
+    pipeline: SigmaPipeline,
+    skipped_status: Vec<LogString>,
+}
+
+/// Rules loaded from a directory and the problems found
+#[derive(Debug, Default, Clone)]
+pub struct SigmaLoadResult {
+    pub rules: RulesDataset,
+    pub report: SigmaLoadReport,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct SigmaLoadReport {
+    /// Number of files read
+    pub files: usize,
+    /// Ids of the rules loaded
+    pub loaded: Vec<LogString>,
+    /// Ids of the rules ignored because of their status
+    pub skipped: Vec<LogString>,
+    pub diagnostics: Vec<SigmaDiagnostic>,
+}
+
+/// Problem found loading a file or one of its rules
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SigmaDiagnostic {
+    pub file: PathBuf,
+    /// Id of the rule. None if the problem affects the whole file.
+    pub rule: Option<LogString>,
+    pub kind: SigmaDiagnosticKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
+pub enum SigmaDiagnosticKind {
+    /// The file or directory cannot be read
+    Io(LogString),
+    /// The document is not a valid Sigma rule
+    Parse(LogString),
+    /// The rule has no id
+    MissingId,
+    /// A rule with the same id was already loaded from the file
+    DuplicateId(PathBuf),
+    /// The modifier is not supported: (Field, Modifier)
+    UnsupportedModifier(LogString, LogString),
+    /// The pipeline has no mapping for the logsource of the rule
+    UnknownLogSource(SigmaRuleLogSource),
+    /// The rule cannot be converted
+    Conversion(SigmaError),
+}
+
+/// Sigma rule accepted for conversion and the file it comes from
+struct SourceRule {
+    file: PathBuf,
+    id: LogString,
+    rule: SigmaRule,
+}
+
+impl Default for SigmaLoader {
+    fn default() -> Self {
+        Self::new(SigmaPipeline::default())
+    }
+}
+
+impl SigmaLoader {
+    /// Loader that skips the deprecated and unsupported rules
+    pub fn new(pipeline: SigmaPipeline) -> Self {
+        Self {
+            pipeline,
+            skipped_status: vec![
+                LogString::Borrowed("deprecated"),
+                LogString::Borrowed("unsupported"),
+            ],
+        }
+    }
+
+    /// Rules with any of these statuses are not loaded. Replaces the default ones.
+    pub fn skip_status(mut self, status: Vec<LogString>) -> Self {
+        self.skipped_status = status;
+        self
+    }
+
+    /// Loads the rules of the directory and sends them to replace the rules of the dataset
+    pub fn load_into<P: AsRef<Path>>(
+        &self,
+        path: P,
+        dataset: &CorrelationRulesDataset,
+    ) -> SigmaLoadReport {
+        let loaded = self.load_dir(path);
+        dataset.replace(loaded.rules);
+        loaded.report
+    }
+
+    /// Loads the rules of all the YAML files inside the directory and its subdirectories
+    pub fn load_dir<P: AsRef<Path>>(&self, path: P) -> SigmaLoadResult {
+        let mut diagnostics = Vec::new();
+        let mut files = Vec::new();
+        find_rule_files(path.as_ref(), &mut files, &mut diagnostics);
+        files.sort();
+        let mut documents = Vec::with_capacity(files.len());
+        for file in files {
+            match std::fs::read_to_string(&file) {
+                Ok(content) => documents.push((file, content)),
+                Err(err) => diagnostics.push(file_diagnostic(
+                    file,
+                    SigmaDiagnosticKind::Io(LogString::Owned(err.to_string())),
+                )),
+            }
+        }
+        let mut loaded = self.load_documents(documents);
+        diagnostics.append(&mut loaded.report.diagnostics);
+        loaded.report.diagnostics = diagnostics;
+        loaded
+    }
+
+    /// Loads the rules of already read files: (Path, Content).
+    /// When multiple rules have the same id only the first one is loaded.
+    pub fn load_documents(&self, documents: Vec<(PathBuf, String)>) -> SigmaLoadResult {
+        let mut report = SigmaLoadReport {
+            files: documents.len(),
+            ..Default::default()
+        };
+        let mut seen: BTreeMap<LogString, PathBuf> = BTreeMap::new();
+        let mut accepted = Vec::new();
+        for (file, content) in documents {
+            for rule in parse_documents(&file, &content, &mut report.diagnostics) {
+                let id = match &rule.id {
+                    Some(id) => id.clone(),
+                    None => {
+                        report.diagnostics.push(file_diagnostic(
+                            file.clone(),
+                            SigmaDiagnosticKind::MissingId,
+                        ));
+                        continue;
+                    }
+                };
+                if self.is_skipped(&rule) {
+                    report.skipped.push(id);
+                    continue;
+                }
+                if let Some(first) = seen.get(&id) {
+                    report.diagnostics.push(SigmaDiagnostic {
+                        file: file.clone(),
+                        rule: Some(id),
+                        kind: SigmaDiagnosticKind::DuplicateId(first.clone()),
+                    });
+                    continue;
+                }
+                seen.insert(id.clone(), file.clone());
+                accepted.push(SourceRule {
+                    file: file.clone(),
+                    id,
+                    rule,
+                });
+            }
+        }
+        let (correlations, base_rules): (Vec<SourceRule>, Vec<SourceRule>) = accepted
+            .into_iter()
+            .partition(|v| v.rule.correlation.is_some());
+
+        let mut converted: Vec<(SourceRule, SiemRule)> = Vec::with_capacity(base_rules.len());
+        for source in base_rules {
+            if !self.knows_logsource(&source.rule.logsource) {
+                let kind = SigmaDiagnosticKind::UnknownLogSource(
+                    source.rule.logsource.clone().into_owned(),
+                );
+                report.diagnostics.push(rule_diagnostic(source, kind));
+                continue;
+            }
+            match source.rule.clone().into_siem_rule(&self.pipeline) {
+                Ok(rule) => converted.push((source, rule)),
+                Err(err) => report
+                    .diagnostics
+                    .push(rule_diagnostic(source, error_kind(err))),
+            }
+        }
+
+        // Rules referenced by correlations that do not generate alerts by themselves
+        let mut hidden: BTreeSet<LogString> = BTreeSet::new();
+        let mut correlation_rules = Vec::with_capacity(correlations.len());
+        for source in correlations {
+            let correlation = match &source.rule.correlation {
+                Some(v) => v,
+                None => continue,
+            };
+            let references: Vec<SigmaRule> = converted
+                .iter()
+                .filter(|(base, _)| is_referenced(&base.rule, |v| correlation.rules.contains(v)))
+                .map(|(base, _)| base.rule.clone())
+                .collect();
+            let mut rules = references;
+            rules.push(source.rule.clone());
+            // The correlation is always the last rule returned
+            match convert_sigma_rules(rules, &self.pipeline).map(|mut v| v.pop()) {
+                Ok(Some(rule)) => {
+                    if !correlation.generate {
+                        hidden.extend(correlation.rules.iter().cloned());
+                    }
+                    correlation_rules.push((source, rule));
+                }
+                Ok(None) => {}
+                Err(err) => report
+                    .diagnostics
+                    .push(rule_diagnostic(source, error_kind(err))),
+            }
+        }
+
+        let mut rules = RulesDataset::new();
+        let visible = converted
+            .into_iter()
+            .filter(|(source, _)| !is_referenced(&source.rule, |v| hidden.contains(v)));
+        for (source, rule) in visible.chain(correlation_rules) {
+            match rules.insert(rule) {
+                Ok(_) => report.loaded.push(source.id),
+                Err(err) => report.diagnostics.push(rule_diagnostic(
+                    source,
+                    SigmaDiagnosticKind::Conversion(SigmaError::InvalidRule(err)),
+                )),
+            }
+        }
+        SigmaLoadResult { rules, report }
+    }
+
+    fn is_skipped(&self, rule: &SigmaRule) -> bool {
+        match &rule.status {
+            Some(status) => self
+                .skipped_status
+                .iter()
+                .any(|v| v.eq_ignore_ascii_case(status)),
+            None => false,
+        }
+    }
+
+    /// Without mappings in the pipeline all the logsources are accepted
+    fn knows_logsource(&self, logsource: &SigmaRuleLogSource) -> bool {
+        self.pipeline.logsources.is_empty()
+            || self
+                .pipeline
+                .logsources
+                .iter()
+                .any(|v| v.matches(logsource))
+    }
+}
+
+/// Symbolic links to directories are not followed, so a link to a parent directory cannot cause a loop
+fn find_rule_files(path: &Path, files: &mut Vec<PathBuf>, diagnostics: &mut Vec<SigmaDiagnostic>) {
+    let entries = match std::fs::read_dir(path) {
+        Ok(v) => v,
+        Err(err) => {
+            diagnostics.push(file_diagnostic(
+                path.to_path_buf(),
+                SigmaDiagnosticKind::Io(LogString::Owned(err.to_string())),
+            ));
+            return;
+        }
+    };
+    for entry in entries {
+        let (entry_path, file_type) = match entry.and_then(|v| Ok((v.path(), v.file_type()?))) {
+            Ok(v) => v,
+            Err(err) => {
+                diagnostics.push(file_diagnostic(
+                    path.to_path_buf(),
+                    SigmaDiagnosticKind::Io(LogString::Owned(err.to_string())),
+                ));
+                continue;
+            }
+        };
+        if file_type.is_dir() {
+            find_rule_files(&entry_path, files, diagnostics);
+        } else if is_yaml(&entry_path) && entry_path.is_file() {
+            files.push(entry_path);
+        }
+    }
+}
+
+fn is_yaml(path: &Path) -> bool {
+    match path.extension().and_then(|v| v.to_str()) {
+        Some(extension) => {
+            extension.eq_ignore_ascii_case("yml") || extension.eq_ignore_ascii_case("yaml")
+        }
+        None => false,
+    }
+}
+
+/// Rules of a multi-document YAML file
+fn parse_documents(
+    file: &Path,
+    content: &str,
+    diagnostics: &mut Vec<SigmaDiagnostic>,
+) -> Vec<SigmaRule> {
+    let mut rules = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(content) {
+        // A syntax error ends the stream: the next documents cannot be read
+        let value = match serde_yaml::Value::deserialize(document) {
+            Ok(v) => v,
+            Err(err) => {
+                diagnostics.push(file_diagnostic(
+                    file.to_path_buf(),
+                    SigmaDiagnosticKind::Parse(LogString::Owned(err.to_string())),
+                ));
+                break;
+            }
+        };
+        match serde_yaml::from_value(value) {
+            Ok(rule) => rules.push(rule),
+            Err(err) => diagnostics.push(file_diagnostic(
+                file.to_path_buf(),
+                SigmaDiagnosticKind::Parse(LogString::Owned(err.to_string())),
+            )),
+        }
+    }
+    rules
+}
+
+/// The id or the name of the rule is one of the references
+fn is_referenced(rule: &SigmaRule, referenced: impl Fn(&LogString) -> bool) -> bool {
+    rule.id.iter().chain(rule.name.iter()).any(referenced)
+}
+
+fn error_kind(err: SigmaError) -> SigmaDiagnosticKind {
+    match err {
+        SigmaError::UnsupportedModifier(field, modifier) => {
+            SigmaDiagnosticKind::UnsupportedModifier(field, modifier)
+        }
+        err => SigmaDiagnosticKind::Conversion(err),
+    }
+}
+
+fn file_diagnostic(file: PathBuf, kind: SigmaDiagnosticKind) -> SigmaDiagnostic {
+    SigmaDiagnostic {
+        file,
+        rule: None,
+        kind,
+    }
+}
+
+fn rule_diagnostic(source: SourceRule, kind: SigmaDiagnosticKind) -> SigmaDiagnostic {
+    SigmaDiagnostic {
+        file: source.file,
+        rule: Some(source.id),
+        kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::dataset::rules::UpdateRules;
+    use std::sync::Arc;
+
+    const PROCESS_RULES: &str = r#"
+title: Whoami
+id: whoami
+status: stable
+logsource:
+    category: process_creation
+    product: windows
+detection:
+    selection:
+        Image|endswith: '\whoami.exe'
+    condition: selection
+---
+title: Net user
+id: net_user
+status: experimental
+logsource:
+    category: process_creation
+    product: windows
+detection:
+    selection:
+        CommandLine|contains: 'net user'
+    condition: selection
+---
+title: Broken
+id: broken
+logsource:
+    category: process_creation
+    product: windows
+detection:
+    selection:
+        CommandLine|unknown: 'test'
+    condition: selection
+"#;
+
+    const OTHER_RULES: &str = r#"
+title: Whoami again
+id: whoami
+logsource:
+    category: process_creation
+    product: windows
+detection:
+    selection:
+        Image|endswith: '\whoami.exe'
+    condition: selection
+---
+title: Linux rule
+id: linux_rule
+logsource:
+    product: linux
+detection:
+    selection:
+        Image: '/usr/bin/id'
+    condition: selection
+---
+detection: [
+"#;
+
+    const CORRELATION: &str = r#"
+title: Failed login
+id: failed_login
+logsource:
+    category: authentication
+    product: windows
+detection:
+    selection:
+        EventID: 4625
+    condition: selection
+---
+title: Many failed logins
+id: many_failed_logins
+correlation:
+    type: event_count
+    rules:
+        - failed_login
+    group-by:
+        - TargetUserName
+    timespan: 5m
+    condition:
+        gte: 10
+"#;
+
+    const PIPELINE: &str = r#"
+name: windows
+logsources:
+  - logsource:
+        product: windows
+    product: Windows
+"#;
+
+    #[test]
+    fn should_load_a_directory_of_sigma_rules() {
+        let dir = std::env::temp_dir().join(format!(
+            "usiem_sigma_loader_{}_{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|v| v.as_nanos())
+                .unwrap_or_default()
+        ));
+        std::fs::create_dir_all(dir.join("windows")).unwrap();
+        std::fs::write(dir.join("a_process.yml"), PROCESS_RULES).unwrap();
+        std::fs::write(dir.join("windows").join("b_other.yaml"), OTHER_RULES).unwrap();
+        std::fs::write(dir.join("windows").join("correlation.yml"), CORRELATION).unwrap();
+        std::fs::write(dir.join("README.md"), "Not a rule").unwrap();
+        // Links to directories are not followed
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("windows").join("loop.yml")).unwrap();
+
+        let pipeline: SigmaPipeline = serde_yaml::from_str(PIPELINE).unwrap();
+        let loader =
+            SigmaLoader::new(pipeline).skip_status(vec![LogString::Borrowed("experimental")]);
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let dataset = CorrelationRulesDataset::new(Arc::new(RulesDataset::new()), sender);
+        let report = loader.load_into(&dir, &dataset);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(3, report.files);
+        assert_eq!(vec!["whoami", "many_failed_logins"], report.loaded);
+        assert_eq!(vec!["net_user"], report.skipped);
+        let kinds: Vec<(Option<&str>, &SigmaDiagnosticKind)> = report
+            .diagnostics
+            .iter()
+            .map(|v| (v.rule.as_deref(), &v.kind))
+            .collect();
+        assert_eq!(4, kinds.len());
+        // Syntax errors are found before reading the documents of the file
+        assert!(matches!(kinds[0], (None, SigmaDiagnosticKind::Parse(_))));
+        assert!(matches!(
+            kinds[1],
+            (Some("whoami"), SigmaDiagnosticKind::DuplicateId(first)) if first == &dir.join("a_process.yml")
+        ));
+        assert_eq!(
+            (
+                Some("broken"),
+                &SigmaDiagnosticKind::UnsupportedModifier(
+                    LogString::Borrowed("CommandLine"),
+                    LogString::Borrowed("unknown")
+                )
+            ),
+            kinds[2]
+        );
+        assert!(matches!(
+            kinds[3],
+            (Some("linux_rule"), SigmaDiagnosticKind::UnknownLogSource(_))
+        ));
+
+        let rules = match receiver.try_recv().unwrap() {
+            UpdateRules::Replace(rules) => rules,
+            _ => panic!("The rules must be replaced"),
+        };
+        assert_eq!(2, rules.len());
+        // The rule referenced by the correlation is only used inside it
+        assert!(rules.get(&LogString::Borrowed("failed_login")).is_none());
+        let correlation = rules
+            .get(&LogString::Borrowed("many_failed_logins"))
+            .unwrap();
+        assert!(correlation.aggregation.is_some());
+    }
+}
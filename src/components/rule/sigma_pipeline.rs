@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::types::LogString;
+use crate::prelude::SiemField;
+
+use super::sigma::{parse_rule_condition, SigmaError, SigmaRuleLogSource, SigmaValue};
+use super::{RuleCondition, RuleOperator};
+
+/// Name of the subrule with the conditions of the logsource added by a pipeline
+pub const LOGSOURCE_SUBRULE: &str = "_logsource";
+
+/// Processing pipeline applied when converting Sigma rules into SiemRules.
+/// Maps the logsources of Sigma to the product, service and category of the logs and renames the
+/// Sigma fields (`Image`, `CommandLine`...) to the fields of the SiemLogs.
+///
+/// ```yaml
+/// name: windows_ecs
+/// fields:
+///   Image: process.executable
+///   CommandLine: process.command_line
+/// logsources:
+///   - logsource:
+///       category: process_creation
+///       product: windows
+///     product: Windows
+///     service: Sysmon
+///     conditions:
+///       event.code: 1
+/// ```
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct SigmaPipeline {
+    #[serde(default)]
+    pub name: LogString,
+    /// Sigma field => Log field. Used with all the logsources.
+    #[serde(default)]
+    pub fields: BTreeMap<LogString, LogString>,
+    /// All the mappings that match the logsource of the rule are applied
+    #[serde(default)]
+    pub logsources: Vec<SigmaLogSourceMapping>,
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+pub struct SigmaLogSourceMapping {
+    /// Logsource of the Sigma rules. The attributes not defined match any rule.
+    pub logsource: SigmaRuleLogSource,
+    /// Value of the `product` field of the logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<LogString>,
+    /// Value of the `service` field of the logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<LogString>,
+    /// Value of the `category` field of the logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<LogString>,
+    /// Sigma field => Log field. Takes precedence over the fields of the pipeline.
+    #[serde(default)]
+    pub fields: BTreeMap<LogString, LogString>,
+    /// Additional conditions with the Sigma syntax over the fields of the logs. Ex: `event.code: 1`
+    #[serde(default)]
+    pub conditions: BTreeMap<LogString, SigmaValue>,
+}
+
+/// Field mapping and conditions of the pipeline for the logsource of a rule
+#[derive(Clone, Default, Debug)]
+pub struct ResolvedPipeline {
+    pub fields: BTreeMap<LogString, LogString>,
+    /// Conditions that the logs must match. Empty if no mapping adds conditions.
+    pub conditions: Vec<RuleCondition>,
+}
+
+impl SigmaLogSourceMapping {
+    pub fn matches(&self, logsource: &SigmaRuleLogSource) -> bool {
+        attribute_matches(&self.logsource.category, &logsource.category)
+            && attribute_matches(&self.logsource.product, &logsource.product)
+            && attribute_matches(&self.logsource.service, &logsource.service)
+    }
+}
+
+fn attribute_matches(expected: &Option<LogString>, value: &Option<LogString>) -> bool {
+    match (expected, value) {
+        (None, _) => true,
+        (Some(expected), Some(value)) => expected.eq_ignore_ascii_case(value),
+        (Some(_), None) => false,
+    }
+}
+
+impl SigmaPipeline {
+    pub fn new(name: LogString) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// Applies the mappings that match the logsource
+    pub fn resolve(&self, logsource: &SigmaRuleLogSource) -> Result<ResolvedPipeline, SigmaError> {
+        let mut resolved = ResolvedPipeline {
+            fields: self.fields.clone(),
+            conditions: Vec::new(),
+        };
+        for mapping in self.logsources.iter().filter(|v| v.matches(logsource)) {
+            for (sigma, field) in &mapping.fields {
+                resolved.fields.insert(sigma.clone(), field.clone());
+            }
+            let targets = [
+                ("product", &mapping.product),
+                ("service", &mapping.service),
+                ("category", &mapping.category),
+            ];
+            for (field, value) in targets {
+                if let Some(value) = value {
+                    resolved.conditions.push(RuleCondition {
+                        field: LogString::Borrowed(field),
+                        operator: RuleOperator::Equals(SiemField::Text(value.clone())),
+                    });
+                }
+            }
+            for (field, value) in &mapping.conditions {
+                resolved.conditions.push(parse_rule_condition(
+                    field.clone(),
+                    value.clone(),
+                    &BTreeMap::new(),
+                )?);
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::holder::DatasetHolder;
+    use crate::prelude::rule::expression::RuleExpression;
+    use crate::prelude::rule::sigma::SigmaRule;
+    use crate::prelude::rule::{AlertContent, SiemRule};
+    use crate::prelude::SiemLog;
+
+    const PIPELINE: &str = r#"
+name: windows_ecs
+fields:
+    Image: process.executable
+    CommandLine: process.command_line
+logsources:
+  - logsource:
+        category: process_creation
+        product: windows
+    product: Windows
+    service: Sysmon
+    fields:
+        CommandLine: process.args
+    conditions:
+        event.code: 1
+  - logsource:
+        product: linux
+    product: Linux
+"#;
+
+    const RULE: &str = r#"
+title: Hidden PowerShell
+id: hidden_powershell
+description: Hidden window in $CommandLine
+logsource:
+    category: process_creation
+    product: windows
+detection:
+    selection:
+        Image|endswith: '\powershell.exe'
+        CommandLine|contains: '-w hidden'
+    filter:
+        User: 'SYSTEM'
+    same_image:
+        ParentImage|fieldref: Image
+    condition: selection and not filter and not same_image
+"#;
+
+    #[test]
+    fn should_map_fields_and_logsources() {
+        let pipeline: SigmaPipeline = serde_yaml::from_str(PIPELINE).unwrap();
+        let sigma: SigmaRule = serde_yaml::from_str(RULE).unwrap();
+        let rule = sigma.into_siem_rule(&pipeline).unwrap();
+
+        let selection = &rule.subrules["selection"];
+        // The mapping of the logsource takes precedence
+        assert_eq!("process.args", selection.conditions[0].field);
+        assert_eq!("process.executable", selection.conditions[1].field);
+        // Fields without mapping are not renamed
+        assert_eq!("User", rule.subrules["filter"].conditions[0].field);
+        // Nor the referenced fields
+        assert_eq!(
+            RuleOperator::EqualsField(LogString::Borrowed("process.executable")),
+            rule.subrules["same_image"].conditions[0].operator
+        );
+        assert_eq!(
+            AlertContent::Field(LogString::Borrowed("process.args")),
+            rule.alert.content[1]
+        );
+
+        let logsource = &rule.subrules[LOGSOURCE_SUBRULE];
+        let fields: Vec<&str> = logsource.conditions.iter().map(|v| &v.field[..]).collect();
+        assert_eq!(vec!["product", "service", "event.code"], fields);
+        assert_eq!(
+            Some(Box::new(RuleExpression::And(vec![
+                RuleExpression::Subrule(LogString::Borrowed(LOGSOURCE_SUBRULE)),
+                RuleExpression::And(vec![
+                    RuleExpression::Subrule(LogString::Borrowed("selection")),
+                    RuleExpression::Not(Box::new(RuleExpression::Subrule(LogString::Borrowed(
+                        "filter"
+                    )))),
+                    RuleExpression::Not(Box::new(RuleExpression::Subrule(LogString::Borrowed(
+                        "same_image"
+                    )))),
+                ]),
+            ]))),
+            rule.expression
+        );
+
+        let datasets = DatasetHolder::new();
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.set_product("Windows");
+        log.set_service("Sysmon");
+        log.add_field("event.code", SiemField::I64(1));
+        log.add_field(
+            "process.executable",
+            "C:\\Windows\\System32\\PowerShell.exe".into(),
+        );
+        log.add_field("process.args", "powershell.exe -W Hidden".into());
+        log.add_field("User", "bob".into());
+        log.add_field("ParentImage", "C:\\Windows\\explorer.exe".into());
+        assert!(rule.matches(&mut log, &datasets).is_some());
+        log.set_product("Linux");
+        assert!(rule.matches(&mut log, &datasets).is_none());
+
+        // Only the mappings of the logsource are applied
+        let mut sigma: SigmaRule = serde_yaml::from_str(RULE).unwrap();
+        sigma.logsource.to_mut().product = Some(LogString::Borrowed("linux"));
+        sigma.logsource.to_mut().category = None;
+        let resolved = pipeline.resolve(&sigma.logsource).unwrap();
+        assert_eq!(1, resolved.conditions.len());
+        assert_eq!(
+            Some(&LogString::Borrowed("process.command_line")),
+            resolved.fields.get("CommandLine")
+        );
+
+        // Without a pipeline the rule is not changed
+        let sigma: SigmaRule = serde_yaml::from_str(RULE).unwrap();
+        let rule: SiemRule = sigma.try_into().unwrap();
+        assert!(!rule.subrules.contains_key(LOGSOURCE_SUBRULE));
+        assert_eq!("Image", rule.subrules["selection"].conditions[1].field);
+
+        // The subrule of the logsource cannot be replaced
+        let sigma: SigmaRule =
+            serde_yaml::from_str(&RULE.replace("filter", LOGSOURCE_SUBRULE)).unwrap();
+        assert_eq!(
+            SigmaError::ReservedIdentifier(LogString::Borrowed(LOGSOURCE_SUBRULE)),
+            sigma.into_siem_rule(&pipeline).unwrap_err()
+        );
+    }
+}
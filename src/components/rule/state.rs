@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::storage::SiemComponentStateStorage;
+use crate::prelude::types::LogString;
+use crate::prelude::{SiemLog, SiemResult};
+
+use super::{RuleState, RuleStateValue};
+
+/// Key used to store the snapshot of the states in the component storage
+pub const RULE_STATE_STORAGE_KEY: &str = "rule_state";
+
+/// Stores the states created by the subrules as to correlate logs between them.
+/// Ex: a host downloaded a file and then executed powershell.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RuleStateStore {
+    /// State name => Key => Expiration time in milliseconds
+    states: BTreeMap<LogString, BTreeMap<LogString, i64>>,
+}
+
+/// States available during the evaluation of a log
+#[derive(Clone, Copy)]
+pub struct StateContext<'a> {
+    pub store: &'a RuleStateStore,
+    /// Time of the log being evaluated
+    pub now: i64,
+}
+
+impl RuleStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Creates or refreshes a state that expires at the given time
+    pub fn insert(&mut self, name: LogString, key: LogString, expiration: i64) {
+        self.states.entry(name).or_default().insert(key, expiration);
+    }
+    /// Records the state using the content of the log
+    pub fn record(&mut self, state: &RuleState, log: &SiemLog, now: i64) {
+        let key = match state.key(log) {
+            Some(v) => v,
+            None => return,
+        };
+        let expiration = match state.ttl {
+            Some(ttl) => now.saturating_add(ttl),
+            None => i64::MAX,
+        };
+        self.insert(state.name.clone(), key, expiration);
+    }
+    /// Checks if the state exists and has not expired
+    pub fn contains(&self, name: &str, key: &str, now: i64) -> bool {
+        self.states
+            .get(name)
+            .and_then(|keys| keys.get(key))
+            .map(|expiration| *expiration > now)
+            .unwrap_or(false)
+    }
+    /// Checks if the state referenced by the log exists
+    pub fn exists(&self, state: &RuleState, log: &SiemLog, now: i64) -> bool {
+        match state.key(log) {
+            Some(key) => self.contains(&state.name, &key, now),
+            None => false,
+        }
+    }
+    pub fn remove(&mut self, name: &str, key: &str) {
+        if let Some(keys) = self.states.get_mut(name) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.states.remove(name);
+            }
+        }
+    }
+    /// Removes the expired states
+    pub fn clean(&mut self, now: i64) {
+        for keys in self.states.values_mut() {
+            keys.retain(|_, expiration| *expiration > now);
+        }
+        self.states.retain(|_, keys| !keys.is_empty());
+    }
+    /// Number of stored states
+    pub fn len(&self) -> usize {
+        self.states.values().map(|keys| keys.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+    /// Saves a snapshot of the states in the component storage
+    pub fn save(&self, storage: &mut dyn SiemComponentStateStorage) -> SiemResult<()> {
+        let snapshot = serde_json::to_string(self)?;
+        storage.set_value(RULE_STATE_STORAGE_KEY, LogString::Owned(snapshot), true)?;
+        Ok(())
+    }
+    /// Loads the snapshot of the states from the component storage
+    pub fn load(storage: &dyn SiemComponentStateStorage) -> SiemResult<Self> {
+        let snapshot = storage.get_value(RULE_STATE_STORAGE_KEY)?;
+        Ok(serde_json::from_str(&snapshot)?)
+    }
+}
+
+impl RuleState {
+    /// Key of the state: the text itself or the content of the field in the log
+    pub fn key(&self, log: &SiemLog) -> Option<LogString> {
+        match &self.states {
+            RuleStateValue::Text(v) => Some(v.clone()),
+            RuleStateValue::Field(field) => Some(LogString::Owned(log.field(field)?.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::storage::TestingStorage;
+
+    #[test]
+    fn should_expire_states() {
+        let state = RuleState {
+            name: LogString::Borrowed("downloaded_file"),
+            states: RuleStateValue::Field(LogString::Borrowed("host.hostname")),
+            ttl: Some(1_000),
+        };
+        let mut log = SiemLog::new("", 0, "localhost");
+        log.add_field("host.hostname", "host001".into());
+        let mut store = RuleStateStore::new();
+        store.record(&state, &log, 10_000);
+        assert!(store.exists(&state, &log, 10_500));
+        assert!(!store.exists(&state, &log, 11_000));
+        log.add_field("host.hostname", "host002".into());
+        assert!(!store.exists(&state, &log, 10_500));
+        store.clean(11_000);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn should_save_and_load_snapshot() {
+        let mut store = RuleStateStore::new();
+        store.insert(
+            LogString::Borrowed("downloaded_file"),
+            LogString::Borrowed("host001"),
+            i64::MAX,
+        );
+        let mut storage = TestingStorage::new();
+        store.save(&mut storage).unwrap();
+        let loaded = RuleStateStore::load(&storage).unwrap();
+        assert!(loaded.contains("downloaded_file", "host001", 0));
+        assert_eq!(1, loaded.len());
+    }
+}
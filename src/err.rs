@@ -3,6 +3,7 @@ use std::io::Error as IoError;
 use serde::{Deserialize, Serialize};
 
 use crate::prelude::parsing::LogParsingError;
+use crate::prelude::rule::RuleError;
 pub type SiemResult<T> = Result<T, SiemError>;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -27,6 +28,8 @@ pub enum SiemError {
     Messaging(MessagingError),
     Other(String),
     Component(ComponentError),
+    /// A rule is not valid
+    Rule(RuleError),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -68,6 +71,11 @@ impl From<ComponentError> for SiemError {
         Self::Component(e)
     }
 }
+impl From<RuleError> for SiemError {
+    fn from(e: RuleError) -> Self {
+        Self::Rule(e)
+    }
+}
 impl From<StorageError> for SiemError {
     fn from(e: StorageError) -> Self {
         Self::Storage(e)
@@ -32,6 +32,24 @@ impl SiemIp {
             SiemIp::V6(ip) => is_local_ipv6(*ip),
         }
     }
+    /// Checks if both IPs belong to the same network with the given prefix length
+    pub fn same_net(&self, other: &SiemIp, net: u8) -> bool {
+        match (self, other) {
+            (SiemIp::V4(ip1), SiemIp::V4(ip2)) => {
+                let mask = u32::MAX
+                    .checked_shl(32u32.saturating_sub(net as u32))
+                    .unwrap_or(0);
+                ip1 & mask == ip2 & mask
+            }
+            (SiemIp::V6(ip1), SiemIp::V6(ip2)) => {
+                let mask = u128::MAX
+                    .checked_shl(128u32.saturating_sub(net as u32))
+                    .unwrap_or(0);
+                ip1 & mask == ip2 & mask
+            }
+            _ => false,
+        }
+    }
     pub fn equals(&self, val: &str) -> bool {
         match self {
             SiemIp::V4(ip1) => match ipv4_from_str(val) {
@@ -168,6 +186,15 @@ mod tst {
         assert_eq!(SiemIp::V4(111).to_string(), "0.0.0.111");
     }
 
+    #[test]
+    fn should_be_in_the_same_net() {
+        let ip: SiemIp = [192, 168, 1, 1].into();
+        assert!(ip.same_net(&[192, 168, 1, 200].into(), 24));
+        assert!(!ip.same_net(&[192, 168, 2, 1].into(), 24));
+        assert!(ip.same_net(&[10, 0, 0, 1].into(), 0));
+        assert!(!ip.same_net(&[192, 168, 1, 2].into(), 32));
+    }
+
     #[test]
     fn from_u32_vec() {
         let ip: SiemIp = [192, 168, 1, 1].into();
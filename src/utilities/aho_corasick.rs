@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/// Multi-pattern substring search. Finds which of the patterns appear in a text with a single pass.
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+    nodes: Vec<Node>,
+    patterns: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    next: BTreeMap<u8, usize>,
+    fail: usize,
+    /// Patterns that end in this node, including the ones reachable by the fail links
+    outputs: Vec<usize>,
+}
+
+impl AhoCorasick {
+    /// Builds the automaton. The position of each pattern is used as its identifier. Empty patterns never match.
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let mut nodes = vec![Node::default()];
+        for (id, pattern) in patterns.iter().enumerate() {
+            let pattern = pattern.as_ref().as_bytes();
+            if pattern.is_empty() {
+                continue;
+            }
+            let mut current = 0;
+            for byte in pattern {
+                current = match nodes[current].next.get(byte) {
+                    Some(v) => *v,
+                    None => {
+                        nodes.push(Node::default());
+                        let position = nodes.len() - 1;
+                        nodes[current].next.insert(*byte, position);
+                        position
+                    }
+                };
+            }
+            nodes[current].outputs.push(id);
+        }
+        // Breadth first so the fail node is always computed before its children
+        let mut queue: VecDeque<usize> = nodes[0].next.values().copied().collect();
+        while let Some(current) = queue.pop_front() {
+            let children: Vec<(u8, usize)> =
+                nodes[current].next.iter().map(|(k, v)| (*k, *v)).collect();
+            for (byte, child) in children {
+                let mut fail = nodes[current].fail;
+                let fail = loop {
+                    if let Some(v) = nodes[fail].next.get(&byte) {
+                        break *v;
+                    }
+                    if fail == 0 {
+                        break 0;
+                    }
+                    fail = nodes[fail].fail;
+                };
+                nodes[child].fail = fail;
+                let inherited = nodes[fail].outputs.clone();
+                nodes[child].outputs.extend(inherited);
+                queue.push_back(child);
+            }
+        }
+        Self {
+            nodes,
+            patterns: patterns.len(),
+        }
+    }
+
+    /// Identifiers of the patterns found in the text, without duplicates
+    pub fn find_all(&self, text: &str) -> Vec<usize> {
+        let mut found = vec![false; self.patterns];
+        let mut to_ret = Vec::new();
+        let mut current = 0;
+        for byte in text.as_bytes() {
+            current = loop {
+                if let Some(v) = self.nodes[current].next.get(byte) {
+                    break *v;
+                }
+                if current == 0 {
+                    break 0;
+                }
+                current = self.nodes[current].fail;
+            };
+            for id in &self.nodes[current].outputs {
+                if !found[*id] {
+                    found[*id] = true;
+                    to_ret.push(*id);
+                }
+            }
+        }
+        to_ret
+    }
+
+    /// Checks if any pattern appears in the text
+    pub fn is_match(&self, text: &str) -> bool {
+        let mut current = 0;
+        for byte in text.as_bytes() {
+            current = loop {
+                if let Some(v) = self.nodes[current].next.get(byte) {
+                    break *v;
+                }
+                if current == 0 {
+                    break 0;
+                }
+                current = self.nodes[current].fail;
+            };
+            if !self.nodes[current].outputs.is_empty() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_all_patterns() {
+        let automaton = AhoCorasick::new(&["he", "she", "his", "hers", ""]);
+        let mut found = automaton.find_all("ushers");
+        found.sort();
+        assert_eq!(vec![0, 1, 3], found);
+        assert_eq!(vec![2], automaton.find_all("this"));
+        assert!(automaton.find_all("xyz").is_empty());
+        assert!(automaton.is_match("ahishers"));
+        assert!(!automaton.is_match("hi"));
+        let automaton = AhoCorasick::new(&["-enc", "-EncodedCommand", "powershell"]);
+        let mut found = automaton.find_all("powershell.exe -EncodedCommand AAAA");
+        found.sort();
+        assert_eq!(vec![1, 2], found);
+    }
+}
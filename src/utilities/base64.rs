@@ -0,0 +1,78 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes a list of bytes into a standard base64 text with padding
+pub fn encode(input: &[u8]) -> String {
+    let mut to_ret = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).copied().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).copied().unwrap_or(0) as u32;
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+        to_ret.push(ALPHABET[(triple >> 18 & 0x3f) as usize] as char);
+        to_ret.push(ALPHABET[(triple >> 12 & 0x3f) as usize] as char);
+        if chunk.len() > 1 {
+            to_ret.push(ALPHABET[(triple >> 6 & 0x3f) as usize] as char);
+        } else {
+            to_ret.push('=');
+        }
+        if chunk.len() > 2 {
+            to_ret.push(ALPHABET[(triple & 0x3f) as usize] as char);
+        } else {
+            to_ret.push('=');
+        }
+    }
+    to_ret
+}
+
+fn decode_char(ch: u8) -> Result<u32, &'static str> {
+    Ok(match ch {
+        b'A'..=b'Z' => ch - b'A',
+        b'a'..=b'z' => ch - b'a' + 26,
+        b'0'..=b'9' => ch - b'0' + 52,
+        b'+' | b'-' => 62,
+        b'/' | b'_' => 63,
+        _ => return Err("Invalid base64 character"),
+    } as u32)
+}
+
+/// Decodes a base64 text. Accepts both the standard and the URL safe alphabet, with or without padding.
+pub fn decode(input: &str) -> Result<Vec<u8>, &'static str> {
+    let input = input.trim_end_matches('=').as_bytes();
+    if input.len() % 4 == 1 {
+        return Err("Invalid base64 length");
+    }
+    let mut to_ret = Vec::with_capacity(input.len() * 3 / 4);
+    for chunk in input.chunks(4) {
+        let mut quad = 0u32;
+        for (i, ch) in chunk.iter().enumerate() {
+            quad |= decode_char(*ch)? << (18 - 6 * i);
+        }
+        to_ret.push((quad >> 16) as u8);
+        if chunk.len() > 2 {
+            to_ret.push((quad >> 8) as u8);
+        }
+        if chunk.len() > 3 {
+            to_ret.push(quad as u8);
+        }
+    }
+    Ok(to_ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode() {
+        assert_eq!("", encode(b""));
+        assert_eq!("Zg==", encode(b"f"));
+        assert_eq!("Zm8=", encode(b"fo"));
+        assert_eq!("Zm9v", encode(b"foo"));
+        assert_eq!("Zm9vYmFy", encode(b"foobar"));
+        assert_eq!(b"f".to_vec(), decode("Zg==").unwrap());
+        assert_eq!(b"fo".to_vec(), decode("Zm8").unwrap());
+        assert_eq!(b"foobar".to_vec(), decode("Zm9vYmFy").unwrap());
+        assert!(decode("Zm9vY").is_err());
+        assert!(decode("Zm9*").is_err());
+    }
+}
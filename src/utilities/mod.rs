@@ -1,3 +1,5 @@
+pub mod aho_corasick;
+pub mod base64;
 pub mod http_utils;
 pub mod ip_utils;
 pub mod mac;